@@ -1,9 +0,0 @@
-pub enum Errors {
-    MessageTooBig,
-    SendFailed,
-}
-
-pub enum ErrorType {
-    DecryptionError,
-    ReceiveError,
-}
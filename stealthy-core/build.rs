@@ -28,18 +28,9 @@ fn try_gcc(lib: &str, msg: &str) {
 
 fn main() {
 
-    try_gcc("-lpcap", "pcap not found. On Ubuntu try 'sudo apt-get install libpcap-dev' before continuing.");
+    // gcc is not available on Windows.
+    if env::var("CARGO_CFG_UNIX").is_err() {
+        return;
+    }
     try_gcc("-lcrypto", "crypto not found. On Ubuntu try 'sudo apt-get install libssl-dev' before continuing.");
-
-    let out_dir = env::var("OUT_DIR").unwrap();
-
-    Command::new("gcc").args(&["icmp/net.c", "-c", "-fPIC", "-o"])
-                       .arg(&format!("{}/net.o", out_dir)).status().unwrap();
-    Command::new("ar").args(&["crus", "libicmp.a", "net.o"])
-                      .current_dir(&Path::new(&out_dir)).status().unwrap();
-
-    println!("cargo:rustc-link-search=native={}", out_dir);
-    println!("cargo:rustc-link-lib=static=icmp");
 }
-
-
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::layer::{Layer, Layers};
+use crate::message::{IncomingMessage, Message};
+use crate::sendhandle::SendResult;
+
+const CLOSED: &str = "The layers have been closed.";
+/// Reason why a message sent via `AsyncLayers::send_timeout` has been given up.
+const TIMED_OUT: &str = "The message has not been acknowledged in time.";
+/// Interval in milliseconds in which the deadlines of `AsyncLayers::send_timeout` are checked.
+const DEADLINE_INTERVAL: u64 = 50;
+
+/// State shared between the thread which receives the incoming messages and the futures.
+struct Shared {
+    queue: VecDeque<IncomingMessage>,
+    /// True if the layers have closed the channel of the incoming messages.
+    closed: bool,
+    /// Task which waits for the next incoming message.
+    waker: Option<Waker>,
+    /// Messages sent via `AsyncLayers::send` by their id.
+    sends: HashMap<u64, PendingSend>,
+}
+
+struct PendingSend {
+    result: Option<SendResult>,
+    waker: Option<Waker>,
+    /// Time after which the message is given up (see `AsyncLayers::send_timeout`).
+    deadline: Option<Instant>,
+}
+
+/// Async interface of the layers for applications which run on an executor like tokio.
+///
+/// The layers work with threads and blocking channels. `Layer::into_async` moves the
+/// receiving end of the channel into a thread which passes the incoming messages to
+/// `IncomingMessages`. The same thread resolves the `SendFuture` of a message as soon as the
+/// message has been acknowledged or given up or its deadline has passed. As both wake the
+/// task which waits for them, they work with any executor.
+pub struct AsyncLayers {
+    layers: Arc<Layers>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Layer {
+
+    pub fn into_async(self) -> (AsyncLayers, IncomingMessages) {
+        let layers = Arc::new(self.layers);
+        // A weak reference so that the layers are dropped together with `AsyncLayers`.
+        let weak = Arc::downgrade(&layers);
+        let shared = dispatch(self.rx, move |id| if let Some(l) = weak.upgrade() {
+            l.cancel(id);
+        });
+        (AsyncLayers { layers, shared: shared.clone() }, IncomingMessages { shared })
+    }
+}
+
+impl AsyncLayers {
+
+    /// Sends the message in the background. The future resolves when the message has been
+    /// acknowledged or given up, e.g. after too many retransmissions (see `Layers::send`).
+    /// Use `send_timeout` to wait at most for a given time.
+    pub fn send(&self, msg: Message) -> SendFuture {
+        self.send_until(msg, None)
+    }
+
+    /// Sends the message like `send`. If the message has not been acknowledged or given up
+    /// after the timeout, it is cancelled (see `Layers::cancel`) and the future resolves
+    /// with an error.
+    pub fn send_timeout(&self, msg: Message, timeout: Duration) -> SendFuture {
+        self.send_until(msg, Some(Instant::now() + timeout))
+    }
+
+    fn send_until(&self, msg: Message, deadline: Option<Instant>) -> SendFuture {
+        // The future is registered first as the ACK could arrive before `send` returns.
+        let f = SendFuture::register(&self.shared, rand::random::<u64>(), deadline);
+        self.layers.send(msg, f.id, true);
+        f
+    }
+
+    /// Returns the layers for all functions which do not wait for the network.
+    pub fn layers(&self) -> &Layers {
+        &self.layers
+    }
+}
+
+/// Future of a message which resolves when the message has been acknowledged or given up.
+pub struct SendFuture {
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl SendFuture {
+
+    fn register(shared: &Arc<Mutex<Shared>>, id: u64, deadline: Option<Instant>) -> SendFuture {
+        let mut s = shared.lock().unwrap();
+        let result = match s.closed {
+            true => Some(Err(CLOSED.to_string())),
+            false => None
+        };
+        s.sends.insert(id, PendingSend { result, waker: None, deadline });
+        SendFuture { id, shared: shared.clone() }
+    }
+
+    /// Returns the id of the message which is reported in `IncomingMessage::Ack`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Future for SendFuture {
+    type Output = SendResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<SendResult> {
+        let mut s = self.shared.lock().unwrap();
+        let done = match s.sends.get_mut(&self.id) {
+            Some(p) if p.result.is_some() => true,
+            Some(p) => {
+                p.waker = Some(cx.waker().clone());
+                false
+            },
+            // The result has already been returned.
+            None => return Poll::Ready(Err(CLOSED.to_string()))
+        };
+        match done {
+            true => Poll::Ready(s.sends.remove(&self.id).and_then(|p| p.result).unwrap()),
+            false => Poll::Pending
+        }
+    }
+}
+
+impl Drop for SendFuture {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap().sends.remove(&self.id);
+    }
+}
+
+/// Receives the incoming messages asynchronously like the receiving end of a channel.
+/// `recv` returns a future of the next message which resolves with None when the layers
+/// have been closed. No stream trait is implemented, so that only the standard library is
+/// needed.
+pub struct IncomingMessages {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl IncomingMessages {
+
+    /// Polls for the next message. Returns None when the layers have been closed.
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<IncomingMessage>> {
+        let mut s = self.shared.lock().unwrap();
+        match s.queue.pop_front() {
+            Some(m) => Poll::Ready(Some(m)),
+            None if s.closed => Poll::Ready(None),
+            None => {
+                s.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv { incoming: self }
+    }
+}
+
+/// Future of the next message of `IncomingMessages`.
+pub struct Recv<'a> {
+    incoming: &'a mut IncomingMessages,
+}
+
+impl<'a> Future for Recv<'a> {
+    type Output = Option<IncomingMessage>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<IncomingMessage>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+/// Receives the incoming messages in a thread and wakes the tasks which wait for them.
+/// Messages whose deadline has passed are given up and passed to `cancel`.
+fn dispatch<F: Fn(u64) + Send + 'static>(rx: Receiver<IncomingMessage>, cancel: F) -> Arc<Mutex<Shared>> {
+
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        closed: false,
+        waker: None,
+        sends: HashMap::new(),
+    }));
+    let s = shared.clone();
+    thread::spawn(move || {
+        loop {
+            let m = rx.recv_timeout(Duration::from_millis(DEADLINE_INTERVAL));
+            let mut s = s.lock().unwrap();
+            let received = m.is_ok();
+            match m {
+                Ok(m) => {
+                    match m {
+                        IncomingMessage::Ack(id) => resolve(&mut s, id, Ok(())),
+                        IncomingMessage::SendFailed(id, ref reason) => resolve(&mut s, id, Err(reason.clone())),
+                        _ => { }
+                    }
+                    s.queue.push_back(m);
+                },
+                Err(RecvTimeoutError::Timeout) => { },
+                Err(RecvTimeoutError::Disconnected) => {
+                    s.closed = true;
+                    let ids = s.sends.keys().cloned().collect::<Vec<_>>();
+                    for id in ids {
+                        resolve(&mut s, id, Err(CLOSED.to_string()));
+                    }
+                }
+            }
+            if received || s.closed {
+                if let Some(w) = s.waker.take() {
+                    w.wake();
+                }
+            }
+            if s.closed {
+                break;
+            }
+
+            // The futures are resolved before the messages are cancelled so that they do
+            // not report the cancellation.
+            let now = Instant::now();
+            let expired = s.sends.iter()
+                .filter(|(_, p)| p.result.is_none() && p.deadline.is_some_and(|d| d <= now))
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            for id in &expired {
+                resolve(&mut s, *id, Err(TIMED_OUT.to_string()));
+            }
+            drop(s);
+            for id in expired {
+                cancel(id);
+            }
+        }
+    });
+    shared
+}
+
+fn resolve(s: &mut Shared, id: u64, r: SendResult) {
+    if let Some(p) = s.sends.get_mut(&id) {
+        if p.result.is_none() {
+            p.result = Some(r);
+        }
+        if let Some(w) = p.waker.take() {
+            w.wake();
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{dispatch, IncomingMessages, SendFuture, TIMED_OUT};
+    use crate::builder::LayersBuilder;
+    use crate::iptools::IpAddresses;
+    use crate::memory::MemoryNetwork;
+    use crate::message::{IncomingMessage, Message};
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = Box::pin(f);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => thread::park()
+            }
+        }
+    }
+
+    #[test]
+    fn test_async() {
+
+        let (tx, rx) = channel();
+        let (cancel_tx, cancelled) = channel();
+        let shared = dispatch(rx, move |id| cancel_tx.send(id).unwrap());
+        let mut incoming = IncomingMessages { shared: shared.clone() };
+        let acked = SendFuture::register(&shared, 1, None);
+        let failed = SendFuture::register(&shared, 2, None);
+        let expired = SendFuture::register(&shared, 3, Some(Instant::now() + Duration::from_millis(20)));
+
+        let t = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            tx.send(IncomingMessage::Ack(1)).unwrap();
+            tx.send(IncomingMessage::SendFailed(2, "timeout".to_string())).unwrap();
+        });
+        assert_eq!(block_on(acked), Ok(()));
+        assert_eq!(block_on(failed), Err("timeout".to_string()));
+        assert_eq!(block_on(expired), Err(TIMED_OUT.to_string()));
+        assert_eq!(cancelled.recv(), Ok(3));
+        t.join().unwrap();
+
+        // All messages are passed on until the layers are closed.
+        assert!(matches!(block_on(incoming.recv()), Some(IncomingMessage::Ack(1))));
+        assert!(matches!(block_on(incoming.recv()), Some(IncomingMessage::SendFailed(2, _))));
+        assert!(block_on(incoming.recv()).is_none());
+        assert!(block_on(SendFuture::register(&shared, 4, None)).is_err());
+    }
+
+    #[test]
+    fn test_send_timeout() {
+
+        // Nobody answers the packets to 10.0.0.2.
+        let net = MemoryNetwork::new();
+        let (a, _incoming) = LayersBuilder::new()
+            .with_key("11111111111111111111111111111111")
+            .with_transport(net.host("10.0.0.1"))
+            .with_accept(IpAddresses::from_comma_list("10.0.0.2"))
+            .build()
+            .unwrap()
+            .into_async();
+        let f = a.send_timeout(Message::new("10.0.0.2".to_string(), b"hello".to_vec()), Duration::from_millis(300));
+        assert_eq!(block_on(f), Err(TIMED_OUT.to_string()));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::wire::{push_value, push_slice};
+
+/// Size of the length prefix of each message in a batch.
+pub const LEN_SIZE: usize = 4;
+
+/// Packs several messages into one buffer. Each message is prefixed with its length.
+pub fn pack(messages: &[&[u8]]) -> Vec<u8> {
+
+    let mut v = Vec::with_capacity(packed_size(messages.iter().map(|m| m.len())));
+    for m in messages {
+        push_value(&mut v, m.len() as u64, LEN_SIZE);
+        push_slice(&mut v, m);
+    }
+    v
+}
+
+/// Returns the size of the buffer created by `pack` for messages of the given sizes.
+pub fn packed_size<I: Iterator<Item = usize>>(sizes: I) -> usize {
+    sizes.map(|n| n + LEN_SIZE).sum()
+}
+
+/// Unpacks a buffer created with `pack`. Returns None if the buffer is malformed.
+pub fn unpack(buf: &[u8]) -> Option<Vec<Vec<u8>>> {
+
+    let mut r = vec![];
+    let mut p = 0;
+    while p < buf.len() {
+        if p + LEN_SIZE > buf.len() {
+            return None;
+        }
+        let n = (0..LEN_SIZE).rev().fold(0usize, |acc, i| (acc << 8) + buf[p + i] as usize);
+        p += LEN_SIZE;
+        if n > buf.len() - p {
+            return None;
+        }
+        r.push(buf[p..p + n].to_vec());
+        p += n;
+    }
+    Some(r)
+}
+
+/// Groups messages of the given sizes so that each group fits into `maxsiz` bytes after
+/// packing. `overhead` is the number of bytes that are added to each group, e.g. by the
+/// encryption. Messages which do not even fit alone get their own group.
+pub fn groups(sizes: &[usize], overhead: usize, maxsiz: usize) -> Vec<Vec<usize>> {
+
+    let mut r: Vec<Vec<usize>> = vec![];
+    let mut current: Vec<usize> = vec![];
+    let mut current_siz = overhead;
+
+    for (i, n) in sizes.iter().enumerate() {
+        if !current.is_empty() && current_siz + n + LEN_SIZE > maxsiz {
+            r.push(current);
+            current = vec![];
+            current_siz = overhead;
+        }
+        current.push(i);
+        current_siz += n + LEN_SIZE;
+    }
+    if !current.is_empty() {
+        r.push(current);
+    }
+    r
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{pack, unpack, groups};
+    use crate::cryp::{Encryption, SymmetricEncryption};
+    use crate::message::Message;
+
+    #[test]
+    fn test_pack_unpack() {
+
+        let v = pack(&[b"hello", b"", b"world!"]);
+        assert_eq!(v.len(), 5 + 6 + 3 * 4);
+        assert_eq!(unpack(&v).unwrap(), vec![b"hello".to_vec(), vec![], b"world!".to_vec()]);
+
+        assert_eq!(unpack(&[]).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_unpack_malformed() {
+
+        let v = pack(&[b"hello"]);
+        assert!(unpack(&v[..v.len() - 1]).is_none());
+        assert!(unpack(&v[..2]).is_none());
+    }
+
+    #[test]
+    fn test_groups() {
+
+        // overhead 10, each message needs 4 bytes for its length
+        assert_eq!(groups(&[10, 10, 10], 10, 38), vec![vec![0, 1], vec![2]]);
+        assert_eq!(groups(&[10, 10, 10], 10, 52), vec![vec![0, 1, 2]]);
+        // A message which is too big gets its own group.
+        assert_eq!(groups(&[1, 100, 1], 10, 30), vec![vec![0], vec![1], vec![2]]);
+        assert!(groups(&[], 10, 30).is_empty());
+    }
+
+    #[test]
+    fn test_batch_round_trip() {
+
+        let e = SymmetricEncryption::new(&"11111111111111111111111111111111".to_string()).unwrap();
+        let texts = vec!["first line", "second line", "", "last line"];
+
+        let packed = pack(&texts.iter().map(|t| t.as_bytes()).collect::<Vec<_>>());
+        let cipher = e.encrypt(&packed).unwrap();
+
+        let messages = unpack(&e.decrypt(&cipher).unwrap()).unwrap()
+            .into_iter()
+            .map(|buf| Message::new("1.2.3.4".to_string(), buf))
+            .collect::<Vec<_>>();
+
+        assert_eq!(messages.len(), texts.len());
+        for (m, t) in messages.iter().zip(texts.iter()) {
+            assert_eq!(m.get_ip(), "1.2.3.4");
+            assert_eq!(String::from_utf8(m.get_payload()).unwrap(), *t);
+        }
+    }
+}
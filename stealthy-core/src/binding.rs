@@ -0,0 +1,2217 @@
+use std::thread;
+use std::mem;
+use std::io::{self, Read, Write};
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use std::convert::From;
+
+use crate::message::{IncomingMessage, Message, MessageType};
+use crate::error::StealthyError;
+use crate::packet::{Packet, IdType};
+use crate::iptools::{self, IpAddresses};
+use crate::tools;
+use crate::negotiation;
+use crate::peerkeys::PeerKeys;
+use crate::cryp;
+use crate::socks::Proxy;
+use crate::transport::{Transport, IcmpOptions, Carrier};
+use crate::link::PacketTransport;
+use crate::camouflage::Camouflage;
+use crate::window::Window;
+use crate::rtt::Rtt;
+use crate::dedup::SeenPackets;
+use crate::outbox::Outbox;
+use crate::delivery::Priority;
+use crate::shutdown::Shutdown;
+use crate::stats::{Counters, Stats};
+#[cfg(unix)]
+use crate::bpf;
+use crate::status::Status;
+#[cfg(windows)]
+use crate::winsock::RawSocket;
+
+use std::collections::{HashMap, HashSet};
+use std::iter::repeat;
+
+const MAX_MESSAGE_SIZE: usize = (1024 * 1024 * 1024);
+/// Number of retransmissions after which a packet is given up if --max-retries is not given.
+pub const DEFAULT_MAX_RETRIES: u32 = 8;
+/// Interval in milliseconds in which the outbox is updated if the pending packets have changed.
+const OUTBOX_INTERVAL: i64 = 1000;
+/// Time in milliseconds for which the ACKs of file upload packets are collected before
+/// they are sent in one packet.
+const ACK_DELAY: u64 = 40;
+/// Maximum number of packets which are acknowledged with one packet.
+const ACK_BATCH_SIZE: usize = 32;
+/// Offset of the list of advertised ciphers in a PROBING ping.
+const PROBING_CIPHERS_OFFSET: usize = 21;
+/// Identifier in the ICMP header of the packets of stealthy.
+const MAGIC: u16 = 0xa387;
+/// Size of the buffer for received packets which is large enough for any IP packet.
+const RECV_BUFFER_SIZE: usize = 65536;
+/// Time in milliseconds after which a receiver which waits for packets checks whether the
+/// network has been shut down.
+pub const RECV_TIMEOUT: u64 = 200;
+const SHUT_DOWN: &str = "The network has been shut down.";
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+/// Code of the echo replies which carry a packet so that they can be distinguished from
+/// the replies of the kernel.
+const CARRIER_CODE: u8 = 1;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const IPV6_HEADER_LEN: usize = 40;
+const IPPROTO_ICMPV6: u8 = 58;
+const ICMP_HEADER_LEN: usize = 8;
+const IPV4_HEADER_LEN: usize = 20;
+const IPPROTO_ICMP: u8 = 1;
+/// TTL of spoofed datagrams if no TTL is given.
+const DEFAULT_TTL: u8 = 64;
+/// Maximum size of the payload of a ping.
+const MAX_PING_PAYLOAD: usize = 1 << 14;
+/// Size of the PROBING ping which determines the maximum payload size.
+const PROBING_SIZE: usize = 8192;
+/// Seconds to wait for the echo reply of a PROBING ping.
+const PROBE_TIMEOUT: u64 = 3;
+/// The binary search stops when the maximum payload size is known to this number of bytes.
+const PROBE_PRECISION: usize = 64;
+/// Seconds after which the maximum payload size is probed again as the path can change.
+const PROBE_INTERVAL: i64 = 600;
+/// Seconds between the pings which check whether a peer is reachable.
+const MONITOR_INTERVAL: u64 = 60;
+/// Seconds to wait for the TCP connection to a peer.
+const TCP_CONNECT_TIMEOUT: u64 = 5;
+/// Size of the length which precedes each packet sent via TCP.
+const TCP_LENGTH_SIZE: usize = 4;
+
+/// Sequence number of the next camouflaged ping. Like the ping utilities the pings are
+/// numbered consecutively.
+static PING_SEQ: AtomicUsize = AtomicUsize::new(1);
+
+// Sending packets.---------------------------------------------------------------------
+
+/// Internet checksum (RFC 1071) over the given data.
+fn checksum(buf: &[u8]) -> u16 {
+
+	let mut sum = buf.chunks(2)
+		.map(|c| (c[0] as u32) << 8 | *c.get(1).unwrap_or(&0) as u32)
+		.fold(0u32, |a, x| a + x);
+	while sum >> 16 != 0 {
+		sum = (sum & 0xffff) + (sum >> 16);
+	}
+	!(sum as u16)
+}
+
+/// Returns an ICMP or ICMPv6 echo request with the identifier of stealthy which contains
+/// the given payload. The checksum of ICMPv6 packets depends on the source address and
+/// is computed by the kernel (RFC 3542).
+fn echo_request(buf: &[u8], v6: bool, seq: u16) -> Vec<u8> {
+
+	let (typ, code) = match v6 {
+		true => (ICMPV6_ECHO_REQUEST, 0),
+		false => (ICMP_ECHO_REQUEST, 1),
+	};
+	let mut v = vec![typ, code, 0, 0, (MAGIC >> 8) as u8, MAGIC as u8, (seq >> 8) as u8, seq as u8];
+	v.extend_from_slice(buf);
+	if !v6 {
+		set_checksum(&mut v);
+	}
+	v
+}
+
+/// Returns an ICMP packet of the given carrier which contains the payload. Carriers which
+/// do not exist in ICMPv6 are sent as echo requests.
+fn carrier_packet(buf: &[u8], v6: bool, seq: u16, carrier: Carrier) -> Vec<u8> {
+
+	match carrier {
+		Carrier::EchoReply => {
+			let mut v = echo_reply(&echo_request(buf, v6, seq), v6);
+			v[1] = CARRIER_CODE;
+			if !v6 {
+				set_checksum(&mut v);
+			}
+			v
+		},
+		Carrier::Timestamp | Carrier::AddressMask if !v6 => {
+			let mut v = vec![carrier.icmp_type(), 0, 0, 0, (MAGIC >> 8) as u8, MAGIC as u8, (seq >> 8) as u8, seq as u8];
+			let mut fields = vec![0; carrier.offset()];
+			if carrier == Carrier::Timestamp {
+				// The originate timestamp in milliseconds since midnight UTC.
+				let ms = (current_millis() % (24 * 3600 * 1000)) as u32;
+				fields[..4].copy_from_slice(&ms.to_be_bytes());
+			}
+			v.extend_from_slice(&fields);
+			v.extend_from_slice(buf);
+			set_checksum(&mut v);
+			v
+		},
+		_ => echo_request(buf, v6, seq)
+	}
+}
+
+/// Returns an echo request which looks like the ping of the ping utility. The receiver
+/// cannot use the identifier to recognize the packets of stealthy and has to unwrap the
+/// payload instead.
+fn camouflaged_request(buf: &[u8], v6: bool, camouflage: Camouflage, seq: u16) -> Vec<u8> {
+
+	let mut v = echo_request(&camouflage.wrap(buf), v6, seq);
+	let id = camouflage.identifier();
+	v[1] = 0;
+	v[4] = (id >> 8) as u8;
+	v[5] = id as u8;
+	if !v6 {
+		set_checksum(&mut v);
+	}
+	v
+}
+
+/// Returns the echo reply for an echo request. Like the reply of the kernel it contains
+/// the identifier, the sequence number and the payload of the request.
+fn echo_reply(request: &[u8], v6: bool) -> Vec<u8> {
+
+	let mut v = request.to_vec();
+	v[0] = match v6 {
+		true => ICMPV6_ECHO_REPLY,
+		false => ICMP_ECHO_REPLY,
+	};
+	v[1] = 0;
+	v[2] = 0;
+	v[3] = 0;
+	if !v6 {
+		set_checksum(&mut v);
+	}
+	v
+}
+
+fn set_checksum(icmp: &mut [u8]) {
+	icmp[2] = 0;
+	icmp[3] = 0;
+	let sum = checksum(icmp);
+	icmp[2] = (sum >> 8) as u8;
+	icmp[3] = sum as u8;
+}
+
+/// Returns true if stealthy answers the echo request in place of the kernel. These are
+/// the pings of other programs and the PROBING pings which determine the maximum payload
+/// size. Camouflaged pings which do not contain a packet are pings of other programs.
+fn is_answered(icmp: &[u8], v6: bool, options: &IcmpOptions) -> bool {
+
+	match parse_carrier(icmp, v6, options) {
+		Some((Echo::Request, payload)) => Packet::deserialize(payload, String::new())
+			.map(|p| p.is_new_message() && Network::is_probing(&p.data))
+			.unwrap_or(options.camouflage.is_some()),
+		Some((Echo::Reply, _)) => false,
+		None => icmp.len() >= ICMP_HEADER_LEN && icmp[0] == if v6 { ICMPV6_ECHO_REQUEST } else { ICMP_ECHO_REQUEST }
+	}
+}
+
+/// Sends the data as payload of a ping to the given ip.
+fn send_icmp(ip: &str, buf: &[u8], options: &IcmpOptions) -> Result<(), StealthyError> {
+
+	if buf.len() > MAX_PING_PAYLOAD {
+		return Err(StealthyError::Transport("Packet is too large."));
+	}
+	let addr: IpAddr = ip.parse().map_err(|_| StealthyError::Transport("Invalid IP address."))?;
+	let v6 = addr.is_ipv6();
+	let packet = match options.camouflage {
+		Some(c) => camouflaged_request(buf, v6, c, PING_SEQ.fetch_add(1, Ordering::SeqCst) as u16),
+		None => carrier_packet(buf, v6, rand::random::<u16>(), options.carriers[0])
+	};
+	match (options.spoof, addr) {
+		(Some(src), IpAddr::V4(dst)) => {
+			let datagram = ipv4_datagram(src, dst, options.ttl.unwrap_or(DEFAULT_TTL), options.dont_fragment.unwrap_or(false), &packet);
+			spoofing_socket().and_then(|sock| sock.send(&addr, &datagram))
+		},
+		(Some(_), IpAddr::V6(_)) => Err(StealthyError::Transport("Spoofing is only supported for IPv4.")),
+		(None, _) => RawSocket::new(v6).and_then(|sock| {
+			sock.set_header_options(options.ttl, options.dont_fragment)?;
+			sock.send(&addr, &packet)
+		})
+	}
+}
+
+/// Returns an IPv4 datagram with the given source address which contains the ICMP packet.
+fn ipv4_datagram(src: Ipv4Addr, dst: Ipv4Addr, ttl: u8, dont_fragment: bool, icmp: &[u8]) -> Vec<u8> {
+
+	let len = (IPV4_HEADER_LEN + icmp.len()) as u16;
+	let id = rand::random::<u16>();
+	let flags = if dont_fragment { 0x40 } else { 0 };
+	let mut v = vec![0x45, 0, (len >> 8) as u8, len as u8, (id >> 8) as u8, id as u8, flags, 0, ttl, IPPROTO_ICMP, 0, 0];
+	v.extend_from_slice(&src.octets());
+	v.extend_from_slice(&dst.octets());
+	let sum = checksum(&v);
+	v[10] = (sum >> 8) as u8;
+	v[11] = sum as u8;
+	v.extend_from_slice(icmp);
+	v
+}
+
+/// Opens a raw socket which sends IPv4 datagrams with the IP header of the caller.
+#[cfg(unix)]
+fn spoofing_socket() -> Result<RawSocket, StealthyError> {
+
+	let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW) };
+	if fd < 0 {
+		return Err(io_error(io::Error::last_os_error(), "Could not open raw socket for spoofing."));
+	}
+	Ok(RawSocket { fd, v6: false })
+}
+
+/// Windows does not send raw IP datagrams with a foreign source address.
+#[cfg(windows)]
+fn spoofing_socket() -> Result<RawSocket, StealthyError> {
+	Err(StealthyError::Transport("Spoofing is not supported on Windows."))
+}
+
+/// Returns the largest size between lo and hi for which a ping is echoed. The largest size
+/// is tried first. If its ping is lost, e.g. because fragments are dropped, the size is
+/// searched via a binary search. Sizes up to lo are assumed to be echoed.
+fn largest_echoed<F: FnMut(usize) -> bool>(mut lo: usize, mut hi: usize, mut is_echoed: F) -> usize {
+
+	if is_echoed(hi) {
+		return hi;
+	}
+	while hi - lo > PROBE_PRECISION {
+		let mid = (lo + hi + 1) / 2;
+		match is_echoed(mid) {
+			true => lo = mid,
+			false => hi = mid - 1
+		}
+	}
+	lo
+}
+
+/// Error code of packets which are too large for the socket.
+#[cfg(unix)]
+const EMSGSIZE: i32 = libc::EMSGSIZE;
+#[cfg(windows)]
+const EMSGSIZE: i32 = winapi::shared::winerror::WSAEMSGSIZE as i32;
+
+/// Maps the error of a socket operation to an error message.
+fn socket_error(e: &io::Error, default: &'static str) -> &'static str {
+	match e.kind() {
+		io::ErrorKind::PermissionDenied => "No permissions to send data. Please check the documentation for more information.",
+		io::ErrorKind::NetworkUnreachable | io::ErrorKind::HostUnreachable => "Network is unreachable.",
+		io::ErrorKind::AddrInUse => "Port is already in use.",
+		io::ErrorKind::ConnectionRefused => "Connection refused by the peer.",
+		io::ErrorKind::TimedOut => "Connection to the peer timed out.",
+		_ if e.raw_os_error() == Some(EMSGSIZE) => "Packet is too large.",
+		_ => default
+	}
+}
+
+/// Returns the error of a socket operation together with its message.
+pub fn io_error(e: io::Error, default: &'static str) -> StealthyError {
+	StealthyError::Io(socket_error(&e, default), e)
+}
+
+/// Opens the UDP socket on the port. The socket accepts IPv4 and IPv6 packets if IPv6 is
+/// available on the system.
+fn open_udp(port: u16) -> Result<UdpSocket, StealthyError> {
+	UdpSocket::bind((Ipv6Addr::UNSPECIFIED, port))
+		.or_else(|_| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)))
+		.map_err(|e| io_error(e, "Could not open UDP socket."))
+}
+
+/// Opens the TCP socket on the port which accepts the connections of the peers.
+fn open_tcp(port: u16) -> Result<TcpListener, StealthyError> {
+	TcpListener::bind((Ipv6Addr::UNSPECIFIED, port))
+		.or_else(|_| TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)))
+		.map_err(|e| io_error(e, "Could not open TCP socket."))
+}
+
+/// Returns our address from which packets to the ip are sent without a proxy. This is the
+/// address by which the peer knows us. No packet is sent.
+fn local_ip(ip: IpAddr) -> Result<IpAddr, StealthyError> {
+
+	let any: IpAddr = match ip {
+		IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+		IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+	};
+	let sock = UdpSocket::bind((any, 0)).map_err(|e| io_error(e, "Could not determine the local address."))?;
+	sock.connect((ip, MAGIC)).map_err(|e| io_error(e, "Could not determine the local address."))?;
+	sock.local_addr().map(|a| a.ip()).map_err(|e| io_error(e, "Could not determine the local address."))
+}
+
+/// Returns the first bytes of a TCP connection which contain the address of the sender.
+fn preamble(ip: IpAddr) -> Vec<u8> {
+	match ip {
+		IpAddr::V4(a) => [&[4][..], &a.octets()[..]].concat(),
+		IpAddr::V6(a) => [&[16][..], &a.octets()[..]].concat(),
+	}
+}
+
+/// Reads the address of the sender at the beginning of a TCP connection.
+fn read_preamble<R: Read>(r: &mut R) -> Option<IpAddr> {
+
+	let mut len = [0; 1];
+	r.read_exact(&mut len).ok()?;
+	match len[0] {
+		4 => {
+			let mut a = [0; 4];
+			r.read_exact(&mut a).ok()?;
+			Some(IpAddr::from(a))
+		},
+		16 => {
+			let mut a = [0; 16];
+			r.read_exact(&mut a).ok()?;
+			Some(unmapped(IpAddr::from(a)))
+		},
+		_ => None
+	}
+}
+
+/// Connections to the TCP ports of the peers. Each connection starts with the address of
+/// the sender as the peer cannot see it if the connection is established via a proxy. Each
+/// packet is preceded by its length.
+struct TcpLink {
+	port: u16,
+	/// Taken by the thread which accepts the connections.
+	listener: Mutex<Option<TcpListener>>,
+	proxy: Option<Proxy>,
+	conns: Mutex<HashMap<String, TcpStream>>,
+	/// Connections of the peers by their address so that they can be closed on shutdown.
+	incoming: Mutex<HashMap<SocketAddr, TcpStream>>,
+}
+
+impl TcpLink {
+
+	fn open(port: u16, proxy: Option<Proxy>) -> Result<TcpLink, StealthyError> {
+		Ok(TcpLink {
+			port,
+			listener: Mutex::new(Some(open_tcp(port)?)),
+			proxy,
+			conns: Mutex::new(HashMap::new()),
+			incoming: Mutex::new(HashMap::new()),
+		})
+	}
+
+	fn connect(&self, ip: IpAddr) -> Result<TcpStream, StealthyError> {
+
+		let dst = SocketAddr::new(ip, self.port);
+		let timeout = Duration::from_secs(TCP_CONNECT_TIMEOUT);
+		let mut s = match self.proxy {
+			Some(ref proxy) => proxy.connect(&dst, timeout)?,
+			None => TcpStream::connect_timeout(&dst, timeout).map_err(|e| io_error(e, "Could not connect to the peer."))?
+		};
+		s.write_all(&preamble(unmapped(local_ip(ip)?))).map_err(|e| io_error(e, "Could not connect to the peer."))?;
+		Ok(s)
+	}
+
+	fn send(&self, ip: &str, buf: &[u8]) -> Result<(), StealthyError> {
+
+		let addr: IpAddr = ip.parse().map_err(|_| StealthyError::Transport("Invalid IP address."))?;
+		let frame = [&(buf.len() as u32).to_be_bytes()[..], buf].concat();
+		let mut conns = self.conns.lock().unwrap();
+		// A connection which has been closed by the peer is replaced by a new one.
+		for _ in 0..2 {
+			if !conns.contains_key(ip) {
+				let s = self.connect(addr)?;
+				conns.insert(ip.to_string(), s);
+			}
+			if conns.get_mut(ip).map(|s| s.write_all(&frame).is_ok()) == Some(true) {
+				return Ok(());
+			}
+			conns.remove(ip);
+		}
+		Err(StealthyError::Transport("Could not send packet."))
+	}
+
+	/// Closes the connections of the peers so that the threads which read them return.
+	fn close_incoming(&self) {
+		for (_, s) in self.incoming.lock().unwrap().drain() {
+			let _ = s.shutdown(net::Shutdown::Both);
+		}
+	}
+
+	fn close(&self) {
+		for (_, s) in self.conns.lock().unwrap().drain() {
+			let _ = s.shutdown(net::Shutdown::Both);
+		}
+		self.close_incoming();
+	}
+}
+
+/// Reads the packets of a connection of a peer.
+fn read_packets(n: Network, link: Arc<TcpLink>, mut stream: TcpStream, addr: SocketAddr) {
+
+	if let Some(ip) = read_preamble(&mut stream) {
+		let ip = ip.to_string();
+		let mut len = [0; TCP_LENGTH_SIZE];
+		let mut buf = vec![0; RECV_BUFFER_SIZE];
+		while stream.read_exact(&mut len).is_ok() {
+			let len = u32::from_be_bytes(len) as usize;
+			if len > buf.len() || stream.read_exact(&mut buf[..len]).is_err() {
+				break;
+			}
+			n.receive_via_fallback(&ip);
+			n.recv_packet(&buf[..len], ip.clone(), None);
+		}
+	}
+	link.incoming.lock().unwrap().remove(&addr);
+}
+
+/// Returns the IPv4 address of an IPv4-mapped IPv6 address which is how a socket for IPv4
+/// and IPv6 reports the source of IPv4 packets.
+fn unmapped(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V6(a) => a.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+		_ => ip
+	}
+}
+
+/// Socket which is used to send the packets to the peers.
+#[derive(Clone)]
+enum Link {
+	/// Pings are sent with the first carrier of the options.
+	Icmp(IcmpOptions),
+	/// The socket also receives the packets of the peers which listen on the same port.
+	/// It is removed on shutdown.
+	Udp(Arc<Mutex<Option<UdpSocket>>>, u16),
+	Tcp(Arc<TcpLink>),
+	Custom(Arc<dyn PacketTransport>),
+}
+
+impl Link {
+
+	fn send(&self, ip: &str, buf: &[u8]) -> Result<(), StealthyError> {
+
+		match self {
+			Link::Icmp(options) => send_icmp(ip, buf, options),
+			Link::Udp(sock, port) => {
+				let sock = sock.lock().unwrap();
+				let sock = sock.as_ref().ok_or(StealthyError::Transport(SHUT_DOWN))?;
+				let addr = match (ip.parse().map_err(|_| StealthyError::Transport("Invalid IP address."))?, sock.local_addr()) {
+					(IpAddr::V4(a), Ok(local)) if local.is_ipv6() => IpAddr::V6(a.to_ipv6_mapped()),
+					(a, _) => a
+				};
+				sock.send_to(buf, (addr, *port))
+					.map(|_| ())
+					.map_err(|e| io_error(e, "Could not send packet."))
+			},
+			Link::Tcp(link) => link.send(ip, buf),
+			Link::Custom(t) => t.send(ip, buf)
+		}
+	}
+
+	/// Closes the sockets so that the ports can be used again.
+	fn close(&self) {
+		match self {
+			Link::Icmp(_) => { },
+			Link::Udp(sock, _) => { sock.lock().unwrap().take(); },
+			Link::Tcp(link) => link.close(),
+			Link::Custom(t) => t.close()
+		}
+	}
+}
+
+// Receiving packets.-------------------------------------------------------------------
+
+#[derive(Debug, PartialEq)]
+enum Echo {
+	/// A ping of a peer which contains a packet of stealthy.
+	Request,
+	/// The reply of a peer for our ping which contains the payload of our ping.
+	Reply,
+}
+
+/// Returns the kind and the payload of an ICMP or ICMPv6 echo request or reply of
+/// stealthy. Other ICMP packets are ignored.
+fn parse_echo(icmp: &[u8], v6: bool) -> Option<(Echo, &[u8])> {
+
+	if !has_magic(icmp) {
+		return None;
+	}
+	let echo = match (icmp[0], v6) {
+		(ICMP_ECHO_REQUEST, false) | (ICMPV6_ECHO_REQUEST, true) => Echo::Request,
+		(ICMP_ECHO_REPLY, false) | (ICMPV6_ECHO_REPLY, true) => Echo::Reply,
+		_ => return None
+	};
+	Some((echo, &icmp[ICMP_HEADER_LEN..]))
+}
+
+fn has_magic(icmp: &[u8]) -> bool {
+	icmp.len() >= ICMP_HEADER_LEN && ((icmp[4] as u16) << 8 | icmp[5] as u16) == MAGIC
+}
+
+/// Like `parse_echo` but also returns the packets of the peers carried by the other ICMP
+/// types which are enabled. The echo replies which carry a packet are returned as requests.
+/// Camouflaged pings are recognized by their payload.
+fn parse_carrier<'a>(icmp: &'a [u8], v6: bool, options: &IcmpOptions) -> Option<(Echo, &'a [u8])> {
+
+	let carriers = &options.carriers;
+	if let Some(c) = options.camouflage {
+		return parse_camouflaged(icmp, v6, c);
+	}
+	match parse_echo(icmp, v6) {
+		Some((Echo::Reply, payload)) if icmp[1] == CARRIER_CODE => match carriers.contains(&Carrier::EchoReply) {
+			true => Some((Echo::Request, payload)),
+			false => None
+		},
+		Some(echo) => Some(echo),
+		None if v6 || !has_magic(icmp) => None,
+		None => carriers.iter()
+			.filter(|c| c.offset() > 0 && c.icmp_type() == icmp[0])
+			.map(|c| ICMP_HEADER_LEN + c.offset())
+			.find(|&off| icmp.len() >= off)
+			.map(|off| (Echo::Request, &icmp[off..]))
+	}
+}
+
+/// Returns the kind and the data of a camouflaged ping. Echo requests and echo replies of
+/// any identifier are returned if their payload looks like the one of the ping utility.
+fn parse_camouflaged(icmp: &[u8], v6: bool, camouflage: Camouflage) -> Option<(Echo, &[u8])> {
+
+	if icmp.len() < ICMP_HEADER_LEN {
+		return None;
+	}
+	let echo = match (icmp[0], v6) {
+		(ICMP_ECHO_REQUEST, false) | (ICMPV6_ECHO_REQUEST, true) => Echo::Request,
+		(ICMP_ECHO_REPLY, false) | (ICMPV6_ECHO_REPLY, true) => Echo::Reply,
+		_ => return None
+	};
+	camouflage.unwrap(&icmp[ICMP_HEADER_LEN..]).map(|data| (echo, data))
+}
+
+/// Returns the reason of an ICMP or ICMPv6 error message which reports that a packet has
+/// not reached its destination. Other ICMP packets are ignored.
+fn error_reason(typ: u8, code: u8, v6: bool) -> Option<&'static str> {
+
+	match (v6, typ, code) {
+		(false, ICMP_DEST_UNREACHABLE, 0) => Some("network unreachable"),
+		(false, ICMP_DEST_UNREACHABLE, 1) => Some("host unreachable"),
+		(false, ICMP_DEST_UNREACHABLE, 9) | (false, ICMP_DEST_UNREACHABLE, 10) | (false, ICMP_DEST_UNREACHABLE, 13) |
+		(true, ICMPV6_DEST_UNREACHABLE, 1) => Some("communication administratively prohibited"),
+		(false, ICMP_DEST_UNREACHABLE, _) | (true, ICMPV6_DEST_UNREACHABLE, _) => Some("destination unreachable"),
+		(false, ICMP_TIME_EXCEEDED, _) | (true, ICMPV6_TIME_EXCEEDED, _) => Some("time exceeded"),
+		_ => None
+	}
+}
+
+/// Returns the destination and the payload of our packet together with the reason if the
+/// ICMP packet is an error message for a packet of stealthy. Error messages quote the IP
+/// header and the start of the packet which caused the error. Routers usually quote
+/// enough to contain the header of the packet of stealthy.
+fn parse_error<'a>(icmp: &'a [u8], v6: bool, options: &IcmpOptions) -> Option<(String, &'a [u8], &'static str)> {
+
+	if icmp.len() < ICMP_HEADER_LEN {
+		return None;
+	}
+	let reason = error_reason(icmp[0], icmp[1], v6)?;
+	let quote = &icmp[ICMP_HEADER_LEN..];
+	let (dst, inner) = match v6 {
+		false if quote.len() >= IPV4_HEADER_LEN && quote[9] == IPPROTO_ICMP => {
+			let hdrlen = (quote[0] & 0xf) as usize * 4;
+			(Ipv4Addr::new(quote[16], quote[17], quote[18], quote[19]).to_string(), quote.get(hdrlen..)?)
+		},
+		true if quote.len() >= IPV6_HEADER_LEN && quote[6] == IPPROTO_ICMPV6 => {
+			let mut a = [0; 16];
+			a.copy_from_slice(&quote[24..40]);
+			(Ipv6Addr::from(a).to_string(), &quote[IPV6_HEADER_LEN..])
+		},
+		_ => return None
+	};
+	match parse_carrier(inner, v6, options)? {
+		(Echo::Request, payload) => Some((dst, payload, reason)),
+		(Echo::Reply, _) => None
+	}
+}
+
+/// Returns the ICMP packet of an IPv4 packet. Raw sockets of IPv4 return the packet
+/// together with its IP header.
+pub fn strip_ipv4_header(buf: &[u8]) -> Option<&[u8]> {
+
+	if buf.len() < 20 || buf[0] >> 4 != 4 {
+		return None;
+	}
+	let hdrlen = (buf[0] & 0xf) as usize * 4;
+	let total = (buf[2] as usize) << 8 | buf[3] as usize;
+	if hdrlen < 20 || total < hdrlen || total > buf.len() {
+		return None;
+	}
+	Some(&buf[hdrlen..total])
+}
+
+/// Raw socket which sends and receives ICMP or ICMPv6 packets.
+#[cfg(unix)]
+struct RawSocket {
+	fd: libc::c_int,
+	v6: bool,
+}
+
+#[cfg(unix)]
+impl RawSocket {
+
+	fn new(v6: bool) -> Result<RawSocket, StealthyError> {
+
+		let fd = match v6 {
+			true => unsafe { libc::socket(libc::AF_INET6, libc::SOCK_RAW, libc::IPPROTO_ICMPV6) },
+			false => unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_ICMP) },
+		};
+		if fd < 0 {
+			return Err(io_error(io::Error::last_os_error(), "Could not open raw socket."));
+		}
+		Ok(RawSocket { fd, v6 })
+	}
+
+	/// Opens a raw socket which receives the packets of the given device.
+	fn open(dev: &str, v6: bool) -> Result<RawSocket, StealthyError> {
+
+		let sock = RawSocket::new(v6)?;
+		// Only packets which are received on the device are delivered to the socket.
+		if dev != "any" {
+			let r = unsafe {
+				libc::setsockopt(sock.fd, libc::SOL_SOCKET, libc::SO_BINDTODEVICE, dev.as_ptr() as *const libc::c_void, dev.len() as libc::socklen_t)
+			};
+			if r < 0 {
+				return Err(StealthyError::Io("Could not bind raw socket to the device.", io::Error::last_os_error()));
+			}
+		}
+		Ok(sock)
+	}
+
+	/// Sets the TTL (hop limit for IPv6) and the DF bit of the sent packets. Options which
+	/// are None keep the default of the OS.
+	fn set_header_options(&self, ttl: Option<u8>, dont_fragment: Option<bool>) -> Result<(), StealthyError> {
+
+		let (level, ttl_opt, df_opt, df_on, df_off) = match self.v6 {
+			true => (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO, libc::IPV6_PMTUDISC_DONT),
+			false => (libc::IPPROTO_IP, libc::IP_TTL, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO, libc::IP_PMTUDISC_DONT),
+		};
+		let options = ttl.map(|ttl| (ttl_opt, ttl as libc::c_int)).into_iter()
+			.chain(dont_fragment.map(|df| (df_opt, if df { df_on } else { df_off })));
+		for (name, value) in options {
+			let r = unsafe {
+				libc::setsockopt(self.fd, level, name, &value as *const libc::c_int as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+			};
+			if r < 0 {
+				return Err(StealthyError::Io("Could not set the TTL or the DF bit.", io::Error::last_os_error()));
+			}
+		}
+		Ok(())
+	}
+
+	/// Installs the BPF program so that the kernel drops all other packets.
+	fn attach_filter(&self, prog: &[bpf::Instruction]) -> Result<(), StealthyError> {
+
+		let filter = prog.iter()
+			.map(|i| libc::sock_filter { code: i.code, jt: i.jt, jf: i.jf, k: i.k })
+			.collect::<Vec<_>>();
+		let fprog = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_ptr() as *mut libc::sock_filter };
+		let r = unsafe {
+			libc::setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER,
+				&fprog as *const libc::sock_fprog as *const libc::c_void, mem::size_of::<libc::sock_fprog>() as libc::socklen_t)
+		};
+		match r < 0 {
+			true => Err(StealthyError::Io("Could not install the packet filter.", io::Error::last_os_error())),
+			false => Ok(())
+		}
+	}
+
+	/// Lets `recv` return an error if no packet has been received within the given number
+	/// of milliseconds.
+	fn set_recv_timeout(&self, millis: u64) -> Result<(), StealthyError> {
+
+		let t = libc::timeval { tv_sec: (millis / 1000) as libc::time_t, tv_usec: ((millis % 1000) * 1000) as libc::suseconds_t };
+		let r = unsafe {
+			libc::setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO,
+				&t as *const libc::timeval as *const libc::c_void, mem::size_of::<libc::timeval>() as libc::socklen_t)
+		};
+		match r < 0 {
+			true => Err(StealthyError::Io("Could not set the receive timeout.", io::Error::last_os_error())),
+			false => Ok(())
+		}
+	}
+
+	/// Sends the ICMP or ICMPv6 packet to the given address.
+	fn send(&self, ip: &IpAddr, packet: &[u8]) -> Result<(), StealthyError> {
+
+		let r = match ip {
+			IpAddr::V4(a) => {
+				let mut s: libc::sockaddr_in = unsafe { mem::zeroed() };
+				s.sin_family = libc::AF_INET as libc::sa_family_t;
+				s.sin_addr.s_addr = u32::from(*a).to_be();
+				unsafe {
+					libc::sendto(self.fd, packet.as_ptr() as *const libc::c_void, packet.len(), 0,
+						&s as *const libc::sockaddr_in as *const libc::sockaddr, mem::size_of::<libc::sockaddr_in>() as libc::socklen_t)
+				}
+			},
+			IpAddr::V6(a) => {
+				let mut s: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+				s.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+				s.sin6_addr.s6_addr = a.octets();
+				unsafe {
+					libc::sendto(self.fd, packet.as_ptr() as *const libc::c_void, packet.len(), 0,
+						&s as *const libc::sockaddr_in6 as *const libc::sockaddr, mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t)
+				}
+			}
+		};
+		match r < 0 {
+			true => Err(io_error(io::Error::last_os_error(), "Could not send packet.")),
+			false => Ok(())
+		}
+	}
+
+	/// Waits for the next packet and returns its ICMP part and the source address.
+	fn recv<'a>(&self, buf: &'a mut [u8]) -> Result<(&'a [u8], String), StealthyError> {
+
+		let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+		let mut addrlen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+		let n = unsafe {
+			libc::recvfrom(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0,
+				&mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr, &mut addrlen)
+		};
+		if n < 0 {
+			return Err(StealthyError::Io("Could not receive packet.", io::Error::last_os_error()));
+		}
+		let ip = match addr.ss_family as libc::c_int {
+			libc::AF_INET => {
+				let a = unsafe { *(&addr as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+				Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr)).to_string()
+			},
+			libc::AF_INET6 => {
+				let a = unsafe { *(&addr as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+				Ipv6Addr::from(a.sin6_addr.s6_addr).to_string()
+			},
+			_ => return Err(StealthyError::Transport("Received packet with an unknown address family."))
+		};
+		let buf = &buf[..n as usize];
+		match self.v6 {
+			true => Ok((buf, ip)),
+			false => strip_ipv4_header(buf).map(|icmp| (icmp, ip)).ok_or(StealthyError::Transport("Received invalid IP packet."))
+		}
+	}
+}
+
+#[cfg(unix)]
+impl Drop for RawSocket {
+
+	fn drop(&mut self) {
+		unsafe { libc::close(self.fd); }
+	}
+}
+
+// -------------------------------------------------------------------------------------
+
+struct PendingPacket {
+	p: Packet,
+	millis: i64,
+	/// Number of times the packet has been retransmitted.
+	retries: u32,
+}
+
+impl PendingPacket {
+	pub fn new(p: Packet, millis: i64) -> PendingPacket {
+		PendingPacket {
+			p,
+			millis,
+			retries: 0,
+		}
+	}
+}
+
+/// A packet which has not been acknowledged yet (see `Network::pending`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct InFlight {
+	pub id: u64,
+	pub ip: String,
+	/// Time since the packet has been created.
+	pub age: Duration,
+	/// Number of times the packet has been retransmitted.
+	pub retries: u32,
+}
+
+pub struct SharedData {
+	// Packets that have been transmitted and for which we
+	// are waiting for the acknowledge.
+	packets          : HashMap<u64, PendingPacket>,
+	// Sliding window of each peer which limits the number of pending packets.
+	windows          : HashMap<String, Window>,
+	// Round-trip time of each peer from which the retransmission timeout is computed.
+	rtts             : HashMap<String, Rtt>,
+	// Number of retransmissions after which a packet is given up.
+	max_retries      : u32,
+	// Ids of the packets which have recently been received from each peer.
+	seen             : SeenPackets,
+	// File in which the pending packets are stored.
+	outbox           : Option<Outbox>,
+	// Number of senders of each peer and priority which wait for the window of the peer.
+	waiting          : HashMap<(String, Priority), usize>,
+	// Ids of the received file upload packets of each peer which have not been acknowledged yet.
+	acks             : HashMap<String, Vec<IdType>>,
+}
+
+
+/// Sends and receives the packets of stealthy. Clones share their state so that the
+/// threads which receive packets can work with their own instance.
+#[derive(Clone)]
+pub struct Network {
+    tx_msg: Sender<IncomingMessage>,
+	shared: Arc<Mutex<SharedData>>,
+	/// Signalled whenever pending packets are removed from `shared` so that a sender which
+	/// waits for its window is released immediately.
+	released: Arc<Condvar>,
+	status: Status,
+	/// Peers whose packets are accepted. Can be changed at runtime (see `accept`).
+	accept_ip: Arc<Mutex<Vec<String>>>,
+	/// Maximum payload size of each peer determined via the PROBING ping.
+	sizes: Arc<Mutex<HashMap<String, usize>>>,
+	/// Maximum payload size of peers which have not answered the PROBING ping.
+	default_siz: usize,
+	/// Size of the last PROBING ping of each peer which has been echoed.
+	probes: Arc<Mutex<HashMap<String, usize>>>,
+	ping_id: u32,
+	/// Whether the maximum payload size of a peer is probed or only a PROBING ping is sent.
+	probing: bool,
+	keys: Arc<PeerKeys>,
+	link: Link,
+	/// Used for peers which do not acknowledge the packets sent via `link`.
+	fallback: Option<Link>,
+	/// Peers whose packets are sent via the fallback.
+	downgraded: Arc<Mutex<HashSet<String>>>,
+	icmp: IcmpOptions,
+	/// Raw sockets of the ICMP receivers whose filter is updated when the accepted peers
+	/// change.
+	#[cfg(unix)]
+	raw_sockets: Arc<Mutex<Vec<Arc<RawSocket>>>>,
+	/// Stops the threads of the network and of the layers above.
+	shutdown: Shutdown,
+	/// Counts the sent and received packets (see `Layers::stats`).
+	counters: Counters,
+}
+
+fn current_millis() -> i64 {
+	let t = time::now().to_timespec();
+	t.sec * 1000 +  (t.nsec / 1000 / 1000) as i64
+}
+
+impl Network {
+	pub fn new(transport: &Transport, tx_msg: Sender<IncomingMessage>, status: Status, accept_ip: &IpAddresses, keys: Arc<PeerKeys>) -> Result<Network, StealthyError> {
+
+		let s = Arc::new(Mutex::new(SharedData {
+			packets : HashMap::new(),
+			windows : HashMap::new(),
+			rtts    : HashMap::new(),
+			max_retries: DEFAULT_MAX_RETRIES,
+			seen    : SeenPackets::new(),
+			outbox  : None,
+			waiting : HashMap::new(),
+			acks    : HashMap::new(),
+		}));
+
+		let ping_id = rand::random::<u32>();
+
+		// UDP datagrams and ICMP packets other than echo requests are not echoed by the peer
+		// so that the maximum payload size cannot be probed. As large datagrams are fragmented
+		// by IP the size of the PROBING ping is used.
+		let (link, siz) = match transport {
+			Transport::Icmp(_, _, options) => (Link::Icmp(options.clone()), match options.carriers[0] {
+				Carrier::EchoRequest => 128,
+				_ => PROBING_SIZE
+			}),
+			Transport::Udp(port) => (Link::Udp(Arc::new(Mutex::new(Some(open_udp(*port)?))), *port), PROBING_SIZE),
+			Transport::Tcp(port, proxy) => (Link::Tcp(Arc::new(TcpLink::open(*port, proxy.clone())?)), PROBING_SIZE),
+			Transport::Custom(t) => (Link::Custom(t.0.clone()), PROBING_SIZE),
+		};
+		// Only echo requests of the host itself are echoed.
+		let probing = match transport {
+			Transport::Icmp(_, _, options) => options.carriers[0] == Carrier::EchoRequest && options.spoof.is_none(),
+			_ => false
+		};
+		let fallback = match transport {
+			Transport::Icmp(_, Some(port), _) => Some(Link::Tcp(Arc::new(TcpLink::open(*port, None)?))),
+			_ => None
+		};
+
+		if let Transport::Icmp(_, _, IcmpOptions { spoof: Some(src), .. }) = transport {
+			spoofing_socket()?;
+			Network::msg(status.clone(), format!("Packets are sent from the spoofed address {}. Peers cannot acknowledge them.", src));
+		}
+
+		let n = Network {
+			shared: s.clone(),
+			released: Arc::new(Condvar::new()),
+            tx_msg,
+			status: status.clone(),
+			accept_ip: Arc::new(Mutex::new(accept_ip.as_strings())),
+			sizes: Arc::new(Mutex::new(HashMap::new())),
+			default_siz: siz,
+			probes: Arc::new(Mutex::new(HashMap::new())),
+			ping_id,
+			probing,
+			keys: keys.clone(),
+			link,
+			fallback,
+			downgraded: Arc::new(Mutex::new(HashSet::new())),
+			icmp: match transport {
+				Transport::Icmp(_, _, options) => options.clone(),
+				_ => IcmpOptions::default()
+			},
+			#[cfg(unix)]
+			raw_sockets: Arc::new(Mutex::new(vec![])),
+			shutdown: Shutdown::new(),
+			counters: Counters::new(),
+		};
+
+		match transport {
+			Transport::Icmp(devs, _, _) => n.init_receivers(devs),
+			Transport::Udp(_) => n.init_udp_receiver(),
+			Transport::Custom(_) => n.init_custom_receiver(),
+			Transport::Tcp(_, _) => { }
+		}
+		n.init_tcp_receiver();
+		n.init_retry_event_receiver();
+		n.init_ack_timer();
+
+		for ip in accept_ip.as_strings() {
+			n.probe(ip);
+		}
+		Ok(n)
+	}
+
+	fn probe(&self, ip: String) {
+		match self.probing {
+			true => self.start_probing(ip),
+			false => self.ping(PROBING_SIZE, ip.clone(), self.ping_id, &negotiation::advertise(&self.keys.get(&ip)))
+		}
+	}
+
+	/// Accepts the packets of the ip from now on and determines the maximum payload size for
+	/// it. Returns false if the ip is already accepted.
+	pub fn accept(&self, ip: &str) -> bool {
+
+		let ip = iptools::canonical(ip);
+		{
+			let mut accept_ip = self.accept_ip.lock().unwrap();
+			if accept_ip.contains(&ip) {
+				return false;
+			}
+			accept_ip.push(ip.clone());
+			self.update_filters(&accept_ip);
+		}
+		self.probe(ip);
+		true
+	}
+
+	/// Drops the packets of the ip from now on. Returns false if the ip is not accepted.
+	pub fn remove_accepted(&self, ip: &str) -> bool {
+
+		let ip = iptools::canonical(ip);
+		let mut accept_ip = self.accept_ip.lock().unwrap();
+		match accept_ip.iter().position(|x| *x == ip) {
+			Some(i) => {
+				accept_ip.remove(i);
+				self.update_filters(&accept_ip);
+				true
+			},
+			None => false
+		}
+	}
+
+	/// Returns the peers whose packets are accepted.
+	pub fn accepted(&self) -> Vec<String> {
+		self.accept_ip.lock().unwrap().clone()
+	}
+
+	/// Replaces the filters of the raw sockets so that the packets of the peers pass.
+	#[cfg(unix)]
+	fn update_filters(&self, peers: &[String]) {
+		for sock in self.raw_sockets.lock().unwrap().iter() {
+			self.attach_filter(sock, peers);
+		}
+	}
+
+	#[cfg(not(unix))]
+	fn update_filters(&self, _peers: &[String]) { }
+
+	#[cfg(unix)]
+	fn attach_filter(&self, sock: &RawSocket, peers: &[String]) {
+
+		let peers = peers.iter().filter_map(|ip| ip.parse().ok()).collect::<Vec<IpAddr>>();
+		let types = self.icmp.carriers.iter().map(|c| c.icmp_type()).collect::<Vec<_>>();
+		let magic = match self.icmp.camouflage {
+			Some(_) => None,
+			None => Some(MAGIC)
+		};
+		if let Err(e) = sock.attach_filter(&bpf::echo_filter(sock.v6, magic, &peers, self.icmp.echo_replies, &types)) {
+			tools::log_to_file(format!("{}\n", e));
+		}
+	}
+
+	fn init_retry_event_receiver(&self) {
+		let n = self.clone();
+		self.shutdown.spawn(move || {
+			let mut saved = (0, HashSet::new());
+			while n.shutdown.sleep(Duration::from_millis(100)) {
+				if current_millis() > saved.0 + OUTBOX_INTERVAL {
+					saved = (current_millis(), n.save_outbox(saved.1));
+				}
+				let mut packets_for_resend = vec![];
+				let mut failed = vec![];
+				{
+					let mut shared = n.shared.lock().unwrap();
+					let SharedData { packets, windows, rtts, max_retries, .. } = &mut *shared;
+					for pp in packets.values_mut() {
+						let timeout = rtts.get(&pp.p.ip).unwrap_or(&Rtt::new()).backoff(pp.retries);
+						if current_millis() > pp.millis + timeout {
+							windows.entry(pp.p.ip.clone())
+								.or_insert_with(Window::new)
+								.on_loss(pp.millis, current_millis());
+							if pp.retries >= *max_retries {
+								failed.push((pp.p.id, pp.p.ip.clone(), pp.retries));
+								continue;
+							}
+							pp.retries += 1;
+							packets_for_resend.push((pp.p.clone(), pp.retries));
+							pp.millis = current_millis();
+						}
+					}
+					for (id, _, _) in &failed {
+						packets.remove(id);
+					}
+				}
+				if !failed.is_empty() {
+					n.released.notify_all();
+				}
+				for (id, ip, retries) in failed {
+					tools::log_to_file(format!("Gave up package with id: {}\n", id));
+					let reason = format!("{}: no ACK after {} retransmissions", ip, retries);
+					let _ = n.tx_msg.send(IncomingMessage::PacketFailed(id, reason));
+				}
+				for (packet, retries) in packets_for_resend {
+					// The delivery layer decides whether the fallback is used for the peer.
+					let _ = n.tx_msg.send(IncomingMessage::Timeout(packet.ip.clone(), packet.id, retries));
+					n.counters.retransmitted();
+					tools::log_to_file(format!("Resent package with id: {}\n", packet.id));
+					if let Err(e) = n.transmit(packet) {
+						tools::log_to_file(format!("Resending package failed: {}\n", e));
+					}
+				}
+			}
+			// The packets which are still pending are sent when we are started again.
+			n.save_outbox(saved.1);
+		});
+	}
+
+	/// Sends the collected ACKs of file upload packets every `ACK_DELAY` milliseconds.
+	fn init_ack_timer(&self) {
+		let n = self.clone();
+		self.shutdown.spawn(move || while n.shutdown.sleep(Duration::from_millis(ACK_DELAY)) {
+			n.flush_acks();
+		});
+	}
+
+	/// Sends the collected ACKs of file upload packets.
+	fn flush_acks(&self) {
+		let acks = mem::replace(&mut self.shared.lock().unwrap().acks, HashMap::new());
+		for (ip, ids) in acks {
+			let _ = self.transmit(Packet::ack_batch(&ids, ip));
+		}
+	}
+
+	/// Acknowledges a received file upload packet. If the peer supports it, the ACKs of
+	/// several packets are collected and sent in one packet. This halves the number of
+	/// packets of a file transfer. The ids are random so that they are listed one by one.
+	fn ack_file_upload(&self, p: Packet) {
+
+		if self.keys.features().supports(&p.ip, negotiation::FEATURE_ACK_BATCH) != Some(true) {
+			let _ = self.transmit(Packet::create_ack(p));
+			return;
+		}
+		let full = {
+			let mut shared = self.shared.lock().expect("Cannot lock.");
+			let ids = shared.acks.entry(p.ip.clone()).or_insert_with(Vec::new);
+			ids.push(p.id);
+			match ids.len() >= ACK_BATCH_SIZE {
+				true => shared.acks.remove(&p.ip),
+				false => None
+			}
+		};
+		if let Some(ids) = full {
+			let _ = self.transmit(Packet::ack_batch(&ids, p.ip));
+		}
+	}
+
+	/// Loads the packets of the outbox which have not been acknowledged before we have been
+	/// stopped. They are retransmitted immediately. From now on the pending packets are
+	/// stored in the outbox. Returns the number of loaded packets.
+	pub fn set_outbox(&self, outbox: Outbox) -> Result<usize, StealthyError> {
+
+		let packets = outbox.load()?;
+		let mut shared = self.shared.lock().unwrap();
+		for p in &packets {
+			shared.packets.insert(p.id, PendingPacket::new(p.clone(), 0));
+		}
+		shared.outbox = Some(outbox);
+		Ok(packets.len())
+	}
+
+	/// Returns the outbox if one is used.
+	pub fn outbox(&self) -> Option<Outbox> {
+		self.shared.lock().unwrap().outbox.clone()
+	}
+
+	/// Writes the pending packets into the outbox if they differ from the given ids of the
+	/// packets which have been saved before. Returns the ids of the saved packets.
+	fn save_outbox(&self, saved: HashSet<u64>) -> HashSet<u64> {
+
+		let (outbox, packets) = {
+			let shared = self.shared.lock().unwrap();
+			let ids = shared.packets.keys().cloned().collect::<HashSet<_>>();
+			match shared.outbox {
+				Some(ref o) if ids != saved => (o.clone(), shared.packets.values().map(|pp| pp.p.clone()).collect::<Vec<_>>()),
+				_ => return saved
+			}
+		};
+		match outbox.save(&packets) {
+			Ok(_) => packets.iter().map(|p| p.id).collect(),
+			Err(e) => {
+				tools::log_to_file(format!("Saving outbox failed: {}\n", e));
+				saved
+			}
+		}
+	}
+
+	/// Receives ICMP and ICMPv6 packets of the devices in background. Each device has its
+	/// own sockets. IPv6 is optional as it could be disabled on the system.
+	fn init_receivers(&self, devs: &[String]) {
+
+		if self.icmp.echo_replies && iptools::kernel_answers_pings() {
+			Network::msg(self.status.clone(), format!("The kernel answers pings as well. Set net.ipv4.icmp_echo_ignore_all to 1."));
+		}
+		for (dev, &v6) in devs.iter().flat_map(|d| [false, true].iter().map(move |v6| (d, v6))) {
+			let sock = match RawSocket::open(dev, v6) {
+				Ok(sock) => Arc::new(sock),
+				Err(_) if v6 => continue,
+				Err(e) => {
+					Network::msg(self.status.clone(), format!("{} Could not listen on device {}.", e, dev));
+					continue;
+				}
+			};
+			// Without the filter the packets are filtered by the receiver which costs more
+			// CPU time on busy links.
+			#[cfg(unix)]
+			{
+				// The accepted peers are locked until the socket has been registered so that
+				// a concurrent change also updates its filter.
+				let peers = self.accept_ip.lock().unwrap();
+				self.attach_filter(&sock, &peers);
+				self.raw_sockets.lock().unwrap().push(sock.clone());
+			}
+			// The socket also sends the echo replies.
+			if let Err(e) = sock.set_header_options(self.icmp.ttl, self.icmp.dont_fragment) {
+				tools::log_to_file(format!("{}\n", e));
+			}
+			// The device of packets received on all devices is not known.
+			let device = match dev.as_str() {
+				"any" => None,
+				_ => Some(dev.clone())
+			};
+			let n = self.clone();
+			let timeout = sock.set_recv_timeout(RECV_TIMEOUT);
+			self.spawn_receiver(timeout, move || {
+				let mut buf = vec![0; RECV_BUFFER_SIZE];
+				while !n.shutdown.is_stopped() {
+					match sock.recv(&mut buf) {
+						Ok((icmp, ip)) => {
+							if n.icmp.echo_replies && is_answered(icmp, sock.v6, &n.icmp) {
+								if let Ok(addr) = ip.parse::<IpAddr>() {
+									let _ = sock.send(&addr, &echo_reply(icmp, sock.v6));
+								}
+							}
+							if let Some((dst, payload, reason)) = parse_error(icmp, sock.v6, &n.icmp) {
+								n.reject(&dst, payload, reason);
+								continue;
+							}
+							match parse_carrier(icmp, sock.v6, &n.icmp) {
+								Some((Echo::Request, payload)) => n.recv_packet(payload, ip, device.clone()),
+								Some((Echo::Reply, payload)) => n.pong(payload, ip),
+								None => { }
+							}
+						},
+						// E.g. interrupted system calls or the timeout.
+						Err(_) => { }
+					}
+				}
+			});
+		}
+	}
+
+	/// Starts a thread which receives packets. It is joined on shutdown only if it cannot
+	/// block forever, i.e. if the timeout of its socket could be set.
+	fn spawn_receiver<F: FnOnce() + Send + 'static>(&self, timeout: Result<(), StealthyError>, f: F) {
+		match timeout {
+			Ok(_) => self.shutdown.spawn(f),
+			Err(e) => {
+				tools::log_to_file(format!("{}\n", e));
+				thread::spawn(f);
+			}
+		}
+	}
+
+	/// Receives the UDP datagrams of the peers in background.
+	fn init_udp_receiver(&self) {
+
+		// The receiver has its own handle of the socket as the socket of the link is
+		// removed on shutdown.
+		let sock = match self.link {
+			Link::Udp(ref sock, _) => sock.lock().unwrap().as_ref().and_then(|s| s.try_clone().ok()),
+			_ => return
+		};
+		let sock = match sock {
+			Some(sock) => sock,
+			None => return Network::msg(self.status.clone(), format!("Could not receive UDP datagrams."))
+		};
+		let n = self.clone();
+		let timeout = sock.set_read_timeout(Some(Duration::from_millis(RECV_TIMEOUT))).map_err(|e| StealthyError::Io("Could not set the receive timeout.", e));
+		self.spawn_receiver(timeout, move || {
+			let mut buf = vec![0; RECV_BUFFER_SIZE];
+			while !n.shutdown.is_stopped() {
+				match sock.recv_from(&mut buf) {
+					Ok((len, addr)) => n.recv_packet(&buf[..len], unmapped(addr.ip()).to_string(), None),
+					// E.g. interrupted system calls or the timeout.
+					Err(_) => { }
+				}
+			}
+		});
+	}
+
+	/// Receives the packets of a custom transport in background.
+	fn init_custom_receiver(&self) {
+
+		let t = match self.link {
+			Link::Custom(ref t) => t.clone(),
+			_ => return
+		};
+		let n = self.clone();
+		self.shutdown.spawn(move || {
+			while !n.shutdown.is_stopped() {
+				if let Some((buf, ip)) = t.recv(Duration::from_millis(RECV_TIMEOUT)) {
+					n.recv_packet(&buf, ip, None);
+				}
+			}
+		});
+	}
+
+	/// Accepts the TCP connections of the peers in background if TCP is used as transport
+	/// or as fallback.
+	fn init_tcp_receiver(&self) {
+
+		let link = match (&self.link, &self.fallback) {
+			(Link::Tcp(link), _) | (_, Some(Link::Tcp(link))) => link.clone(),
+			_ => return
+		};
+		let listener = match link.listener.lock().unwrap().take() {
+			Some(listener) => listener,
+			None => return
+		};
+		let n = self.clone();
+		// The listener is polled so that the thread notices the shutdown.
+		let timeout = listener.set_nonblocking(true).map_err(|e| StealthyError::Io("Could not set the receive timeout.", e));
+		self.spawn_receiver(timeout, move || {
+			while !n.shutdown.is_stopped() {
+				match listener.accept() {
+					Ok((stream, addr)) => {
+						// On some systems the connection inherits the mode of the listener.
+						let _ = stream.set_nonblocking(false);
+						if let Ok(s) = stream.try_clone() {
+							link.incoming.lock().unwrap().insert(addr, s);
+						}
+						// The connection may have been accepted after the connections have
+						// been closed on shutdown.
+						if n.shutdown.is_stopped() {
+							let _ = stream.shutdown(net::Shutdown::Both);
+						}
+						let (m, l) = (n.clone(), link.clone());
+						n.shutdown.spawn(move || read_packets(m, l, stream, addr));
+					},
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+						n.shutdown.sleep(Duration::from_millis(RECV_TIMEOUT));
+					},
+					Err(_) => { }
+				}
+			}
+		});
+	}
+
+	/// Sends the packets to the ip via the fallback from now on and retransmits the packets
+	/// which have not been acknowledged yet. Returns false if there is no fallback or if the
+	/// fallback is already used for the ip.
+	pub fn fail_over(&self, ip: &str) -> bool {
+
+		if self.fallback.is_none() || !self.downgraded.lock().unwrap().insert(ip.to_string()) {
+			return false;
+		}
+		let packets = self.shared.lock().unwrap().packets.values_mut()
+			.filter(|pp| pp.p.ip == ip)
+			.map(|pp| {
+				pp.millis = current_millis();
+				pp.p.clone()
+			})
+			.collect::<Vec<_>>();
+		for p in packets {
+			let _ = self.transmit(p);
+		}
+		true
+	}
+
+	/// A peer which sends its packets via the fallback probably does not receive our packets
+	/// via the transport either. Hence, the fallback is used for the peer as well.
+	fn receive_via_fallback(&self, ip: &str) {
+
+		if self.fallback.is_some() && self.downgraded.lock().unwrap().insert(ip.to_string()) {
+			Network::msg(self.status.clone(), format!("{} sends its packets via TCP. TCP is used for {} from now on.", ip, ip));
+		}
+	}
+
+	/// Stops retransmitting a packet to which an ICMP error message refers and reports the
+	/// failure to the delivery layer. `payload` is the quoted start of the packet.
+	fn reject(&self, ip: &str, payload: &[u8], reason: &str) {
+
+		let id = match Packet::deserialize(payload, ip.to_string()) {
+			Some(p) => p.id,
+			None => return
+		};
+		if self.shared.lock().unwrap().packets.remove(&id).is_some() {
+			self.released.notify_all();
+			tools::log_to_file(format!("Packet {} to {} failed: {}\n", id, ip, reason));
+			let _ = self.tx_msg.send(IncomingMessage::PacketFailed(id, format!("{}: {}", ip, reason)));
+		}
+	}
+
+	/// Sends the collected ACKs and stops the threads of the network and of the layers
+	/// which use `shutdown_signal`. The pending packets are saved in the outbox. Afterwards
+	/// the sockets are closed so that the ports can be used again.
+	pub fn shutdown(&self) {
+
+		self.flush_acks();
+		self.shutdown.signal();
+		self.released.notify_all();
+		// The threads which read the connections of the peers return when the connections
+		// are closed.
+		for link in Some(&self.link).into_iter().chain(self.fallback.as_ref()) {
+			if let Link::Tcp(l) = link {
+				l.close_incoming();
+			}
+		}
+		self.shutdown.join();
+		#[cfg(unix)]
+		self.raw_sockets.lock().unwrap().clear();
+		self.link.close();
+		if let Some(ref f) = self.fallback {
+			f.close();
+		}
+	}
+
+	/// Returns the signal which stops the threads on shutdown.
+	pub fn shutdown_signal(&self) -> Shutdown {
+		self.shutdown.clone()
+	}
+
+	/// Sets the number of retransmissions after which a packet is given up and the message
+	/// fails.
+	pub fn set_max_retries(&self, max_retries: u32) {
+		self.shared.lock().unwrap().max_retries = max_retries;
+	}
+
+	/// Stops retransmitting the packets with the given ids.
+	pub fn drop_packets(&self, ids: &HashSet<u64>) {
+		let mut shared = self.shared.lock().unwrap();
+		for id in ids {
+			shared.packets.remove(id);
+		}
+		self.released.notify_all();
+	}
+
+	/// Returns the counters of the network and the round-trip times of the peers.
+	pub fn stats(&self) -> Stats {
+		let rtts = self.shared.lock().unwrap().rtts.iter()
+			.filter_map(|(ip, rtt)| rtt.srtt().map(|d| (ip.clone(), d)))
+			.collect();
+		self.counters.snapshot(rtts)
+	}
+
+	/// Returns the counters of the network. Clones share them.
+	pub fn counters(&self) -> Counters {
+		self.counters.clone()
+	}
+
+	/// Returns the packets which have not been acknowledged yet, the oldest first.
+	pub fn pending(&self) -> Vec<InFlight> {
+		let now = time::PreciseTime::now();
+		let mut v = self.shared.lock().unwrap().packets.values()
+			.map(|pp| InFlight {
+				id: pp.p.id,
+				ip: pp.p.ip.clone(),
+				age: pp.p.created.to(now).to_std().unwrap_or_default(),
+				retries: pp.retries,
+			})
+			.collect::<Vec<_>>();
+		v.sort_by(|a, b| b.age.cmp(&a.age));
+		v
+	}
+
+	/// Returns the maximum payload size of a packet to the given peer. The path to each
+	/// peer can have another maximum.
+	pub fn current_size(&self, ip: &str) -> usize {
+		*self.sizes.lock().unwrap().get(&iptools::canonical(ip)).unwrap_or(&self.default_siz)
+	}
+
+	fn msg(status: Status, s: String) {
+		thread::spawn(move || {
+			thread::sleep(Duration::from_millis(200));
+			status.info(s);
+		});
+	}
+
+	/// Sends a ping with n bytes to determine the maximum payload size. The ping also
+	/// advertises the supported ciphers and features to the peer.
+	fn ping(&self, n: usize, ip: String, ping_id: u32, advertisement: &str) {
+		let s = Network::probing_header(ping_id, advertisement);
+		let b = s.as_bytes();
+		if n < b.len() {
+			panic!("Invalid n.");
+		}
+		let v = b.iter().cloned().chain(repeat(1 as u8).take(n - b.len())).collect();
+		if let Err(e) = self.send_data_as_ping(v, ip.clone()) {
+			Network::msg(self.status.clone(), format!("Could not send ping to {}: {}", ip, e))
+		}
+	}
+
+	/// Determines the maximum payload size for the peer in background and checks
+	/// periodically whether the peer is still reachable. The size is searched again
+	/// periodically or if a ping of the current size is lost as the path can change.
+	/// Changes of the size are used for the next messages.
+	fn start_probing(&self, ip: String) {
+
+		let n = self.clone();
+		thread::spawn(move || {
+			let mut searched = 0;
+			let mut reachable = None;
+			loop {
+				let advertisement = negotiation::advertise(&n.keys.get(&ip));
+				let min = n.default_siz.max(Network::probing_header(n.ping_id, &advertisement).len());
+				let due = current_millis() - searched >= PROBE_INTERVAL * 1000;
+				let echoed = !due && n.is_echoed(&ip, n.current_size(&ip).max(min), &advertisement);
+				let now = match echoed || (!due && reachable == Some(false)) {
+					true => echoed,
+					false => {
+						searched = current_millis();
+						let (siz, echoed) = n.search_size(&ip, min, &advertisement);
+						if echoed && n.sizes.lock().unwrap().insert(iptools::canonical(&ip), siz).unwrap_or(n.default_siz) != siz {
+							Network::msg(n.status.clone(), format!("Maximum payload size for {} is {}.", ip, siz));
+						}
+						echoed
+					}
+				};
+				match (reachable, now) {
+					(None, false) | (Some(true), false) => Network::msg(n.status.clone(), format!("{} is unreachable. It does not answer pings.", ip)),
+					(Some(false), true) => Network::msg(n.status.clone(), format!("{} is reachable again.", ip)),
+					_ => { }
+				}
+				reachable = Some(now);
+				if !n.shutdown.sleep(Duration::from_secs(MONITOR_INTERVAL)) {
+					break;
+				}
+			}
+		});
+	}
+
+	/// Returns the largest size of a PROBING ping which is echoed by the peer and whether
+	/// any ping has been echoed.
+	fn search_size(&self, ip: &str, min: usize, advertisement: &str) -> (usize, bool) {
+
+		let mut echoed = false;
+		let siz = largest_echoed(min, PROBING_SIZE, |n| {
+			let e = self.is_echoed(ip, n, advertisement);
+			echoed |= e;
+			e
+		});
+		(siz, echoed)
+	}
+
+	/// Sends a PROBING ping with n bytes and waits for its echo reply.
+	fn is_echoed(&self, ip: &str, n: usize, advertisement: &str) -> bool {
+
+		let ip = iptools::canonical(ip);
+		self.probes.lock().unwrap().remove(&ip);
+		self.ping(n, ip.clone(), self.ping_id, advertisement);
+		for _ in 0..PROBE_TIMEOUT * 10 {
+			thread::sleep(Duration::from_millis(100));
+			if self.probes.lock().unwrap().get(&ip) == Some(&n) {
+				return true;
+			}
+		}
+		false
+	}
+
+	fn probing_header(ping_id: u32, advertisement: &str) -> String {
+		format!("PROBING:{:12}/{}/", ping_id, advertisement)
+	}
+
+	fn is_probing(buf: &[u8]) -> bool {
+		buf.iter().cloned().take(8).collect::<Vec<_>>() == "PROBING:".as_bytes().to_vec()
+	}
+
+	fn probing_id(buf: &[u8]) -> u32 {
+		String::from_utf8(buf.iter()
+			.cloned()
+			.skip(8)
+			.take(12)
+			.collect::<Vec<_>>()
+		).unwrap_or(String::from("0")).trim().parse::<u32>().unwrap_or(0)
+	}
+
+	/// Handles the PROBING ping of a peer and learns the ciphers and features supported by
+	/// the peer. A ping with a non-zero id is answered with a small ping with id 0 so that
+	/// the peer learns our ciphers and features as well. Advertisements whose signature is
+	/// invalid are ignored (see `Features::accept`).
+	fn handle_probe(&self, p: Packet) {
+
+		let id = Network::probing_id(&p.data);
+		if id == self.ping_id {
+			// Our own ping.
+			return;
+		}
+		let features = self.keys.features();
+		let signed = negotiation::authenticate(&p.data, PROBING_CIPHERS_OFFSET, &self.keys.get(&p.ip));
+		if signed == Some(false) {
+			Network::msg(self.status.clone(), format!("Ignored the advertisement of {} because its signature is invalid.", p.ip));
+		}
+		if features.accept(&p.ip, signed) {
+			if let Some(c) = negotiation::parse(&p.data, PROBING_CIPHERS_OFFSET) {
+				if let Some(cipher) = self.keys.get(&p.ip).learn(&p.ip, c) {
+					Network::msg(self.status.clone(), format!("Using cipher {} for {}.", cryp::cipher_name(cipher), p.ip));
+				}
+				if features.learn(&p.ip, negotiation::parse_features(&p.data, PROBING_CIPHERS_OFFSET)) &&
+					features.supports(&p.ip, negotiation::FEATURE_FILE_METADATA) == Some(false) {
+					Network::msg(self.status.clone(), format!("{} runs an older version of stealthy. The metadata of file transfers is not fully encrypted.", p.ip));
+				}
+				// Features which the peer does not support are not used. A newer wire format,
+				// however, cannot be read.
+				if let Some(r) = negotiation::parse_revision(&p.data, PROBING_CIPHERS_OFFSET) {
+					if features.learn_revision(&p.ip, r) && r > negotiation::PROTOCOL_REVISION {
+						Network::msg(self.status.clone(), format!("{} uses a newer wire format (revision {}, ours is {}). Update stealthy if messages of {} cannot be read.", p.ip, r, negotiation::PROTOCOL_REVISION, p.ip));
+					}
+				}
+			}
+		}
+		if id != 0 {
+			let advertisement = negotiation::advertise(&self.keys.get(&p.ip));
+			let n = Network::probing_header(0, &advertisement).len();
+			self.ping(n, p.ip, 0, &advertisement);
+		}
+	}
+
+	pub fn pong(&self, buf: &[u8], ip: String) {
+
+		match Packet::deserialize(buf, ip.clone()) {
+			Some(p) => {
+				if p.data.len() < 10 {
+					return;
+				}
+				if !Network::is_probing(&p.data) {
+					return;
+				}
+				if Network::probing_id(&p.data) == self.ping_id {
+					self.probes.lock().unwrap().insert(iptools::canonical(&ip), p.data.len());
+				}
+			},
+			_ => {}
+		}
+
+	}
+
+	// This method is called with the encrypted content in buf. `device` is the device on
+	// which the packet has been received if it is known.
+	pub fn recv_packet(&self, buf: &[u8], ip: String, device: Option<String>) {
+
+		#[cfg(feature="debugout")]
+		self.status.send(String::from("[Network::recv_packet()] ============= called =============")).expect("send failed");
+
+		if buf.is_empty() {
+			// TODO: hack: ip is the reason for the invalid packet
+			/*
+			self.status_tx.send(
+				format!("[Network::recv_packet()] received invalid packet: {}", ip)).unwrap();
+			*/
+			return;
+		}
+
+		if !self.accept_ip.lock().unwrap().contains(&ip) {
+			// Ignore packet as it comes from an IP which is not accepted.
+			self.counters.dropped_unaccepted();
+			#[cfg(feature = "show_dropped")]
+			self.status.send(format!("Dropped packet from {} / {:?}", ip, self.accept_ip)).expect("Send failed.");
+
+			return;
+		}
+
+		self.counters.received(&ip, buf.len());
+
+		// TODO error handling
+		//self.status_tx.send(String::from("[Network::recv_packet()] receving packet")).unwrap();
+
+		#[cfg(feature="debugout")]
+		self.status.send(format!("[Network::recv_packet()] new message; len = {}, {:?}", buf.len(), buf)).unwrap();
+
+		let tagged = Packet::is_tagged(buf);
+		let r = Packet::deserialize(buf, ip);
+		// The payload in the packet in r is still encrypted.
+		match r {
+			// Peers which support tags send untagged packets only until they know that we
+			// support them as well, i.e. only the PROBING ping. Other untagged packets are
+			// foreign packets like the pings of other programs.
+			Some(ref p) if !tagged && !(p.is_new_message() && Network::is_probing(&p.data)) &&
+				self.keys.features().supports(&p.ip, negotiation::FEATURE_TAG) == Some(true) => { },
+			Some(p) => {
+				if p.is_new_message() && Network::is_probing(&p.data) {
+					self.handle_probe(p);
+				} else if p.is_file_upload() {
+					self.handle_file_upload(p, device);
+				} else if p.is_batch() {
+					self.handle_batch(p, device);
+				} else if p.is_rollover() {
+					self.handle_rollover(p, device);
+				} else if p.is_group() {
+					self.handle_group(p, device);
+				} else if p.is_new_message() {
+					#[cfg(feature="debugout")]
+					self.status.send(String::from("[Network::recv_packet()] new message")).unwrap();
+                    self.handle_new_message(p, device);
+                } else if p.is_ack() {
+					//self.status_tx.send(String::from("[Network::recv_packet()] ack")).expect("bindings:ack failed");
+                    self.handle_ack(p.id);
+                } else if p.is_ack_batch() {
+					for id in p.acked_ids() {
+						self.handle_ack(id);
+					}
+				} else if p.is_key_challenge() {
+					self.handle_key_check(IncomingMessage::KeyChallenge(Message::new(p.ip, p.data).with_device(device)));
+				} else if p.is_key_response() {
+					self.handle_key_check(IncomingMessage::KeyResponse(Message::new(p.ip, p.data).with_device(device)));
+				} else if p.is_handshake() {
+					self.handle_key_check(IncomingMessage::Handshake(Message::new(p.ip, p.data).with_device(device)));
+				} else if p.is_receipt() {
+					self.handle_key_check(IncomingMessage::Receipt(Message::new(p.ip, p.data).with_device(device)));
+				} else if p.is_keepalive() {
+					self.handle_key_check(IncomingMessage::Keepalive(Message::new(p.ip, p.data).with_device(device)));
+				} else if p.is_cancel() {
+					self.handle_key_check(IncomingMessage::Cancel(Message::new(p.ip, p.data).with_device(device)));
+				} else {
+					#[cfg(feature="debugout")]
+					self.status.send(String::from("[Network::recv_packet()] unknown packet type")).unwrap();
+                }
+			},
+			None => {
+				#[cfg(feature="debugout")]
+				self.status.send(String::from("[Network::recv_packet()] deserialization failed")).unwrap();
+			}
+		}
+	}
+
+    fn contains(&self, id: IdType) -> bool {
+
+		self.shared.lock()
+			.expect("Cannot lock.")
+			.packets
+			.contains_key(&id)
+    }
+
+	/// Returns true if the packet has been received before, i.e. it has been retransmitted
+	/// because our ACK has been lost. It is acknowledged again but not passed on.
+	fn is_duplicate(&self, p: &Packet) -> bool {
+
+		if self.shared.lock().expect("Cannot lock.").seen.insert(&p.ip, p.id) {
+			return false;
+		}
+		let _ = self.transmit(Packet::create_ack(p.clone()));
+		true
+	}
+
+	// Packet could be one of a lot of packets.
+	fn handle_file_upload(&self, p: Packet, device: Option<String>) {
+
+		if !self.contains(p.id) && !self.is_duplicate(&p) { // we are not the sender of the message
+			let m = Message::new(p.ip.clone(), p.data.clone()).with_device(device);
+
+			// Send message to receiver of the last argument of Delivery::new(..., rx) which
+			// is handled in Delivers::init_rx().
+			self.deliver(IncomingMessage::FileUpload(m));
+			self.ack_file_upload(p);
+			// TODO error
+		}
+	}
+
+	// This method is called when several small messages packed into one packet have
+	// been received.
+	fn handle_batch(&self, p: Packet, device: Option<String>) {
+
+		if !self.contains(p.id) && !self.is_duplicate(&p) { // we are not the sender of the message
+			let m = Message::batch(p.ip.clone(), p.data.clone()).with_device(device);
+
+			self.deliver(IncomingMessage::Batch(m));
+			let _ = self.transmit(Packet::create_ack(p));
+		}
+	}
+
+	// This method is called when a peer has announced a new signing key.
+	fn handle_rollover(&self, p: Packet, device: Option<String>) {
+
+		if !self.contains(p.id) && !self.is_duplicate(&p) { // we are not the sender of the message
+			let m = Message::rollover(p.ip.clone(), p.data.clone()).with_device(device);
+
+			self.deliver(IncomingMessage::Rollover(m));
+			let _ = self.transmit(Packet::create_ack(p));
+		}
+	}
+
+	// This method is called when a member has changed the members of the group.
+	fn handle_group(&self, p: Packet, device: Option<String>) {
+
+		if !self.contains(p.id) && !self.is_duplicate(&p) { // we are not the sender of the message
+			let m = Message::group(p.ip.clone(), p.data.clone()).with_device(device);
+
+			self.deliver(IncomingMessage::Group(m));
+			let _ = self.transmit(Packet::create_ack(p));
+		}
+	}
+
+	// This method is called when a new message has been received.
+    fn handle_new_message(&self, p: Packet, device: Option<String>) {
+
+        if !self.contains(p.id) && !self.is_duplicate(&p) { // we are not the sender of the message
+            let m = Message::new(p.ip.clone(), p.data.clone()).with_device(device);
+
+			#[cfg(feature="debugout")]
+			self.status.send(format!("NEW MESSAGE: {} {}", p.data.len(), m.sha2())).unwrap();
+
+            self.deliver(IncomingMessage::New(m));
+			#[cfg(feature="debugout")]
+			self.status.send(String::from("binding.rs::sending ack")).expect("Could not send.");
+            let _ = self.transmit(Packet::create_ack(p));
+            // TODO error
+        }
+    }
+
+	// Key check, handshake, receipt, keepalive and cancel packets are not acknowledged. They are passed to the
+	// upper layer which owns the keys.
+	fn handle_key_check(&self, m: IncomingMessage) {
+		self.deliver(m);
+	}
+
+	/// Passes a received message to the delivery layer. If the delivery layer has stopped,
+	/// e.g. while the layers are shut down, the failure is reported via the status.
+	fn deliver(&self, m: IncomingMessage) {
+		if self.tx_msg.send(m).is_err() {
+			self.status.error(String::from("A received message could not be passed to the upper layer."));
+		}
+	}
+
+    fn handle_ack(&self, id: IdType) {
+		let mut shared = self.shared.lock().expect("Lock failed.");
+		if let Some(pp) = shared.packets.remove(&id) {
+			if pp.retries == 0 {
+				shared.rtts.entry(pp.p.ip.clone())
+					.or_insert_with(Rtt::new)
+					.sample(current_millis() - pp.millis);
+			}
+			shared.windows.entry(pp.p.ip)
+				.or_insert_with(Window::new)
+				.on_ack();
+			self.released.notify_all();
+			//tools::log_to_file(format!("Got ACK with id: {}\n", p.id));
+			self.tx_msg.send(IncomingMessage::Ack(id)).expect("Send failed.");
+		}
+  	}
+
+	/// message format:
+	/// u8 : version { 1 }
+	/// u8 : type    { 16 = send message, 17 = ack }
+	/// u64: id
+	/// Vec<u8> : payload (msg) from layer above  (if type == 1)
+
+	/// Sends a message to the receiver ip.
+	///
+	/// The message is send via an ICMP echo request and the function
+	/// returns to the caller a handle which can be used by the caller
+	/// to identify the message. The message is now in the status
+	/// `transmitting`. As soon as an acknowledge is received the
+	/// configured callback function is called with the handle.
+	///
+	/// ip  = IPv4 of the receiver
+	/// buf = data to be transmitted to the receiver
+	///
+	/// If the window of the receiver is full, the call blocks until the window has room
+	/// and no sender with a higher priority waits for it.
+	pub fn send_msg(&self, msg: Message, mini_id: u64, priority: Priority) -> Result<u64, StealthyError> {
+
+		let ip  = msg.get_ip();
+		let buf = msg.get_payload();
+
+		if buf.len() > MAX_MESSAGE_SIZE {
+			return Err(StealthyError::Protocol("Message is too big."));
+		}
+
+		let p = match msg.typ {
+			MessageType::FileUpload => Packet::file_upload(buf, ip, mini_id),
+			MessageType::Batch => Packet::batch(buf, ip, mini_id),
+			MessageType::Rollover => Packet::rollover(buf, ip, mini_id),
+			MessageType::Group => Packet::group(buf, ip, mini_id),
+			_ => Packet::new(buf, ip, mini_id)
+		};
+
+		self.wait_for_queue(&p.ip, priority);
+
+		// Push message before sending it. Otherwise there could be a race condition that the ACK
+		// is received before message is sent. Spoofed packets are not acknowledged as the peers
+		// answer the spoofed address.
+		if self.icmp.spoof.is_none() {
+			Network::add_packet(self.shared.clone(), p.clone());
+		}
+
+		let id = p.id;
+		match self.transmit(p) {
+			//tools::log_to_file(format!("Sent package with id: {}\n", id));
+			Ok(_) => Ok(id),
+			Err(e) => {
+				Network::remove_packet(self.shared.clone(), id);
+				self.released.notify_all();
+				Err(e)
+			}
+		}
+	}
+
+	fn remove_packet(shared: Arc<Mutex<SharedData>>, id: u64) {
+		shared.lock()
+			.expect("binding::push_packet: lock failed")
+			.packets
+			.remove(&id);
+	}
+
+	fn add_packet(shared: Arc<Mutex<SharedData>>, p: Packet) {
+		shared.lock()
+			.expect("binding::push_packet: lock failed")
+			.packets
+			.insert(p.id, PendingPacket::new(p, current_millis()));
+	}
+
+	/// Returns true if the window of the peer is full.
+	fn queue_full(shared: &SharedData, ip: &str) -> bool {
+		let pending = shared.packets.values().filter(|pp| pp.p.ip == ip).count();
+		let window = shared.windows.get(ip).map(|w| w.size()).unwrap_or_else(|| Window::new().size());
+		pending >= window
+	}
+
+	/// Returns true if a sender with a higher priority than the given one waits for the
+	/// window of the ip.
+	fn preempted(shared: &SharedData, ip: &str, priority: Priority) -> bool {
+		shared.waiting.iter().any(|((i, p), n)| i == ip && *p > priority && *n > 0)
+	}
+
+	/// Returns true if a sender waits for the window of the ip.
+	fn has_waiting(shared: &SharedData, ip: &str) -> bool {
+		shared.waiting.keys().any(|(i, _)| i == ip)
+	}
+
+	/// Returns true if a packet to the ip is sent without waiting for the window of the peer
+	/// (see `wait_for_queue`).
+	pub fn has_room(&self, ip: &str) -> bool {
+		let shared = self.shared.lock().unwrap();
+		!Network::queue_full(&shared, ip) && !Network::has_waiting(&shared, ip)
+	}
+
+	/// Blocks until `has_room` returns true for the ip. Returns false if the network has
+	/// been shut down before.
+	pub fn wait_for_room(&self, ip: &str) -> bool {
+		let mut shared = self.shared.lock().unwrap();
+		while Network::queue_full(&shared, ip) || Network::has_waiting(&shared, ip) {
+			if self.shutdown.is_stopped() {
+				return false;
+			}
+			shared = self.released.wait_timeout(shared, Duration::from_millis(RECV_TIMEOUT)).unwrap().0;
+		}
+		true
+	}
+
+	fn wait_for_queue(&self, ip: &str, priority: Priority) {
+		// IMPORTANT!
+		// It seems that sending too many ICMP packets in a short time results in ICMP echo request
+		// drops. Hence, the number of pending ACKs of each peer is limited by its sliding window
+		// which grows with each ACK and shrinks if packets are lost. The sender is woken up as
+		// soon as a pending packet has been removed. Senders with a higher priority go first.
+		let mut shared = self.shared.lock().expect("binding::wait_for_queue: lock failed");
+		let key = (ip.to_string(), priority);
+		*shared.waiting.entry(key.clone()).or_insert(0) += 1;
+		while (Network::queue_full(&shared, ip) || Network::preempted(&shared, ip, priority)) && !self.shutdown.is_stopped() {
+			// Pending packets are not removed anymore after the shutdown.
+			shared = self.released.wait_timeout(shared, Duration::from_millis(RECV_TIMEOUT)).expect("binding::wait_for_queue: wait failed").0;
+		}
+		let n = shared.waiting.get_mut(&key).map(|n| { *n -= 1; *n }).unwrap_or(0);
+		if n == 0 {
+			shared.waiting.remove(&key);
+		}
+		// Senders with a lower priority may have been waiting only for this sender.
+		self.released.notify_all();
+	}
+
+	fn transmit(&self, packet: Packet) -> Result<(), StealthyError> {
+		//tools::log_to_file(format!("transmit: sent package with id: {}\n", packet.id));
+		if self.shutdown.is_stopped() {
+			return Err(StealthyError::Transport(SHUT_DOWN));
+		}
+		let features = self.keys.features();
+		let buf = match features.supports(&packet.ip, negotiation::FEATURE_TAG) {
+			Some(true) if features.supports(&packet.ip, negotiation::FEATURE_CHECKSUM) == Some(true) => packet.serialize_checked(),
+			Some(true) => packet.serialize_tagged(),
+			_ => packet.serialize()
+		};
+		let r = match self.fallback {
+			Some(ref f) if self.downgraded.lock().unwrap().contains(&packet.ip) => f.send(&packet.ip, &buf),
+			_ => self.link.send(&packet.ip, &buf)
+		};
+		if r.is_ok() {
+			self.counters.sent(&packet.ip, buf.len());
+		}
+		r
+	}
+
+	/// Sends a key check challenge to the given ip.
+	pub fn send_key_challenge(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::key_challenge(buf, ip, rand::random::<u64>()))
+	}
+
+	/// Sends the response for a key check challenge to the given ip.
+	pub fn send_key_response(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::key_response(buf, ip, rand::random::<u64>()))
+	}
+
+	/// Sends a message of the session handshake to the given ip.
+	pub fn send_handshake(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::handshake(buf, ip, rand::random::<u64>()))
+	}
+
+	/// Sends an encrypted receipt for a received message to the given ip.
+	pub fn send_receipt(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::receipt(buf, ip, rand::random::<u64>()))
+	}
+
+	/// Sends an encrypted keepalive which announces our presence to the given ip.
+	pub fn send_keepalive(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::keepalive(buf, ip, rand::random::<u64>()))
+	}
+
+	/// Sends an encrypted cancellation of a message or file transfer to the given ip.
+	pub fn send_cancel(&self, buf: Vec<u8>, ip: String) -> Result<(), StealthyError> {
+		self.transmit(Packet::cancel(buf, ip, rand::random::<u64>()))
+	}
+
+	pub fn send_data_as_ping(&self, buf: Vec<u8>, ip: String) -> Result<u64, StealthyError> {
+
+		let id = rand::random::<u64>();
+		self.transmit(Packet::new(buf, ip, id)).map(|_| id)
+	}
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+	use std::net::{IpAddr, Ipv6Addr};
+	use super::{parse_echo, parse_carrier, carrier_packet, strip_ipv4_header, checksum, echo_request, echo_reply, camouflaged_request, ipv4_datagram, largest_echoed, parse_error, is_answered, preamble, read_preamble, Echo};
+	use crate::transport::{Carrier, IcmpOptions};
+	use crate::camouflage::Camouflage;
+
+	fn carriers(carriers: &[Carrier]) -> IcmpOptions {
+		IcmpOptions { carriers: carriers.to_vec(), ..IcmpOptions::default() }
+	}
+
+	#[test]
+	fn test_parse_echo() {
+
+		let request = [8, 0, 0, 0, 0xa3, 0x87, 0, 1, b'h', b'i'];
+		assert_eq!(parse_echo(&request, false), Some((Echo::Request, &b"hi"[..])));
+		let reply = [129, 0, 0, 0, 0xa3, 0x87, 0, 1];
+		assert_eq!(parse_echo(&reply, true), Some((Echo::Reply, &b""[..])));
+		// Other ids, types and truncated packets are ignored.
+		assert_eq!(parse_echo(&[8, 0, 0, 0, 0xa3, 0x88, 0, 1], false), None);
+		assert_eq!(parse_echo(&[128, 0, 0, 0, 0xa3, 0x87, 0, 1], false), None);
+		assert_eq!(parse_echo(&[3, 0, 0, 0, 0xa3, 0x87, 0, 1], false), None);
+		assert_eq!(parse_echo(&request[..7], false), None);
+
+		// The IP header and trailing bytes are removed.
+		let mut ip = vec![0x45, 0, 0, 30, 0, 0, 0, 0, 64, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+		ip.extend_from_slice(&request);
+		ip.push(0);
+		assert_eq!(strip_ipv4_header(&ip), Some(&request[..]));
+		assert_eq!(strip_ipv4_header(&ip[..25]), None);
+		assert_eq!(strip_ipv4_header(&[0x65; 40]), None);
+	}
+
+	#[test]
+	fn test_echo_request() {
+
+		// Example of RFC 1071.
+		assert_eq!(checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]), 0x220d);
+		assert_eq!(checksum(&[0x00, 0x01, 0xf2]), !0xf201);
+
+		let v = echo_request(b"hello", false, 0x1234);
+		assert_eq!(&v[..8], &[8, 1, v[2], v[3], 0xa3, 0x87, 0x12, 0x34]);
+		assert_eq!(checksum(&v), 0);
+		assert_eq!(parse_echo(&v, false), Some((Echo::Request, &b"hello"[..])));
+
+		// The kernel computes the checksum of ICMPv6 packets.
+		let v = echo_request(b"hello", true, 1);
+		assert_eq!(&v[..8], &[128, 0, 0, 0, 0xa3, 0x87, 0, 1]);
+		assert_eq!(parse_echo(&v, true), Some((Echo::Request, &b"hello"[..])));
+	}
+
+	#[test]
+	fn test_carrier() {
+
+		let all = [Carrier::EchoRequest, Carrier::EchoReply, Carrier::Timestamp, Carrier::AddressMask];
+		for &c in all.iter() {
+			let v = carrier_packet(b"hello", false, 7, c);
+			assert_eq!(v[0], c.icmp_type());
+			assert_eq!(checksum(&v), 0);
+			assert_eq!(parse_carrier(&v, false, &carriers(&all)), Some((Echo::Request, &b"hello"[..])));
+		}
+		assert_eq!(carrier_packet(b"hello", false, 7, Carrier::Timestamp).len(), 8 + 12 + 5);
+
+		// Only the enabled carriers are accepted.
+		let v = carrier_packet(b"hello", false, 7, Carrier::Timestamp);
+		assert_eq!(parse_carrier(&v, false, &carriers(&[Carrier::EchoRequest])), None);
+		let v = carrier_packet(b"hello", true, 7, Carrier::EchoReply);
+		assert_eq!(&v[..2], &[129, 1]);
+		assert_eq!(parse_carrier(&v, true, &carriers(&[Carrier::EchoRequest])), None);
+		assert_eq!(parse_carrier(&v, true, &carriers(&all)), Some((Echo::Request, &b"hello"[..])));
+
+		// The replies of the kernel for our pings.
+		let v = echo_reply(&echo_request(b"hello", false, 7), false);
+		assert_eq!(parse_carrier(&v, false, &carriers(&all)), Some((Echo::Reply, &b"hello"[..])));
+
+		// ICMPv6 has no timestamp requests.
+		assert_eq!(carrier_packet(b"x", true, 1, Carrier::Timestamp), echo_request(b"x", true, 1));
+	}
+
+	#[test]
+	fn test_parse_error() {
+
+		let options = IcmpOptions::default();
+		let packet = [1, 16, 8, 7, 6, 5, 4, 3, 2, 1, b'h', b'i'];
+		let datagram = ipv4_datagram("10.0.0.9".parse().unwrap(), "10.0.0.1".parse().unwrap(), 1, false, &echo_request(&packet, false, 1));
+		let error = |typ: u8, code: u8, quote: &[u8]| [&[typ, code, 0, 0, 0, 0, 0, 0][..], quote].concat();
+
+		assert_eq!(parse_error(&error(3, 1, &datagram), false, &options), Some(("10.0.0.1".to_string(), &packet[..], "host unreachable")));
+		assert_eq!(parse_error(&error(11, 0, &datagram), false, &options).map(|e| e.2), Some("time exceeded"));
+		// Only the first 8 bytes of the ping are quoted.
+		assert_eq!(parse_error(&error(3, 13, &datagram[..28]), false, &options).map(|e| e.1.len()), Some(0));
+		// Errors for pings of other programs, echo requests and ICMPv6 errors in IPv4.
+		let mut other = datagram.clone();
+		other[24] = 0x12;
+		assert_eq!(parse_error(&error(3, 1, &other), false, &options), None);
+		assert_eq!(parse_error(&error(8, 0, &datagram), false, &options), None);
+		assert_eq!(parse_error(&error(1, 0, &datagram), false, &options), None);
+
+		let mut v6 = vec![0x60, 0, 0, 0, 0, 20, 58, 64];
+		v6.extend_from_slice(&[0; 16]);
+		v6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+		v6.extend_from_slice(&echo_request(&packet, true, 1));
+		assert_eq!(parse_error(&error(1, 3, &v6), true, &options), Some(("2001:db8::1".to_string(), &packet[..], "destination unreachable")));
+		assert_eq!(parse_error(&error(3, 0, &v6), true, &options).map(|e| e.2), Some("time exceeded"));
+	}
+
+	#[test]
+	fn test_largest_echoed() {
+
+		let mut probes = vec![];
+		assert_eq!(largest_echoed(128, 8192, |n| { probes.push(n); true }), 8192);
+		assert_eq!(probes, vec![8192]);
+
+		// Pings larger than the MTU are lost.
+		let mut probes = 0;
+		let n = largest_echoed(128, 8192, |n| { probes += 1; n <= 1472 });
+		assert!(n <= 1472 && n > 1472 - 64);
+		assert!(probes <= 8);
+		assert_eq!(largest_echoed(128, 8192, |_| false), 128);
+	}
+
+	#[test]
+	fn test_ipv4_datagram() {
+
+		let v = ipv4_datagram("10.0.0.9".parse().unwrap(), "10.0.0.1".parse().unwrap(), 42, true, &[8, 0, 0, 0]);
+		assert_eq!(v.len(), 24);
+		assert_eq!(&v[..4], &[0x45, 0, 0, 24]);
+		assert_eq!(&v[6..10], &[0x40, 0, 42, 1]);
+		assert_eq!(&v[12..], &[10, 0, 0, 9, 10, 0, 0, 1, 8, 0, 0, 0]);
+		assert_eq!(checksum(&v[..20]), 0);
+		assert_eq!(strip_ipv4_header(&v), Some(&[8, 0, 0, 0][..]));
+		assert_eq!(ipv4_datagram("10.0.0.9".parse().unwrap(), "10.0.0.1".parse().unwrap(), 1, false, &[])[6], 0);
+	}
+
+	#[test]
+	fn test_camouflage() {
+
+		let options = IcmpOptions { camouflage: Some(Camouflage::Windows), echo_replies: true, ..IcmpOptions::default() };
+		let v = camouflaged_request(b"hello", false, Camouflage::Windows, 3);
+		assert_eq!(&v[..8], &[8, 0, v[2], v[3], 0, 1, 0, 3]);
+		assert_eq!(checksum(&v), 0);
+		assert_eq!(&v[8..40], b"abcdefghijklmnopqrstuvwabcdefghi");
+		assert_eq!(parse_carrier(&v, false, &options), Some((Echo::Request, &b"hello"[..])));
+		assert_eq!(parse_carrier(&v, false, &IcmpOptions::default()), None);
+		assert_eq!(parse_carrier(&echo_reply(&v, false), false, &options), Some((Echo::Reply, &b"hello"[..])));
+
+		// The pings of the ping utility are answered but not the ones of stealthy.
+		assert!(is_answered(&camouflaged_request(b"", false, Camouflage::Windows, 1), false, &options));
+		assert!(!is_answered(&camouflaged_request(&[1, 16, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2], false, Camouflage::Windows, 1), false, &options));
+	}
+
+	#[test]
+	fn test_echo_reply() {
+
+		// Ping of another program.
+		let mut req = vec![8, 0, 0, 0, 0x12, 0x34, 0, 7, 1, 2, 3];
+		let sum = checksum(&req);
+		req[2] = (sum >> 8) as u8;
+		req[3] = sum as u8;
+		assert!(is_answered(&req, false, &IcmpOptions::default()));
+		let v = echo_reply(&req, false);
+		assert_eq!(&v[..8], &[0, 0, v[2], v[3], 0x12, 0x34, 0, 7]);
+		assert_eq!(&v[8..], &[1, 2, 3]);
+		assert_eq!(checksum(&v), 0);
+		assert!(!is_answered(&v, false, &IcmpOptions::default()));
+		assert!(is_answered(&[128, 0, 0, 0, 0x12, 0x34, 0, 7], true, &IcmpOptions::default()));
+
+		// The packets of stealthy are not echoed except for the PROBING ping.
+		let probe = [&[1, 16, 0, 0, 0, 0, 0, 0, 0, 0][..], b"PROBING:        1234/"].concat();
+		assert!(is_answered(&echo_request(&probe, false, 1), false, &IcmpOptions::default()));
+		assert!(!is_answered(&echo_request(&[1, 16, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2], false, 1), false, &IcmpOptions::default()));
+		assert_eq!(&echo_reply(&echo_request(b"x", true, 1), true)[..], &[129, 0, 0, 0, 0xa3, 0x87, 0, 1, b'x']);
+	}
+
+	#[test]
+	fn test_preamble() {
+
+		for ip in &["1.2.3.4", "2001:db8::1"] {
+			let ip: IpAddr = ip.parse().unwrap();
+			assert_eq!(read_preamble(&mut Cursor::new(preamble(ip))), Some(ip));
+		}
+		// IPv4-mapped addresses are reported as IPv4 addresses.
+		let mapped: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+		assert_eq!(read_preamble(&mut Cursor::new(preamble(mapped))), Some("1.2.3.4".parse().unwrap()));
+		assert_eq!(read_preamble(&mut Cursor::new(vec![5, 1, 2, 3, 4, 5])), None);
+		assert_eq!(read_preamble(&mut Cursor::new(vec![4, 1, 2])), None);
+	}
+}
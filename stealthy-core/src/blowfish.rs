@@ -2,6 +2,9 @@ use rand::rngs::OsRng;
 use rand::Rng;
 use std::iter;
 
+use crate::secret::{SecretBytes, zeroize};
+use crate::error::StealthyError;
+
 #[repr(C)]
 struct BF_KEY {
     p: [libc::c_uint; 18],
@@ -32,36 +35,36 @@ const BF_DECRYPT: libc::c_long = 0;
 
 
 pub struct Blowfish {
-    key: Vec<u8>
+    key: SecretBytes
 }
 
 pub const KEY_LEN: usize = 16;
 pub const IV_LEN: usize = 8;
 pub const BLOCKSIZE: usize = 8;
 
-pub type ResultVec = Result<Vec<u8>, &'static str>;
+pub type ResultVec = Result<Vec<u8>, StealthyError>;
 
 impl Blowfish {
 
     /// Returns a new instance of Blowfish with a random key.
-    pub fn new() -> Result<Blowfish, &'static str> { 
+    pub fn new() -> Result<Blowfish, StealthyError> { 
         Blowfish::from_key(Blowfish::new_key()?)
     }
 
     /// Returns a new instance of Blowfish with the given key.
-    pub fn from_key(key: Vec<u8>) -> Result<Blowfish, &'static str> {
+    pub fn from_key(key: Vec<u8>) -> Result<Blowfish, StealthyError> {
         match key.len() {
             KEY_LEN => 
                 Ok(Blowfish {
-                    key: key
+                    key: SecretBytes::new(key)
                 }),
-            _ => Err("Invalid key length.")
+            _ => Err(StealthyError::Crypto("Invalid key length."))
         }
     }
 
     /// Returns the current key used by this instance.
-    pub fn key(&self) -> Vec<u8> {
-        self.key.clone()
+    pub fn key(&self) -> &[u8] {
+        self.key.as_slice()
     }
 
     /// Returns cryptographically secure pseudorandom numbers for
@@ -72,7 +75,7 @@ impl Blowfish {
                 Ok((0..n).map(|_| { r.gen() }).collect())
                 //Ok(r.gen_iter::<u8>().take(n).collect())
             },
-            _ => Err("Could not get OsRng.")
+            _ => Err(StealthyError::Crypto("Could not get OsRng."))
         }
     }
 
@@ -97,17 +100,17 @@ impl Blowfish {
     fn remove_padding(data: &[u8]) -> ResultVec {
     
         if data.len() < BLOCKSIZE {
-            return Err("Length of ciphertext is smaller than the block size.");
+            return Err(StealthyError::Crypto("Length of ciphertext is smaller than the block size."));
         }
 
         let padval = *data.last().unwrap();
         if padval as usize > BLOCKSIZE {
-            return Err("Invalid padding value.");
+            return Err(StealthyError::Crypto("Invalid padding value."));
         }
 
         let valid = data.iter().rev().take(padval as usize).all(|x| *x == padval);
         match valid {
-            false => Err("Invalid padding."),
+            false => Err(StealthyError::Crypto("Invalid padding.")),
             true  => Ok(data.iter().take(data.len() - padval as usize).cloned().collect()),
         }
     }
@@ -138,6 +141,8 @@ impl Blowfish {
                 i.as_ptr() as *mut u8,
                 mode
             );
+            // The key schedule is derived from the key.
+            zeroize(std::slice::from_raw_parts_mut(&mut *schedule as *mut BF_KEY as *mut u8, std::mem::size_of::<BF_KEY>()));
         }
         result.iter().cloned().collect()
     }
@@ -150,7 +155,7 @@ impl Blowfish {
         Ok(
             iv.iter().cloned()
                 .chain(
-                    self.crypt(&Blowfish::padding(data), &iv, &self.key, BF_ENCRYPT)
+                    self.crypt(&Blowfish::padding(data), &iv, self.key(), BF_ENCRYPT)
                         .iter().cloned())
                 .collect()
         )
@@ -160,12 +165,12 @@ impl Blowfish {
     pub fn decrypt(&self, ciphertext: &[u8]) -> ResultVec {
 
         if IV_LEN > ciphertext.len() {
-            return Err("Ciphertext has invalid length.");
+            return Err(StealthyError::Crypto("Ciphertext has invalid length."));
         }
 
         let (iv, cipher) = ciphertext.split_at(IV_LEN);
         Blowfish::remove_padding(
-            &self.crypt(cipher, iv, &self.key, BF_DECRYPT)
+            &self.crypt(cipher, iv, self.key(), BF_DECRYPT)
         )
     }
 }
@@ -186,7 +191,7 @@ mod tests {
         s
     }
 
-    use ::cryp::{from_hex};
+    use crate::cryp::from_hex;
     use super::Blowfish;
 
     #[test]
@@ -282,7 +287,7 @@ mod tests {
         let k = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
         let c = super::Blowfish::from_key(k.clone());
         assert!(c.is_ok());
-        assert_eq!(c.unwrap().key, k);
+        assert_eq!(c.unwrap().key(), &k[..]);
     }
 
      #[test]
@@ -0,0 +1,237 @@
+use std::net::IpAddr;
+
+// Opcodes of classic BPF.
+const LD_W_ABS: u16  = 0x20;
+const LD_H_ABS: u16  = 0x28;
+const LD_B_ABS: u16  = 0x30;
+const LD_H_IND: u16  = 0x48;
+const LD_B_IND: u16  = 0x50;
+const LDX_B_MSH: u16 = 0xb1;
+const JEQ_K: u16     = 0x15;
+const RET_K: u16     = 0x06;
+
+/// Offset which refers to the IP header independent of where the socket sees the packet.
+const NET_OFF: u32 = -0x100000i32 as u32;
+/// Number of bytes of an accepted packet which are passed to the socket.
+const SNAPLEN: u32 = 0x40000;
+
+const IPPROTO_ICMPV6: u32 = 58;
+const IPV6_HEADER_LEN: u32 = 40;
+
+/// Destination unreachable and time exceeded of ICMP and ICMPv6.
+const ICMP_ERRORS: [u32; 2] = [3, 11];
+const ICMPV6_ERRORS: [u32; 2] = [1, 3];
+
+/// Instruction of a classic BPF program as used by SO_ATTACH_FILTER.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Instruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+fn stmt(code: u16, k: u32) -> Instruction {
+    Instruction { code, jt: 0, jf: 0, k }
+}
+
+fn jeq(k: u32, jt: u8, jf: u8) -> Instruction {
+    Instruction { code: JEQ_K, jt, jf, k }
+}
+
+/// Continues with the next check if the accumulator is one of the values, otherwise the
+/// packet is dropped.
+fn expect(values: &[u32]) -> Vec<Instruction> {
+    let n = values.len() as u8;
+    let mut v = values.iter().enumerate()
+        .map(|(i, &k)| jeq(k, n - i as u8, 0))
+        .collect::<Vec<_>>();
+    v.push(stmt(RET_K, 0));
+    v
+}
+
+/// Lets the packet pass if the accumulator has the value.
+fn accept_if(k: u32) -> Vec<Instruction> {
+    vec![jeq(k, 0, 1), stmt(RET_K, SNAPLEN)]
+}
+
+/// Returns a filter which lets only the echo requests and echo replies of stealthy pass
+/// whose source is one of the peers. Peers of the other IP version are ignored. Packets
+/// are filtered in the kernel so that the traffic of busy links does not reach the
+/// receivers. `magic` is the identifier in the ICMP header or None if the identifier is
+/// not checked, e.g. for camouflaged pings. `types` are further ICMP types which carry
+/// packets of stealthy; they are only used for IPv4. ICMPv6 packets with extension
+/// headers are dropped. If `requests` is true, all echo requests pass so that they can
+/// be answered. Error messages pass from any source as they are sent by routers.
+pub fn echo_filter(v6: bool, magic: Option<u16>, peers: &[IpAddr], requests: bool, types: &[u8]) -> Vec<Instruction> {
+
+    let mut v = vec![];
+    match v6 {
+        true => {
+            v.push(stmt(LD_B_ABS, NET_OFF + 6));
+            v.extend(expect(&[IPPROTO_ICMPV6]));
+            v.push(stmt(LD_B_ABS, NET_OFF + IPV6_HEADER_LEN));
+            if requests {
+                v.extend(accept_if(128));
+            }
+            for &t in ICMPV6_ERRORS.iter() {
+                v.extend(accept_if(t));
+            }
+            if let Some(magic) = magic {
+                v.push(stmt(LD_H_ABS, NET_OFF + IPV6_HEADER_LEN + 4));
+                v.extend(expect(&[magic as u32]));
+            }
+            v.push(stmt(LD_B_ABS, NET_OFF + IPV6_HEADER_LEN));
+            v.extend(expect(&[128, 129]));
+        },
+        false => {
+            // The length of the IPv4 header is loaded into X.
+            v.push(stmt(LDX_B_MSH, NET_OFF));
+            v.push(stmt(LD_B_IND, NET_OFF));
+            if requests {
+                v.extend(accept_if(8));
+            }
+            for &t in ICMP_ERRORS.iter() {
+                v.extend(accept_if(t));
+            }
+            if let Some(magic) = magic {
+                v.push(stmt(LD_H_IND, NET_OFF + 4));
+                v.extend(expect(&[magic as u32]));
+            }
+            let mut accepted = vec![0, 8];
+            accepted.extend(types.iter().map(|&t| t as u32).filter(|t| *t != 0 && *t != 8));
+            v.push(stmt(LD_B_IND, NET_OFF));
+            v.extend(expect(&accepted));
+        }
+    }
+    for ip in peers {
+        // Each word of the address is compared. If a word differs the next peer is checked.
+        let words = match (ip, v6) {
+            (IpAddr::V4(a), false) => vec![(12, u32::from(*a))],
+            (IpAddr::V6(a), true) => a.octets().chunks(4)
+                .enumerate()
+                .map(|(i, w)| (8 + 4 * i as u32, (w[0] as u32) << 24 | (w[1] as u32) << 16 | (w[2] as u32) << 8 | w[3] as u32))
+                .collect(),
+            _ => continue
+        };
+        let n = words.len();
+        for (i, (off, w)) in words.into_iter().enumerate() {
+            v.push(stmt(LD_W_ABS, NET_OFF + off));
+            v.push(jeq(w, 0, (2 * (n - i) - 1) as u8));
+        }
+        v.push(stmt(RET_K, SNAPLEN));
+    }
+    v.push(stmt(RET_K, 0));
+    v
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Runs the subset of classic BPF which is used by the filters on an IP packet.
+    fn run(prog: &[Instruction], pkt: &[u8]) -> u32 {
+        let load = |off: u32, n: usize| -> Option<u32> {
+            let off = off.wrapping_sub(NET_OFF) as usize;
+            pkt.get(off..off + n).map(|b| b.iter().fold(0, |a, &x| a << 8 | x as u32))
+        };
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0);
+        loop {
+            let i = prog[pc];
+            pc += 1;
+            let r = match i.code {
+                LD_W_ABS => load(i.k, 4),
+                LD_H_ABS => load(i.k, 2),
+                LD_B_ABS => load(i.k, 1),
+                LD_H_IND => load(i.k.wrapping_add(x), 2),
+                LD_B_IND => load(i.k.wrapping_add(x), 1),
+                LDX_B_MSH => { x = (load(i.k, 1).unwrap_or(0) & 0xf) * 4; Some(a) },
+                JEQ_K => { pc += (if a == i.k { i.jt } else { i.jf }) as usize; Some(a) },
+                RET_K => return i.k,
+                _ => panic!("unknown opcode")
+            };
+            match r {
+                Some(r) => a = r,
+                None => return 0
+            }
+        }
+    }
+
+    fn ipv4(src: [u8; 4], icmp_type: u8, id: u16) -> Vec<u8> {
+        let mut p = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, 1, 0, 0];
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&[127, 0, 0, 1, icmp_type, 0, 0, 0, (id >> 8) as u8, id as u8, 0, 1]);
+        p
+    }
+
+    fn ipv6(src: &str, next: u8, icmp_type: u8, id: u16) -> Vec<u8> {
+        let mut p = vec![0x60, 0, 0, 0, 0, 8, next, 64];
+        p.extend_from_slice(&src.parse::<std::net::Ipv6Addr>().unwrap().octets());
+        p.extend_from_slice(&[0; 16]);
+        p.extend_from_slice(&[icmp_type, 0, 0, 0, (id >> 8) as u8, id as u8, 0, 1]);
+        p
+    }
+
+    #[test]
+    fn test_echo_filter() {
+
+        let peers = vec!["10.0.0.1".parse().unwrap(), "2001:db8::1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let f = echo_filter(false, Some(0xa387), &peers, false, &[]);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 8, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 2], 0, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 3], 8, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 5, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 8, 0x1234)), 0);
+        // Options in the IP header.
+        let mut p = ipv4([10, 0, 0, 1], 8, 0xa387);
+        p[0] = 0x46;
+        p.splice(20..20, vec![1, 1, 1, 1]);
+        assert_eq!(run(&f, &p), SNAPLEN);
+
+        let f = echo_filter(true, Some(0xa387), &peers, false, &[]);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 58, 128, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 58, 129, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv6("2001:db8::2", 58, 128, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 58, 135, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 0, 128, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 58, 128, 0x1234)), 0);
+
+        // Error messages of routers.
+        let f = echo_filter(false, Some(0xa387), &peers, false, &[]);
+        assert_eq!(run(&f, &ipv4([192, 168, 0, 1], 3, 0)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([192, 168, 0, 1], 11, 0)), SNAPLEN);
+        let f6 = echo_filter(true, Some(0xa387), &peers, false, &[]);
+        assert_eq!(run(&f6, &ipv6("fe80::1", 58, 1, 0)), SNAPLEN);
+        assert_eq!(run(&f6, &ipv6("fe80::1", 58, 3, 0)), SNAPLEN);
+        assert_eq!(run(&f6, &ipv6("fe80::1", 58, 2, 0)), 0);
+
+        // Echo requests of other programs which are answered by stealthy.
+        let f = echo_filter(false, Some(0xa387), &peers, true, &[]);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 3], 8, 0x1234)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 3], 0, 0x1234)), 0);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 0, 0xa387)), SNAPLEN);
+        let f = echo_filter(true, Some(0xa387), &peers, true, &[]);
+        assert_eq!(run(&f, &ipv6("2001:db8::2", 58, 128, 0x1234)), SNAPLEN);
+        assert_eq!(run(&f, &ipv6("2001:db8::2", 58, 129, 0x1234)), 0);
+
+        // Timestamp and address mask requests which carry packets.
+        let f = echo_filter(false, Some(0xa387), &peers, false, &[13, 8, 17]);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 13, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 17, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 0, 0xa387)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 14, 0xa387)), 0);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 3], 13, 0xa387)), 0);
+
+        // Camouflaged pings have any identifier.
+        let f = echo_filter(false, None, &peers, false, &[]);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 1], 8, 1)), SNAPLEN);
+        assert_eq!(run(&f, &ipv4([10, 0, 0, 3], 8, 1)), 0);
+        let f = echo_filter(true, None, &peers, false, &[]);
+        assert_eq!(run(&f, &ipv6("2001:db8::1", 58, 129, 0x1234)), SNAPLEN);
+    }
+}
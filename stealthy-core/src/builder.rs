@@ -0,0 +1,194 @@
+use crate::binding;
+use crate::cryp::Encryption;
+use crate::status::Status;
+use crate::error::StealthyError;
+use crate::handshake::RekeyPolicy;
+use crate::iptools::IpAddresses;
+use crate::layer::{Layer, Layers, Settings};
+use crate::signing::Signing;
+use crate::transport::{IcmpOptions, Transport};
+
+/// Keys which are used to encrypt the messages.
+enum Keys {
+    /// Hexadecimal key for symmetric encryption.
+    Symmetric(String),
+    /// Public keys of the recipients separated by commas, our private key and the PIN of
+    /// the token if the private key is stored on a token.
+    Hybrid(String, String, Option<String>),
+    /// Custom cipher (see `Layers::with_encryption`).
+    Custom(Box<dyn Encryption>),
+}
+
+/// Creates the layers step by step instead of passing the keys and the `Settings` to
+/// `Layers::symmetric` or `Layers::asymmetric`. Only the keys are required. By default packets are sent via
+/// ICMP on all devices and status messages are discarded.
+pub struct LayersBuilder {
+    keys: Option<Keys>,
+    cipher: String,
+    peer_keys: Option<String>,
+    transport: Transport,
+    devices: Option<Vec<String>>,
+    accept: IpAddresses,
+    max_retries: u32,
+    status: Option<Status>,
+    replay_window: Option<u64>,
+    signing: Signing,
+    rekey: RekeyPolicy,
+}
+
+impl Default for LayersBuilder {
+    fn default() -> LayersBuilder {
+        LayersBuilder::new()
+    }
+}
+
+impl LayersBuilder {
+
+    pub fn new() -> LayersBuilder {
+        LayersBuilder {
+            keys: None,
+            cipher: "blowfish".to_string(),
+            peer_keys: None,
+            transport: Transport::Icmp(vec!["any".to_string()], None, IcmpOptions::default()),
+            devices: None,
+            accept: IpAddresses::from_comma_list(""),
+            max_retries: binding::DEFAULT_MAX_RETRIES,
+            status: None,
+            replay_window: None,
+            signing: Signing::disabled(),
+            rekey: RekeyPolicy::new(None, None),
+        }
+    }
+
+    /// Uses symmetric encryption with the given hexadecimal key.
+    pub fn with_key(mut self, hexkey: &str) -> LayersBuilder {
+        self.keys = Some(Keys::Symmetric(hexkey.to_string()));
+        self
+    }
+
+    /// Sets the cipher for symmetric encryption, e.g. "chacha20poly1305".
+    pub fn with_cipher(mut self, cipher: &str) -> LayersBuilder {
+        self.cipher = cipher.to_string();
+        self
+    }
+
+    /// Sets the keys of the peers which do not use the key given with `with_key` (see
+    /// `PeerKeys`).
+    pub fn with_peer_keys(mut self, content: &str) -> LayersBuilder {
+        self.peer_keys = Some(content.to_string());
+        self
+    }
+
+    /// Uses hybrid encryption instead of symmetric encryption (see `Layers::asymmetric`).
+    pub fn with_hybrid(mut self, pubkey_files: &str, privkey_file: &str, pin: Option<&String>) -> LayersBuilder {
+        self.keys = Some(Keys::Hybrid(pubkey_files.to_string(), privkey_file.to_string(), pin.cloned()));
+        self
+    }
+
+    /// Uses the given cipher instead of the built-in ones (see `Layers::with_encryption`).
+    pub fn with_encryption(mut self, e: Box<dyn Encryption>) -> LayersBuilder {
+        self.keys = Some(Keys::Custom(e));
+        self
+    }
+
+    pub fn with_transport(mut self, transport: Transport) -> LayersBuilder {
+        self.transport = transport;
+        self
+    }
+
+    /// Receives the packets of the ICMP transport only on the given device. Can be called
+    /// several times for several devices.
+    pub fn with_device(mut self, device: &str) -> LayersBuilder {
+        self.devices.get_or_insert_with(Vec::new).push(device.to_string());
+        self
+    }
+
+    /// Sets the peers whose packets are accepted. Handshakes are started with all of them.
+    pub fn with_accept(mut self, ips: IpAddresses) -> LayersBuilder {
+        self.accept = ips;
+        self
+    }
+
+    /// Gives up a message if one of its packets has not been acknowledged after the given
+    /// number of retransmissions.
+    pub fn with_max_retries(mut self, max_retries: u32) -> LayersBuilder {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the channel to which the layers report their status and errors.
+    pub fn with_status(mut self, status: Status) -> LayersBuilder {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_replay_window(mut self, replay_window: Option<u64>) -> LayersBuilder {
+        self.replay_window = replay_window;
+        self
+    }
+
+    pub fn with_signing(mut self, signing: Signing) -> LayersBuilder {
+        self.signing = signing;
+        self
+    }
+
+    pub fn with_rekey(mut self, rekey: RekeyPolicy) -> LayersBuilder {
+        self.rekey = rekey;
+        self
+    }
+
+    /// Opens the network and starts the handshakes with the peers.
+    pub fn build(self) -> Result<Layer, StealthyError> {
+
+        let keys = self.keys.ok_or(StealthyError::Crypto("No key has been given."))?;
+        let status = self.status.unwrap_or_else(Status::discard);
+        let settings = Settings {
+            transport: with_devices(self.transport, self.devices),
+            status,
+            accept: self.accept,
+            replay_window: self.replay_window,
+            signing: self.signing,
+            rekey: self.rekey,
+        };
+        let l = match keys {
+            Keys::Symmetric(ref hexkey) => Layers::symmetric(hexkey, &self.cipher, self.peer_keys.as_ref(), settings)?,
+            Keys::Hybrid(ref pubkey_files, ref privkey_file, ref pin) => Layers::asymmetric(pubkey_files, privkey_file, pin.as_ref(), settings)?,
+            Keys::Custom(e) => Layers::with_encryption(e, settings)?
+        };
+        Ok(l.with_max_retries(self.max_retries))
+    }
+}
+
+/// Replaces the devices of the ICMP transport. Other transports are not affected.
+fn with_devices(transport: Transport, devices: Option<Vec<String>>) -> Transport {
+    match (transport, devices) {
+        (Transport::Icmp(_, port, options), Some(devs)) => Transport::Icmp(devs, port, options),
+        (t, _) => t
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{with_devices, LayersBuilder};
+    use crate::transport::{IcmpOptions, Transport};
+
+    #[test]
+    fn test_devices() {
+
+        let icmp = Transport::Icmp(vec!["any".to_string()], Some(1234), IcmpOptions::default());
+        let devs = Some(vec!["eth0".to_string(), "wlan0".to_string()]);
+        assert_eq!(with_devices(icmp.clone(), devs.clone()), Transport::Icmp(devs.clone().unwrap(), Some(1234), IcmpOptions::default()));
+        assert_eq!(with_devices(icmp.clone(), None), icmp);
+        assert_eq!(with_devices(Transport::Udp(1234), devs), Transport::Udp(1234));
+    }
+
+    #[test]
+    fn test_missing_key() {
+        assert!(LayersBuilder::new().with_transport(Transport::Udp(0)).build().is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::StealthyError;
+
+/// Start of the payload of the pings of Windows.
+const WINDOWS_PATTERN: &[u8] = b"abcdefghijklmnopqrstuvwabcdefghi";
+/// Size of the struct timeval at the start of the payload of the pings of Linux.
+const TIMEVAL_LEN: usize = 16;
+
+/// Ping utility whose echo requests are mimicked so that the pings of stealthy match the
+/// signatures of normal pings of simple intrusion detection systems.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Camouflage {
+    /// The ping of iputils: the identifier is the process id and the payload starts with
+    /// the time when the ping has been sent.
+    Linux,
+    /// The ping of Windows: the identifier is 1 and the payload starts with the alphabet.
+    Windows,
+}
+
+impl Camouflage {
+
+    pub fn parse(s: &str) -> Result<Camouflage, StealthyError> {
+        match s.trim() {
+            "linux" => Ok(Camouflage::Linux),
+            "windows" => Ok(Camouflage::Windows),
+            _ => Err(StealthyError::Transport("Unknown camouflage. Use linux or windows."))
+        }
+    }
+
+    /// Returns the identifier in the ICMP header.
+    pub fn identifier(&self) -> u16 {
+        match self {
+            Camouflage::Linux => std::process::id() as u16,
+            Camouflage::Windows => 1,
+        }
+    }
+
+    /// Returns the payload of a ping which contains the data behind the part which looks
+    /// like the payload of the ping utility.
+    pub fn wrap(&self, data: &[u8]) -> Vec<u8> {
+        let mut v = match self {
+            Camouflage::Linux => {
+                let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                [t.as_secs().to_le_bytes(), (t.subsec_micros() as u64).to_le_bytes()].concat()
+            },
+            Camouflage::Windows => WINDOWS_PATTERN.to_vec(),
+        };
+        v.extend_from_slice(data);
+        v
+    }
+
+    /// Returns the data of a payload created with `wrap` or None if the payload does not
+    /// look like the one of the ping utility.
+    pub fn unwrap<'a>(&self, payload: &'a [u8]) -> Option<&'a [u8]> {
+        match self {
+            Camouflage::Linux if payload.len() >= TIMEVAL_LEN => {
+                let mut usec = [0; 8];
+                usec.copy_from_slice(&payload[8..TIMEVAL_LEN]);
+                match u64::from_le_bytes(usec) < 1_000_000 {
+                    true => Some(&payload[TIMEVAL_LEN..]),
+                    false => None
+                }
+            },
+            Camouflage::Windows if payload.starts_with(WINDOWS_PATTERN) => Some(&payload[WINDOWS_PATTERN.len()..]),
+            _ => None
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Camouflage;
+
+    #[test]
+    fn test_camouflage() {
+
+        assert_eq!(Camouflage::parse("linux").map_err(|e| e.message()), Ok(Camouflage::Linux));
+        assert_eq!(Camouflage::parse("windows").map_err(|e| e.message()), Ok(Camouflage::Windows));
+        assert!(Camouflage::parse("macos").is_err());
+        assert_eq!(Camouflage::Windows.identifier(), 1);
+
+        for c in [Camouflage::Linux, Camouflage::Windows].iter() {
+            let v = c.wrap(b"hello");
+            assert_eq!(c.unwrap(&v), Some(&b"hello"[..]));
+            assert_eq!(c.unwrap(&c.wrap(b"")), Some(&b""[..]));
+        }
+        let v = Camouflage::Windows.wrap(b"x");
+        assert!(v.starts_with(b"abcdefghijklmnopqrstuvwabcdefghi"));
+        assert_eq!(Camouflage::Windows.unwrap(b"abcdefghijklmnop"), None);
+
+        // The payload of the ping of Linux: timeval followed by 0x10, 0x11, ...
+        let mut ping = vec![0x5f, 0x3a, 0x10, 0x67, 0, 0, 0, 0, 0x40, 0x42, 0x0f, 0, 0, 0, 0, 0];
+        assert_eq!(Camouflage::Linux.unwrap(&ping), None);
+        ping[8] = 0x3f;
+        ping.extend(0x10..0x38);
+        assert_eq!(Camouflage::Linux.unwrap(&ping).map(|p| p[0]), Some(0x10));
+        assert_eq!(Camouflage::Linux.unwrap(&ping[..15]), None);
+    }
+}
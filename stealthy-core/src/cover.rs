@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use rand::Rng;
+use crate::error::StealthyError;
+
+/// Marks the plaintext of a dummy message. Text messages never start with a zero byte.
+const COVER: u8 = 0;
+/// Range of the sizes of dummy messages which covers most chat messages.
+const MIN_SIZE: usize = 2;
+const MAX_SIZE: usize = 160;
+/// Intervals are limited to this multiple of the mean so that there are no long gaps.
+const MAX_INTERVAL_FACTOR: f64 = 8.0;
+
+/// Dummy messages which are sent at random intervals to the peers.
+///
+/// Without cover traffic an observer sees when two peers are chatting even if the content
+/// is encrypted. Dummy messages are encrypted and acknowledged like real messages so that
+/// they cannot be distinguished from them. The receiver drops them after decryption.
+/// Dummy messages are only sent to peers which have advertised that they support them as
+/// older versions would show them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CoverTraffic {
+    /// Mean interval between two dummy messages in seconds.
+    mean: f64,
+}
+
+impl CoverTraffic {
+
+    pub fn parse(s: &str) -> Result<CoverTraffic, StealthyError> {
+        match s.parse::<f64>() {
+            Ok(mean) if mean > 0.0 && mean.is_finite() => Ok(CoverTraffic { mean }),
+            _ => Err(StealthyError::Transport("Invalid interval for cover traffic."))
+        }
+    }
+
+    /// Returns the time until the next dummy message. The intervals are exponentially
+    /// distributed like the arrivals of messages which are written independently.
+    pub fn interval(&self) -> Duration {
+        let u: f64 = rand::thread_rng().gen();
+        let secs = (-(1.0 - u).ln() * self.mean).min(MAX_INTERVAL_FACTOR * self.mean);
+        Duration::from_millis((secs * 1000.0) as u64)
+    }
+}
+
+/// Returns the plaintext of a dummy message with a random size.
+pub fn decoy() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let n = rng.gen_range(MIN_SIZE, MAX_SIZE + 1);
+    let mut v = vec![COVER];
+    v.extend((1..n).map(|_| rng.gen::<u8>()));
+    v
+}
+
+/// Returns true if the decrypted message is a dummy message.
+pub fn is_cover(buf: &[u8]) -> bool {
+    buf.first() == Some(&COVER)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{CoverTraffic, decoy, is_cover, MIN_SIZE, MAX_SIZE};
+    use std::time::Duration;
+
+    #[test]
+    fn test_cover_traffic() {
+
+        let c = CoverTraffic::parse("2").unwrap();
+        assert!((0..1000).map(|_| c.interval()).all(|d| d <= Duration::from_secs(16)));
+        let mean = (0..1000).map(|_| c.interval().as_millis()).sum::<u128>() / 1000;
+        assert!(mean > 1500 && mean < 2500);
+        assert!(CoverTraffic::parse("0").is_err());
+        assert!(CoverTraffic::parse("x").is_err());
+
+        for _ in 0..100 {
+            let d = decoy();
+            assert!(is_cover(&d));
+            assert!(d.len() >= MIN_SIZE && d.len() <= MAX_SIZE);
+        }
+        assert!(!is_cover(b"hello"));
+        assert!(!is_cover(b""));
+    }
+}
@@ -0,0 +1,716 @@
+use std::sync::Arc;
+
+use crate::blowfish;
+use crate::rsa::{self, Padding};
+use crate::rsatools;
+use crate::wire::{push_value, pop_value, push_slice};
+use crate::tools::read_file;
+use crate::gpg::{self, AgentKey};
+use crate::pkcs11::{self, TokenKey};
+use crate::secret::SecretBytes;
+use crate::error::StealthyError;
+
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::aead::{AeadEncryptor, AeadDecryptor};
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::util::fixed_time_eq;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+pub type ResultVec = Result<Vec<u8>, StealthyError>;
+
+// Identifiers of the ciphers. The identifier is transmitted as the first byte of each
+// encrypted payload so that the receiver can detect a cipher mismatch.
+pub const CIPHER_BLOWFISH: u8 = 1;
+pub const CIPHER_RSA_BLOWFISH: u8 = 2;
+pub const CIPHER_CHACHA20_POLY1305: u8 = 3;
+/// Message of a session negotiated via the handshake and encrypted via the Double Ratchet.
+pub const CIPHER_SESSION: u8 = 4;
+
+/// First identifier which can be used by custom implementations of `Encryption` so that
+/// they do not clash with the built-in ciphers.
+pub const CIPHER_CUSTOM: u8 = 128;
+
+/// Ciphers which can be negotiated with a peer ordered by their strength, strongest first.
+pub const CIPHERS_BY_STRENGTH: [u8; 3] = [CIPHER_CHACHA20_POLY1305, CIPHER_RSA_BLOWFISH, CIPHER_BLOWFISH];
+
+/// Error which is returned by `Encryption::decrypt` if a message has been modified or
+/// has not been encrypted with the expected key.
+pub const AUTHENTICATION_FAILED: &str = "Authentication of ciphertext failed.";
+
+const MAC_LEN: usize = 32;
+
+/// Format version of messages in hybrid mode whose key is encrypted with RSA-OAEP and
+/// SHA-256. Messages of older versions have no version byte.
+const KEY_WRAP_OAEP_SHA256: u8 = 2;
+/// Format version of messages in hybrid mode whose key is encrypted for several recipients.
+const KEY_WRAP_MULTI: u8 = 3;
+
+/// Cipher which encrypts and signs the messages. Applications can implement it to use their
+/// own cipher (see `Layers::with_encryption`).
+pub trait Encryption : Send + Sync {
+    fn encrypt(&self, v: &Vec<u8>) -> ResultVec;
+    fn decrypt(&self, v: &Vec<u8>) -> ResultVec;
+    /// Returns the key which is used for encryption, in hybrid mode the public key. Secret
+    /// keys are returned in locked memory which is zeroized when it is dropped.
+    fn encryption_key(&self) -> SecretBytes;
+    /// Returns the identifier of the cipher which is used in the wire format. Custom
+    /// implementations use an identifier from `CIPHER_CUSTOM` on.
+    fn cipher_id(&self) -> u8;
+    /// Signs the data so that the peer can verify that it has been created by us. In
+    /// hybrid mode the data is signed with our private RSA key. For symmetric ciphers
+    /// a HMAC with the shared key is used.
+    fn sign(&self, v: &[u8]) -> ResultVec;
+    /// Verifies a signature of the peer created via `sign`.
+    fn verify(&self, v: &[u8], sig: &[u8]) -> bool;
+}
+
+/// Returns the implementation for symmetric encryption with the given cipher name.
+pub fn symmetric(cipher: &str, hexkey: &String) -> Result<Box<dyn Encryption>, StealthyError> {
+    match cipher {
+        "blowfish" => Ok(Box::new(SymmetricEncryption::new(hexkey)?)),
+        "chacha20poly1305" => Ok(Box::new(ChaChaEncryption::new(hexkey)?)),
+        _ => Err(StealthyError::Crypto("Unknown cipher."))
+    }
+}
+
+/// Returns the implementations of all symmetric ciphers which can be used with the given
+/// key. The configured cipher is not contained.
+pub fn symmetric_alternatives(cipher: &str, hexkey: &String) -> Vec<Box<dyn Encryption>> {
+    ["blowfish", "chacha20poly1305"].iter()
+        .filter(|name| **name != cipher)
+        .filter_map(|name| symmetric(name, hexkey).ok())
+        .collect()
+}
+
+/// Returns the name of the cipher with the given identifier.
+pub fn cipher_name(id: u8) -> &'static str {
+    match id {
+        CIPHER_BLOWFISH => "blowfish",
+        CIPHER_RSA_BLOWFISH => "rsa+blowfish",
+        CIPHER_CHACHA20_POLY1305 => "chacha20poly1305",
+        CIPHER_SESSION => "session",
+        id if id >= CIPHER_CUSTOM => "custom",
+        _ => "unknown"
+    }
+}
+
+/// Encrypts the data and prepends the identifier of the cipher.
+pub fn seal(e: &dyn Encryption, v: &Vec<u8>) -> ResultVec {
+    let mut r = vec![e.cipher_id()];
+    r.extend(e.encrypt(v)?);
+    Ok(r)
+}
+
+/// Checks the identifier of the cipher and decrypts the data.
+pub fn open(e: &dyn Encryption, v: &Vec<u8>) -> ResultVec {
+    match v.first() {
+        Some(id) if *id == e.cipher_id() => e.decrypt(&v[1..].to_vec()),
+        Some(_) => Err(StealthyError::Crypto("Message has been encrypted with a different cipher.")),
+        None => Err(StealthyError::Crypto("Empty message."))
+    }
+}
+
+pub struct SymmetricEncryption {
+    algorithm: blowfish::Blowfish
+}
+
+pub struct AsymmetricEncryption {
+    /// Public key of the peer which is used to verify its signatures.
+    pub_key: String,
+    /// Public keys of the other recipients for which the key of each message is encrypted,
+    /// too.
+    others: Vec<String>,
+    priv_key: Arc<PrivateKey>
+}
+
+/// Our private key is either read from a PEM file, kept by gpg-agent or stored on a
+/// PKCS#11 token.
+enum PrivateKey {
+    Pem(SecretBytes),
+    Agent(AgentKey),
+    Token(TokenKey)
+}
+
+// ---------------------------------
+
+impl SymmetricEncryption {
+
+    pub fn new(hexkey: &String) -> Result<SymmetricEncryption, StealthyError> {
+
+        Ok(SymmetricEncryption {
+            algorithm: blowfish::Blowfish::from_key(from_hex(hexkey.clone())?)?
+        })
+    }
+}
+
+impl Encryption for SymmetricEncryption {
+
+    /// Encrypts the given data stored in a vector and returns the concatenated
+    /// IV, ciphertext and MAC.
+    fn encrypt(&self, v: &Vec<u8>) -> ResultVec {
+        encrypt_then_mac(&self.algorithm, v)
+    }
+
+    /// Verifies the MAC and decrypts the given data stored in a vector and returns the
+    /// plaintext.
+    fn decrypt(&self, v: &Vec<u8>) -> ResultVec {
+        verify_then_decrypt(&self.algorithm, v)
+    }
+
+    /// Returns the symmetric key used for encryption and decryption.
+    fn encryption_key(&self) -> SecretBytes {
+        SecretBytes::new(self.algorithm.key().to_vec())
+    }
+
+    fn cipher_id(&self) -> u8 {
+        CIPHER_BLOWFISH
+    }
+
+    fn sign(&self, v: &[u8]) -> ResultVec {
+        Ok(signature_mac(&self.algorithm.key(), v))
+    }
+
+    fn verify(&self, v: &[u8], sig: &[u8]) -> bool {
+        fixed_time_eq(&signature_mac(&self.algorithm.key(), v), sig)
+    }
+}
+
+// ---------------------------------
+
+const CHACHA_KEY_LEN: usize = 32;
+const CHACHA_NONCE_LEN: usize = 8;
+const CHACHA_TAG_LEN: usize = 16;
+
+/// Authenticated encryption with ChaCha20-Poly1305. It is fast on machines without
+/// hardware support for AES.
+pub struct ChaChaEncryption {
+    key: SecretBytes
+}
+
+impl ChaChaEncryption {
+
+    /// Creates a new instance from a key with 256 bit. For compatibility with the
+    /// default key also keys with 128 bit are accepted. From these keys a 256 bit key is
+    /// derived via SHA-256.
+    pub fn new(hexkey: &String) -> Result<ChaChaEncryption, StealthyError> {
+
+        let key = SecretBytes::new(from_hex(hexkey.clone())?);
+        match key.len() {
+            CHACHA_KEY_LEN => Ok(ChaChaEncryption { key }),
+            blowfish::KEY_LEN => {
+                let mut h = Sha256::new();
+                h.input(key.as_slice());
+                let mut k = vec![0; CHACHA_KEY_LEN];
+                h.result(&mut k);
+                Ok(ChaChaEncryption { key: SecretBytes::new(k) })
+            },
+            _ => Err(StealthyError::Crypto("Invalid key length."))
+        }
+    }
+}
+
+impl Encryption for ChaChaEncryption {
+
+    /// Encrypts the data and returns the concatenated nonce, ciphertext and tag.
+    fn encrypt(&self, v: &Vec<u8>) -> ResultVec {
+
+        let mut nonce = [0u8; CHACHA_NONCE_LEN];
+        OsRng::new().map_err(|_| StealthyError::Crypto("Could not get OsRng."))?.fill_bytes(&mut nonce);
+
+        let mut cipher = vec![0; v.len()];
+        let mut tag = [0u8; CHACHA_TAG_LEN];
+        ChaCha20Poly1305::new(self.key.as_slice(), &nonce, &[]).encrypt(v, &mut cipher, &mut tag);
+
+        let mut r = Vec::with_capacity(CHACHA_NONCE_LEN + v.len() + CHACHA_TAG_LEN);
+        push_slice(&mut r, &nonce);
+        push_slice(&mut r, &cipher);
+        push_slice(&mut r, &tag);
+        Ok(r)
+    }
+
+    /// Verifies the tag and decrypts the data.
+    fn decrypt(&self, v: &Vec<u8>) -> ResultVec {
+
+        if v.len() < CHACHA_NONCE_LEN + CHACHA_TAG_LEN {
+            return Err(StealthyError::Crypto("Ciphertext has invalid length."));
+        }
+        let (nonce, rest) = v.split_at(CHACHA_NONCE_LEN);
+        let (cipher, tag) = rest.split_at(rest.len() - CHACHA_TAG_LEN);
+
+        let mut plain = vec![0; cipher.len()];
+        match ChaCha20Poly1305::new(self.key.as_slice(), nonce, &[]).decrypt(cipher, &mut plain, tag) {
+            true => Ok(plain),
+            false => Err(StealthyError::Crypto(AUTHENTICATION_FAILED))
+        }
+    }
+
+    fn encryption_key(&self) -> SecretBytes {
+        self.key.clone()
+    }
+
+    fn cipher_id(&self) -> u8 {
+        CIPHER_CHACHA20_POLY1305
+    }
+
+    fn sign(&self, v: &[u8]) -> ResultVec {
+        Ok(signature_mac(self.key.as_slice(), v))
+    }
+
+    fn verify(&self, v: &[u8], sig: &[u8]) -> bool {
+        fixed_time_eq(&signature_mac(self.key.as_slice(), v), sig)
+    }
+}
+
+// ---------------------------------
+
+impl AsymmetricEncryption {
+
+    /// Both keys can also refer to a key in the keyring of GnuPG via `gpg:<key id>`. The
+    /// private key can also be given as PKCS#11 URI. `pin` is used to log in to the token if
+    /// the URI does not contain the PIN.
+    pub fn new(pubkey_file: &str, privkey_file: &str, pin: Option<&String>) -> Result<AsymmetricEncryption, StealthyError> {
+        Ok(AsymmetricEncryption::group(&[pubkey_file], privkey_file, pin)?.remove(0))
+    }
+
+    /// Creates an instance for each of the recipients. The key of each message is encrypted
+    /// for all recipients in a single envelope so that each of them can decrypt the message.
+    /// The instances share our private key.
+    pub fn group(pubkey_files: &[&str], privkey_file: &str, pin: Option<&String>) -> Result<Vec<AsymmetricEncryption>, StealthyError> {
+
+        let priv_key = Arc::new(match gpg::key_id(privkey_file) {
+            Some(id) => PrivateKey::Agent(AgentKey::new(id)?),
+            None if privkey_file.starts_with(pkcs11::PREFIX) => PrivateKey::Token(TokenKey::open(privkey_file, pin)?),
+            None => PrivateKey::Pem(SecretBytes::from_string(read_file(privkey_file)?))
+        });
+        let keys = pubkey_files.iter().map(|f| gpg::read_public_key(f)).collect::<Result<Vec<_>, _>>()?;
+        Ok(keys.iter().enumerate().map(|(i, k)| AsymmetricEncryption {
+            pub_key: k.clone(),
+            others: keys.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, k)| k.clone()).collect(),
+            priv_key: priv_key.clone()
+        }).collect())
+    }
+
+    /// Decrypts the key of a message with our private key.
+    fn unwrap_key(&self, cipher_key: &[u8], padding: Padding) -> Result<Vec<u8>, StealthyError> {
+
+        // If the key cannot be decrypted the message has not been encrypted for us.
+        match *self.priv_key {
+            PrivateKey::Pem(ref k) => rsa::RSA::new(&self.pub_key, k.as_slice())?.decrypt(cipher_key, padding),
+            PrivateKey::Agent(ref k) => k.decrypt(cipher_key, padding),
+            PrivateKey::Token(ref k) => k.decrypt(cipher_key, padding)
+        }.map_err(|_| StealthyError::Crypto(AUTHENTICATION_FAILED))
+    }
+
+    fn unwrap_and_decrypt(&self, v: &[u8], padding: Padding) -> Result<Vec<u8>, StealthyError> {
+
+        let mut data = v.to_vec();
+        let clen = pop_value(&mut data, 8).map_err(StealthyError::Crypto)? as usize;
+
+        if clen > data.len() {
+            return Err(StealthyError::Crypto("Invalid ciphertext length."));
+        }
+
+        let (cipher, cipher_key) = data.split_at(clen);
+        let key = self.unwrap_key(cipher_key, padding)?;
+        verify_then_decrypt(&blowfish::Blowfish::from_key(key)?, cipher)
+    }
+
+    /// Decrypts a message whose key has been encrypted for several recipients. Each of the
+    /// encrypted keys is tried as we do not know which one is ours.
+    fn unwrap_envelope(&self, v: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+        let mut data = v.to_vec();
+        let clen = pop_value(&mut data, 8).map_err(StealthyError::Crypto)? as usize;
+
+        if clen > data.len() {
+            return Err(StealthyError::Crypto("Invalid ciphertext length."));
+        }
+
+        let mut keys = data.split_off(clen);
+        while !keys.is_empty() {
+            let n = pop_value(&mut keys, 2).map_err(StealthyError::Crypto)? as usize;
+            if n > keys.len() {
+                return Err(StealthyError::Crypto("Invalid length of encrypted key."));
+            }
+            let rest = keys.split_off(n);
+            let plain = self.unwrap_key(&keys, Padding::OaepSha256)
+                .and_then(|key| verify_then_decrypt(&blowfish::Blowfish::from_key(key)?, &data));
+            if plain.is_ok() {
+                return plain;
+            }
+            keys = rest;
+        }
+        Err(StealthyError::Crypto(AUTHENTICATION_FAILED))
+    }
+}
+
+// ---------------------------------
+
+impl Encryption for AsymmetricEncryption {
+
+    fn encrypt(&self, v: &Vec<u8>) -> ResultVec {
+
+        // Encrypt the data with Blowfish.
+        let symenc = blowfish::Blowfish::new()?;
+        let cipher = encrypt_then_mac(&symenc, v)?;
+
+        // Encrypt the key used by Blowfish with RSA.
+        let ekey = rsa::RSA::public(&self.pub_key)
+            .and_then(|r| r.encrypt(&symenc.key(), Padding::OaepSha256))
+            ?;
+
+        let mut v: Vec<u8> = Vec::new();
+        if self.others.is_empty() {
+            v.push(KEY_WRAP_OAEP_SHA256);                 // format version
+            push_value(&mut v, cipher.len() as u64, 8); // length of ciphertext and MAC
+            push_slice(&mut v, &cipher);                // ciphertext and MAC
+            push_slice(&mut v, &ekey);                  // with RSA encrypted key
+            return Ok(v);
+        }
+
+        v.push(KEY_WRAP_MULTI);                          // format version
+        push_value(&mut v, cipher.len() as u64, 8);    // length of ciphertext and MAC
+        push_slice(&mut v, &cipher);                   // ciphertext and MAC
+        push_value(&mut v, ekey.len() as u64, 2);      // for each recipient the length
+        push_slice(&mut v, &ekey);                     // and the encrypted key
+        for k in &self.others {
+            let ekey = rsa::RSA::public(k)
+                .and_then(|r| r.encrypt(&symenc.key(), Padding::OaepSha256))
+                ?;
+            push_value(&mut v, ekey.len() as u64, 2);
+            push_slice(&mut v, &ekey);
+        }
+        Ok(v)
+    }
+ 
+    fn decrypt(&self, v: &Vec<u8>) -> ResultVec {
+
+        // Messages of older versions have no version byte and use OAEP with SHA-1. If a
+        // message of an older version starts with the version byte by chance decrypting it
+        // with SHA-256 fails and it is decrypted as message of an older version. If both
+        // fail the error of the current format is returned.
+        match v.split_first() {
+            Some((&KEY_WRAP_OAEP_SHA256, rest)) => self.unwrap_and_decrypt(rest, Padding::OaepSha256)
+                .or_else(|e| self.unwrap_and_decrypt(v, Padding::OaepSha1).map_err(|_| e)),
+            Some((&KEY_WRAP_MULTI, rest)) => self.unwrap_envelope(rest)
+                .or_else(|e| self.unwrap_and_decrypt(v, Padding::OaepSha1).map_err(|_| e)),
+            _ => self.unwrap_and_decrypt(v, Padding::OaepSha1)
+        }
+    }
+
+    /// Returns the public key.
+    fn encryption_key(&self) -> SecretBytes {
+        SecretBytes::new(rsatools::key_as_der(&self.pub_key))
+    }
+
+    fn cipher_id(&self) -> u8 {
+        CIPHER_RSA_BLOWFISH
+    }
+
+    /// Signs the data with our private key.
+    fn sign(&self, v: &[u8]) -> ResultVec {
+        match *self.priv_key {
+            PrivateKey::Pem(ref k) => rsa::RSA::new(&self.pub_key, k.as_slice()).and_then(|r| r.sign(v)),
+            PrivateKey::Agent(ref k) => k.sign(v),
+            PrivateKey::Token(ref k) => k.sign(v)
+        }
+    }
+
+    /// Verifies the signature with the public key of the recipient.
+    fn verify(&self, v: &[u8], sig: &[u8]) -> bool {
+        match rsa::RSA::public(&self.pub_key) {
+            Ok(r) => r.verify(v, sig),
+            Err(_) => false
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
+/// Derives the key for a MAC from the key used for encryption so that both keys are
+/// different. Different labels result in different keys.
+fn mac_key(label: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.input(label);
+    h.input(key);
+    let mut k = vec![0; MAC_LEN];
+    h.result(&mut k);
+    k
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut h = Hmac::new(Sha256::new(), key);
+    h.input(data);
+    h.result().code().to_vec()
+}
+
+fn mac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(&mac_key(b"stealthy mac", key), data)
+}
+
+/// A separate key is used for signatures so that a signature can never be mistaken for
+/// the MAC of a ciphertext.
+fn signature_mac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(&mac_key(b"stealthy signature", key), data)
+}
+
+/// Encrypts the data with Blowfish and appends a HMAC-SHA256 over the ciphertext.
+fn encrypt_then_mac(b: &blowfish::Blowfish, v: &[u8]) -> Result<Vec<u8>, StealthyError> {
+    let mut cipher = b.encrypt(v)?;
+    let tag = mac(&b.key(), &cipher);
+    push_slice(&mut cipher, &tag);
+    Ok(cipher)
+}
+
+/// Verifies the HMAC-SHA256 of the ciphertext before the ciphertext is decrypted.
+fn verify_then_decrypt(b: &blowfish::Blowfish, v: &[u8]) -> Result<Vec<u8>, StealthyError> {
+    if v.len() < MAC_LEN {
+        return Err(StealthyError::Crypto(AUTHENTICATION_FAILED));
+    }
+    let (cipher, tag) = v.split_at(v.len() - MAC_LEN);
+    if !fixed_time_eq(&mac(&b.key(), cipher), tag) {
+        return Err(StealthyError::Crypto(AUTHENTICATION_FAILED));
+    }
+    b.decrypt(cipher)
+}
+
+pub fn from_hex(s: String) -> Result<Vec<u8>, StealthyError> {
+
+    let bytes = s.into_bytes();
+
+    if bytes.len() % 2 != 0 {
+        return Err(StealthyError::Crypto("Length of hexadecimal string is not a multiple of 2."));
+    }
+
+    let mut v: Vec<u8> = vec![];
+    let mut p: usize = 0;
+    while p < bytes.len() {
+        let mut b: u8 = 0;
+        for _ in 0..2 {
+            b = b << 4;
+            let val = bytes[p];
+            match val {
+                b'A'...b'F' => b += val - b'A' + 10,
+                b'a'...b'f' => b += val - b'a' + 10,
+                b'0'...b'9' => b += val - b'0',
+                _ => { return Err(StealthyError::Crypto("Invalid character in hexadecimal string.")); }
+            }
+            p += 1;
+        }
+        v.push(b);
+    }
+    Ok(v)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn test_from_hex() {
+        
+        let mut r = super::from_hex("0".to_string());
+        assert!(r.is_err());
+
+        r = super::from_hex("0001090A0F10".to_string());
+        assert!(r.is_ok());
+
+        let o: Vec<u8> = vec![0, 1, 9, 10, 15, 16];
+        let v = r.unwrap();
+        assert!(v.len() == 6);
+        assert_eq!(o, v);
+    }
+
+    // --------------------------------------------------------------
+ 
+    use super::{Encryption, AsymmetricEncryption};
+
+    #[test]
+    fn test_asymmetric_encryption() {
+        
+        let a = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None);
+        assert!(a.is_ok());
+
+        let b = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "abc", None);
+        assert!(b.is_err());
+
+    }
+
+    #[test]
+    fn test_asymmetric_encrypt_decrypt() {
+        
+        let a = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None);
+        assert!(a.is_ok());
+        match a {
+            Ok(a) => {
+                let plain  = "hello".to_string().into_bytes();
+                let cipher = a.encrypt(&plain).unwrap();
+                let p      = a.decrypt(&cipher).unwrap();
+                assert_eq!(plain, p);
+            }
+            _ => { }
+        }
+    }
+
+    // --------------------------------------------------------------
+
+    use super::{ChaChaEncryption, SymmetricEncryption, seal, open, AUTHENTICATION_FAILED};
+
+    #[test]
+    fn test_symmetric_tampered() {
+
+        let e = SymmetricEncryption::new(&"11111111111111111111111111111111".to_string()).unwrap();
+        let plain = "hello".to_string().into_bytes();
+        let cipher = e.encrypt(&plain).unwrap();
+        assert_eq!(e.decrypt(&cipher).unwrap(), plain);
+
+        // Flip each bit of the ciphertext once.
+        for i in 0..cipher.len() * 8 {
+            let mut c = cipher.clone();
+            c[i / 8] ^= 1 << (i % 8);
+            assert_eq!(e.decrypt(&c).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+        }
+
+        // A message encrypted with another key is rejected.
+        let other = SymmetricEncryption::new(&"22222222222222222222222222222222".to_string()).unwrap();
+        assert_eq!(other.decrypt(&cipher).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+    }
+
+    #[test]
+    fn test_asymmetric_tampered() {
+
+        let a = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+        let mut cipher = a.encrypt(&"hello".to_string().into_bytes()).unwrap();
+        cipher[10] ^= 1;
+        assert_eq!(a.decrypt(&cipher).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+
+        // A message encrypted for another recipient is rejected.
+        let b = AsymmetricEncryption::new("tests/keys/rsa2_pub.pem", "tests/keys/rsa2_priv.pem", None).unwrap();
+        let cipher = b.encrypt(&"hello".to_string().into_bytes()).unwrap();
+        assert_eq!(a.decrypt(&cipher).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+    }
+
+    #[test]
+    fn test_asymmetric_group() {
+
+        let g = AsymmetricEncryption::group(&["tests/keys/rsa_pub.pem", "tests/keys/rsa2_pub.pem"], "tests/keys/rsa_priv.pem", None).unwrap();
+        assert_eq!(g.len(), 2);
+        let plain = "hello".to_string().into_bytes();
+        let cipher = g[1].encrypt(&plain).unwrap();
+        assert_eq!(cipher[0], super::KEY_WRAP_MULTI);
+
+        // Each recipient can decrypt the message.
+        let a = AsymmetricEncryption::new("tests/keys/rsa2_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+        let b = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa2_priv.pem", None).unwrap();
+        assert_eq!(a.decrypt(&cipher).unwrap(), plain);
+        assert_eq!(b.decrypt(&cipher).unwrap(), plain);
+
+        // Signatures of a peer are verified with its own key.
+        let sig = b.sign(b"hello").unwrap();
+        assert!(g[1].verify(b"hello", &sig));
+        assert!(!g[0].verify(b"hello", &sig));
+
+        // Someone who is not a recipient cannot decrypt the message.
+        let c = AsymmetricEncryption::group(&["tests/keys/rsa2_pub.pem", "tests/keys/gpg_pub.pem"], "tests/keys/rsa2_priv.pem", None).unwrap();
+        assert_eq!(a.decrypt(&c[0].encrypt(&plain).unwrap()).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+        let mut t = cipher.clone();
+        t[10] ^= 1;
+        assert_eq!(b.decrypt(&t).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+    }
+
+    #[test]
+    fn test_asymmetric_legacy_key_wrap() {
+
+        use crate::blowfish::Blowfish;
+        use crate::wire::{push_value, push_slice};
+        use crate::rsa::{RSA, Padding};
+        use crate::tools::read_file;
+
+        let a = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+        let plain = "hello".to_string().into_bytes();
+        assert_eq!(a.encrypt(&plain).unwrap()[0], super::KEY_WRAP_OAEP_SHA256);
+
+        // Format of older versions without version byte and with OAEP and SHA-1.
+        let symenc = Blowfish::new().unwrap();
+        let cipher = super::encrypt_then_mac(&symenc, &plain).unwrap();
+        let rsa = RSA::new(&read_file("tests/keys/rsa_pub.pem").unwrap(), read_file("tests/keys/rsa_priv.pem").unwrap().as_bytes()).unwrap();
+        let mut old = vec![];
+        push_value(&mut old, cipher.len() as u64, 8);
+        push_slice(&mut old, &cipher);
+        push_slice(&mut old, &rsa.encrypt(&symenc.key(), Padding::OaepSha1).unwrap());
+        assert_eq!(a.decrypt(&old).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_chacha_encrypt_decrypt() {
+
+        let e = ChaChaEncryption::new(&"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f".to_string()).unwrap();
+        let plain = "hello".to_string().into_bytes();
+        let cipher = e.encrypt(&plain).unwrap();
+
+        assert_eq!(cipher.len(), 8 + plain.len() + 16);
+        assert_eq!(e.decrypt(&cipher).unwrap(), plain);
+        // Each encryption uses a new nonce.
+        assert!(e.encrypt(&plain).unwrap() != cipher);
+    }
+
+    #[test]
+    fn test_chacha_key() {
+
+        // 128 bit keys are accepted for compatibility with the default key.
+        assert!(ChaChaEncryption::new(&"11111111111111111111111111111111".to_string()).is_ok());
+        assert!(ChaChaEncryption::new(&"1111".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_chacha_tampered() {
+
+        let e = ChaChaEncryption::new(&"11111111111111111111111111111111".to_string()).unwrap();
+        let mut cipher = e.encrypt(&"hello".to_string().into_bytes()).unwrap();
+        cipher[9] ^= 1;
+        assert!(e.decrypt(&cipher).is_err());
+        assert!(e.decrypt(&vec![1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_seal_open() {
+
+        let key = "11111111111111111111111111111111".to_string();
+        let chacha = ChaChaEncryption::new(&key).unwrap();
+        let blowfish = SymmetricEncryption::new(&key).unwrap();
+        let plain = "hello".to_string().into_bytes();
+
+        let cipher = seal(&chacha, &plain).unwrap();
+        assert_eq!(cipher[0], super::CIPHER_CHACHA20_POLY1305);
+        assert_eq!(open(&chacha, &cipher).unwrap(), plain);
+        // A message encrypted with another cipher is rejected.
+        assert!(open(&blowfish, &cipher).is_err());
+        assert!(open(&chacha, &vec![]).is_err());
+    }
+
+    #[test]
+    fn test_sign_verify() {
+
+        let key = "11111111111111111111111111111111".to_string();
+        for e in vec![super::symmetric("blowfish", &key).unwrap(), super::symmetric("chacha20poly1305", &key).unwrap()] {
+            let sig = e.sign(b"hello").unwrap();
+            assert!(e.verify(b"hello", &sig));
+            assert!(!e.verify(b"hellO", &sig));
+        }
+
+        // The peer verifies the signature with our public key.
+        let a = AsymmetricEncryption::new("tests/keys/rsa2_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+        let b = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa2_priv.pem", None).unwrap();
+        let sig = a.sign(b"hello").unwrap();
+        assert!(b.verify(b"hello", &sig));
+        assert!(!b.verify(b"hellO", &sig));
+        assert!(!a.verify(b"hello", &sig));
+    }
+}
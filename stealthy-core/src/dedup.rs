@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Number of packet ids which are remembered per peer.
+const SEEN_CAPACITY: usize = 1024;
+
+/// Least recently used packet ids of one peer.
+struct Lru {
+    /// Time of the last use of each id.
+    ids: HashMap<u64, u64>,
+    /// Ids ordered by the time of their last use.
+    order: BTreeMap<u64, u64>,
+}
+
+/// Remembers the ids of the packets which have recently been received from each peer so
+/// that a packet which is retransmitted because our ACK has been lost is not passed to the
+/// upper layers again.
+///
+/// For each peer at most `SEEN_CAPACITY` ids are kept. If the limit is reached the id
+/// which has not been seen for the longest time is forgotten.
+pub struct SeenPackets {
+    peers: HashMap<String, Lru>,
+    capacity: usize,
+    /// Counter which orders the uses of the ids.
+    clock: u64,
+}
+
+impl SeenPackets {
+
+    pub fn new() -> SeenPackets {
+        SeenPackets::with_capacity(SEEN_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> SeenPackets {
+        SeenPackets {
+            peers: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    /// Returns true if the packet with the id has not been received from the ip before.
+    pub fn insert(&mut self, ip: &str, id: u64) -> bool {
+
+        self.clock += 1;
+        let lru = self.peers.entry(ip.to_string()).or_insert_with(|| Lru {
+            ids: HashMap::new(),
+            order: BTreeMap::new(),
+        });
+        if let Some(t) = lru.ids.insert(id, self.clock) {
+            lru.order.remove(&t);
+            lru.order.insert(self.clock, id);
+            return false;
+        }
+        lru.order.insert(self.clock, id);
+        if lru.ids.len() > self.capacity {
+            let (&t, &oldest) = lru.order.iter().next().unwrap();
+            lru.order.remove(&t);
+            lru.ids.remove(&oldest);
+        }
+        true
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::SeenPackets;
+
+    #[test]
+    fn test_seen_packets() {
+
+        let mut s = SeenPackets::with_capacity(3);
+        assert!(s.insert("a", 1));
+        assert!(!s.insert("a", 1));
+        assert!(s.insert("b", 1));
+        assert!(s.insert("a", 2));
+        assert!(s.insert("a", 3));
+
+        // 1 has been used recently. Hence, 2 is forgotten.
+        assert!(!s.insert("a", 1));
+        assert!(s.insert("a", 4));
+        assert!(!s.insert("a", 3));
+        assert!(!s.insert("a", 1));
+        assert!(s.insert("a", 2));
+        assert!(!s.insert("b", 1));
+    }
+}
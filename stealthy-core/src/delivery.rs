@@ -0,0 +1,802 @@
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::message::{Message, IncomingMessage};
+use crate::message::MessageType;
+use crate::binding::{self, Network};
+use crate::rate::{self, RateControl};
+use crate::replay;
+use crate::sendhandle::SendTracker;
+use crate::wire::{FragmentHeader, FRAGMENT_HEADER_LEN};
+use crate::status::Status;
+
+#[cfg(feature="debugout")]
+use crypto::sha2::Sha256;
+#[cfg(feature="debugout")]
+use crypto::digest::Digest;
+
+#[derive(Clone)]
+struct SmallMessage {
+    buf: Vec<u8>,
+    seq: u32,
+    id : u64,
+    n  : u32,
+    mini_id: u64,
+}
+
+#[cfg(feature="debugout")]
+impl SmallMessage {
+    pub fn sha2(&self) -> String {
+        let mut sha2 = Sha256::new();
+        sha2.input(&self.buf);
+        sha2.result_str()
+    }
+
+    pub fn as_string(&self) -> String {
+        format!("{} {} {} {} {:?}", self.buf.len(), self.n, self.id, self.seq, self.buf)
+    }
+}
+
+#[derive(Clone)]
+pub struct SmallMessages {
+    messages: Vec<SmallMessage>,
+    acks: HashSet<u64>,  /// pending acks
+    id: u64,
+    /// Destination of the message.
+    ip: String,
+}
+
+/// Received parts of a message which has been split into several packets.
+struct Fragments {
+    parts: HashMap<u32, SmallMessage>,
+    /// Number of parts of the message.
+    n: u32,
+    /// Time in seconds when the last part has been received.
+    updated: i64,
+    /// Number of bytes of the received parts.
+    len: usize,
+}
+
+/// Incomplete messages by ip of the sender and id of the message.
+type Incoming = Arc<Mutex<HashMap<(String, u64), Fragments>>>;
+
+/// Priority of the packets of a message. If the window of a peer is full, the packets of a
+/// higher priority are sent first as soon as the window has room again. Hence, a chat
+/// message does not wait behind the chunks of a file upload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Chunks of file uploads.
+    Bulk,
+    /// Messages of the user.
+    Chat,
+    /// Messages which keep the connection working, e.g. the announcement of a new key.
+    Control,
+}
+
+impl Priority {
+
+    pub fn of(typ: &MessageType) -> Priority {
+        match typ {
+            MessageType::FileUpload => Priority::Bulk,
+            MessageType::Rollover | MessageType::Group => Priority::Control,
+            _ => Priority::Chat
+        }
+    }
+}
+
+pub struct Delivery {
+    pub pending: Arc<Mutex<Vec<SmallMessages>>>,
+    incoming: Incoming,
+    tx: Sender<IncomingMessage>,
+    network_layer: Network,
+    status: Status,
+    /// Rate of file uploads which adapts to ACKs and retransmissions.
+    rate: Arc<Mutex<RateControl>>,
+    /// Messages whose retransmissions and result are reported to a `SendHandle`.
+    sends: SendTracker,
+}
+
+/// Number of retransmissions of a packet after which its destination is switched to the
+/// fallback transport, if there is one.
+const FAILOVER_RETRIES: u32 = 2;
+/// Time in seconds after which the parts of an incomplete message are discarded.
+const REASSEMBLY_TIMEOUT: i64 = 600;
+/// Maximum number of incomplete messages of one peer.
+const MAX_REASSEMBLIES_PER_PEER: usize = 32;
+/// Maximum number of incomplete messages of all peers.
+const MAX_REASSEMBLIES: usize = 256;
+/// Maximum number of bytes of the parts of all incomplete messages.
+const MAX_REASSEMBLY_BYTES: usize = 64 * 1024 * 1024;
+
+//const MAX_MESSAGE_PART_SIZE: usize = 8192;
+
+impl Delivery {
+
+    /// Via rx1 this layer receives incoming messages from the
+    /// network layer (message with encrypted payload).
+    pub fn new(n: Network, tx: Sender<IncomingMessage>, rx: Receiver<IncomingMessage>, status: Status) -> Delivery {
+
+        let d = Delivery {
+            pending: Arc::new(Mutex::new(vec![])),
+            tx: tx,
+            network_layer: n,
+            incoming: Arc::new(Mutex::new(HashMap::new())),
+            status,
+            rate: Arc::new(Mutex::new(RateControl::new())),
+            sends: SendTracker::new(),
+        };
+
+        d.init_rx(rx);
+        d
+    }
+
+    /// Adds a received part of a message of the peer with the given ip and returns the
+    /// message if all parts have been received. Parts with an invalid sequence number or
+    /// number of parts are dropped as well as the parts of messages which have not been
+    /// completed in time. If a peer or all peers together have too many incomplete
+    /// messages or too many bytes are buffered, the message which has not been updated for
+    /// the longest time is discarded.
+    fn insert_packet(incoming: Incoming, ip: &str, small_msg: SmallMessage, now: i64) -> Option<Vec<u8>> {
+        let id = small_msg.id;
+        let n = small_msg.n;
+        let seq = small_msg.seq;
+        let mut i = incoming.lock().unwrap();
+
+        i.retain(|_, f| f.updated + REASSEMBLY_TIMEOUT > now);
+
+        if seq == 0 || seq > n {
+            return None;
+        }
+        let key = (ip.to_string(), id);
+        if n > 1 && !i.contains_key(&key) {
+            if i.keys().filter(|(p, _)| p == ip).count() >= MAX_REASSEMBLIES_PER_PEER {
+                Delivery::evict_oldest(&mut i, Some(ip));
+            }
+            if i.len() >= MAX_REASSEMBLIES {
+                Delivery::evict_oldest(&mut i, None);
+            }
+        }
+
+        // If an id for the packet(s) does not already exist in the incoming data structure
+        // insert an empty map to collect all packets of this stream.
+        let f = i.entry(key.clone()).or_insert_with(|| Fragments {
+            parts: HashMap::new(),
+            n,
+            updated: now,
+            len: 0,
+        });
+        if f.n != n || f.parts.contains_key(&seq) {
+            return None;
+        }
+        f.len += small_msg.buf.len();
+        f.parts.insert(seq, small_msg);
+        f.updated = now;
+
+        // As each sequence number in 1..=n is inserted only once, all packets have been
+        // received if there are n packets.
+        if f.parts.len() as u32 == n {
+            let f = i.remove(&key).unwrap();
+            let buf = (1..n + 1).flat_map(|seq| f.parts[&seq].buf.iter()).cloned().collect();
+            return Some(buf);
+        }
+        while i.values().map(|f| f.len).sum::<usize>() > MAX_REASSEMBLY_BYTES {
+            Delivery::evict_oldest(&mut i, None);
+        }
+        None
+    }
+
+    /// Discards the incomplete message which has not been updated for the longest time,
+    /// only among the messages of the given ip if there is one.
+    fn evict_oldest(i: &mut HashMap<(String, u64), Fragments>, ip: Option<&str>) {
+        let oldest = i.iter()
+            .filter(|((p, _), _)| ip.is_none() || ip == Some(p.as_str()))
+            .min_by_key(|(_, f)| f.updated)
+            .map(|(k, _)| k.clone());
+        if let Some(k) = oldest {
+            i.remove(&k);
+        }
+    }
+
+    fn init_rx(&self, rx: Receiver<IncomingMessage>) {
+
+        let tx       = self.tx.clone();
+        let queue    = self.pending.clone();
+        let incoming = self.incoming.clone();
+        let network  = self.network_layer.clone();
+        let status  = self.status.clone();
+        let rate     = self.rate.clone();
+        let sends    = self.sends.clone();
+
+        #[cfg(feature="debugout")]
+        let stx = self.status.clone();
+
+        let shutdown = network.shutdown_signal();
+
+		shutdown.clone().spawn(move || { while !shutdown.is_stopped() {
+            match rx.recv_timeout(Duration::from_millis(binding::RECV_TIMEOUT)) {
+                Ok(msg) => {
+                    match msg {
+                        IncomingMessage::Error(_error) => {
+                            // TODO implement
+                        },
+                        // msg could be just one of many messages. The stream of single messages is merged in this struct.
+                        IncomingMessage::FileUpload(m) => {
+                            match Delivery::deserialize(&m.buf) {
+                                Some(small_msg) => {
+                                    let r = Delivery::insert_packet(incoming.clone(), &m.ip, small_msg, replay::now());
+                                    if r.is_some() {
+                                        // The payload is still encrypted.
+                                        //println!("TTT received all");
+                                        if tx.send(IncomingMessage::FileUpload(Message::new(m.ip, r.unwrap()).with_device(m.device))).is_err() {
+                                            // TODO error handling
+                                        }
+                                    }
+                                }
+                                _ => { } // TODO error handling
+                            }
+                        },
+                        IncomingMessage::Batch(m) => {
+                            match Delivery::deserialize(&m.buf) {
+                                Some(small_msg) => {
+                                    let r = Delivery::insert_packet(incoming.clone(), &m.ip, small_msg, replay::now());
+                                    if r.is_some() {
+                                        // The payload is still encrypted.
+                                        if tx.send(IncomingMessage::Batch(Message::batch(m.ip, r.unwrap()).with_device(m.device))).is_err() {
+                                            // TODO error handling
+                                        }
+                                    }
+                                }
+                                _ => { } // TODO error handling
+                            }
+                        },
+                        IncomingMessage::Rollover(m) => {
+                            match Delivery::deserialize(&m.buf) {
+                                Some(small_msg) => {
+                                    let r = Delivery::insert_packet(incoming.clone(), &m.ip, small_msg, replay::now());
+                                    if r.is_some() {
+                                        // The payload is still encrypted.
+                                        if tx.send(IncomingMessage::Rollover(Message::rollover(m.ip, r.unwrap()).with_device(m.device))).is_err() {
+                                            // TODO error handling
+                                        }
+                                    }
+                                }
+                                _ => { } // TODO error handling
+                            }
+                        },
+                        IncomingMessage::Group(m) => {
+                            match Delivery::deserialize(&m.buf) {
+                                Some(small_msg) => {
+                                    let r = Delivery::insert_packet(incoming.clone(), &m.ip, small_msg, replay::now());
+                                    if r.is_some() {
+                                        // The payload is still encrypted.
+                                        if tx.send(IncomingMessage::Group(Message::group(m.ip, r.unwrap()).with_device(m.device))).is_err() {
+                                            // TODO error handling
+                                        }
+                                    }
+                                }
+                                _ => { } // TODO error handling
+                            }
+                        },
+                        IncomingMessage::New(m) => { // TODO beautify
+                            match Delivery::deserialize(&m.buf) {
+                                Some(small_msg) => {
+                                    #[cfg(feature="debugout")]
+                                    stx.send(format!("delivery.rs::deserialize result hash: {} [{}]", small_msg.sha2(), small_msg.as_string())).unwrap();
+                                    let r = Delivery::insert_packet(incoming.clone(), &m.ip, small_msg, replay::now());
+                                    if r.is_some() {
+                                        // The payload is still encrypted.
+                                        if tx.send(IncomingMessage::New(Message::new(m.ip, r.unwrap()).with_device(m.device))).is_err() {
+                                            // TODO error handling
+                                        }
+
+                                    }
+                                }
+                                _ => { } // TODO error handling
+                            }
+                        }
+                        IncomingMessage::AckProgress(_id, _pending, _total) => {
+
+                        },
+                        IncomingMessage::PacketFailed(id, reason) => {
+                            let mut q = queue.lock().expect("delivery: lock failed");
+                            if let Some(idx) = q.iter().position(|m| m.acks.contains(&id)) {
+                                // The other packets of the message are not sent anymore.
+                                let m = q.swap_remove(idx);
+                                network.drop_packets(&m.acks);
+                                sends.resolve(m.id, Err(reason.clone()));
+                                if tx.send(IncomingMessage::SendFailed(m.id, reason)).is_err() {
+                                    // TODO error handling
+                                }
+                            }
+                        },
+                        IncomingMessage::Timeout(ip, id, retries) => {
+                            rate.lock().unwrap().on_loss(rate::now_millis());
+                            if let Some(m) = queue.lock().unwrap().iter().find(|m| m.acks.contains(&id)) {
+                                sends.retransmitted(m.id);
+                            }
+                            if retries >= FAILOVER_RETRIES && network.fail_over(&ip) {
+                                status.info(format!("Packets to {} are not acknowledged. Falling back to TCP.", ip));
+                            }
+                        },
+                        IncomingMessage::KeyChallenge(_) | IncomingMessage::KeyResponse(_) | IncomingMessage::Handshake(_) |
+                        IncomingMessage::Receipt(_) | IncomingMessage::Delivered(_) | IncomingMessage::Read(_) | IncomingMessage::KeyChanged(_, _) |
+                        IncomingMessage::Keepalive(_) | IncomingMessage::Presence(_, _) |
+                        IncomingMessage::SessionOpened(_) | IncomingMessage::SessionClosed(_) | IncomingMessage::SendFailed(_, _) | IncomingMessage::Writable(_) |
+                        IncomingMessage::Cancel(_) | IncomingMessage::Cancelled(_, _) => {
+                            if tx.send(msg).is_err() {
+                                // TODO error handling
+                            }
+                        },
+                        IncomingMessage::Ack(id) => { // TODO beautify + performance for uploads
+                            rate.lock().unwrap().on_ack();
+                            let mut q = queue.lock().expect("delivery: lock failed");  // lock guard on Vec<SmallMessages>
+                            let mut idx = 0;
+                            let mut b = false;
+
+                            for i in q.iter() {
+                                if i.acks.contains(&id) {
+                                    b = true;
+                                    break;
+                                }
+                                idx += 1;
+                            }
+
+                            if b {
+                                q[idx].acks.remove(&id);
+                                tx.send(IncomingMessage::AckProgress(q[idx].id.clone(), q[idx].acks.len(), q[idx].messages.len())).expect("Error");
+
+                                if q[idx].acks.len() == 0 { // received all akcs
+                                    let iid = q[idx].id.clone();
+                                    q.swap_remove(idx);
+                                    sends.resolve(iid, Ok(()));
+                                    if tx.send(IncomingMessage::Ack(iid)).is_err() {
+                                        // TODO error handling
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => { } // TODO error handling
+            }
+        }});
+    }
+
+    /// Returns the maximum payload size of a packet to the given peer.
+    pub fn max_size(&self, ip: &str) -> usize {
+        self.network_layer.current_size(ip)
+    }
+
+    /// Returns the messages whose result is reported to a `SendHandle`.
+    pub fn sends(&self) -> SendTracker {
+        self.sends.clone()
+    }
+
+    /// Gives up the message with the given id which has not been acknowledged yet. Its
+    /// remaining parts are not sent, its packets are not retransmitted anymore and its
+    /// `SendHandle` resolves with the reason. Returns the destination of the message.
+    pub fn cancel(&self, id: u64, reason: &str) -> Option<String> {
+        let m = {
+            let mut pending = self.pending.lock().unwrap();
+            let idx = pending.iter().position(|m| m.id == id)?;
+            pending.swap_remove(idx)
+        };
+        self.network_layer.drop_packets(&m.acks);
+        self.sends.resolve(id, Err(reason.to_string()));
+        Some(m.ip)
+    }
+
+    /// Returns the ids of the messages which have not been acknowledged yet.
+    pub fn unacknowledged(&self) -> Vec<u64> {
+        self.pending.lock().unwrap().iter().map(|m| m.id).collect()
+    }
+
+    pub fn get_pending(&self) -> Arc<Mutex<Vec<SmallMessages>>> {
+        self.pending.clone()
+    }
+
+    pub fn network(&self) -> Network {
+        self.network_layer.clone()
+    }
+
+    pub fn rate(&self) -> Arc<Mutex<RateControl>> {
+        self.rate.clone()
+    }
+
+    pub fn send_msg(msg: Message, id: u64, pending: Arc<Mutex<Vec<SmallMessages>>>, network: Network, status: Status, siz: usize) -> SendObject {
+
+        // Total allowed payload: siz (= Network::current_siz)
+
+        // Split big message into smaller messages.
+        let mut small_messages = Self::split_message(&msg, id, siz.saturating_sub(FRAGMENT_HEADER_LEN).max(1));
+
+        // Save ids for acks.
+        let j = &small_messages.messages;
+        for i in j {
+            small_messages.acks.insert(i.mini_id);
+        }
+
+        pending.lock()
+            .expect("Could not lock.")
+            .push(small_messages.clone());
+
+        let o = SendObject {
+            msg,
+            small_messages,
+            pending,
+            network,
+            status,
+            jitter: 0,
+            rate: None,
+        };
+
+        o
+    }
+
+    /// Sends parts which have been encrypted separately. Each part must fit into one packet
+    /// and is sent with its own stream id so that the packets of a part do not reveal to
+    /// which message they belong. The id is reported when all parts are acknowledged.
+    pub fn send_parts(msg: Message, parts: Vec<Vec<u8>>, id: u64, pending: Arc<Mutex<Vec<SmallMessages>>>, network: Network, status: Status) -> SendObject {
+
+        let mut small_messages = SmallMessages {
+            messages: parts.into_iter().map(|buf| SmallMessage {
+                buf,
+                seq: 1,
+                id: rand::random::<u64>(),
+                n: 1,
+                mini_id: rand::random::<u64>(),
+            }).collect(),
+            id,
+            acks: HashSet::new(),
+            ip: msg.ip.clone(),
+        };
+        for i in &small_messages.messages {
+            small_messages.acks.insert(i.mini_id);
+        }
+
+        pending.lock()
+            .expect("Could not lock.")
+            .push(small_messages.clone());
+
+        SendObject {
+            msg,
+            small_messages,
+            pending,
+            network,
+            status,
+            jitter: 0,
+            rate: None,
+        }
+    }
+
+    fn split_message(msg: &Message, id: u64, maxsiz: usize) -> SmallMessages {
+
+        let mut parts: Vec<SmallMessage> = Vec::new();
+        let mut i: u32 = 1;
+
+        //println!("!!!!!!!!!!!!! {} !!!!!!!!!!", maxsiz);
+
+        let chunks = msg.buf.chunks(maxsiz);
+        let n = chunks.len();
+
+        for win in chunks {
+            parts.push(SmallMessage {
+                buf: win.to_vec(),
+                seq: i,
+                id: id,  // id from the big message
+                n: n as u32,
+                mini_id: rand::random::<u64>(),
+            });
+            i += 1;
+        }
+
+        SmallMessages {
+            messages: parts,
+            id: id,
+            acks: HashSet::new(),
+            ip: msg.ip.clone(),
+        }
+    }
+
+    /// Serializes a chunk into a vector which is ready to be transmitted
+    /// via an icmp echo request.
+    fn serialize(m: &SmallMessage) -> Vec<u8> {
+
+        let mut v = FragmentHeader { id: m.id, n: m.n, seq: m.seq }.encode();
+        v.extend_from_slice(&m.buf);
+        v
+    }
+
+    /// Deserialized a received icmp echo request into a chunk.
+    fn deserialize(data: &Vec<u8>) -> Option<SmallMessage> {
+
+        let (h, buf) = FragmentHeader::decode(data).ok()?;
+        Some(SmallMessage {
+            buf: buf.to_vec(),
+            seq: h.seq,
+            id : h.id,
+            n  : h.n,
+            mini_id: 0,
+        })
+    }
+}
+
+pub struct SendObject {
+    msg: Message,
+    small_messages: SmallMessages,
+    /// Messages which have been neither acknowledged nor failed.
+    pending: Arc<Mutex<Vec<SmallMessages>>>,
+    network: Network,
+    status: Status,
+    /// Maximum delay in milliseconds before each packet is sent.
+    jitter: u64,
+    /// Paces the packets of file uploads and limits the rate of all packets.
+    rate: Option<Arc<Mutex<RateControl>>>,
+}
+
+impl SendObject {
+
+    /// Delays each packet by a random time of up to `jitter` milliseconds so that the
+    /// packets cannot be correlated with the time at which a message has been written.
+    pub fn with_jitter(mut self, jitter: u64) -> SendObject {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Paces the packets with the given rate control. The packets of a file upload are paced
+    /// with the adaptive rate so that the upload does not delay the packets of other
+    /// messages. The packets of all messages are limited by the maximum rate.
+    pub fn with_rate(mut self, rate: Arc<Mutex<RateControl>>) -> SendObject {
+        self.rate = Some(rate);
+        self
+    }
+
+    pub fn run(&self) {
+        for i in &self.small_messages.messages {
+            // The message has failed, e.g. as the peer is unreachable.
+            if !self.pending.lock().unwrap().iter().any(|m| m.id == self.small_messages.id) {
+                break;
+            }
+            if self.jitter > 0 {
+                thread::sleep(Duration::from_millis(rand::thread_rng().gen_range(0, self.jitter + 1)));
+            }
+            let message = self.msg.set_payload(Delivery::serialize(i));
+            let priority = Priority::of(&self.msg.typ);
+            if let Some(ref rate) = self.rate {
+                let upload = priority == Priority::Bulk;
+                let delay = rate.lock().unwrap().delay(message.buf.len(), rate::now_millis(), upload);
+                thread::sleep(Duration::from_millis(delay));
+            }
+            match self.network.send_msg(message, i.mini_id, priority) {
+                Ok(_id) => {
+                },
+                Err(e) => {
+                    self.status.info(format!("Sending of message failed: {}", e));
+                    // TODO remove small_message from delivery.rs:Delivery:self.pending on error
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Delivery, SmallMessage};
+    use crate::message::Message;
+
+    /// Maximum size of a part of a message in the tests.
+    const PART_SIZE: usize = 1024;
+
+    /*
+    #[test]
+    fn test_new() {
+        
+        let d = Delivery::new();
+        assert_eq!(d.pending.len(), 0);
+    }
+    */
+
+
+    #[test]
+    fn test_split_small_message() {
+        
+        let data = "hallo".to_string().into_bytes();
+        let msg  = Message::new("1.2.3.4".to_string(), data.clone());
+        let r    = Delivery::split_message(&msg, 7, PART_SIZE);
+
+        // Check that the id of the message is used.
+        assert_eq!(r.id, 7);
+        assert_eq!(r.ip, "1.2.3.4");
+        // Check that there is one message.
+        assert!(r.messages.len() == 1);
+        // An empty vector for received acks.
+        assert!(r.acks.len() == 0);
+
+        // Check that the sequence number of the first message is 1.
+        assert!(r.messages[0].seq == 1);
+        // Check that the first message is equal to the original message.
+        assert_eq!(r.messages[0].buf, data);
+        assert_eq!(r.messages[0].id, r.id);
+        assert_eq!(r.messages[0].n, 1);
+    }
+
+    #[test]
+    fn test_split_small_message_a() {
+
+        let v = (0..PART_SIZE).map(|x| x as u8).collect::<Vec<_>>();
+        let m = Message::new("1.2.3.4".to_string(), v.clone());
+        let r = Delivery::split_message(&m, 7, PART_SIZE);
+
+        assert_eq!(r.acks.len(), 0);
+        assert_eq!(r.id, 7);
+        assert_eq!(r.messages.len(), 1);
+        assert_eq!(r.messages[0].buf, v);
+        assert_eq!(r.messages[0].seq, 1);
+        assert_eq!(r.messages[0].id, r.id);
+        assert_eq!(r.messages[0].n, 1);
+    }
+
+    #[test]
+    fn test_split_big_message() {
+
+        // Create a message that should be divided into two pieces.
+        let v = (0..PART_SIZE + 1).map(|x| x as u8).collect::<Vec<_>>();
+        let m = Message::new("1.2.3.4".to_string(), v.clone());
+        let r = Delivery::split_message(&m, 7, PART_SIZE);
+
+        assert_eq!(r.id, 7);
+        assert!(r.messages.len() == 2);
+        assert!(r.messages[0].seq == 1);
+        assert!(r.messages[0].id == r.id);
+        assert!(r.messages[0].n == 2);
+
+        assert!(r.messages[1].seq == 2);
+        assert!(r.messages[1].id == r.id);
+        assert!(r.messages[1].n == 2);
+
+        assert!(r.messages[0].buf.len() == PART_SIZE);
+        assert!(r.messages[1].buf.len() == 1);
+
+        let (v1, v2) = v.split_at(PART_SIZE);
+        assert_eq!(r.messages[0].buf, v1);
+        assert_eq!(r.messages[1].buf, v2);
+    }
+
+    #[test]
+    fn test_de_and_serialize() {
+
+        let mp = SmallMessage {
+            buf: vec![1, 2, 3, 8, 9],
+            seq: 211 * 256 + 189,
+            n  : (99 * 256 + 134) * 256 + 177,
+            id : (12 * 256 + 19) * 256 + 18,
+            mini_id: 0,
+        };
+
+        let v = Delivery::serialize(&mp);
+        assert_eq!(v, vec![
+                1,                         // version
+                18, 19, 12, 0, 0, 0, 0, 0, // Id
+                177, 134, 99, 0,           // total
+                189, 211, 0, 0,            // seq
+                1, 2, 3, 8, 9              // msg
+            ]);
+
+        let m = Delivery::deserialize(&v);
+
+        assert!(m.is_some());
+        let p = m.unwrap();
+        assert_eq!(p.id, (12 * 256 + 19) * 256 + 18);
+        assert_eq!(p.seq, 211 * 256 + 189);
+        assert_eq!(p.n, (99 * 256 + 134) * 256 + 177);
+
+        // Check that length check does work.
+        let mut x: Vec<u8> = vec![1, 2];
+        assert!(!Delivery::deserialize(&x).is_some());
+
+        // Check that version check does work.
+        x = vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(!Delivery::deserialize(&x).is_some());
+
+        // Check that version check does work.
+        x = vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(Delivery::deserialize(&x).is_none());
+        x = vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(Delivery::deserialize(&x).is_some());
+
+        // Check that length check does work.
+        x = vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(Delivery::deserialize(&x).is_some());
+    }
+
+    #[test]
+    fn test_insert_packet() {
+
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+
+        const IP: &str = "1.2.3.4";
+        let part = |id, n, seq, buf: &[u8]| SmallMessage { buf: buf.to_vec(), seq, id, n, mini_id: 0 };
+        let incoming = Arc::new(Mutex::new(HashMap::new()));
+
+        // The parts are reassembled in the order of their sequence numbers.
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(1, 3, 2, b"b"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(1, 3, 2, b"b"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(1, 3, 3, b"c"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(1, 3, 1, b"a"), 100), Some(b"abc".to_vec()));
+        assert!(incoming.lock().unwrap().is_empty());
+
+        // Invalid sequence numbers and a changed number of parts.
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(2, 2, 0, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(2, 2, 3, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(2, 2, 1, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(2, 1, 1, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(2, 2, 2, b"b"), 100), Some(b"ab".to_vec()));
+
+        // Incomplete messages expire.
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(3, 2, 1, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(4, 1, 1, b"x"), 800), Some(b"x".to_vec()));
+        assert!(incoming.lock().unwrap().is_empty());
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(3, 2, 2, b"b"), 800), None);
+
+        // Parts of different peers with the same id are not mixed.
+        let incoming = Arc::new(Mutex::new(HashMap::new()));
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(5, 2, 1, b"a"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), "5.6.7.8", part(5, 2, 2, b"x"), 100), None);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), IP, part(5, 2, 2, b"b"), 100), Some(b"ab".to_vec()));
+    }
+
+    #[test]
+    fn test_reassembly_limits() {
+
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+        use super::{MAX_REASSEMBLIES, MAX_REASSEMBLIES_PER_PEER};
+
+        let part = |id, seq| SmallMessage { buf: b"a".to_vec(), seq, id, n: 2, mini_id: 0 };
+        let incoming = Arc::new(Mutex::new(HashMap::new()));
+
+        // The incomplete message of a peer which has not been updated for the longest time
+        // is discarded if the peer starts too many messages.
+        for id in 0..MAX_REASSEMBLIES_PER_PEER as u64 + 1 {
+            Delivery::insert_packet(incoming.clone(), "1.2.3.4", part(id, 1), 100 + id as i64);
+        }
+        assert_eq!(incoming.lock().unwrap().len(), MAX_REASSEMBLIES_PER_PEER);
+        assert_eq!(Delivery::insert_packet(incoming.clone(), "1.2.3.4", part(1, 2), 200), Some(b"aa".to_vec()));
+        assert_eq!(Delivery::insert_packet(incoming.clone(), "1.2.3.4", part(0, 2), 200), None);
+
+        // The limit of all peers.
+        let incoming = Arc::new(Mutex::new(HashMap::new()));
+        for peer in 0..MAX_REASSEMBLIES + 1 {
+            let ip = format!("10.0.{}.{}", peer / 256, peer % 256);
+            Delivery::insert_packet(incoming.clone(), &ip, part(1, 1), 100 + peer as i64);
+        }
+        assert_eq!(incoming.lock().unwrap().len(), MAX_REASSEMBLIES);
+        assert!(!incoming.lock().unwrap().contains_key(&("10.0.0.0".to_string(), 1)));
+    }
+
+    #[test]
+    fn test_priority() {
+
+        use super::Priority;
+        use crate::message::MessageType;
+
+        assert_eq!(Priority::of(&MessageType::FileUpload), Priority::Bulk);
+        assert_eq!(Priority::of(&MessageType::NewMessage), Priority::Chat);
+        assert_eq!(Priority::of(&MessageType::Batch), Priority::Chat);
+        assert_eq!(Priority::of(&MessageType::Rollover), Priority::Control);
+        assert_eq!(Priority::of(&MessageType::Group), Priority::Control);
+        assert!(Priority::Control > Priority::Chat && Priority::Chat > Priority::Bulk);
+    }
+}
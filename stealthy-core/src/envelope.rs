@@ -0,0 +1,222 @@
+use crate::wire::{push_value, push_slice, pop_value};
+use crate::error::StealthyError;
+
+/// Marks the plaintext of a text message with options. Text messages never start with this
+/// byte. Dummy messages start with a zero byte (see `cover`).
+const ENVELOPE: u8 = 1;
+
+/// Time in seconds after which the receiver removes the message (4 bytes).
+const OPTION_EXPIRE: u8 = 1;
+/// Id of the message chosen by the sender (8 bytes).
+const OPTION_ID: u8 = 2;
+/// Id of the message whose text is replaced by the text of this message (8 bytes).
+const OPTION_EDIT: u8 = 3;
+/// Id of the message which is deleted (8 bytes).
+const OPTION_DELETE: u8 = 4;
+
+/// A text message together with options which are encrypted with it.
+///
+/// Format: ENVELOPE (1) | number of options (1) | options | text
+///
+/// Each option consists of its type (1 byte), the length of its value (1 byte) and the
+/// value. Unknown options are skipped so that options can be added without breaking
+/// newer versions. Messages without options are sent as plain text like in older versions.
+/// Older versions would show the options as part of the text. Hence, options are only
+/// sent to peers which have advertised that they support them.
+///
+/// Each message carries a random id so that it can be edited later. An edit is a message
+/// which references the id of the original message. A retraction references the id and
+/// has no text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub text: Vec<u8>,
+    /// Time in seconds after which the receiver removes the message.
+    pub expire: Option<u32>,
+    pub id: Option<u64>,
+    /// Id of the message which is edited.
+    pub edit: Option<u64>,
+    /// Id of the message which is deleted.
+    pub delete: Option<u64>,
+}
+
+impl Envelope {
+
+    pub fn new(text: Vec<u8>) -> Envelope {
+        Envelope {
+            text,
+            expire: None,
+            id: None,
+            edit: None,
+            delete: None,
+        }
+    }
+
+    pub fn with_id(mut self, id: u64) -> Envelope {
+        self.id = Some(id);
+        self
+    }
+
+    /// Replaces the text of the message with the given id.
+    pub fn with_edit(mut self, id: u64) -> Envelope {
+        self.edit = Some(id);
+        self
+    }
+
+    /// Deletes the message with the given id.
+    pub fn with_delete(mut self, id: u64) -> Envelope {
+        self.delete = Some(id);
+        self
+    }
+
+    /// Lets the message expire the given number of seconds after it has been received.
+    pub fn with_expire(mut self, secs: u32) -> Envelope {
+        self.expire = Some(secs);
+        self
+    }
+
+    /// Returns true if the message has options which get lost if the message is sent as
+    /// plain text to a peer which does not support envelopes. The id is not needed by such
+    /// peers.
+    pub fn has_options(&self) -> bool {
+        self.expire.is_some() || self.edit.is_some() || self.delete.is_some()
+    }
+
+    /// Returns the plaintext of the message.
+    pub fn encode(&self) -> Vec<u8> {
+
+        let mut options: Vec<(u8, Vec<u8>)> = vec![];
+        if let Some(secs) = self.expire {
+            let mut v = vec![];
+            push_value(&mut v, secs as u64, 4);
+            options.push((OPTION_EXPIRE, v));
+        }
+        for (typ, id) in vec![(OPTION_ID, self.id), (OPTION_EDIT, self.edit), (OPTION_DELETE, self.delete)] {
+            if let Some(id) = id {
+                let mut v = vec![];
+                push_value(&mut v, id, 8);
+                options.push((typ, v));
+            }
+        }
+        if options.is_empty() {
+            return self.text.clone();
+        }
+
+        let mut v = vec![ENVELOPE, options.len() as u8];
+        for (typ, value) in options {
+            v.push(typ);
+            v.push(value.len() as u8);
+            push_slice(&mut v, &value);
+        }
+        push_slice(&mut v, &self.text);
+        v
+    }
+
+    /// Parses the plaintext of a received text message. A message without envelope is
+    /// returned without options. Returns None if the envelope is invalid.
+    pub fn decode(buf: &[u8]) -> Option<Envelope> {
+
+        if buf.first() != Some(&ENVELOPE) {
+            return Some(Envelope::new(buf.to_vec()));
+        }
+        let mut env = Envelope::new(vec![]);
+        let n = *buf.get(1)?;
+        let mut pos = 2;
+        for _ in 0..n {
+            let typ = *buf.get(pos)?;
+            let len = *buf.get(pos + 1)? as usize;
+            let mut value = buf.get(pos + 2..pos + 2 + len)?.to_vec();
+            match typ {
+                OPTION_EXPIRE => env.expire = Some(pop_value(&mut value, 4).ok()? as u32),
+                OPTION_ID => env.id = Some(pop_value(&mut value, 8).ok()?),
+                OPTION_EDIT => env.edit = Some(pop_value(&mut value, 8).ok()?),
+                OPTION_DELETE => env.delete = Some(pop_value(&mut value, 8).ok()?),
+                _ => { }
+            }
+            pos += 2 + len;
+        }
+        env.text = buf[pos..].to_vec();
+        Some(env)
+    }
+}
+
+/// Parses a duration in seconds with an optional suffix s, m, h or d, e.g. "10m".
+pub fn parse_duration(s: &str) -> Result<u32, StealthyError> {
+    let s = s.trim().to_lowercase();
+    let (n, factor) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 3600),
+        Some('d') => (&s[..s.len() - 1], 86400),
+        _ => (&s[..], 1)
+    };
+    match n.parse::<u32>() {
+        Ok(n) if n > 0 => n.checked_mul(factor).ok_or(StealthyError::Protocol("Duration is too long.")),
+        _ => Err(StealthyError::Protocol("Invalid duration. Use seconds or a suffix, e.g. 60 or 10m."))
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Envelope, parse_duration, ENVELOPE};
+
+    #[test]
+    fn test_envelope() {
+
+        // Messages without options are plain text.
+        let e = Envelope::new(b"hello".to_vec());
+        assert!(!e.has_options());
+        assert_eq!(e.encode(), b"hello".to_vec());
+        assert_eq!(Envelope::decode(b"hello"), Some(e));
+
+        let e = Envelope::new(b"hello".to_vec()).with_expire(60);
+        assert!(e.has_options());
+        let buf = e.encode();
+        assert_eq!(buf[0], ENVELOPE);
+        assert_eq!(Envelope::decode(&buf), Some(e));
+
+        // Unknown options are skipped.
+        let buf = [&[ENVELOPE, 2, 99, 2, 7, 7, 1, 4, 10, 0, 0, 0][..], b"hi"].concat();
+        assert_eq!(Envelope::decode(&buf), Some(Envelope::new(b"hi".to_vec()).with_expire(10)));
+
+        // Truncated envelopes are invalid.
+        for i in 1..12 {
+            assert_eq!(Envelope::decode(&buf[..i]), None);
+        }
+        assert_eq!(Envelope::decode(&[ENVELOPE, 1, 1, 2, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_edit() {
+
+        // The id alone is not an option which older versions miss.
+        let e = Envelope::new(b"hello".to_vec()).with_id(u64::max_value());
+        assert!(!e.has_options());
+        assert_eq!(Envelope::decode(&e.encode()), Some(e));
+
+        let e = Envelope::new(b"hallo".to_vec()).with_edit(7).with_expire(5);
+        assert!(e.has_options());
+        assert_eq!(Envelope::decode(&e.encode()), Some(e));
+
+        let e = Envelope::new(vec![]).with_delete(7);
+        assert!(e.has_options());
+        assert_eq!(Envelope::decode(&e.encode()), Some(e));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("60").map_err(|e| e.message()), Ok(60));
+        assert_eq!(parse_duration("30s").map_err(|e| e.message()), Ok(30));
+        assert_eq!(parse_duration("10m").map_err(|e| e.message()), Ok(600));
+        assert_eq!(parse_duration(" 2H ").map_err(|e| e.message()), Ok(7200));
+        assert_eq!(parse_duration("1d").map_err(|e| e.message()), Ok(86400));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("9999999d").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors of the layers. Each error contains a message which can be shown to the user and,
+/// if there is one, the error of the operating system which has caused it. Errors which
+/// occur while a message of a peer is handled also contain the peer and the message.
+#[derive(Debug)]
+pub enum StealthyError {
+    /// A key could not be loaded or a message could not be decrypted or authenticated.
+    Crypto(&'static str),
+    /// A packet could not be sent or received, e.g. because it is too large for the link.
+    Transport(&'static str),
+    /// A message does not fit into the protocol, e.g. because it is too big or because it
+    /// belongs to an unknown session.
+    Protocol(&'static str),
+    /// An operation of the operating system has failed, e.g. opening a socket.
+    Io(&'static str, io::Error),
+    /// An error which has occurred while handling a message of a peer. It contains the IP
+    /// address of the peer, the id of the message if it is known and the error.
+    Peer(String, Option<u64>, Box<StealthyError>),
+}
+
+impl StealthyError {
+
+    /// Returns the message of the error without its source.
+    pub fn message(&self) -> &'static str {
+        match self {
+            StealthyError::Crypto(s) | StealthyError::Transport(s) | StealthyError::Protocol(s) | StealthyError::Io(s, _) => s,
+            StealthyError::Peer(_, _, e) => e.message()
+        }
+    }
+
+    /// Adds the peer and, if known, the id of the message to the error.
+    pub fn with_peer(self, ip: &str, id: Option<u64>) -> StealthyError {
+        match self {
+            StealthyError::Peer(_, _, e) => StealthyError::Peer(ip.to_string(), id, e),
+            e => StealthyError::Peer(ip.to_string(), id, Box::new(e))
+        }
+    }
+
+    /// Returns the IP address of the peer whose message has caused the error.
+    pub fn peer(&self) -> Option<&str> {
+        match self {
+            StealthyError::Peer(ip, _, _) => Some(ip),
+            _ => None
+        }
+    }
+
+    /// Returns the id of the message which has caused the error.
+    pub fn message_id(&self) -> Option<u64> {
+        match self {
+            StealthyError::Peer(_, id, _) => *id,
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for StealthyError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StealthyError::Io(s, e) => write!(f, "{} ({})", s, e),
+            StealthyError::Peer(ip, Some(id), e) => write!(f, "{} (peer {}, message {})", e, ip, id),
+            StealthyError::Peer(ip, None, e) => write!(f, "{} (peer {})", e, ip),
+            _ => write!(f, "{}", self.message())
+        }
+    }
+}
+
+impl Error for StealthyError {
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StealthyError::Io(_, e) => Some(e),
+            StealthyError::Peer(_, _, e) => e.source(),
+            _ => None
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::error::Error;
+    use std::io;
+
+    use super::StealthyError;
+
+    #[test]
+    fn test_error() {
+
+        let e = StealthyError::Io("Could not open UDP socket.", io::Error::new(io::ErrorKind::Other, "no socket"));
+        assert_eq!(e.message(), "Could not open UDP socket.");
+        assert_eq!(e.to_string(), "Could not open UDP socket. (no socket)");
+        assert_eq!(e.source().map(|s| s.to_string()), Some("no socket".to_string()));
+
+        let e = StealthyError::Crypto("Unknown cipher.");
+        assert_eq!(e.to_string(), "Unknown cipher.");
+        assert!(e.source().is_none());
+    }
+
+    #[test]
+    fn test_peer() {
+
+        let e = StealthyError::Io("Could not write outbox.", io::Error::other("disk full")).with_peer("1.2.3.4", Some(7));
+        assert_eq!(e.message(), "Could not write outbox.");
+        assert_eq!(e.peer(), Some("1.2.3.4"));
+        assert_eq!(e.message_id(), Some(7));
+        assert_eq!(e.to_string(), "Could not write outbox. (disk full) (peer 1.2.3.4, message 7)");
+        assert_eq!(e.source().map(|s| s.to_string()), Some("disk full".to_string()));
+
+        // The peer is replaced instead of being nested.
+        let e = e.with_peer("5.6.7.8", None);
+        assert_eq!(e.to_string(), "Could not write outbox. (disk full) (peer 5.6.7.8)");
+        assert_eq!(StealthyError::Crypto("Unknown cipher.").peer(), None);
+    }
+}
@@ -0,0 +1,479 @@
+use std::io::{BufRead, BufReader, Write};
+#[cfg(windows)]
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use crypto::sha2::Sha256;
+use crate::rsa::{RSA, Padding};
+use crate::rsatools::key_as_pem;
+use crate::secret::SecretBytes;
+use crate::tools::{read_file, to_hex};
+use crate::error::StealthyError;
+
+/// Prefix of a key argument which refers to a key in the keyring of GnuPG instead of a
+/// file, e.g. `gpg:0x1234ABCD` or `gpg:alice@example.org`.
+pub const PREFIX: &str = "gpg:";
+
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const ALGO_RSA: u8 = 1;
+const ALGO_RSA_ENCRYPT: u8 = 2;
+/// Identifier of SHA-256 used by gpg-agent.
+const HASH_SHA256: u8 = 8;
+/// Maximum number of bytes sent in one data line of the Assuan protocol.
+const DATA_CHUNK: usize = 256;
+
+const AGENT_FAILED: &str = "Operation of gpg-agent failed.";
+
+/// Returns the id of the key if the argument refers to a key of GnuPG.
+pub fn key_id(arg: &str) -> Option<&str> {
+    if arg.starts_with(PREFIX) {
+        Some(&arg[PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+/// Reads a public key in PEM format either from a file or, if the argument starts with
+/// `gpg:`, from the keyring of GnuPG.
+pub fn read_public_key(arg: &str) -> Result<String, StealthyError> {
+    match key_id(arg) {
+        Some(id) => {
+            let (fpr, _) = encryption_key(id)?;
+            let (n, e) = find_rsa_key(&gpg(&["--export", &fpr])?, &fpr)?;
+            Ok(key_as_pem(&der(&n, &e)))
+        },
+        None => read_file(arg)
+    }
+}
+
+/// A private key which is kept by gpg-agent. The key never leaves the agent. Messages
+/// are decrypted and signed by the agent.
+pub struct AgentKey {
+    keygrip: String,
+    /// Size of the modulus in bytes.
+    siz: usize,
+}
+
+impl AgentKey {
+
+    /// Looks up the RSA key for encryption of the given key id. The same key is used for
+    /// signing so that the peers can verify the signatures with the public key they use for
+    /// encryption. gpg-agent is asked to unlock the key so that it can ask for the
+    /// passphrase before the user interface is started.
+    pub fn new(id: &str) -> Result<AgentKey, StealthyError> {
+
+        let (fpr, keygrip) = encryption_key(id)?;
+        let (n, _) = find_rsa_key(&gpg(&["--export", &fpr])?, &fpr)?;
+        let k = AgentKey {
+            keygrip,
+            siz: n.len()
+        };
+        k.sign(b"stealthy").map_err(|_| StealthyError::Crypto("Could not unlock the key via gpg-agent."))?;
+        Ok(k)
+    }
+
+    /// Decrypts a key which has been encrypted via RSA-OAEP.
+    pub fn decrypt(&self, cipher: &[u8], padding: Padding) -> Result<Vec<u8>, StealthyError> {
+
+        let mut sexp = b"(7:enc-val(3:rsa(1:a".to_vec();
+        sexp.extend_from_slice(format!("{}:", cipher.len()).as_bytes());
+        sexp.extend_from_slice(cipher);
+        sexp.extend_from_slice(b")))");
+
+        let mut c = Agent::connect()?;
+        c.command(&format!("SETKEY {}", self.keygrip), None)?;
+        let r = SecretBytes::new(c.command("PKDECRYPT", Some(&sexp))?);
+        let padded = SecretBytes::new(self.left_pad(sexp_value(r.as_slice(), b"(5:value")?)?);
+        RSA::remove_oaep(padded.as_slice(), self.siz, padding)
+    }
+
+    /// Signs the SHA-256 hash of the data via PKCS#1 v1.5 like `RSA::sign`.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+        let mut h = Sha256::new();
+        h.input(data);
+        let mut hash = vec![0; h.output_bytes()];
+        h.result(&mut hash);
+
+        let mut c = Agent::connect()?;
+        c.command(&format!("SIGKEY {}", self.keygrip), None)?;
+        c.command(&format!("SETHASH {} {}", HASH_SHA256, to_hex(&hash)), None)?;
+        let r = c.command("PKSIGN", None)?;
+        self.left_pad(sexp_value(&r, b"(1:s")?)
+    }
+
+    /// Values returned by the agent have no leading zeros.
+    fn left_pad(&self, v: &[u8]) -> Result<Vec<u8>, StealthyError> {
+        if v.len() > self.siz {
+            return Err(StealthyError::Crypto(AGENT_FAILED));
+        }
+        let mut r = vec![0; self.siz - v.len()];
+        r.extend_from_slice(v);
+        Ok(r)
+    }
+}
+
+#[cfg(unix)]
+type AgentStream = UnixStream;
+#[cfg(windows)]
+type AgentStream = TcpStream;
+
+/// Connection to gpg-agent via the Assuan protocol.
+struct Agent {
+    reader: BufReader<AgentStream>,
+    writer: AgentStream,
+}
+
+impl Agent {
+
+    fn connect() -> Result<Agent, StealthyError> {
+
+        let out = Command::new("gpgconf").arg("--list-dirs").arg("agent-socket")
+            .output()
+            .map_err(|e| StealthyError::Io("Could not run gpgconf.", e))?;
+        let path = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        // Starts the agent if it is not running.
+        let _ = Command::new("gpgconf").arg("--launch").arg("gpg-agent").status();
+
+        let writer = Agent::open(&path).map_err(|e| StealthyError::Io("Could not connect to gpg-agent.", e))?;
+        let mut a = Agent {
+            reader: BufReader::new(writer.try_clone().map_err(|e| StealthyError::Io(AGENT_FAILED, e))?),
+            writer
+        };
+        a.response(None)?;
+        // Tells pinentry where to ask for the passphrase.
+        for (opt, var) in &[("ttyname", "GPG_TTY"), ("ttytype", "TERM"), ("display", "DISPLAY")] {
+            if let Ok(v) = std::env::var(var) {
+                a.command(&format!("OPTION {}={}", opt, v), None)?;
+            }
+        }
+        Ok(a)
+    }
+
+    #[cfg(unix)]
+    fn open(path: &str) -> std::io::Result<AgentStream> {
+        UnixStream::connect(path)
+    }
+
+    /// On Windows the socket of the agent is emulated by a file which contains the TCP
+    /// port on localhost in the first line followed by a nonce of 16 bytes which has to
+    /// be sent after connecting.
+    #[cfg(windows)]
+    fn open(path: &str) -> std::io::Result<AgentStream> {
+        use std::io::{Error, ErrorKind};
+
+        let data = std::fs::read(path)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "invalid socket file of gpg-agent");
+        let pos = data.iter().position(|&b| b == b'\n').ok_or_else(invalid)?;
+        let port = std::str::from_utf8(&data[..pos]).ok()
+            .and_then(|s| s.trim().parse::<u16>().ok())
+            .ok_or_else(invalid)?;
+        let nonce = &data[pos + 1..];
+        if nonce.len() != 16 {
+            return Err(invalid());
+        }
+        let mut s = TcpStream::connect(("127.0.0.1", port))?;
+        s.write_all(nonce)?;
+        Ok(s)
+    }
+
+    /// Sends a command and returns the data of the response. If the agent inquires data,
+    /// `inquiry` is sent.
+    fn command(&mut self, cmd: &str, inquiry: Option<&[u8]>) -> Result<Vec<u8>, StealthyError> {
+        self.writer.write_all(format!("{}\n", cmd).as_bytes()).map_err(|e| StealthyError::Io(AGENT_FAILED, e))?;
+        self.response(inquiry)
+    }
+
+    fn response(&mut self, inquiry: Option<&[u8]>) -> Result<Vec<u8>, StealthyError> {
+
+        let mut data = vec![];
+        loop {
+            let mut line = vec![];
+            if self.reader.read_until(b'\n', &mut line).map_err(|e| StealthyError::Io(AGENT_FAILED, e))? == 0 {
+                return Err(StealthyError::Crypto(AGENT_FAILED));
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if line == b"OK" || line.starts_with(b"OK ") {
+                return Ok(data);
+            } else if line.starts_with(b"ERR") {
+                return Err(StealthyError::Crypto(AGENT_FAILED));
+            } else if line.starts_with(b"D ") {
+                data.extend(unescape(&line[2..]));
+            } else if line.starts_with(b"INQUIRE CIPHERTEXT") && inquiry.is_some() {
+                for chunk in inquiry.unwrap().chunks(DATA_CHUNK) {
+                    let mut l = b"D ".to_vec();
+                    l.extend(escape(chunk));
+                    l.push(b'\n');
+                    self.writer.write_all(&l).map_err(|e| StealthyError::Io(AGENT_FAILED, e))?;
+                }
+                self.writer.write_all(b"END\n").map_err(|e| StealthyError::Io(AGENT_FAILED, e))?;
+            } else if line.starts_with(b"INQUIRE") {
+                // E.g. PINENTRY_LAUNCHED which only informs about pinentry.
+                self.writer.write_all(b"END\n").map_err(|e| StealthyError::Io(AGENT_FAILED, e))?;
+            }
+            // Status lines and comments are ignored.
+        }
+    }
+}
+
+fn escape(v: &[u8]) -> Vec<u8> {
+    v.iter().flat_map(|&b| match b {
+        b'%' | b'\r' | b'\n' => format!("%{:02X}", b).into_bytes(),
+        _ => vec![b]
+    }).collect()
+}
+
+fn unescape(v: &[u8]) -> Vec<u8> {
+    let mut r = vec![];
+    let mut i = 0;
+    while i < v.len() {
+        let hex = v.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(b) if v[i] == b'%' => {
+                r.push(b);
+                i += 3;
+                continue;
+            },
+            _ => { }
+        }
+        r.push(v[i]);
+        i += 1;
+    }
+    r
+}
+
+/// Returns the value which follows the given tag in a canonical S-expression, e.g. the
+/// signature in `(7:sig-val(3:rsa(1:s256:...)))`.
+fn sexp_value<'a>(sexp: &'a [u8], tag: &[u8]) -> Result<&'a [u8], StealthyError> {
+
+    let pos = sexp.windows(tag.len()).position(|w| w == tag).ok_or(StealthyError::Crypto(AGENT_FAILED))? + tag.len();
+    let colon = sexp[pos..].iter().position(|&b| b == b':').ok_or(StealthyError::Crypto(AGENT_FAILED))? + pos;
+    let len = std::str::from_utf8(&sexp[pos..colon]).ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .ok_or(StealthyError::Crypto(AGENT_FAILED))?;
+    sexp.get(colon + 1..colon + 1 + len).ok_or(StealthyError::Crypto(AGENT_FAILED))
+}
+
+fn gpg(args: &[&str]) -> Result<Vec<u8>, StealthyError> {
+
+    let out = Command::new("gpg").arg("--batch").args(args)
+        .output()
+        .map_err(|e| StealthyError::Io("Could not run gpg.", e))?;
+    if !out.status.success() {
+        return Err(StealthyError::Crypto("gpg failed. Is the key in your keyring?"));
+    }
+    Ok(out.stdout)
+}
+
+/// Returns the fingerprint and keygrip of the RSA key for encryption of the given key id.
+fn encryption_key(id: &str) -> Result<(String, String), StealthyError> {
+    let out = gpg(&["--with-colons", "--with-keygrip", "--list-keys", id])?;
+    parse_colons(&String::from_utf8_lossy(&out)).ok_or(StealthyError::Crypto("The GnuPG key has no RSA key for encryption."))
+}
+
+/// Parses the output of `gpg --with-colons --with-keygrip --list-keys` and returns the
+/// fingerprint and keygrip of the last valid RSA key which can be used for encryption.
+fn parse_colons(s: &str) -> Option<(String, String)> {
+
+    let mut found = None;
+    let mut usable = false;
+    let mut fpr = String::new();
+    for line in s.lines() {
+        let f = line.split(':').collect::<Vec<_>>();
+        match f[0] {
+            "pub" | "sub" if f.len() > 11 => {
+                // Revoked, expired, invalid or disabled keys are skipped.
+                usable = !["r", "e", "i", "d"].contains(&f[1])
+                    && (f[3] == ALGO_RSA.to_string() || f[3] == ALGO_RSA_ENCRYPT.to_string())
+                    && f[11].contains('e');
+                fpr.clear();
+            },
+            "pub" | "sub" => usable = false,
+            "fpr" if f.len() > 9 => fpr = f[9].to_string(),
+            "grp" if f.len() > 9 && usable => found = Some((fpr.clone(), f[9].to_string())),
+            _ => { }
+        }
+    }
+    found
+}
+
+/// Searches the exported public keys in binary OpenPGP format for the RSA key with the
+/// given fingerprint and returns its modulus and exponent.
+fn find_rsa_key(buf: &[u8], fpr: &str) -> Result<(Vec<u8>, Vec<u8>), StealthyError> {
+
+    let mut v = buf;
+    while !v.is_empty() {
+        let (tag, body, rest) = packet(v).ok_or(StealthyError::Crypto("Invalid OpenPGP packet."))?;
+        if (tag == TAG_PUBLIC_KEY || tag == TAG_PUBLIC_SUBKEY) && body.first() == Some(&4) {
+            if fingerprint(body).eq_ignore_ascii_case(fpr) {
+                return rsa_key(body).ok_or(StealthyError::Crypto("The GnuPG key is not a RSA key."));
+            }
+        }
+        v = rest;
+    }
+    Err(StealthyError::Crypto("Could not find the GnuPG key."))
+}
+
+/// Returns the tag, the body and the remaining data of the first packet.
+fn packet(v: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+
+    let h = *v.first()?;
+    if h & 0x80 == 0 {
+        return None;
+    }
+    let (tag, hlen, len) = if h & 0x40 != 0 {
+        // new format
+        let o = *v.get(1)? as usize;
+        match o {
+            0..=191 => (h & 0x3f, 2, o),
+            192..=223 => (h & 0x3f, 3, ((o - 192) << 8) + *v.get(2)? as usize + 192),
+            255 => (h & 0x3f, 6, be(v.get(2..6)?)),
+            // Partial lengths are not used for keys.
+            _ => return None
+        }
+    } else {
+        // old format
+        match h & 3 {
+            0 => ((h >> 2) & 0xf, 2, *v.get(1)? as usize),
+            1 => ((h >> 2) & 0xf, 3, be(v.get(1..3)?)),
+            2 => ((h >> 2) & 0xf, 5, be(v.get(1..5)?)),
+            _ => return None
+        }
+    };
+    let body = v.get(hlen..hlen + len)?;
+    Some((tag, body, &v[hlen + len..]))
+}
+
+fn be(v: &[u8]) -> usize {
+    v.iter().fold(0, |acc, &b| (acc << 8) | b as usize)
+}
+
+/// Fingerprint of a v4 key.
+fn fingerprint(body: &[u8]) -> String {
+    let mut h = Sha1::new();
+    h.input(&[0x99, (body.len() >> 8) as u8, body.len() as u8]);
+    h.input(body);
+    h.result_str()
+}
+
+/// Returns modulus and exponent of a v4 RSA key. The body contains the version, the
+/// creation time, the algorithm and the MPIs.
+fn rsa_key(body: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+
+    let algo = *body.get(5)?;
+    if algo != ALGO_RSA && algo != ALGO_RSA_ENCRYPT {
+        return None;
+    }
+    let (n, rest) = mpi(&body[6..])?;
+    let (e, _) = mpi(rest)?;
+    Some((n.to_vec(), e.to_vec()))
+}
+
+fn mpi(v: &[u8]) -> Option<(&[u8], &[u8])> {
+    let bits = be(v.get(0..2)?);
+    let len = (bits + 7) / 8;
+    Some((v.get(2..2 + len)?, &v[2 + len..]))
+}
+
+/// Encodes modulus and exponent as SubjectPublicKeyInfo in DER.
+fn der(n: &[u8], e: &[u8]) -> Vec<u8> {
+
+    let mut key = asn1_integer(n);
+    key.extend(asn1_integer(e));
+    let mut bits = vec![0];
+    bits.extend(asn1(0x30, &key));
+
+    // rsaEncryption (1.2.840.113549.1.1.1) without parameters
+    let mut spki = vec![0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00];
+    spki.extend(asn1(0x03, &bits));
+    asn1(0x30, &spki)
+}
+
+fn asn1_integer(v: &[u8]) -> Vec<u8> {
+    let v = &v[v.iter().position(|&b| b != 0).unwrap_or(v.len())..];
+    let mut i = vec![];
+    if v.first().map_or(true, |&b| b & 0x80 != 0) {
+        i.push(0);
+    }
+    i.extend_from_slice(v);
+    asn1(0x02, &i)
+}
+
+fn asn1(tag: u8, v: &[u8]) -> Vec<u8> {
+    let mut r = vec![tag];
+    let len = v.len();
+    match len {
+        0..=127 => r.push(len as u8),
+        128..=255 => r.extend_from_slice(&[0x81, len as u8]),
+        _ => r.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+    }
+    r.extend_from_slice(v);
+    r
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{find_rsa_key, der, parse_colons, sexp_value, escape, unescape};
+    use crate::rsatools::key_as_pem;
+    use crate::tools::{read_file, read_bin_file};
+
+    const COLONS: &str = "\
+pub:u:2048:1:CF3FFF64F34C342F:1792045855:::u:::scESC::::::23::0:
+fpr:::::::::A0A50A0AD5B41C71FF010748CF3FFF64F34C342F:
+grp:::::::::7B63182A07FFE9ABE385E9B1E8E22F5C7AE774C6:
+uid:u::::1792045855::BA77EE05FD8EAD2B6A88FC3A17493330B5F7519E::Test <test@example.org>::::::::::0:
+sub:u:2048:1:E3661B459D9D116F:1792045856::::::e::::::23:
+fpr:::::::::F3E1C114E8E25C0B34A56374E3661B459D9D116F:
+grp:::::::::CC39F46103452E363F15A23FFF2F0803073C9A40:
+sub:e:2048:1:0000000000000000:1592045856::::::e::::::23:
+fpr:::::::::0000000000000000000000000000000000000000:
+grp:::::::::1111111111111111111111111111111111111111:
+";
+
+    #[test]
+    fn test_parse_colons() {
+
+        // The expired subkey is skipped.
+        assert_eq!(parse_colons(COLONS), Some((
+            "F3E1C114E8E25C0B34A56374E3661B459D9D116F".to_string(),
+            "CC39F46103452E363F15A23FFF2F0803073C9A40".to_string()
+        )));
+        // The primary key cannot be used for encryption.
+        assert_eq!(parse_colons(&COLONS[..COLONS.find("sub").unwrap()]), None);
+    }
+
+    #[test]
+    fn test_export() {
+
+        let buf = read_bin_file("tests/keys/gpg_pub.gpg").unwrap();
+        let (n, e) = find_rsa_key(&buf, "f3e1c114e8e25c0b34a56374e3661b459d9d116f").unwrap();
+        assert_eq!(n.len(), 256);
+        assert_eq!(key_as_pem(&der(&n, &e)), read_file("tests/keys/gpg_pub.pem").unwrap());
+        assert!(find_rsa_key(&buf, "0000000000000000000000000000000000000000").is_err());
+        assert!(find_rsa_key(&buf[..100], "f3e1c114e8e25c0b34a56374e3661b459d9d116f").is_err());
+    }
+
+    #[test]
+    fn test_assuan() {
+
+        let v = b"a%b\nc".to_vec();
+        assert_eq!(escape(&v), b"a%25b%0Ac".to_vec());
+        assert_eq!(unescape(&escape(&v)), v);
+        assert_eq!(sexp_value(b"(7:sig-val(3:rsa(1:s3:abc)))", b"(1:s").map_err(|e| e.message()), Ok(&b"abc"[..]));
+        assert!(sexp_value(b"(7:sig-val(3:rsa(1:s9:abc)))", b"(1:s").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::secret::SecretBytes;
+use crate::wire::{push_value, push_slice, pop_value};
+use crate::error::StealthyError;
+
+const VERSION: u8 = 1;
+const INVITE: u8 = 1;
+const REMOVE: u8 = 2;
+/// Length of the key of the group. Like the default key it can be used with all symmetric
+/// ciphers.
+const GROUP_KEY_LEN: usize = 16;
+
+const INVALID_CHANGE: &str = "Invalid group change.";
+
+/// Change of the members of the group.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The peer with the given ip joins the group.
+    Invite(String),
+    /// The peer with the given ip has to leave the group.
+    Remove(String),
+}
+
+/// A change of the members which is sent to all members after the change.
+///
+/// Each change comes with a new random key which replaces the key shared by the group. It
+/// is encrypted with the current key of each remaining member. Hence, a removed member
+/// cannot read the messages which are sent after its removal. The change is only accepted
+/// if it has been signed with a trusted key (see `Signing`).
+///
+/// Format: VERSION (1) | type (1) | epoch (4) | ip | number of members (1) | members | key
+///
+/// Each ip is prepended by its length (1 byte).
+#[derive(Clone, Debug)]
+pub struct Membership {
+    /// Number of the change. Changes which are not newer than the last applied change are
+    /// ignored.
+    pub epoch: u32,
+    pub change: Change,
+    /// The members after the change as seen by the sender. The sender is not contained.
+    pub members: Vec<String>,
+    pub key: SecretBytes,
+}
+
+impl Membership {
+
+    pub fn encode(&self) -> Vec<u8> {
+
+        let (typ, ip) = match self.change {
+            Change::Invite(ref ip) => (INVITE, ip),
+            Change::Remove(ref ip) => (REMOVE, ip),
+        };
+        let mut v = vec![VERSION, typ];
+        push_value(&mut v, self.epoch as u64, 4);
+        push_ip(&mut v, ip);
+        v.push(self.members.len() as u8);
+        for m in &self.members {
+            push_ip(&mut v, m);
+        }
+        push_slice(&mut v, self.key.as_slice());
+        v
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Membership, StealthyError> {
+
+        let mut v = buf.to_vec();
+        if pop_value(&mut v, 1).map_err(StealthyError::Protocol)? != VERSION as u64 {
+            return Err(StealthyError::Protocol(INVALID_CHANGE));
+        }
+        let typ = pop_value(&mut v, 1).map_err(StealthyError::Protocol)? as u8;
+        let epoch = pop_value(&mut v, 4).map_err(StealthyError::Protocol)? as u32;
+        let ip = pop_ip(&mut v)?;
+        let change = match typ {
+            INVITE => Change::Invite(ip),
+            REMOVE => Change::Remove(ip),
+            _ => return Err(StealthyError::Protocol(INVALID_CHANGE))
+        };
+        let n = pop_value(&mut v, 1).map_err(StealthyError::Protocol)?;
+        let mut members = vec![];
+        for _ in 0..n {
+            members.push(pop_ip(&mut v)?);
+        }
+        if v.len() != GROUP_KEY_LEN {
+            return Err(StealthyError::Protocol(INVALID_CHANGE));
+        }
+        Ok(Membership {
+            epoch,
+            change,
+            members,
+            key: SecretBytes::new(v),
+        })
+    }
+}
+
+/// The members of the group chat.
+///
+/// Initially all peers given on the command line are members. Messages are only sent to
+/// the members. As packets of other ips are dropped, only peers given on the command line
+/// or added with `Layers::add_peer` can be invited.
+///
+/// If two members change the group at the same time, both changes have the same epoch. Of
+/// these the change with the larger key wins, so that all members which receive both
+/// changes end up with the same key.
+pub struct Group {
+    /// Peers which can be members.
+    peers: Vec<String>,
+    members: Vec<String>,
+    /// Epoch of the last applied change.
+    epoch: u32,
+    /// The last applied change and the ip of its sender, None for our own change.
+    last: Option<(Membership, Option<String>)>,
+}
+
+impl Group {
+
+    pub fn new(peers: Vec<String>) -> Group {
+        Group {
+            members: peers.clone(),
+            peers,
+            epoch: 0,
+            last: None,
+        }
+    }
+
+    pub fn members(&self) -> Vec<String> {
+        self.members.clone()
+    }
+
+    /// Returns true if the ip has been given on the command line or has been added.
+    pub fn is_peer(&self, ip: &str) -> bool {
+        self.peers.iter().any(|p| p == ip)
+    }
+
+    /// Adds a peer which becomes a member as well. Returns false if it is already a peer.
+    pub fn add_peer(&mut self, ip: &str) -> bool {
+
+        if self.is_peer(ip) {
+            return false;
+        }
+        self.peers.push(ip.to_string());
+        self.members.push(ip.to_string());
+        true
+    }
+
+    /// Removes a peer and its membership. Returns false if it is not a peer.
+    pub fn remove_peer(&mut self, ip: &str) -> bool {
+
+        if !self.is_peer(ip) {
+            return false;
+        }
+        self.peers.retain(|p| p != ip);
+        self.members.retain(|m| m != ip);
+        true
+    }
+
+    /// Returns the change together with a new random key. The change is not applied until
+    /// `apply` is called.
+    pub fn change(&self, c: Change) -> Result<Membership, StealthyError> {
+
+        let members = match c {
+            Change::Invite(ref ip) if !self.peers.contains(ip) => return Err(StealthyError::Protocol("Only peers given with -d can be invited.")),
+            Change::Invite(ref ip) if self.members.contains(ip) => return Err(StealthyError::Protocol("The peer is already a member of the group.")),
+            Change::Invite(ref ip) => self.peers.iter().filter(|p| self.members.contains(p) || *p == ip).cloned().collect(),
+            Change::Remove(ref ip) if !self.members.contains(ip) => return Err(StealthyError::Protocol("The peer is not a member of the group.")),
+            Change::Remove(ref ip) => self.members.iter().filter(|m| *m != ip).cloned().collect(),
+        };
+        Ok(Membership {
+            epoch: self.epoch + 1,
+            change: c,
+            members,
+            key: SecretBytes::new((0..GROUP_KEY_LEN).map(|_| rand::random::<u8>()).collect()),
+        })
+    }
+
+    /// Applies a change received from the given ip or our own change if no ip is given.
+    /// Returns false if the change is outdated.
+    pub fn apply(&mut self, m: &Membership, from: Option<&str>) -> bool {
+
+        let newer = match self.last {
+            Some((ref l, _)) if m.epoch == self.epoch => m.key.as_slice() > l.key.as_slice(),
+            _ => m.epoch > self.epoch
+        };
+        if !newer {
+            return false;
+        }
+        self.epoch = m.epoch;
+        // The sender does not know the ip under which we know it.
+        self.members = self.peers.iter()
+            .filter(|p| m.members.contains(p) || from == Some(p.as_str()))
+            .cloned()
+            .collect();
+        self.last = Some((m.clone(), from.map(|ip| ip.to_string())));
+        true
+    }
+
+    /// Returns the last applied change so that it can be stored and applied again after a
+    /// restart (see `restore`). Returns None if the group has not been changed.
+    ///
+    /// Format: length of the ip of the sender (1) | ip, empty for our own change | change
+    pub fn encode_state(&self) -> Option<Vec<u8>> {
+        let (m, from) = self.last.as_ref()?;
+        let mut v = vec![];
+        push_ip(&mut v, from.as_deref().unwrap_or(""));
+        push_slice(&mut v, &m.encode());
+        Some(v)
+    }
+
+    /// Applies a change which has been stored via `encode_state` and returns it.
+    pub fn restore(&mut self, buf: &[u8]) -> Result<Membership, StealthyError> {
+
+        let mut v = buf.to_vec();
+        let from = pop_ip(&mut v)?;
+        let m = Membership::decode(&v)?;
+        self.apply(&m, Some(from.as_str()).filter(|ip| !ip.is_empty()));
+        Ok(m)
+    }
+}
+
+fn push_ip(v: &mut Vec<u8>, ip: &str) {
+    v.push(ip.len() as u8);
+    push_slice(v, ip.as_bytes());
+}
+
+fn pop_ip(v: &mut Vec<u8>) -> Result<String, StealthyError> {
+    let n = pop_value(v, 1).map_err(StealthyError::Protocol)? as usize;
+    if v.len() < n {
+        return Err(StealthyError::Protocol(INVALID_CHANGE));
+    }
+    String::from_utf8(v.drain(..n).collect()).map_err(|_| StealthyError::Protocol(INVALID_CHANGE))
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Change, Group, Membership};
+    use crate::secret::SecretBytes;
+
+    fn ips(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_encode() {
+
+        let g = Group::new(ips(&["1.2.3.4", "::1"]));
+        let m = g.change(Change::Remove("::1".to_string())).unwrap();
+        let buf = m.encode();
+        let d = Membership::decode(&buf).unwrap();
+        assert_eq!((d.epoch, d.change, d.members), (1, Change::Remove("::1".to_string()), ips(&["1.2.3.4"])));
+        assert_eq!(d.key.as_slice(), m.key.as_slice());
+
+        for i in 0..buf.len() {
+            assert!(Membership::decode(&buf[..i]).is_err());
+        }
+        let mut v = buf.clone();
+        v[1] = 9;
+        assert!(Membership::decode(&v).is_err());
+    }
+
+    #[test]
+    fn test_group() {
+
+        let mut g = Group::new(ips(&["a", "b", "c"]));
+        assert!(g.change(Change::Invite("b".to_string())).is_err());
+        assert!(g.change(Change::Invite("x".to_string())).is_err());
+
+        let m = g.change(Change::Remove("b".to_string())).unwrap();
+        assert_eq!(m.members, ips(&["a", "c"]));
+        assert!(g.apply(&m, None));
+        assert_eq!(g.members(), ips(&["a", "c"]));
+        assert!(g.change(Change::Remove("b".to_string())).is_err());
+        // Each change has a new key.
+        let n = g.change(Change::Invite("b".to_string())).unwrap();
+        assert_ne!(n.key.as_slice(), m.key.as_slice());
+        assert_eq!(n.members, ips(&["a", "b", "c"]));
+
+        // Outdated changes are ignored.
+        assert!(!g.apply(&m, None));
+
+        // A received change lists the members as seen by the sender "a". We are "c".
+        let mut h = Group::new(ips(&["a", "b"]));
+        let r = Membership { epoch: 2, change: Change::Remove("b".to_string()), members: ips(&["c"]), key: m.key.clone() };
+        assert!(h.apply(&r, Some("a")));
+        assert_eq!(h.members(), ips(&["a"]));
+    }
+
+    #[test]
+    fn test_concurrent_changes() {
+
+        let change = |c: Change, key: u8| Membership { epoch: 1, change: c, members: ips(&["a"]), key: SecretBytes::new(vec![key; 16]) };
+        let small = change(Change::Remove("b".to_string()), 1);
+        let large = change(Change::Remove("c".to_string()), 2);
+
+        // The change with the larger key wins regardless of the order of arrival.
+        let mut g = Group::new(ips(&["a", "b", "c"]));
+        assert!(g.apply(&small, None));
+        assert!(g.apply(&large, Some("b")));
+        assert!(!g.apply(&small, None));
+        let mut h = Group::new(ips(&["a", "b", "c"]));
+        assert!(h.apply(&large, Some("b")));
+        assert!(!h.apply(&small, None));
+        assert_eq!(g.members(), h.members());
+    }
+
+    #[test]
+    fn test_state() {
+
+        let mut g = Group::new(ips(&["a", "b", "c"]));
+        assert_eq!(g.encode_state(), None);
+        let r = Membership { epoch: 3, change: Change::Remove("b".to_string()), members: ips(&["x"]), key: SecretBytes::new(vec![7; 16]) };
+        assert!(g.apply(&r, Some("c")));
+
+        // After a restart the last change is applied again.
+        let mut h = Group::new(ips(&["a", "b", "c"]));
+        let m = h.restore(&g.encode_state().unwrap()).unwrap();
+        assert_eq!(m.key.as_slice(), r.key.as_slice());
+        assert_eq!(h.members(), ips(&["c"]));
+        assert!(!h.apply(&r, Some("c")));
+        assert!(h.restore(&[1, 2]).is_err());
+
+        // Our own change.
+        let o = h.change(Change::Invite("a".to_string())).unwrap();
+        assert!(h.apply(&o, None));
+        let mut i = Group::new(ips(&["a", "b", "c"]));
+        i.restore(&h.encode_state().unwrap()).unwrap();
+        assert_eq!(i.members(), h.members());
+    }
+
+    #[test]
+    fn test_peers() {
+
+        let mut g = Group::new(ips(&["a", "b"]));
+        assert!(g.add_peer("c"));
+        assert!(!g.add_peer("c"));
+        assert_eq!(g.members(), ips(&["a", "b", "c"]));
+        assert!(g.remove_peer("a"));
+        assert!(!g.remove_peer("a"));
+        assert_eq!(g.members(), ips(&["b", "c"]));
+        assert!(!g.is_peer("a"));
+        assert!(g.change(Change::Invite("a".to_string())).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::error::StealthyError;
+use crate::layer::{Layer, Layers};
+use crate::message::{IncomingMessage, Message};
+
+type Handler<T> = Option<Arc<dyn Fn(T) + Send + Sync>>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Handlers for the incoming messages of the layers.
+#[derive(Default)]
+struct Callbacks {
+    message: Handler<Message>,
+    ack: Handler<u64>,
+    error: Handler<StealthyError>,
+    event: Handler<IncomingMessage>,
+}
+
+/// Alternative to the receiver of the incoming messages for applications which prefer to
+/// register handlers.
+///
+/// The handlers are registered with `on_message`, `on_ack`, `on_error` and `on_event` and
+/// are called as soon as `start` has been called. A thread receives the incoming messages
+/// and passes each of them to a pool of threads which calls the handler. With more than one
+/// thread the handlers may be called concurrently and not in the order in which the
+/// messages have been received.
+pub struct Handlers {
+    layer: Layer,
+    callbacks: Callbacks,
+}
+
+impl Layer {
+
+    /// Calls the handler for each new message of a peer (see `Handlers`).
+    pub fn on_message<F: Fn(Message) + Send + Sync + 'static>(self, f: F) -> Handlers {
+        Handlers::new(self).on_message(f)
+    }
+
+    /// Calls the handler with the id of each message which has been acknowledged.
+    pub fn on_ack<F: Fn(u64) + Send + Sync + 'static>(self, f: F) -> Handlers {
+        Handlers::new(self).on_ack(f)
+    }
+
+    /// Calls the handler for each error of the layers.
+    pub fn on_error<F: Fn(StealthyError) + Send + Sync + 'static>(self, f: F) -> Handlers {
+        Handlers::new(self).on_error(f)
+    }
+}
+
+impl Handlers {
+
+    fn new(layer: Layer) -> Handlers {
+        Handlers { layer, callbacks: Callbacks::default() }
+    }
+
+    pub fn on_message<F: Fn(Message) + Send + Sync + 'static>(mut self, f: F) -> Handlers {
+        self.callbacks.message = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_ack<F: Fn(u64) + Send + Sync + 'static>(mut self, f: F) -> Handlers {
+        self.callbacks.ack = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_error<F: Fn(StealthyError) + Send + Sync + 'static>(mut self, f: F) -> Handlers {
+        self.callbacks.error = Some(Arc::new(f));
+        self
+    }
+
+    /// Calls the handler for all other incoming messages, e.g. `Delivered` or
+    /// `SendFailed`. Without it these messages are dropped.
+    pub fn on_event<F: Fn(IncomingMessage) + Send + Sync + 'static>(mut self, f: F) -> Handlers {
+        self.callbacks.event = Some(Arc::new(f));
+        self
+    }
+
+    /// Starts to call the handlers on the given number of threads and returns the layers
+    /// to send messages. The threads stop when the layers have been shut down.
+    pub fn start(self, threads: usize) -> Layers {
+        dispatch(self.layer.rx, self.callbacks, threads);
+        self.layer.layers
+    }
+}
+
+/// Receives the incoming messages in a thread and passes the call of their handler to the
+/// pool.
+fn dispatch(rx: Receiver<IncomingMessage>, callbacks: Callbacks, threads: usize) {
+
+    let (tx, jobs) = channel::<Job>();
+    let jobs = Arc::new(Mutex::new(jobs));
+    for _ in 0..threads.max(1) {
+        let jobs = jobs.clone();
+        thread::spawn(move || loop {
+            // The lock is released before the job is run.
+            let job = jobs.lock().unwrap().recv();
+            match job {
+                Ok(job) => job(),
+                Err(_) => break
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        for m in rx {
+            if let Some(job) = callbacks.job(m) {
+                if tx.send(job).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+impl Callbacks {
+
+    /// Returns the call of the handler for the message or None if there is no handler.
+    fn job(&self, m: IncomingMessage) -> Option<Job> {
+        match m {
+            IncomingMessage::New(msg) => call(&self.message, msg),
+            IncomingMessage::Ack(id) => call(&self.ack, id),
+            IncomingMessage::Error(e) => call(&self.error, e),
+            m => call(&self.event, m)
+        }
+    }
+}
+
+fn call<T: Send + 'static>(handler: &Handler<T>, v: T) -> Option<Job> {
+    handler.clone().map(|f| Box::new(move || f(v)) as Job)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+
+    use super::{dispatch, Callbacks};
+    use crate::error::StealthyError;
+    use crate::message::IncomingMessage;
+
+    #[test]
+    fn test_dispatch() {
+
+        let (done_tx, done) = channel();
+        let acks = Arc::new(Mutex::new(vec![]));
+        let (a, d) = (acks.clone(), Mutex::new(done_tx.clone()));
+        let d2 = Mutex::new(done_tx);
+        let callbacks = Callbacks {
+            ack: Some(Arc::new(move |id| {
+                a.lock().unwrap().push(id);
+                d.lock().unwrap().send(()).unwrap();
+            })),
+            error: Some(Arc::new(move |e: StealthyError| {
+                assert_eq!(e.message(), "failed");
+                d2.lock().unwrap().send(()).unwrap();
+            })),
+            ..Callbacks::default()
+        };
+
+        let (tx, rx) = channel();
+        dispatch(rx, callbacks, 2);
+        tx.send(IncomingMessage::Ack(1)).unwrap();
+        // Messages without a handler are dropped.
+        tx.send(IncomingMessage::Delivered(2)).unwrap();
+        tx.send(IncomingMessage::Error(StealthyError::Transport("failed"))).unwrap();
+        tx.send(IncomingMessage::Ack(3)).unwrap();
+        for _ in 0..3 {
+            done.recv().unwrap();
+        }
+
+        let mut acks = acks.lock().unwrap().clone();
+        acks.sort();
+        assert_eq!(acks, vec![1, 3]);
+    }
+}
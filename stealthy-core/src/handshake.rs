@@ -0,0 +1,783 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use crate::cryp::{self, Encryption, ResultVec};
+use crate::error::StealthyError;
+use crate::wire::{push_value, pop_value, push_slice};
+use crate::negotiation::PeerVersion;
+use crate::pq::{self, KemKeyPair};
+use crate::replay::CLOCK_SKEW_TOLERANCE;
+use crate::session::{self, KeyPair, Ratchet, KEY_LEN};
+
+/// Time in seconds after which a handshake without a reply is considered lost.
+pub const HANDSHAKE_TIMEOUT: i64 = 5;
+
+/// Error which is returned by `Sessions::decrypt` if no session with the peer exists.
+pub const NO_SESSION: &str = "No session with the peer exists.";
+
+const INVALID_HANDSHAKE: &str = "Received invalid handshake.";
+const INVALID_SIGNATURE: &str = "Received handshake with an invalid signature.";
+
+/// Maximum age in seconds of a handshake which is accepted.
+const HANDSHAKE_MAX_AGE: i64 = 60;
+/// Number of session keys which are kept for each peer.
+const MAX_SESSIONS: usize = 4;
+/// Time in seconds an old session is kept after a newer session with the peer has been
+/// confirmed so that retransmitted messages of the old session can still be decrypted.
+const SESSION_GRACE: i64 = 60;
+/// Default lifetime of a session in seconds.
+const DEFAULT_REKEY_INTERVAL: i64 = 3600;
+/// Default number of messages which are sent with a session.
+const DEFAULT_REKEY_MESSAGES: u64 = 1000;
+
+const INIT: u8 = 1;
+const REPLY: u8 = 2;
+/// Handshake with post-quantum key encapsulation (see `pq`).
+const INIT_PQ: u8 = 3;
+const REPLY_PQ: u8 = 4;
+/// Flag of the kind of messages which contain the version of the sender.
+const VERSIONED: u8 = 0x80;
+const TIMESTAMP_LEN: usize = 8;
+const HEADER_LEN: usize = 1 + TIMESTAMP_LEN + 2 * KEY_LEN;
+
+/// Derives the session key from the shared secret of the ephemeral keys, the secret of
+/// the post-quantum key encapsulation (empty without) and the ephemeral public keys of the
+/// initiator and the responder.
+fn session_key(eph: &KeyPair, peer: &[u8], init: &[u8], reply: &[u8], kem_secret: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+    let salt = init.iter().chain(reply.iter()).cloned().collect::<Vec<u8>>();
+    let mut ikm = eph.dh(peer)?.to_vec();
+    ikm.extend_from_slice(kem_secret);
+    let mut key = vec![0u8; KEY_LEN];
+    session::hkdf(&salt, &ikm, b"stealthy session", &mut key);
+    Ok(key)
+}
+
+/// A message of the handshake.
+///
+/// Format: kind (1) | timestamp (8) | ephemeral key of the sender (32) |
+///         ephemeral key of the initiator (32, only in a reply) |
+///         ML-KEM encapsulation key or ciphertext (only INIT_PQ and REPLY_PQ) |
+///         version of the sender (only if the kind has the flag VERSIONED) | signature
+///
+/// The version is only sent to peers which understand it (see `PeerVersion`).
+struct Hello {
+    kind: u8,
+    timestamp: i64,
+    public: Vec<u8>,
+    peer: Vec<u8>,
+    kem: Vec<u8>,
+    version: Option<PeerVersion>,
+}
+
+impl Hello {
+
+    /// Serializes the message and appends the signature of the sender.
+    fn sign(&self, enc: &dyn Encryption) -> ResultVec {
+        let mut v = vec![self.kind | if self.version.is_some() { VERSIONED } else { 0 }];
+        push_value(&mut v, self.timestamp as u64, TIMESTAMP_LEN);
+        push_slice(&mut v, &self.public);
+        push_slice(&mut v, &self.peer);
+        push_slice(&mut v, &self.kem);
+        if let Some(ref version) = self.version {
+            push_slice(&mut v, &version.encode());
+        }
+        let sig = enc.sign(&v)?;
+        push_slice(&mut v, &sig);
+        Ok(v)
+    }
+
+    /// Parses a message. Returns the message and the signature.
+    fn parse(data: &[u8]) -> Option<(Hello, Vec<u8>)> {
+        let kind = data.first()? & !VERSIONED;
+        let kem_len = match kind {
+            INIT_PQ => pq::PUBLIC_KEY_LEN,
+            REPLY_PQ => pq::CIPHERTEXT_LEN,
+            _ => 0
+        };
+        if data.len() <= HEADER_LEN + kem_len {
+            return None;
+        }
+        let mut v = data[1..].to_vec();
+        let timestamp = pop_value(&mut v, TIMESTAMP_LEN).ok()? as i64;
+        let (version, version_len) = match data[0] & VERSIONED {
+            0 => (None, 0),
+            _ => PeerVersion::decode(&v[2 * KEY_LEN + kem_len..]).map(|(version, n)| (Some(version), n))?
+        };
+        let sig = v[2 * KEY_LEN + kem_len + version_len..].to_vec();
+        if sig.is_empty() {
+            return None;
+        }
+        Some((Hello {
+            kind,
+            timestamp,
+            public: v[..KEY_LEN].to_vec(),
+            peer: v[KEY_LEN..2 * KEY_LEN].to_vec(),
+            kem: v[2 * KEY_LEN..2 * KEY_LEN + kem_len].to_vec(),
+            version,
+        }, sig))
+    }
+
+    /// Returns the number of bytes which are covered by the signature.
+    fn signed_len(&self) -> usize {
+        HEADER_LEN + self.kem.len() + self.version.as_ref().map(|v| v.encode().len()).unwrap_or(0)
+    }
+}
+
+/// A handshake we have initiated.
+struct Pending {
+    eph: KeyPair,
+    kem: Option<KemKeyPair>,
+    time: i64,
+}
+
+/// Determines when a session is replaced by a new one.
+#[derive(Clone, Copy)]
+pub struct RekeyPolicy {
+    /// Maximum lifetime of a session in seconds.
+    interval: i64,
+    /// Maximum number of messages we send with a session.
+    messages: u64,
+}
+
+impl RekeyPolicy {
+
+    /// Creates a policy with the given values or the default values.
+    pub fn new(interval_minutes: Option<u64>, messages: Option<u64>) -> RekeyPolicy {
+        RekeyPolicy {
+            interval: interval_minutes.map(|m| m as i64 * 60).unwrap_or(DEFAULT_REKEY_INTERVAL),
+            messages: messages.unwrap_or(DEFAULT_REKEY_MESSAGES),
+        }
+    }
+}
+
+/// Describes a session with a peer for the application.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    /// Random id of the session which is only known to us.
+    pub id: u64,
+    /// The peer of the session.
+    pub ip: String,
+    /// True if we have initiated the handshake.
+    pub initiator: bool,
+    /// Time when the session has been established.
+    pub created: i64,
+    /// Id of the session with the peer which is replaced by this session.
+    pub replaces: Option<u64>,
+}
+
+/// Start or end of a session which is reported to the application.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+    /// The session can be used to exchange messages, i.e. both peers own the keys.
+    Opened(SessionInfo),
+    /// The keys of the session have been discarded.
+    Closed(SessionInfo),
+}
+
+/// One conversation with a peer. It owns the keys and the message counters of the
+/// ratchet and knows the peer it belongs to.
+struct Session {
+    ratchet: Ratchet,
+    info: SessionInfo,
+    /// True if we know that the peer owns the session key.
+    confirmed: bool,
+    /// True if the session has been reported as opened.
+    opened: bool,
+    /// Number of messages we have sent with the session.
+    sent: u64,
+    /// Time when a newer session with the peer has been confirmed.
+    superseded: Option<i64>,
+}
+
+/// Establishes sessions with forward secrecy.
+///
+/// The initiator sends an ephemeral X25519 public key, the responder answers with its
+/// own ephemeral public key. Both messages are signed with the long-term key: in hybrid
+/// mode with the RSA keys, with a symmetric cipher with a HMAC of the shared key. Both
+/// peers derive the session key from the shared secret and forget the ephemeral key of
+/// the initiator. The session key is the root of a Double Ratchet (see `session`) which
+/// replaces the ephemeral key of the responder as soon as the conversation proceeds.
+/// Hence, a compromised long-term key does not reveal messages of past sessions.
+///
+/// The initiator uses a session as soon as the reply has been verified. The responder
+/// uses a session not before the initiator has sent a message with it, as the reply
+/// could have been lost.
+///
+/// Sessions are replaced by new sessions according to the `RekeyPolicy`. Old sessions are
+/// discarded `SESSION_GRACE` seconds after the new session has been confirmed.
+///
+/// When a session has been confirmed or discarded a `SessionEvent` is queued which can be
+/// fetched with `take_events`.
+pub struct Sessions {
+    /// Our ephemeral keys of handshakes we have initiated and when they were initiated.
+    pending: HashMap<String, Pending>,
+    /// The session keys of each peer. The newest key is the last one.
+    sessions: HashMap<String, Vec<Session>>,
+    /// Ephemeral keys of received handshakes to detect replays.
+    seen: HashMap<Vec<u8>, i64>,
+    policy: RekeyPolicy,
+    /// True if messages are sent in the deniable format (see `Ratchet`).
+    deniable: bool,
+    /// Events which have not been fetched yet.
+    events: Vec<SessionEvent>,
+    /// Peers which understand the version in the handshake.
+    versioned: HashSet<String>,
+    /// Versions received in handshakes which have not been fetched yet.
+    versions: HashMap<String, PeerVersion>,
+}
+
+impl Sessions {
+
+    pub fn new(policy: RekeyPolicy) -> Sessions {
+        Sessions {
+            pending: HashMap::new(),
+            sessions: HashMap::new(),
+            seen: HashMap::new(),
+            policy,
+            deniable: false,
+            events: vec![],
+            versioned: HashSet::new(),
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Sends our version in the handshakes with the peer as it has advertised that it
+    /// understands it.
+    pub fn set_versioned(&mut self, ip: &str) {
+        self.versioned.insert(ip.to_string());
+    }
+
+    /// Returns the version which the peer has sent in its last handshake if it has not
+    /// been fetched yet.
+    pub fn take_version(&mut self, ip: &str) -> Option<PeerVersion> {
+        self.versions.remove(ip)
+    }
+
+    /// Remembers the version of a verified handshake. A peer which sends its version
+    /// understands ours.
+    fn learn_version(&mut self, ip: &str, version: Option<PeerVersion>) {
+        if let Some(v) = version {
+            self.versioned.insert(ip.to_string());
+            self.versions.insert(ip.to_string(), v);
+        }
+    }
+
+    /// Returns our version if the peer understands it.
+    fn version_for(&self, ip: &str) -> Option<PeerVersion> {
+        match self.versioned.contains(ip) {
+            true => Some(PeerVersion::ours()),
+            false => None
+        }
+    }
+
+    /// Returns the sessions which have been opened or closed since the last call.
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        mem::replace(&mut self.events, vec![])
+    }
+
+    pub fn set_deniable(&mut self, deniable: bool) {
+        self.deniable = deniable;
+    }
+
+    /// Returns true if no handshake with the peer is in progress and either no session
+    /// with the peer exists or the session has to be replaced.
+    pub fn needs_handshake(&self, ip: &str, now: i64) -> bool {
+        !self.is_pending(ip, now) && (!self.is_established(ip) || self.needs_rekey(ip, now))
+    }
+
+    /// Returns true if the session which is used to send messages to the peer has
+    /// reached its maximum lifetime or number of messages.
+    pub fn needs_rekey(&self, ip: &str, now: i64) -> bool {
+        match self.sending(ip) {
+            Some(s) => now - s.info.created >= self.policy.interval || s.sent >= self.policy.messages,
+            None => false
+        }
+    }
+
+    /// Returns true if we are waiting for the reply of the peer.
+    pub fn is_pending(&self, ip: &str, now: i64) -> bool {
+        match self.pending.get(ip) {
+            Some(p) => now - p.time < HANDSHAKE_TIMEOUT,
+            None => false
+        }
+    }
+
+    /// Returns true if a session with the peer can be used to send messages.
+    pub fn is_established(&self, ip: &str) -> bool {
+        self.sending(ip).is_some()
+    }
+
+    /// Starts a handshake with the peer and returns the message for the peer. If
+    /// post-quantum key encapsulation is supported it is used for the handshake.
+    pub fn initiate(&mut self, ip: &str, enc: &dyn Encryption, now: i64) -> ResultVec {
+
+        let eph = KeyPair::generate()?;
+        let kem = match pq::ENABLED {
+            true => Some(KemKeyPair::generate().map_err(StealthyError::Crypto)?),
+            false => None
+        };
+        let hello = Hello {
+            kind: if kem.is_some() { INIT_PQ } else { INIT },
+            timestamp: now,
+            public: eph.public.to_vec(),
+            peer: vec![0; KEY_LEN],
+            kem: kem.as_ref().map(|k| k.public.clone()).unwrap_or_default(),
+            version: self.version_for(ip),
+        }.sign(enc)?;
+        self.pending.insert(ip.to_string(), Pending { eph, kem, time: now });
+        Ok(hello)
+    }
+
+    /// Processes a handshake message of the peer. Returns the reply which has to be sent
+    /// to the peer, if any.
+    pub fn handle(&mut self, ip: &str, data: &[u8], enc: &dyn Encryption, now: i64) -> Result<Option<Vec<u8>>, StealthyError> {
+
+        let (hello, sig) = Hello::parse(data).ok_or(StealthyError::Protocol(INVALID_HANDSHAKE))?;
+
+        match hello.kind {
+            INIT | INIT_PQ => {
+                // We receive our own handshakes if we listen on the device they are sent to.
+                if self.pending.values().any(|p| p.eph.public[..] == hello.public[..]) {
+                    return Ok(None);
+                }
+                // If both peers have started a handshake at the same time only the handshake
+                // with the smaller key is completed. Otherwise each peer could prefer a
+                // different session and discard the session used by the other peer.
+                match self.pending.get(ip) {
+                    Some(p) if now - p.time < HANDSHAKE_TIMEOUT && p.eph.public[..] < hello.public[..] => return Ok(None),
+                    _ => { }
+                }
+                if !enc.verify(&data[..hello.signed_len()], &sig) {
+                    return Err(StealthyError::Crypto(INVALID_SIGNATURE));
+                }
+                if hello.timestamp > now + CLOCK_SKEW_TOLERANCE || now - hello.timestamp > HANDSHAKE_MAX_AGE {
+                    return Err(StealthyError::Protocol("Received an expired handshake."));
+                }
+                self.seen.retain(|_, t| now - *t <= HANDSHAKE_MAX_AGE);
+                if self.seen.insert(hello.public.clone(), hello.timestamp).is_some() {
+                    return Err(StealthyError::Protocol("Received a replayed handshake."));
+                }
+                self.learn_version(ip, hello.version.clone());
+
+                let (kind, ciphertext, kem_secret) = match hello.kind {
+                    INIT_PQ => {
+                        let (ct, secret) = pq::encapsulate(&hello.kem).map_err(StealthyError::Crypto)?;
+                        (REPLY_PQ, ct, secret)
+                    },
+                    _ => (REPLY, vec![], vec![])
+                };
+
+                // Our ephemeral key is the first ratchet key of the session.
+                let eph = KeyPair::generate()?;
+                let key = session_key(&eph, &hello.public, &hello.public, &eph.public, &kem_secret)?;
+                let reply = Hello {
+                    kind,
+                    timestamp: now,
+                    public: eph.public.to_vec(),
+                    peer: hello.public,
+                    kem: ciphertext,
+                    version: self.version_for(ip),
+                }.sign(enc)?;
+
+                // The peer has started a new session, e.g. because it has been restarted.
+                // Older sessions are not used for sending anymore until the peer uses them.
+                if let Some(v) = self.sessions.get_mut(ip) {
+                    for s in v.iter_mut() {
+                        s.confirmed = false;
+                    }
+                }
+                self.add(ip, Ratchet::responder(&key, eph), false, now);
+                Ok(Some(reply))
+            },
+            REPLY | REPLY_PQ => {
+                // Replies which do not belong to our handshake are ignored. They could be
+                // replies for other clients or replays.
+                let key = match self.pending.get(ip) {
+                    Some(p) if p.eph.public[..] == hello.peer[..] => {
+                        if !enc.verify(&data[..hello.signed_len()], &sig) {
+                            return Err(StealthyError::Crypto(INVALID_SIGNATURE));
+                        }
+                        let kem_secret = match (&p.kem, hello.kind) {
+                            (Some(k), REPLY_PQ) => k.decapsulate(&hello.kem).map_err(StealthyError::Crypto)?,
+                            (None, REPLY) => vec![],
+                            _ => return Err(StealthyError::Protocol("Received handshake with an unexpected key encapsulation."))
+                        };
+                        session_key(&p.eph, &hello.public, &hello.peer, &hello.public, &kem_secret)?
+                    },
+                    _ => return Ok(None)
+                };
+                self.learn_version(ip, hello.version.clone());
+                self.pending.remove(ip);
+                self.add(ip, Ratchet::initiator(&key, &hello.public)?, true, now);
+                Ok(None)
+            },
+            _ => Err(StealthyError::Protocol(INVALID_HANDSHAKE))
+        }
+    }
+
+    /// Encrypts a message with the newest session with the peer. Returns None if there is
+    /// no session which can be used to send messages. The deniable format is only used if
+    /// `peer_deniable` is true, i.e. if the peer supports it.
+    pub fn encrypt(&mut self, ip: &str, plain: &[u8], peer_deniable: bool) -> Option<ResultVec> {
+        let s = self.sessions.get_mut(ip)?
+            .iter_mut()
+            .rev()
+            .find(|s| s.confirmed && s.ratchet.can_send())?;
+        s.sent += 1;
+        Some(s.ratchet.encrypt(plain, self.deniable && peer_deniable))
+    }
+
+    /// Decrypts a message of the peer with one of the sessions of the peer.
+    pub fn decrypt(&mut self, ip: &str, data: &[u8], now: i64) -> ResultVec {
+
+        let v = match self.sessions.get_mut(ip) {
+            Some(v) => v,
+            None => return Err(StealthyError::Protocol(NO_SESSION))
+        };
+        let mut r = cryp::AUTHENTICATION_FAILED;
+        for i in (0..v.len()).rev() {
+            match v[i].ratchet.decrypt(data) {
+                Ok(plain) => {
+                    // The peer owns the key.
+                    if !v[i].confirmed {
+                        self.confirm(ip, i, now);
+                    }
+                    return Ok(plain);
+                },
+                Err(e) if e.message() == session::REPLAYED => r = session::REPLAYED,
+                Err(_) => { }
+            }
+        }
+        Err(StealthyError::Crypto(r))
+    }
+
+    /// Discards the sessions which have been superseded by a newer session more than
+    /// `SESSION_GRACE` seconds ago. Their keys are removed from memory.
+    pub fn discard_old(&mut self, now: i64) {
+        let events = &mut self.events;
+        for v in self.sessions.values_mut() {
+            v.retain(|s| match s.superseded {
+                Some(t) if now - t >= SESSION_GRACE => {
+                    if s.opened {
+                        events.push(SessionEvent::Closed(s.info.clone()));
+                    }
+                    false
+                },
+                _ => true
+            });
+        }
+    }
+
+    /// Returns the session which is used to send messages to the peer.
+    fn sending(&self, ip: &str) -> Option<&Session> {
+        self.sessions.get(ip)?
+            .iter()
+            .rev()
+            .find(|s| s.confirmed && s.ratchet.can_send())
+    }
+
+    fn supersede(older: &mut [Session], now: i64) {
+        for s in older.iter_mut().filter(|s| s.superseded.is_none()) {
+            s.superseded = Some(now);
+        }
+    }
+
+    /// Marks the session with the given index as confirmed and supersedes the older
+    /// sessions with the peer.
+    fn confirm(&mut self, ip: &str, i: usize, now: i64) {
+
+        let v = match self.sessions.get_mut(ip) {
+            Some(v) => v,
+            None => return
+        };
+        v[i].confirmed = true;
+        Sessions::supersede(&mut v[..i], now);
+        if !v[i].opened {
+            v[i].opened = true;
+            v[i].info.replaces = v[..i].iter().rev().find(|s| s.opened).map(|s| s.info.id);
+            self.events.push(SessionEvent::Opened(v[i].info.clone()));
+        }
+    }
+
+    /// Adds a new session. The initiator of the handshake knows that the peer owns the
+    /// session key as soon as the reply has been verified.
+    fn add(&mut self, ip: &str, ratchet: Ratchet, initiator: bool, now: i64) {
+
+        let v = self.sessions.entry(ip.to_string()).or_insert_with(Vec::new);
+        v.push(Session {
+            ratchet,
+            info: SessionInfo {
+                id: rand::random::<u64>(),
+                ip: ip.to_string(),
+                initiator,
+                created: now,
+                replaces: None,
+            },
+            confirmed: false,
+            opened: false,
+            sent: 0,
+            superseded: None,
+        });
+        if v.len() > MAX_SESSIONS {
+            let s = v.remove(0);
+            if s.opened {
+                self.events.push(SessionEvent::Closed(s.info));
+            }
+        }
+        if initiator {
+            let i = self.sessions[ip].len() - 1;
+            self.confirm(ip, i, now);
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Hello, Sessions, SessionEvent, RekeyPolicy, NO_SESSION, SESSION_GRACE, VERSIONED};
+    use crate::negotiation::PeerVersion;
+    use crate::cryp::{Encryption, AsymmetricEncryption, SymmetricEncryption};
+    use crate::session::REPLAYED;
+
+    const NOW: i64 = 1_500_000_000;
+    const IP_A: &str = "1.1.1.1";
+    const IP_B: &str = "2.2.2.2";
+
+    fn sessions() -> Sessions {
+        Sessions::new(RekeyPolicy::new(Some(10), Some(3)))
+    }
+
+    fn key() -> SymmetricEncryption {
+        SymmetricEncryption::new(&"11111111111111111111111111111111".to_string()).unwrap()
+    }
+
+    // Runs a handshake between a and b. The ip of a peer is the ip as seen by the other peer.
+    fn handshake(a: &mut Sessions, ea: &dyn Encryption, b: &mut Sessions, eb: &dyn Encryption) {
+        let init = a.initiate(IP_B, ea, NOW).unwrap();
+        let reply = b.handle(IP_A, &init, eb, NOW).unwrap().unwrap();
+        assert!(a.handle(IP_B, &reply, ea, NOW).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_handshake() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        assert!(a.needs_handshake(IP_B, NOW));
+        handshake(&mut a, &key(), &mut b, &key());
+
+        // The initiator uses the session at once, the responder after it has been used.
+        assert!(a.is_established(IP_B));
+        assert!(!b.is_established(IP_A));
+
+        assert!(b.encrypt(IP_A, b"hello", false).is_none());
+        let cipher = a.encrypt(IP_B, b"hello", false).unwrap().unwrap();
+        assert_eq!(b.decrypt(IP_A, &cipher, NOW).unwrap(), b"hello".to_vec());
+        assert!(b.is_established(IP_A));
+        assert_eq!(b.decrypt(IP_A, &cipher, NOW).map_err(|e| e.message()), Err(REPLAYED));
+
+        let cipher = b.encrypt(IP_A, b"world", false).unwrap().unwrap();
+        assert_eq!(a.decrypt(IP_B, &cipher, NOW).unwrap(), b"world".to_vec());
+
+        // The session key is not the long-term key.
+        assert!(key().decrypt(&cipher).is_err());
+    }
+
+    #[test]
+    fn test_new_session_per_handshake() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        handshake(&mut a, &key(), &mut b, &key());
+        let first = a.encrypt(IP_B, b"hello", false).unwrap().unwrap();
+        handshake(&mut a, &key(), &mut b, &key());
+        let second = a.encrypt(IP_B, b"world", false).unwrap().unwrap();
+
+        assert_eq!(b.decrypt(IP_A, &second, NOW).unwrap(), b"world".to_vec());
+        // Messages of the old session can still be received.
+        assert_eq!(b.decrypt(IP_A, &first, NOW).unwrap(), b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_hybrid_handshake() {
+
+        let ea = AsymmetricEncryption::new("tests/keys/rsa2_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+        let eb = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa2_priv.pem", None).unwrap();
+        let (mut a, mut b) = (sessions(), sessions());
+        handshake(&mut a, &ea, &mut b, &eb);
+        assert!(a.is_established(IP_B));
+
+        // A handshake signed with another key is rejected.
+        let init = a.initiate(IP_B, &ea, NOW).unwrap();
+        assert!(sessions().handle(IP_A, &init, &ea, NOW).is_err());
+    }
+
+    #[cfg(feature = "pq")]
+    #[test]
+    fn test_pq_handshake() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        let init = a.initiate(IP_B, &key(), NOW).unwrap();
+        assert_eq!(init[0], super::INIT_PQ);
+        assert!(init.len() > super::HEADER_LEN + crate::pq::PUBLIC_KEY_LEN);
+
+        // The encapsulation key is covered by the signature.
+        let mut tampered = init.clone();
+        tampered[super::HEADER_LEN] ^= 1;
+        assert!(sessions().handle(IP_A, &tampered, &key(), NOW).is_err());
+
+        let reply = b.handle(IP_A, &init, &key(), NOW).unwrap().unwrap();
+        assert_eq!(reply[0], super::REPLY_PQ);
+        assert!(a.handle(IP_B, &reply, &key(), NOW).unwrap().is_none());
+
+        let cipher = a.encrypt(IP_B, b"hello", false).unwrap().unwrap();
+        assert_eq!(b.decrypt(IP_A, &cipher, NOW).unwrap(), b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_rejected_handshakes() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        let init = a.initiate(IP_B, &key(), NOW).unwrap();
+
+        // Our own handshake is ignored.
+        assert!(a.handle(IP_B, &init, &key(), NOW).unwrap().is_none());
+
+        let mut tampered = init.clone();
+        tampered[20] ^= 1;
+        assert!(b.handle(IP_A, &tampered, &key(), NOW).is_err());
+        assert!(b.handle(IP_A, &init, &key(), NOW + 61).is_err());
+        assert!(b.handle(IP_A, &init[..10], &key(), NOW).is_err());
+
+        let reply = b.handle(IP_A, &init, &key(), NOW).unwrap().unwrap();
+        // A replayed handshake is rejected.
+        assert!(b.handle(IP_A, &init, &key(), NOW).is_err());
+
+        // A reply which does not belong to our handshake is ignored.
+        assert!(sessions().handle(IP_B, &reply, &key(), NOW).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_no_session() {
+
+        let mut a = sessions();
+        assert_eq!(a.decrypt(IP_B, &[1, 2, 3], NOW).map_err(|e| e.message()), Err(NO_SESSION));
+        assert!(a.encrypt(IP_B, b"hello", false).is_none());
+
+        a.initiate(IP_B, &key(), NOW).unwrap();
+        assert!(!a.needs_handshake(IP_B, NOW));
+        assert!(a.needs_handshake(IP_B, NOW + super::HANDSHAKE_TIMEOUT));
+    }
+
+    #[test]
+    fn test_rekey() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        handshake(&mut a, &key(), &mut b, &key());
+        assert!(!a.needs_handshake(IP_B, NOW));
+        // The session is replaced after its lifetime ...
+        assert!(a.needs_handshake(IP_B, NOW + 600));
+        // ... or after the maximum number of messages.
+        let first = a.encrypt(IP_B, b"1", false).unwrap().unwrap();
+        a.encrypt(IP_B, b"2", false).unwrap().unwrap();
+        a.encrypt(IP_B, b"3", false).unwrap().unwrap();
+        assert!(a.needs_handshake(IP_B, NOW));
+
+        handshake(&mut a, &key(), &mut b, &key());
+        assert!(!a.needs_handshake(IP_B, NOW));
+        let second = a.encrypt(IP_B, b"4", false).unwrap().unwrap();
+        assert_eq!(b.decrypt(IP_A, &second, NOW).unwrap(), b"4".to_vec());
+
+        // The old session is discarded after the grace period.
+        b.discard_old(NOW + SESSION_GRACE - 1);
+        assert_eq!(b.decrypt(IP_A, &first, NOW).unwrap(), b"1".to_vec());
+        b.discard_old(NOW + SESSION_GRACE);
+        a.discard_old(NOW + SESSION_GRACE);
+        assert!(b.decrypt(IP_A, &a.encrypt(IP_B, b"5", false).unwrap().unwrap(), NOW).is_ok());
+        assert_eq!(b.sessions.get(IP_A).unwrap().len(), 1);
+        assert_eq!(a.sessions.get(IP_B).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_session_events() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        handshake(&mut a, &key(), &mut b, &key());
+        let first = match a.take_events().as_slice() {
+            [SessionEvent::Opened(s)] => s.clone(),
+            _ => panic!("no event")
+        };
+        assert_eq!((first.ip.as_str(), first.initiator, first.replaces), (IP_B, true, None));
+        assert!(a.take_events().is_empty());
+
+        // The responder opens the session with the first message of the initiator.
+        assert!(b.take_events().is_empty());
+        b.decrypt(IP_A, &a.encrypt(IP_B, b"1", false).unwrap().unwrap(), NOW).unwrap();
+        match b.take_events().as_slice() {
+            [SessionEvent::Opened(s)] => assert_eq!((s.ip.as_str(), s.initiator), (IP_A, false)),
+            _ => panic!("no event")
+        }
+
+        // A new session replaces the old one which is closed after the grace period.
+        handshake(&mut a, &key(), &mut b, &key());
+        match a.take_events().as_slice() {
+            [SessionEvent::Opened(s)] => assert_eq!(s.replaces, Some(first.id)),
+            _ => panic!("no event")
+        }
+        a.discard_old(NOW + SESSION_GRACE);
+        assert_eq!(a.take_events(), vec![SessionEvent::Closed(first)]);
+    }
+
+    #[test]
+    fn test_simultaneous_handshakes() {
+
+        let (mut a, mut b) = (sessions(), sessions());
+        let init_a = a.initiate(IP_B, &key(), NOW).unwrap();
+        let init_b = b.initiate(IP_A, &key(), NOW).unwrap();
+
+        // Only the handshake with the smaller key is answered.
+        let reply_a = a.handle(IP_B, &init_b, &key(), NOW).unwrap();
+        let reply_b = b.handle(IP_A, &init_a, &key(), NOW).unwrap();
+        assert!(reply_a.is_some() != reply_b.is_some());
+        match reply_b {
+            Some(r) => { a.handle(IP_B, &r, &key(), NOW).unwrap(); },
+            None => { b.handle(IP_A, &reply_a.unwrap(), &key(), NOW).unwrap(); }
+        }
+        assert!(a.is_established(IP_B) || b.is_established(IP_A));
+    }
+
+    #[test]
+    fn test_version() {
+
+        // The version is only sent to peers which understand it.
+        let (mut a, mut b) = (sessions(), sessions());
+        handshake(&mut a, &key(), &mut b, &key());
+        assert_eq!(b.take_version(IP_A), None);
+        assert_eq!(a.take_version(IP_B), None);
+
+        a.set_versioned(IP_B);
+        let init = a.initiate(IP_B, &key(), NOW).unwrap();
+        assert_eq!(init[0] & VERSIONED, VERSIONED);
+        let reply = b.handle(IP_A, &init, &key(), NOW).unwrap().unwrap();
+        assert_eq!(b.take_version(IP_A), Some(PeerVersion::ours()));
+        assert_eq!(b.take_version(IP_A), None);
+
+        // The responder answers with its version as the initiator understands it.
+        assert_eq!(reply[0] & VERSIONED, VERSIONED);
+        assert!(a.handle(IP_B, &reply, &key(), NOW).unwrap().is_none());
+        assert_eq!(a.take_version(IP_B), Some(PeerVersion::ours()));
+        assert!(a.is_established(IP_B));
+
+        // The version is covered by the signature.
+        let mut c = sessions();
+        c.set_versioned(IP_B);
+        let mut init = c.initiate(IP_B, &key(), NOW).unwrap();
+        let n = Hello::parse(&init).unwrap().0.signed_len();
+        init[n - 1] ^= 1;
+        assert!(sessions().handle(IP_A, &init, &key(), NOW).is_err());
+        assert!(sessions().handle(IP_A, &init[..n], &key(), NOW).is_err());
+    }
+}
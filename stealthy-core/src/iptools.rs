@@ -0,0 +1,119 @@
+use std::fs;
+use std::net::IpAddr;
+
+/// Flag of routes which are up.
+const RTF_UP: u32 = 1;
+
+/// IPv4 and IPv6 addresses of the peers. The addresses are returned in their canonical
+/// form so that they can be compared with the source addresses of received packets.
+#[derive(Clone)]
+pub struct IpAddresses {
+    ips: Vec<IpAddr>
+}
+
+impl IpAddresses {
+    pub fn from_comma_list(s: &str) -> IpAddresses {
+        IpAddresses {
+            ips: s.split(",")
+                .map(|x| String::from(x).trim().to_string())
+                .filter(|x| x.len() > 0)
+                .map(|x| x.parse().expect("Found invalid IP address."))
+                .collect()
+        }
+    }
+
+    pub fn as_strings(&self) -> Vec<String> {
+        self.ips.iter().map(|x| x.to_string()).collect()
+    }
+
+    /// Returns the addresses which are contained in the given list.
+    pub fn only(&self, ips: &[String]) -> IpAddresses {
+        IpAddresses {
+            ips: self.ips.iter().filter(|x| ips.contains(&x.to_string())).cloned().collect()
+        }
+    }
+}
+
+/// Returns the canonical form of an IP address, e.g. IPv6 addresses with lowercase
+/// digits and the longest run of zeros compressed. Other strings are returned unchanged.
+pub fn canonical(ip: &str) -> String {
+    match ip.trim().parse::<IpAddr>() {
+        Ok(a) => a.to_string(),
+        Err(_) => ip.to_string()
+    }
+}
+
+/// Returns the device of the default route. IPv4 routes are preferred. If there are several
+/// default routes the one with the lowest metric is used.
+pub fn default_device() -> Option<String> {
+    fs::read_to_string("/proc/net/route").ok().and_then(|s| default_route_v4(&s))
+        .or_else(|| fs::read_to_string("/proc/net/ipv6_route").ok().and_then(|s| default_route_v6(&s)))
+}
+
+/// Returns true if the kernel answers the echo requests of IPv4.
+pub fn kernel_answers_pings() -> bool {
+    fs::read_to_string("/proc/sys/net/ipv4/icmp_echo_ignore_all")
+        .map(|s| s.trim() == "0")
+        .unwrap_or(false)
+}
+
+/// Returns the device of the default route in the format of /proc/net/route.
+fn default_route_v4(s: &str) -> Option<String> {
+    s.lines()
+        .skip(1)
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .filter(|f| f.len() >= 8 && f[1] == "00000000" && f[7] == "00000000")
+        .filter(|f| hex(f[3]) & RTF_UP != 0)
+        .min_by_key(|f| f[6].parse::<u32>().unwrap_or(u32::max_value()))
+        .map(|f| f[0].to_string())
+}
+
+/// Returns the device of the default route in the format of /proc/net/ipv6_route.
+fn default_route_v6(s: &str) -> Option<String> {
+    s.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .filter(|f| f.len() >= 10 && f[0].chars().all(|c| c == '0') && f[1] == "00" && f[9] != "lo")
+        .filter(|f| hex(f[8]) & RTF_UP != 0)
+        .min_by_key(|f| hex(f[5]))
+        .map(|f| f[9].to_string())
+}
+
+fn hex(s: &str) -> u32 {
+    u32::from_str_radix(s, 16).unwrap_or(0)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{IpAddresses, canonical, default_route_v4, default_route_v6};
+
+    #[test]
+    fn test_ip_addresses() {
+
+        let ips = IpAddresses::from_comma_list("1.2.3.4, 2001:DB8:0:0::1,::1");
+        assert_eq!(ips.as_strings(), vec!["1.2.3.4", "2001:db8::1", "::1"]);
+        assert_eq!(canonical("2001:0db8:0000:0000:0000:0000:0000:0001"), "2001:db8::1");
+        assert_eq!(canonical("10.0.0.1"), "10.0.0.1");
+        assert_eq!(canonical("unknown"), "unknown");
+    }
+
+    #[test]
+    fn test_default_route() {
+
+        let v4 = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                  wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+                  eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n\
+                  eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+                  tun0\t00000000\t00000000\t0000\t0\t0\t0\t00000000\t0\t0\t0\n";
+        assert_eq!(default_route_v4(v4), Some("eth0".to_string()));
+        assert_eq!(default_route_v4(&v4[..v4.find("wlan0").unwrap()]), None);
+
+        let v6 = "00000000000000000000000000000001 80 00000000000000000000000000000000 00 00000000000000000000000000000000 00000000 00000003 00000000 80200001       lo\n\
+                  00000000000000000000000000000000 00 00000000000000000000000000000000 00 fd000000000000000000000000000001 00000400 00000001 00000000 00000003     eth0\n";
+        assert_eq!(default_route_v6(v6), Some("eth0".to_string()));
+    }
+}
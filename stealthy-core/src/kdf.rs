@@ -0,0 +1,168 @@
+use std::fs;
+
+use argon2::{Config, ThreadMode, Variant, Version};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::cryp::from_hex;
+use crate::tools::{read_file, to_hex, write_data};
+use crate::error::StealthyError;
+
+const DEFAULT_MEM_COST: u32 = 65536;
+const DEFAULT_TIME_COST: u32 = 3;
+const DEFAULT_LANES: u32 = 1;
+const SALT_LEN: usize = 16;
+
+/// Name of the file in ~/.stealthy where the parameters are stored.
+const PARAMS_FILE: &str = "kdf";
+
+/// Parameters of Argon2id which is used to derive the symmetric key from a passphrase.
+///
+/// Both peers have to use the same parameters to derive the same key. The parameters are
+/// encoded as `argon2id$m=<KiB>,t=<iterations>,p=<lanes>$<salt in hex>`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct KdfParams {
+    /// Memory in KiB.
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+
+    /// Creates parameters with the default costs and a random salt.
+    pub fn generate() -> Result<KdfParams, StealthyError> {
+
+        let mut salt = vec![0; SALT_LEN];
+        OsRng::new().map_err(|_| StealthyError::Crypto("Could not get OsRng."))?.fill_bytes(&mut salt);
+        Ok(KdfParams {
+            mem_cost: DEFAULT_MEM_COST,
+            time_cost: DEFAULT_TIME_COST,
+            lanes: DEFAULT_LANES,
+            salt,
+        })
+    }
+
+    pub fn parse(s: &str) -> Result<KdfParams, StealthyError> {
+
+        let parts = s.trim().split('$').collect::<Vec<_>>();
+        if parts.len() != 3 || parts[0] != "argon2id" {
+            return Err(StealthyError::Crypto("Invalid format of KDF parameters."));
+        }
+        let mut p = KdfParams {
+            mem_cost: 0,
+            time_cost: 0,
+            lanes: 0,
+            salt: from_hex(parts[2].to_string())?,
+        };
+        for kv in parts[1].split(',') {
+            let (k, v) = match kv.find('=') {
+                Some(i) => (&kv[..i], kv[i + 1..].parse::<u32>().map_err(|_| StealthyError::Crypto("Invalid value in KDF parameters."))?),
+                None => return Err(StealthyError::Crypto("Invalid format of KDF parameters."))
+            };
+            match k {
+                "m" => p.mem_cost = v,
+                "t" => p.time_cost = v,
+                "p" => p.lanes = v,
+                _ => return Err(StealthyError::Crypto("Unknown KDF parameter."))
+            }
+        }
+        if p.mem_cost == 0 || p.time_cost == 0 || p.lanes == 0 || p.salt.len() < 8 {
+            return Err(StealthyError::Crypto("Invalid KDF parameters."));
+        }
+        Ok(p)
+    }
+
+    pub fn encode(&self) -> String {
+        format!("argon2id$m={},t={},p={}${}", self.mem_cost, self.time_cost, self.lanes, to_hex(&self.salt))
+    }
+
+    /// Derives a key with `len` bytes from the passphrase.
+    pub fn derive(&self, passphrase: &str, len: usize) -> Result<Vec<u8>, StealthyError> {
+
+        let config = Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: len as u32,
+        };
+        argon2::hash_raw(passphrase.as_bytes(), &self.salt, &config).map_err(|_| StealthyError::Crypto("Key derivation failed."))
+    }
+}
+
+/// Loads the parameters from ~/.stealthy/kdf. If the file does not exist new parameters
+/// are created and stored so that the same key is derived on the next start.
+pub fn load_or_create() -> Result<KdfParams, StealthyError> {
+
+    let mut path = dirs::home_dir().ok_or(StealthyError::Crypto("Could not find home directory."))?;
+    path.push(".stealthy");
+    let dir = path.clone();
+    path.push(PARAMS_FILE);
+    let fname = path.to_str().ok_or(StealthyError::Crypto("Invalid path."))?;
+
+    match read_file(fname) {
+        Ok(s) => KdfParams::parse(&s),
+        Err(_) => {
+            let p = KdfParams::generate()?;
+            fs::create_dir_all(dir).map_err(|e| StealthyError::Io("Could not create ~/.stealthy.", e))?;
+            if !write_data(fname, p.encode().into_bytes()) {
+                return Err(StealthyError::Crypto("Could not store KDF parameters."));
+            }
+            Ok(p)
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::KdfParams;
+
+    fn params(salt: u8) -> KdfParams {
+        KdfParams {
+            mem_cost: 64,
+            time_cost: 1,
+            lanes: 1,
+            salt: vec![salt; 16],
+        }
+    }
+
+    #[test]
+    fn test_encode_parse() {
+
+        let p = KdfParams::generate().unwrap();
+        assert_eq!(KdfParams::parse(&p.encode()).unwrap(), p);
+        assert_eq!(params(1).encode(), "argon2id$m=64,t=1,p=1$01010101010101010101010101010101");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+
+        assert!(KdfParams::parse("").is_err());
+        assert!(KdfParams::parse("argon2i$m=64,t=1,p=1$0101010101010101").is_err());
+        assert!(KdfParams::parse("argon2id$m=64,t=1,x=1$0101010101010101").is_err());
+        assert!(KdfParams::parse("argon2id$m=64,t=0,p=1$0101010101010101").is_err());
+        assert!(KdfParams::parse("argon2id$m=64,t=1,p=1$01").is_err());
+    }
+
+    #[test]
+    fn test_derive() {
+
+        let k = params(1).derive("secret", 16).unwrap();
+        assert_eq!(k.len(), 16);
+        assert_eq!(params(1).derive("secret", 16).unwrap(), k);
+        assert_ne!(params(2).derive("secret", 16).unwrap(), k);
+        assert_ne!(params(1).derive("Secret", 16).unwrap(), k);
+        assert_eq!(params(1).derive("secret", 32).unwrap().len(), 32);
+    }
+}
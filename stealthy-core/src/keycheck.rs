@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+
+use crate::cryp::Encryption;
+use crate::status::Status;
+
+/// Time in seconds we wait for the response of a peer before we warn the user.
+pub const KEYCHECK_TIMEOUT: u64 = 10;
+
+const MAGIC: &[u8] = b"KEYCHECK";
+const CHALLENGE_LEN: usize = 32;
+
+struct PendingCheck {
+    /// The random challenge in plaintext.
+    challenge: Vec<u8>,
+    /// The encrypted challenge as it was sent to the peer.
+    cipher: Vec<u8>,
+    deadline: SystemTime,
+}
+
+/// Checks in hybrid mode whether the public key of a recipient matches the private key
+/// of the peer.
+///
+/// The sender encrypts a random challenge with the recipient's public key. Only a peer
+/// which owns the matching private key is able to decrypt the challenge and to send back
+/// the hash of it. If no valid response arrives within `KEYCHECK_TIMEOUT` seconds a
+/// warning is shown.
+pub struct KeyCheck {
+    pending: HashMap<String, PendingCheck>,
+    /// Peers for which a check has already been started.
+    checked: HashSet<String>,
+}
+
+impl KeyCheck {
+
+    pub fn new() -> KeyCheck {
+        KeyCheck {
+            pending: HashMap::new(),
+            checked: HashSet::new(),
+        }
+    }
+
+    /// Returns the encrypted challenge for the given peer or None if the peer has
+    /// already been checked. Each peer is checked only once.
+    pub fn challenge(&mut self, ip: &str, enc: &dyn Encryption) -> Option<Vec<u8>> {
+
+        if self.checked.contains(ip) {
+            return None;
+        }
+        self.checked.insert(ip.to_string());
+
+        let challenge = (0..CHALLENGE_LEN).map(|_| rand::random::<u8>()).collect::<Vec<u8>>();
+        let plain = MAGIC.iter().chain(challenge.iter()).cloned().collect();
+        let cipher = enc.encrypt(&plain).ok()?;
+
+        self.pending.insert(ip.to_string(), PendingCheck {
+            challenge,
+            cipher: cipher.clone(),
+            deadline: SystemTime::now() + Duration::from_secs(KEYCHECK_TIMEOUT),
+        });
+        Some(cipher)
+    }
+
+    /// Returns true if the given data is a challenge which has been sent by us. This
+    /// happens if we listen on the same device the challenge is sent to, e.g. "lo".
+    pub fn is_own_challenge(&self, data: &[u8]) -> bool {
+        self.pending.values().any(|p| p.cipher == data)
+    }
+
+    /// Computes the response for a challenge received from a peer. Returns None if the
+    /// challenge could not be decrypted with our private key.
+    ///
+    /// Only data with the format of a challenge is answered. Otherwise, a peer could
+    /// be abused to decrypt arbitrary captured messages.
+    pub fn respond(enc: &dyn Encryption, data: &[u8]) -> Option<Vec<u8>> {
+
+        let plain = enc.decrypt(&data.to_vec()).ok()?;
+        if plain.len() != MAGIC.len() + CHALLENGE_LEN || !plain.starts_with(MAGIC) {
+            return None;
+        }
+        Some(KeyCheck::hash(&plain[MAGIC.len()..]))
+    }
+
+    /// Verifies the response of a peer. Returns true if the response is valid. Invalid
+    /// responses are ignored as they could be responses to challenges of other clients.
+    pub fn verify(&mut self, ip: &str, response: &[u8], status: &Status) -> bool {
+
+        let valid = match self.pending.get(ip) {
+            Some(p) => KeyCheck::hash(&p.challenge) == response,
+            None => false
+        };
+        if valid {
+            self.pending.remove(ip);
+            status.info(format!("Public key of {} verified.", ip));
+        }
+        valid
+    }
+
+    /// Warns about all checks for which no valid response has been received until `now`.
+    pub fn expire(&mut self, now: SystemTime, status: &Status) {
+
+        let expired = self.pending.iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(ip, _)| ip.clone())
+            .collect::<Vec<_>>();
+
+        for ip in expired {
+            self.pending.remove(&ip);
+            status.error(format!(
+                "No valid key check response from {} within {} seconds. Your recipient's public key might not match the private key of {}.",
+                ip, KEYCHECK_TIMEOUT, ip
+            ));
+        }
+    }
+
+    fn hash(challenge: &[u8]) -> Vec<u8> {
+        let mut h = Sha256::new();
+        h.input(MAGIC);
+        h.input(challenge);
+        let mut r = vec![0; h.output_bytes()];
+        h.result(&mut r);
+        r
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::{Duration, SystemTime};
+
+    use super::{KeyCheck, KEYCHECK_TIMEOUT};
+    use crate::cryp::AsymmetricEncryption;
+    use crate::status::{Status, StatusMessage};
+
+    fn warnings(rx: &Receiver<StatusMessage>) -> usize {
+        rx.try_iter().filter(|m| match m {
+            StatusMessage::Error(_) => true,
+            _ => false
+        }).count()
+    }
+
+    // Runs a key check between a sender and a receiver over an in-process loopback.
+    fn run(rcpt_pubkey: &str) -> usize {
+
+        let (tx, rx) = channel();
+        let status = Status::new(tx);
+
+        let sender = AsymmetricEncryption::new(rcpt_pubkey, "tests/keys/rsa_priv.pem", None).unwrap();
+        let receiver = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+
+        let mut k = KeyCheck::new();
+        let challenge = k.challenge("127.0.0.1", &sender).unwrap();
+        assert!(k.is_own_challenge(&challenge));
+        // A peer is checked only once.
+        assert!(k.challenge("127.0.0.1", &sender).is_none());
+
+        if let Some(response) = KeyCheck::respond(&receiver, &challenge) {
+            k.verify("127.0.0.1", &response, &status);
+        }
+
+        k.expire(SystemTime::now() + Duration::from_secs(KEYCHECK_TIMEOUT + 1), &status);
+        warnings(&rx)
+    }
+
+    #[test]
+    fn test_matching_keys() {
+        assert_eq!(run("tests/keys/rsa_pub.pem"), 0);
+    }
+
+    #[test]
+    fn test_mismatching_keys() {
+        assert_eq!(run("tests/keys/rsa2_pub.pem"), 1);
+    }
+
+    #[test]
+    fn test_invalid_response_is_ignored() {
+
+        let (tx, rx) = channel();
+        let status = Status::new(tx);
+        let enc = AsymmetricEncryption::new("tests/keys/rsa_pub.pem", "tests/keys/rsa_priv.pem", None).unwrap();
+
+        let mut k = KeyCheck::new();
+        k.challenge("127.0.0.1", &enc).unwrap();
+        assert!(!k.verify("127.0.0.1", &[1, 2, 3], &status));
+        assert_eq!(warnings(&rx), 0);
+
+        // Arbitrary ciphertexts which are not challenges are not answered.
+        let cipher = super::Encryption::encrypt(&enc, &b"hello".to_vec()).unwrap();
+        assert!(KeyCheck::respond(&enc, &cipher).is_none());
+    }
+}
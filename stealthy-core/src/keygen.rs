@@ -0,0 +1,131 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use crypto::ed25519;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::rsa::RSA;
+use crate::rsatools;
+use crate::tools::{sha1, to_hex};
+use crate::error::StealthyError;
+
+const RSA_BITS: u32 = 2048;
+const SEED_LEN: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyType {
+    /// RSA key pair in PEM format for the hybrid mode.
+    Rsa,
+    /// Ed25519 key pair for signatures (see --sign-key and --verify-keys).
+    Ed25519,
+}
+
+impl KeyType {
+
+    pub fn parse(s: &str) -> Result<KeyType, StealthyError> {
+        match s {
+            "rsa" => Ok(KeyType::Rsa),
+            "ed25519" => Ok(KeyType::Ed25519),
+            _ => Err(StealthyError::Crypto("Unknown key type. Use rsa or ed25519."))
+        }
+    }
+}
+
+/// Files which have been written for a new key pair.
+pub struct GeneratedKey {
+    pub private_file: String,
+    pub public_file: String,
+    /// For RSA the hash of the public key as shown in the welcome screen. For Ed25519 the
+    /// public key in hexadecimal.
+    pub fingerprint: String,
+}
+
+/// Generates a new key pair and writes it into `<prefix>_priv.pem` and `<prefix>_pub.pem`
+/// for RSA or `<prefix>.seed` and `<prefix>.pub` for Ed25519. The private key is only
+/// readable by the owner. Existing files are not overwritten.
+pub fn generate(typ: KeyType, prefix: &str) -> Result<GeneratedKey, StealthyError> {
+
+    let (private_file, public_file, private, public, fingerprint) = match typ {
+        KeyType::Rsa => {
+            let (private, public) = RSA::generate(RSA_BITS)?;
+            let fingerprint = sha1(&rsatools::key_as_der(&public));
+            (format!("{}_priv.pem", prefix), format!("{}_pub.pem", prefix), private, public, fingerprint)
+        },
+        KeyType::Ed25519 => {
+            let mut seed = vec![0; SEED_LEN];
+            OsRng::new().map_err(|_| StealthyError::Crypto("Could not get OsRng."))?.fill_bytes(&mut seed);
+            let (_, public) = ed25519::keypair(&seed);
+            let fingerprint = to_hex(&public);
+            (format!("{}.seed", prefix), format!("{}.pub", prefix), to_hex(&seed) + "\n", fingerprint.clone() + "\n", fingerprint)
+        }
+    };
+
+    write_new(&private_file, &private, 0o600)?;
+    write_new(&public_file, &public, 0o644)?;
+    Ok(GeneratedKey { private_file, public_file, fingerprint })
+}
+
+/// The mode is only applied on unix. On Windows the file inherits the permissions of its
+/// directory.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_new(fname: &str, data: &str, mode: u32) -> Result<(), StealthyError> {
+
+    let mut o = OpenOptions::new();
+    o.write(true).create_new(true);
+    #[cfg(unix)]
+    o.mode(mode);
+    let mut f = o.open(fname).map_err(|e| StealthyError::Io("Could not create key file. Does it already exist?", e))?;
+    f.write_all(data.as_bytes()).map_err(|e| StealthyError::Io("Could not write key file.", e))
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{generate, KeyType};
+    use crate::signing::Signing;
+    use crate::cryp::from_hex;
+    use crate::tools::read_file;
+
+    fn prefix(name: &str) -> String {
+        let mut p = std::env::temp_dir();
+        p.push(format!("stealthy_keygen_{}_{}", name, std::process::id()));
+        p.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_generate_ed25519() {
+
+        let k = generate(KeyType::Ed25519, &prefix("ed")).unwrap();
+        let seed = from_hex(read_file(&k.private_file).unwrap().trim().to_string()).unwrap();
+        let s = Signing::disabled().with_seed(&seed).unwrap();
+        assert_eq!(s.public_key(), Some(k.fingerprint.clone()));
+        assert_eq!(read_file(&k.public_file).unwrap().trim(), k.fingerprint);
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&k.private_file).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // Existing keys are not overwritten.
+        assert!(generate(KeyType::Ed25519, &prefix("ed")).is_err());
+
+        fs::remove_file(&k.private_file).unwrap();
+        fs::remove_file(&k.public_file).unwrap();
+    }
+
+    #[test]
+    fn test_key_type() {
+
+        assert_eq!(KeyType::parse("rsa").map_err(|e| e.message()), Ok(KeyType::Rsa));
+        assert_eq!(KeyType::parse("ed25519").map_err(|e| e.message()), Ok(KeyType::Ed25519));
+        assert!(KeyType::parse("dsa").is_err());
+    }
+}
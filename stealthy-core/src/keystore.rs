@@ -0,0 +1,207 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::cryp::{self, ChaChaEncryption, Encryption, from_hex};
+use crate::kdf::KdfParams;
+use crate::tools::{read_file, to_hex};
+use crate::error::StealthyError;
+
+const KEY_LEN: usize = 32;
+
+/// Keys which are stored in a passphrase-encrypted file so that they do not have to be
+/// given on the command line where they would leak into the shell history and `ps`.
+///
+/// The plaintext contains one entry per line:
+///
+/// ```text
+/// key <key in hex>
+/// peer-key <ip> <key in hex>
+/// rsa-recipient <filename>
+/// rsa-private <filename>
+/// rsa-public <filename>
+/// fingerprint <ip> <hash of the public key of the peer>
+/// ```
+///
+/// The file consists of the parameters of the KDF in the first line and the plaintext
+/// encrypted with ChaCha20-Poly1305 in hex in the second line. The key for the encryption
+/// is derived from the passphrase via Argon2id.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keystore {
+    /// The symmetric key.
+    pub key: Option<String>,
+    /// Symmetric keys of single peers.
+    pub peer_keys: Vec<(String, String)>,
+    pub rsa_recipient: Option<String>,
+    pub rsa_private: Option<String>,
+    pub rsa_public: Option<String>,
+    /// Expected hashes of the public keys of the peers.
+    pub fingerprints: Vec<(String, String)>,
+}
+
+impl Default for Keystore {
+    fn default() -> Keystore {
+        Keystore::new()
+    }
+}
+
+impl Keystore {
+
+    pub fn new() -> Keystore {
+        Keystore {
+            key: None,
+            peer_keys: vec![],
+            rsa_recipient: None,
+            rsa_private: None,
+            rsa_public: None,
+            fingerprints: vec![],
+        }
+    }
+
+    /// Loads and decrypts the keystore.
+    pub fn load(fname: &str, passphrase: &str) -> Result<Keystore, StealthyError> {
+        Keystore::decrypt(&read_file(fname)?, passphrase)
+    }
+
+    /// Encrypts the keystore with new KDF parameters and writes it into a file which is
+    /// only readable by the owner.
+    pub fn save(&self, fname: &str, passphrase: &str) -> Result<(), StealthyError> {
+
+        let data = self.encrypt(passphrase, &KdfParams::generate()?)?;
+        let mut o = OpenOptions::new();
+        o.write(true).create(true).truncate(true);
+        // On Windows the file inherits the permissions of its directory.
+        #[cfg(unix)]
+        o.mode(0o600);
+        let mut f = o.open(fname).map_err(|e| StealthyError::Io("Could not create keystore.", e))?;
+        f.write_all(data.as_bytes()).map_err(|e| StealthyError::Io("Could not write keystore.", e))
+    }
+
+    pub fn encrypt(&self, passphrase: &str, params: &KdfParams) -> Result<String, StealthyError> {
+        let cipher = Keystore::cipher(passphrase, params)?
+            .encrypt(&self.encode().into_bytes())?;
+        Ok(format!("{}\n{}\n", params.encode(), to_hex(&cipher)))
+    }
+
+    pub fn decrypt(data: &str, passphrase: &str) -> Result<Keystore, StealthyError> {
+
+        let lines = data.lines().collect::<Vec<_>>();
+        if lines.len() != 2 {
+            return Err(StealthyError::Crypto("Invalid format of keystore."));
+        }
+        let params = KdfParams::parse(lines[0])?;
+        let plain = Keystore::cipher(passphrase, &params)?
+            .decrypt(&from_hex(lines[1].trim().to_string())?)
+            .map_err(|e| match e.message() {
+                cryp::AUTHENTICATION_FAILED => StealthyError::Crypto("Wrong passphrase or damaged keystore."),
+                _ => e
+            })?;
+        Keystore::parse(&String::from_utf8(plain).map_err(|_| StealthyError::Crypto("Invalid format of keystore."))?)
+    }
+
+    pub fn encode(&self) -> String {
+
+        let mut v = vec![];
+        if let Some(ref k) = self.key {
+            v.push(format!("key {}", k));
+        }
+        for (ip, k) in &self.peer_keys {
+            v.push(format!("peer-key {} {}", ip, k));
+        }
+        if let Some(ref f) = self.rsa_recipient {
+            v.push(format!("rsa-recipient {}", f));
+        }
+        if let Some(ref f) = self.rsa_private {
+            v.push(format!("rsa-private {}", f));
+        }
+        if let Some(ref f) = self.rsa_public {
+            v.push(format!("rsa-public {}", f));
+        }
+        for (ip, h) in &self.fingerprints {
+            v.push(format!("fingerprint {} {}", ip, h));
+        }
+        v.iter().map(|l| l.clone() + "\n").collect()
+    }
+
+    pub fn parse(s: &str) -> Result<Keystore, StealthyError> {
+
+        let mut ks = Keystore::new();
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            let parts = line.splitn(2, ' ').collect::<Vec<_>>();
+            let value = parts.get(1).ok_or(StealthyError::Crypto("Invalid entry in keystore."))?.to_string();
+            match parts[0] {
+                "key" => ks.key = Some(value),
+                "peer-key" => ks.peer_keys.push(Keystore::pair(&value)?),
+                "rsa-recipient" => ks.rsa_recipient = Some(value),
+                "rsa-private" => ks.rsa_private = Some(value),
+                "rsa-public" => ks.rsa_public = Some(value),
+                "fingerprint" => ks.fingerprints.push(Keystore::pair(&value)?),
+                _ => return Err(StealthyError::Crypto("Unknown entry in keystore."))
+            }
+        }
+        Ok(ks)
+    }
+
+    /// Returns the keys of the peers in the format of the file for `--peer-keys`.
+    pub fn peer_keys_content(&self) -> String {
+        self.peer_keys.iter().map(|(ip, k)| format!("{} {}\n", ip, k)).collect()
+    }
+
+    fn pair(s: &str) -> Result<(String, String), StealthyError> {
+        let parts = s.splitn(2, ' ').collect::<Vec<_>>();
+        match parts.len() {
+            2 => Ok((parts[0].to_string(), parts[1].to_string())),
+            _ => Err(StealthyError::Crypto("Invalid entry in keystore."))
+        }
+    }
+
+    fn cipher(passphrase: &str, params: &KdfParams) -> Result<ChaChaEncryption, StealthyError> {
+        ChaChaEncryption::new(&to_hex(&params.derive(passphrase, KEY_LEN)?))
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Keystore;
+    use crate::kdf::KdfParams;
+
+    fn keystore() -> Keystore {
+        Keystore {
+            key: Some("11111111111111111111111111111111".to_string()),
+            peer_keys: vec![("1.2.3.4".to_string(), "22222222222222222222222222222222".to_string())],
+            rsa_recipient: Some("/keys/rcpt pub.pem".to_string()),
+            rsa_private: Some("/keys/priv.pem".to_string()),
+            rsa_public: None,
+            fingerprints: vec![("1.2.3.4".to_string(), "12:34:56".to_string())],
+        }
+    }
+
+    fn params() -> KdfParams {
+        KdfParams::parse("argon2id$m=64,t=1,p=1$01010101010101010101010101010101").unwrap()
+    }
+
+    #[test]
+    fn test_encode_parse() {
+
+        let ks = keystore();
+        assert_eq!(Keystore::parse(&ks.encode()).unwrap(), ks);
+        assert_eq!(ks.peer_keys_content(), "1.2.3.4 22222222222222222222222222222222\n");
+        assert!(Keystore::parse("key").is_err());
+        assert!(Keystore::parse("unknown 1").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+
+        let data = keystore().encrypt("secret", &params()).unwrap();
+        assert!(!data.contains("11111111111111111111111111111111"));
+        assert_eq!(Keystore::decrypt(&data, "secret").unwrap(), keystore());
+        assert_eq!(Keystore::decrypt(&data, "wrong").map_err(|e| e.message()), Err("Wrong passphrase or damaged keystore."));
+    }
+}
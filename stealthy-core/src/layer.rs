@@ -0,0 +1,1462 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, SystemTime};
+
+use rand::seq::SliceRandom;
+
+use crate::cryp::{self, Encryption, AsymmetricEncryption, ResultVec};  // Implemenation for encryption layer
+use crate::delivery::{Delivery, SmallMessages};
+use crate::rate::{self, RateControl};
+use crate::binding::{InFlight, Network};
+use crate::message::{IncomingMessage, Message, MessageType};
+use crate::rollover::Announcement;
+use crate::error::StealthyError;
+use crate::iptools::{self, IpAddresses};
+use crate::keycheck::{KeyCheck, KEYCHECK_TIMEOUT};
+use crate::replay::{self, ReplayWindow, Sequencer, Stamp};
+use crate::reorder::{Reorder, DEFAULT_REORDER_TIMEOUT};
+use crate::outbox::Outbox;
+use crate::receipt::{self, Confirmation, Receipts};
+use crate::batch;
+use crate::handshake::{self, Sessions, SessionEvent, RekeyPolicy, HANDSHAKE_TIMEOUT};
+use crate::session;
+use crate::signing::{Signing, SignatureStatus};
+use crate::negotiation::{self, Ciphers};
+use crate::peerkeys::PeerKeys;
+use crate::transfer::{self, Transfers};
+use crate::padding::{self, Padding};
+use crate::sas;
+use crate::secret::SecretBytes;
+use crate::stats::Stats;
+use crate::sendhandle::{SendHandle, WouldBlock};
+use crate::cover::{self, CoverTraffic};
+use crate::presence::{Presence, KEEPALIVE_INTERVAL};
+use crate::group::{Change, Group, Membership};
+use crate::tools;
+use crate::transport::Transport;
+use crate::wire::FRAGMENT_HEADER_LEN;
+use crate::status::Status;
+
+pub struct Layer {
+    pub rx    : Receiver<IncomingMessage>,
+    pub layers: Layers,
+}
+
+impl Layer {
+
+    /// Pads outgoing messages for peers which support it (see `Padding`).
+    pub fn with_padding(mut self, padding: Padding) -> Layer {
+        self.layers.padding = padding;
+        self
+    }
+
+    /// Delays each outgoing packet of a message by a random time of up to `jitter`
+    /// milliseconds (see `SendObject`).
+    pub fn with_jitter(mut self, jitter: u64) -> Layer {
+        self.layers.jitter = jitter;
+        self
+    }
+
+    /// Gives up a message if one of its packets has not been acknowledged after the given
+    /// number of retransmissions (see `Network`).
+    pub fn with_max_retries(self, max_retries: u32) -> Layer {
+        self.layers.network.set_max_retries(max_retries);
+        self
+    }
+
+    /// Limits the rate of all outgoing packets to the given number of bytes per second (see
+    /// `RateControl`).
+    pub fn with_max_rate(self, max_rate: Option<u64>) -> Layer {
+        self.layers.set_max_rate(max_rate);
+        self
+    }
+
+    /// Holds back a received message for up to the given number of milliseconds until the
+    /// messages sent before it have arrived (see `Reorder`). Zero disables the reordering.
+    pub fn with_reorder_timeout(self, timeout: u64) -> Layer {
+        self.layers.reorder.lock().unwrap().set_timeout(timeout);
+        self
+    }
+
+    /// Stores the pending packets in the outbox and retransmits the packets which have been
+    /// left in it by the previous run (see `Outbox`). The last change of the group is
+    /// applied again so that the key of the group is used after a restart.
+    pub fn with_outbox(self, outbox: Option<Outbox>) -> Layer {
+        if let Some(o) = outbox {
+            match o.load_group() {
+                Ok(Some(buf)) => self.layers.restore_group(&buf),
+                Ok(None) => { },
+                Err(e) => self.layers.status.error(format!("Could not load the state of the group: {}", e))
+            }
+            match self.layers.network.set_outbox(o) {
+                Ok(0) => { },
+                Ok(n) => self.layers.status.info(format!("Resending {} packets from the outbox.", n)),
+                Err(e) => self.layers.status.error(format!("Could not load outbox: {}", e))
+            }
+        }
+        self
+    }
+
+    /// Sends messages in the deniable format to peers which support it (see `Ratchet`).
+    pub fn with_deniable(self, deniable: bool) -> Layer {
+        self.layers.sessions.lock().unwrap().set_deniable(deniable);
+        self
+    }
+
+    /// Sets our public key in hybrid mode which is part of the fingerprint of the keys.
+    pub fn with_identity(mut self, public_key: Vec<u8>) -> Layer {
+        self.layers.identity = public_key;
+        self
+    }
+
+    /// Sends dummy messages to the ips which support them (see `CoverTraffic`).
+    pub fn with_cover_traffic(self, cover: Option<CoverTraffic>, ips: Vec<String>) -> Layer {
+        if let Some(c) = cover {
+            self.layers.start_cover_traffic(c, ips);
+        }
+        self
+    }
+
+    /// Returns the incoming messages. The iterator blocks until the next message has been
+    /// received and ends when the layers have been shut down.
+    pub fn incoming(&self) -> impl Iterator<Item = IncomingMessage> + '_ {
+        self.rx.iter()
+    }
+
+    /// Returns the messages which have already been received without waiting for more.
+    pub fn try_iter(&self) -> impl Iterator<Item = IncomingMessage> + '_ {
+        self.rx.try_iter()
+    }
+
+    /// Stops the layers and closes the channel of the received messages (see
+    /// `Layers::shutdown`).
+    pub fn shutdown(self) {
+        self.layers.shutdown();
+    }
+}
+
+pub struct Layers {
+    /// The long-term keys of the peers and the ciphers negotiated with the peers. Each
+    /// conversation with a peer has its own session (see `Sessions`).
+    keys: Arc<PeerKeys>,
+    delivery_layer  : Arc<Box<Delivery>>,
+    /// Sends the packets which are not passed through the delivery layer, e.g. handshakes.
+    network: Network,
+    status: Status,
+    /// Only used in hybrid mode to check the public keys of the recipients.
+    keycheck: Option<Arc<Mutex<KeyCheck>>>,
+    replay: ReplayWindow,
+    /// Sessions with forward secrecy negotiated via the handshake.
+    sessions: Arc<Mutex<Sessions>>,
+    signing: Arc<Mutex<Signing>>,
+    /// Assigns the sequence numbers of outgoing messages.
+    sequencer: Arc<Mutex<Sequencer>>,
+    /// Brings the received messages into the order in which they have been sent.
+    reorder: Arc<Mutex<Reorder<IncomingMessage>>>,
+    /// Messages for which we expect a receipt of the recipient.
+    receipts: Arc<Mutex<Receipts>>,
+    padding: Padding,
+    /// Maximum delay of outgoing packets in milliseconds.
+    jitter: u64,
+    /// Our public key in hybrid mode.
+    identity: Vec<u8>,
+    /// Sends events to the application which are not caused by a received message.
+    events: Sender<IncomingMessage>,
+    /// Queue of each destination from which the messages are sent (see `enqueue`).
+    queues: Arc<Mutex<HashMap<String, Sender<Queued>>>>,
+    /// The members of the group to which messages are sent.
+    group: Arc<Mutex<Group>>,
+    /// Peers for which `IncomingMessage::Writable` is sent when their window has room.
+    blocked: Arc<Mutex<HashSet<String>>>,
+}
+
+/// The settings of the layers which do not depend on the keys (see `LayersBuilder`).
+pub struct Settings {
+    pub transport: Transport,
+    pub status: Status,
+    /// The peers whose packets are accepted.
+    pub accept: IpAddresses,
+    /// Maximum age of received messages in seconds (see `ReplayWindow`).
+    pub replay_window: Option<u64>,
+    pub signing: Signing,
+    pub rekey: RekeyPolicy,
+}
+
+/// The state of the layers which is needed to encrypt, send and open messages. Each
+/// thread which does this works on its own copy (see `Layers::context`).
+#[derive(Clone)]
+struct Context {
+    network: Network,
+    keys: Arc<PeerKeys>,
+    sessions: Arc<Mutex<Sessions>>,
+    signing: Arc<Mutex<Signing>>,
+    sequencer: Arc<Mutex<Sequencer>>,
+    replay: ReplayWindow,
+    reorder: Arc<Mutex<Reorder<IncomingMessage>>>,
+    receipts: Arc<Mutex<Receipts>>,
+    padding: Padding,
+    jitter: u64,
+    rate: Arc<Mutex<RateControl>>,
+    pending: Arc<Mutex<Vec<SmallMessages>>>,
+    status: Status,
+}
+
+/// A message in the queue of its destination (see `Layers::enqueue`).
+struct Queued {
+    msg: Message,
+    id: u64,
+    /// The parts of a file which are encrypted separately (see `Layers::file_parts`).
+    parts: Option<Vec<Vec<u8>>>,
+    /// Is notified when the message has been passed to the delivery layer.
+    done: Option<Sender<()>>,
+}
+
+/// Reason why the messages which are still pending are given up on shutdown.
+const SHUT_DOWN: &str = "The layers have been shut down.";
+/// Reason why a message has been given up if it has been cancelled.
+const CANCELLED: &str = "The message has been cancelled.";
+/// Reason why a message has been given up if it could not be encrypted.
+const ENCRYPTION_FAILED: &str = "Encryption failed.";
+/// Number of times the handshake with a peer is tried at startup.
+const HANDSHAKE_ATTEMPTS: usize = 3;
+/// Number of steps of 100ms a message waits for a running handshake.
+const HANDSHAKE_WAIT_STEPS: usize = 20;
+/// Interval in seconds in which is checked whether sessions have to be rekeyed.
+const REKEY_CHECK_INTERVAL: u64 = 10;
+/// Interval in milliseconds in which messages which have waited too long for the messages
+/// before them are released.
+const REORDER_INTERVAL: u64 = 100;
+/// Time in seconds after which the first keepalive is sent so that the peers have a chance
+/// to advertise their features before.
+const FIRST_KEEPALIVE_DELAY: u64 = 5;
+/// Time in seconds after which the thread of a send queue which has been empty ends.
+const QUEUE_IDLE_TIMEOUT: u64 = 60;
+
+impl Layers {
+
+    /// Creates the layers for symmetric encryption. If `peer_keys` is given, the peers listed
+    /// in it use their own key instead of `hexkey` (see `PeerKeys`).
+    pub fn symmetric(hexkey: &String, cipher: &str, peer_keys: Option<&String>, settings: Settings) -> Result<Layer, StealthyError> {
+
+        let e: Arc<Box<dyn Encryption>> = Arc::new(cryp::symmetric(cipher, hexkey)?);
+        let mut keys = PeerKeys::new(Ciphers::new(e, cryp::symmetric_alternatives(cipher, hexkey)));
+        if let Some(c) = peer_keys {
+            keys = keys.with_keys(c, cipher).map_err(StealthyError::Crypto)?;
+        }
+        Layers::init(keys, false, settings)
+    }
+
+    /// Creates the layers for hybrid encryption. `pubkey_files` can contain several public
+    /// keys separated by commas, one for each accepted ip in the same order. In this
+    /// case the key of each message is encrypted for all of them so that everyone in the
+    /// group can read it.
+    pub fn asymmetric(pubkey_files: &String, privkey_file: &String, pin: Option<&String>, settings: Settings) -> Result<Layer, StealthyError> {
+
+        let files = pubkey_files.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+        let ips = settings.accept.as_strings();
+        if files.len() > 1 && files.len() != ips.len() {
+            return Err(StealthyError::Crypto("The number of recipient keys does not match the number of IPs."));
+        }
+        let group = match files.len() {
+            1 => vec![AsymmetricEncryption::new(files[0], &privkey_file, pin)?],
+            _ => AsymmetricEncryption::group(&files, &privkey_file, pin)?
+        };
+        let group = group.into_iter()
+            .map(|a| Arc::new(Box::new(a) as Box<dyn Encryption>))
+            .collect::<Vec<_>>();
+
+        let mut keys = PeerKeys::new(Ciphers::new(group[0].clone(), vec![]));
+        if group.len() > 1 {
+            for (ip, a) in ips.iter().zip(group) {
+                keys = keys.with_peer(ip, Ciphers::new(a, vec![]));
+            }
+        }
+        Layers::init(keys, true, settings)
+    }
+
+    /// Creates the layers with a custom cipher, e.g. one which is not built into stealthy.
+    /// The cipher is used for all peers, which need the same implementation.
+    pub fn with_encryption(e: Box<dyn Encryption>, settings: Settings) -> Result<Layer, StealthyError> {
+
+        let keys = PeerKeys::new(Ciphers::new(Arc::new(e), vec![]));
+        Layers::init(keys, false, settings)
+    }
+
+    /// Sends the message with the given id via the queue of its destination (see
+    /// `enqueue`). Unless `background` is set, returns when the message has been encrypted
+    /// and passed to the delivery layer. The returned handle can be used to wait until the
+    /// message has been acknowledged or given up.
+    pub fn send(&self, msg: Message, id: u64, background: bool) -> SendHandle {
+
+        let msg = msg.with_id(id);
+        self.check_key(&msg.ip);
+        Layers::handshake(&self.network, &msg.ip, &self.sessions, &self.keys, &self.status);
+
+        let parts = match msg.typ {
+            MessageType::FileUpload => self.file_parts(&msg),
+            _ => None
+        };
+
+        let handle = self.delivery_layer.sends().register(id);
+        let (tx, done) = channel();
+        self.enqueue(Queued { msg, id, parts, done: Some(tx) });
+        if !background {
+            // Fails if the layers have been shut down before.
+            let _ = done.recv();
+        }
+        handle
+    }
+
+    /// Sends the message in background like `send` if the window of the peer has room.
+    /// Otherwise the message is returned with `WouldBlock` and `IncomingMessage::Writable`
+    /// is sent to the application as soon as the window has room again. Unlike `send` the
+    /// caller is never blocked by a congested link.
+    pub fn try_send(&self, msg: Message, id: u64) -> Result<SendHandle, WouldBlock> {
+
+        if self.network.has_room(&msg.ip) {
+            return Ok(self.send(msg, id, true));
+        }
+        self.notify_writable(&msg.ip);
+        Err(WouldBlock(msg))
+    }
+
+    /// Waits in background until the window of the ip has room. Only one thread waits for
+    /// each ip.
+    fn notify_writable(&self, ip: &str) {
+
+        if !self.blocked.lock().unwrap().insert(ip.to_string()) {
+            return;
+        }
+        let network = self.network.clone();
+        let blocked = self.blocked.clone();
+        let events = self.events.clone();
+        let ip = ip.to_string();
+        thread::spawn(move || {
+            let writable = network.wait_for_room(&ip);
+            // Removed before the event is sent so that a following WouldBlock waits again.
+            blocked.lock().unwrap().remove(&ip);
+            if writable {
+                let _ = events.send(IncomingMessage::Writable(ip));
+            }
+        });
+    }
+
+    /// Cancels the message or file upload with the given id which has not been acknowledged
+    /// yet. Its remaining parts are not sent, its packets are not retransmitted anymore, its
+    /// `SendHandle` resolves with an error and `IncomingMessage::Cancelled` is sent to the
+    /// application. A peer which supports it discards the parts received so far. Returns
+    /// false if the message is not pending, e.g. as it is still being encrypted.
+    pub fn cancel(&self, id: u64) -> bool {
+
+        let ip = match self.delivery_layer.cancel(id, CANCELLED) {
+            Some(ip) => ip,
+            None => return false
+        };
+        if self.keys.features().supports(&ip, negotiation::FEATURE_CANCEL) == Some(true) {
+            // Cancellations have a fixed size and are not padded.
+            if let Ok((_, buf)) = Layers::encrypt(&self.context(), &ip, &transfer::encode_cancel(id), Padding::Off, 0) {
+                let _ = self.network.send_cancel(buf, ip.clone());
+            }
+        }
+        let _ = self.events.send(IncomingMessage::Cancelled(ip, id));
+        true
+    }
+
+    /// Returns the ids of the messages and file uploads which have not been acknowledged
+    /// yet and can be cancelled.
+    pub fn unacknowledged(&self) -> Vec<u64> {
+        self.delivery_layer.unacknowledged()
+    }
+
+    /// Sends several messages. Small messages to the same destination are packed into one
+    /// packet as long as they fit into the maximum payload size. This saves the overhead of
+    /// a packet, the encryption and an ACK for each message.
+    ///
+    /// Returns for each message the id which is reported when the message is acknowledged.
+    /// Messages which have been packed into the same packet share the same id.
+    pub fn send_batch(&self, msgs: Vec<Message>) -> Vec<u64> {
+
+        let mut ids = vec![0; msgs.len()];
+
+        // Group the messages by their destination and keep the order of the messages.
+        let mut ips: Vec<String> = vec![];
+        for m in &msgs {
+            if !ips.contains(&m.ip) {
+                ips.push(m.ip.clone());
+            }
+        }
+
+        let overhead = match self.overhead() {
+            Ok(n) => n + batch::LEN_SIZE,
+            Err(_) => {
+                self.status.info(format!("Encryption failed."));
+                return ids;
+            }
+        };
+
+        // Create the packets first so that the caller knows the ids before the first
+        // packet is acknowledged.
+        let mut packets = vec![];
+        for ip in ips {
+            self.check_key(&ip);
+            Layers::handshake(&self.network, &ip, &self.sessions, &self.keys, &self.status);
+            let idx = (0..msgs.len()).filter(|i| msgs[*i].ip == ip).collect::<Vec<_>>();
+            let sizes = idx.iter().map(|i| msgs[*i].buf.len()).collect::<Vec<_>>();
+
+            for group in batch::groups(&sizes, overhead, self.delivery_layer.max_size(&ip)) {
+                let id = rand::random::<u64>();
+                let msg = if group.len() == 1 {
+                    msgs[idx[group[0]]].set_payload(msgs[idx[group[0]]].buf.clone())
+                } else {
+                    Message::batch(ip.clone(), batch::pack(&group.iter().map(|i| msgs[idx[*i]].buf.as_slice()).collect::<Vec<_>>()))
+                };
+                packets.push((msg, id));
+                for i in group {
+                    ids[idx[i]] = id;
+                }
+            }
+        }
+
+        for (msg, id) in packets {
+            self.enqueue(Queued { msg, id, parts: None, done: None });
+        }
+        ids
+    }
+
+    /// Sends the message in background. Each destination has its own queue which is
+    /// processed by its own thread so that the messages to a peer are sent in order and a
+    /// slow or unreachable peer whose window is full does not delay the messages to other
+    /// peers. The thread ends when the queue has been empty for `QUEUE_IDLE_TIMEOUT`
+    /// seconds and is started again with the next message.
+    fn enqueue(&self, q: Queued) {
+
+        let ip = q.msg.ip.clone();
+        let mut queues = self.queues.lock().unwrap();
+        let tx = queues.entry(ip.clone()).or_insert_with(|| self.start_queue(&ip));
+        if let Err(e) = tx.send(q) {
+            // The thread of the queue has died. The message is sent via a new queue.
+            let tx = self.start_queue(&ip);
+            let _ = tx.send(e.0);
+            queues.insert(ip, tx);
+        }
+    }
+
+    fn start_queue(&self, ip: &str) -> Sender<Queued> {
+
+        let (tx, rx) = channel::<Queued>();
+        let ctx = self.context();
+        let sends = self.delivery_layer.sends();
+        let queues = self.queues.clone();
+        let ip = ip.to_string();
+
+        thread::spawn(move || loop {
+            let q = match rx.recv_timeout(Duration::from_secs(QUEUE_IDLE_TIMEOUT)) {
+                Ok(q) => q,
+                Err(RecvTimeoutError::Timeout) => {
+                    // Messages are enqueued while the queues are locked. Hence, no message
+                    // can arrive after the queue has been found empty here.
+                    let mut queues = queues.lock().unwrap();
+                    match rx.try_recv() {
+                        Ok(q) => q,
+                        Err(_) => {
+                            queues.remove(&ip);
+                            return;
+                        }
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => return
+            };
+            let n = ctx.network.current_size(&q.msg.ip);
+            if let Err(e) = Layers::encrypt_and_send(&ctx, q.msg, q.parts, q.id, n) {
+                sends.resolve(q.id, Err(e.to_string()));
+            }
+            if let Some(done) = q.done {
+                let _ = done.send(());
+            }
+        });
+        tx
+    }
+
+    /// Returns a copy of the state which the background threads need to encrypt, send and
+    /// open messages.
+    fn context(&self) -> Context {
+        Context {
+            network: self.network.clone(),
+            keys: self.keys.clone(),
+            sessions: self.sessions.clone(),
+            signing: self.signing.clone(),
+            sequencer: self.sequencer.clone(),
+            replay: self.replay.clone(),
+            reorder: self.reorder.clone(),
+            receipts: self.receipts.clone(),
+            padding: self.padding,
+            jitter: self.jitter,
+            rate: self.delivery_layer.rate(),
+            pending: self.delivery_layer.get_pending(),
+            status: self.status.clone(),
+        }
+    }
+
+    /// Returns whether the ip supports the optional feature or None if it has not advertised
+    /// its features yet (see `Features`).
+    pub fn supports(&self, ip: &str, feature: &str) -> Option<bool> {
+        self.keys.features().supports(ip, feature)
+    }
+
+    /// Returns the key which is used to encrypt messages for the ip. In hybrid mode this
+    /// is the public key of the ip.
+    pub fn encryption_key(&self, ip: &str) -> SecretBytes {
+        self.keys.get(ip).primary().encryption_key()
+    }
+
+    /// Returns the public key in hexadecimal which verifies our signatures.
+    pub fn signing_key(&self) -> Option<String> {
+        self.signing.lock().unwrap().public_key()
+    }
+
+    /// Returns our public key in DER format in hybrid mode, otherwise an empty vector.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.identity.clone()
+    }
+
+    /// Returns the fingerprint of the keys which are used for the ip (see `sas`).
+    pub fn key_fingerprint(&self, ip: &str) -> Vec<u8> {
+        sas::fingerprint(&self.identity, self.encryption_key(ip).as_slice())
+    }
+
+    /// Returns the statistics of the layers, e.g. the number of sent and received packets,
+    /// the bytes exchanged with each peer and the round-trip times.
+    pub fn stats(&self) -> Stats {
+        let mut s = self.network.stats();
+        s.dropped_replays = self.replay.dropped() as u64;
+        s.upload_rate = self.delivery_layer.rate().lock().unwrap().rate();
+        s
+    }
+
+    /// Returns the packets which have not been acknowledged yet with their age and the
+    /// number of retransmissions, the oldest first.
+    pub fn pending(&self) -> Vec<InFlight> {
+        self.network.pending()
+    }
+
+    /// Returns the maximum rate of outgoing packets in bytes per second.
+    pub fn max_rate(&self) -> Option<u64> {
+        self.delivery_layer.rate().lock().unwrap().max()
+    }
+
+    pub fn set_max_rate(&self, max_rate: Option<u64>) {
+        self.delivery_layer.rate().lock().unwrap().set_max(max_rate);
+    }
+
+    /// Starts a new session with each of the given ips even if the current session has not
+    /// expired yet. The old sessions are discarded shortly after the new ones have been
+    /// established.
+    pub fn rekey(&self, ips: Vec<String>) {
+
+        let now = replay::now();
+        for ip in ips {
+            let hello = {
+                let mut s = self.sessions.lock().unwrap();
+                if s.is_pending(&ip, now) {
+                    continue;
+                }
+                if self.keys.features().supports(&ip, negotiation::FEATURE_VERSION) == Some(true) {
+                    s.set_versioned(&ip);
+                }
+                s.initiate(&ip, self.keys.get(&ip).primary(), now)
+            };
+            Layers::send_hello(&self.network, &ip, hello, &self.status);
+        }
+    }
+
+    /// Replaces our signing key by the key derived from the seed or revokes it if no seed
+    /// is given. The announcement signed with the old key is sent to the given ips so that
+    /// they trust the new key (see `rollover`).
+    pub fn roll_over(&self, seed: Option<&[u8]>, ips: Vec<String>) -> Result<(), StealthyError> {
+
+        let buf = self.signing.lock().unwrap().roll_over(seed)?;
+        for ip in ips {
+            if self.keys.features().supports(&ip, negotiation::FEATURE_KEY_ROLLOVER) != Some(true) {
+                self.status.info(format!("{} does not support key rollover. Its keys for verification have to be updated manually.", ip));
+                continue;
+            }
+            self.send(Message::rollover(ip, buf.clone()), rand::random::<u64>(), true);
+        }
+        Ok(())
+    }
+
+    // ------ private functions
+
+    /// Returns the overhead of a packet which is the overhead of the encryption with the
+    /// stamp, the signature and the header of the padding plus the header of the part of a
+    /// message added by the delivery layer.
+    fn overhead(&self) -> Result<usize, StealthyError> {
+
+        let empty = self.signing.lock().unwrap().sign(&[0; replay::STAMP_LEN]);
+        Ok(cmp::max(self.keys.max_sealed_len(&empty)?, 1 + session::MAX_OVERHEAD + empty.len()) + padding::HEADER_LEN + FRAGMENT_HEADER_LEN)
+    }
+
+    /// Splits a file into parts which are encrypted separately if the peer supports it
+    /// (see `transfer`). Returns None if the file is sent in the old format.
+    fn file_parts(&self, msg: &Message) -> Option<Vec<Vec<u8>>> {
+
+        match self.keys.features().supports(&msg.ip, negotiation::FEATURE_FILE_METADATA) {
+            Some(true) => { },
+            Some(false) => {
+                self.status.info(format!("{} runs an older version of stealthy. The file is sent in the old format in which its size is visible.", msg.ip));
+                return None;
+            },
+            None => {
+                self.status.info(format!("{} has not advertised whether it supports encrypted file metadata. The file is sent in the old format in which its size is visible.", msg.ip));
+                return None;
+            }
+        }
+
+        let maxsiz = self.delivery_layer.max_size(&msg.ip).checked_sub(self.overhead().ok()?)?;
+        let id = msg.id.unwrap_or_else(rand::random::<u64>);
+        match transfer::split(id, &msg.get_filename()?, &msg.get_filedata()?, maxsiz) {
+            Ok(parts) => Some(parts),
+            Err(e) => {
+                self.status.info(format!("{} The file is sent in the old format.", e));
+                None
+            }
+        }
+    }
+
+    fn encrypt_and_send(ctx: &Context, msg: Message, parts: Option<Vec<Vec<u8>>>, id: u64, n: usize) -> Result<(), StealthyError> {
+
+        // Wait a moment for a running handshake so that the message can be sent with
+        // forward secrecy.
+        for _ in 0..HANDSHAKE_WAIT_STEPS {
+            if !ctx.sessions.lock().unwrap().is_pending(&msg.ip, replay::now()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        // The parts of a file are encrypted separately. The receipt for any of the parts is
+        // sent when the whole file has been received.
+        if let Some(parts) = parts {
+            return match parts.iter().map(|b| Layers::encrypt(ctx, &msg.ip, b, ctx.padding, n - FRAGMENT_HEADER_LEN)).collect::<Result<Vec<_>, _>>() {
+                Ok(v) => {
+                    let bufs = v.into_iter().map(|(stamp, buf)| {
+                        ctx.receipts.lock().unwrap().expect(&msg.ip, &stamp, id);
+                        buf
+                    }).collect();
+                    Delivery::send_parts(msg, bufs, id, ctx.pending.clone(), ctx.network.clone(), ctx.status.clone()).with_jitter(ctx.jitter).with_rate(ctx.rate.clone()).run();
+                    Ok(())
+                },
+                _ => {
+                    ctx.status.info(format!("Encryption failed."));
+                    Err(StealthyError::Crypto(ENCRYPTION_FAILED))
+                }
+            };
+        }
+
+        match Layers::encrypt(ctx, &msg.ip, &msg.buf, ctx.padding, n - FRAGMENT_HEADER_LEN) {
+            Ok((stamp, buf)) => {
+                ctx.receipts.lock().unwrap().expect(&msg.ip, &stamp, id);
+                Delivery::send_msg(msg.set_payload(buf), id, ctx.pending.clone(), ctx.network.clone(), ctx.status.clone(), n).with_jitter(ctx.jitter).with_rate(ctx.rate.clone()).run();
+                Ok(())
+            },
+            _ => {
+                ctx.status.info(format!("Encryption failed."));
+                Err(StealthyError::Crypto(ENCRYPTION_FAILED))
+            }
+        }
+    }
+
+    /// Encrypts the payload of a message for the ip. Returns the stamp of the message and
+    /// the ciphertext. `capacity` is the number of bytes of the ciphertext which fit into
+    /// one packet.
+    fn encrypt(ctx: &Context, ip: &String, buf: &[u8], padding: Padding, capacity: usize) -> Result<(Stamp, Vec<u8>), StealthyError> {
+
+        let keys = &ctx.keys;
+        // The timestamp and the sequence number are encrypted together with the message so
+        // that the receiver can detect replayed messages. The signature covers the stamp.
+        let stamp = ctx.sequencer.lock().unwrap().next(ip, replay::now());
+        let mut plain = ctx.signing.lock().unwrap().sign(&replay::stamp(buf, &stamp));
+
+        // Only peers which support padding can remove it.
+        if padding != Padding::Off && keys.features().supports(ip, negotiation::FEATURE_PADDING) == Some(true) {
+            let overhead = cmp::max(keys.max_sealed_len(&vec![])?, 1 + session::MAX_OVERHEAD);
+            plain = padding::pad(&plain, padding.target(plain.len(), capacity, overhead));
+        }
+        let deniable = keys.features().supports(ip, negotiation::FEATURE_DENIABLE) == Some(true);
+        let session = ctx.sessions.lock().unwrap().encrypt(ip, &plain, deniable);
+
+        // Without a session the long-term key of the peer is used with the cipher
+        // negotiated with the peer.
+        let r = match session {
+            Some(r) => r.map(|c| [cryp::CIPHER_SESSION].iter().chain(c.iter()).cloned().collect()),
+            None => keys.seal(ip, &plain)
+        };
+        r.map(|buf| (stamp, buf))
+    }
+
+    /// Returns a sender for the ips and stamps of received messages which have been shown
+    /// to the user. A read receipt is sent in background for each of them.
+    pub fn read_receipts(&self) -> Sender<(String, Stamp)> {
+
+        let (tx, rx) = channel::<(String, Stamp)>();
+        let ctx = self.context();
+
+        thread::spawn(move || {
+            for (ip, stamp) in rx {
+                Layers::send_encrypted_receipt(&ctx, &ip, receipt::encode_read(&stamp));
+            }
+        });
+        tx
+    }
+
+    /// Sends a keepalive with our state to each of the ips which support them every
+    /// `KEEPALIVE_INTERVAL` seconds (see `presence`). Returns a sender for changes of our
+    /// state which are announced immediately.
+    pub fn keepalives(&self, ips: Vec<String>) -> Sender<Presence> {
+
+        let (tx, rx) = channel::<Presence>();
+        let ctx = self.context();
+
+        thread::spawn(move || {
+            let mut state = Presence::Online;
+            let mut wait = FIRST_KEEPALIVE_DELAY;
+            loop {
+                match rx.recv_timeout(Duration::from_secs(wait)) {
+                    Ok(p) => state = p,
+                    Err(RecvTimeoutError::Timeout) => wait = KEEPALIVE_INTERVAL,
+                    Err(RecvTimeoutError::Disconnected) => break
+                }
+                if ctx.network.shutdown_signal().is_stopped() {
+                    break;
+                }
+                for ip in &ips {
+                    Layers::send_keepalive(&ctx, ip, state);
+                }
+            }
+        });
+        tx
+    }
+
+    /// Returns the current members of the group.
+    pub fn members(&self) -> Vec<String> {
+        self.group.lock().unwrap().members()
+    }
+
+    /// Adds a peer at runtime. Its packets are accepted, it becomes a member of the group
+    /// and a handshake is started. Returns false if the ip is already a peer.
+    pub fn add_peer(&self, ip: &str) -> bool {
+
+        let ip = iptools::canonical(ip);
+        {
+            // The group is locked so that the accepted peers and the members change together.
+            let mut group = self.group.lock().unwrap();
+            if !self.network.accept(&ip) {
+                return false;
+            }
+            group.add_peer(&ip);
+        }
+        self.start_handshakes(vec![ip]);
+        true
+    }
+
+    /// Removes a peer at runtime. Its packets are dropped and messages to the group are not
+    /// sent to it anymore. Returns false if the ip is not a peer.
+    pub fn remove_peer(&self, ip: &str) -> bool {
+
+        let ip = iptools::canonical(ip);
+        let mut group = self.group.lock().unwrap();
+        if !self.network.remove_accepted(&ip) {
+            return false;
+        }
+        group.remove_peer(&ip);
+        true
+    }
+
+    /// Returns the peers whose packets are accepted.
+    pub fn list_peers(&self) -> Vec<String> {
+        self.network.accepted()
+    }
+
+    /// Invites a peer to the group or removes it. The change is signed and sent to the
+    /// members after the change together with a new key for the group. Afterwards the new
+    /// key is used so that a removed member cannot read new messages (see `group`).
+    pub fn change_group(&self, c: Change) -> Result<(), StealthyError> {
+
+        if self.keycheck.is_some() {
+            return Err(StealthyError::Protocol("The members of the group can only be changed with symmetric encryption."));
+        }
+        if self.signing.lock().unwrap().public_key().is_none() {
+            return Err(StealthyError::Crypto("Changes of the group have to be signed. A signing key is required."));
+        }
+        let m = self.group.lock().unwrap().change(c)?;
+        let buf = m.encode();
+        for ip in &m.members {
+            if self.keys.features().supports(ip, negotiation::FEATURE_GROUP) != Some(true) {
+                self.status.info(format!("{} does not support group changes and cannot read new messages.", ip));
+                continue;
+            }
+            // Returns after the message has been encrypted with the current key.
+            self.send(Message::group(ip.clone(), buf.clone()), rand::random::<u64>(), false);
+        }
+        self.keys.set_default_key(&tools::to_hex(m.key.as_slice())).map_err(StealthyError::Crypto)?;
+        self.group.lock().unwrap().apply(&m, None);
+        Layers::save_group(&self.network, &self.group, &self.status);
+        Ok(())
+    }
+
+    /// Applies the change of the group which has been stored by the previous run.
+    fn restore_group(&self, buf: &[u8]) {
+        let r = self.group.lock().unwrap().restore(buf);
+        match r {
+            Ok(m) => match self.keys.set_default_key(&tools::to_hex(m.key.as_slice())) {
+                Ok(_) => self.status.info(format!("Using the key of the group from change {}.", m.epoch)),
+                Err(e) => self.status.error(format!("Could not apply the stored key of the group: {}", e))
+            },
+            Err(e) => self.status.error(format!("Could not restore the state of the group: {}", e))
+        }
+    }
+
+    /// Stores the last change of the group in the outbox so that the key of the group is
+    /// not lost when we are restarted.
+    fn save_group(network: &Network, group: &Arc<Mutex<Group>>, status: &Status) {
+        let state = group.lock().unwrap().encode_state();
+        match (network.outbox(), state) {
+            (Some(o), Some(buf)) => if let Err(e) = o.save_group(&buf) {
+                status.error(format!("Could not save the state of the group: {}", e));
+            },
+            (None, _) => status.info("The key of the group is lost when stealthy is restarted. Use an outbox to keep it.".to_string()),
+            _ => { }
+        }
+    }
+
+    /// Stops the layers. The collected ACKs are sent and the pending packets are saved in
+    /// the outbox. Returns when the background threads have finished and the sockets have
+    /// been closed. Afterwards no messages can be sent or received but new layers can be
+    /// created, e.g. on the same port. Handles of messages which are still pending are
+    /// given up.
+    pub fn shutdown(&self) {
+        // The threads which send the queued messages return when their queue is closed.
+        self.queues.lock().unwrap().clear();
+        self.network.shutdown();
+        self.delivery_layer.sends().resolve_all(SHUT_DOWN);
+    }
+
+    /// Tells the given ips that we are going offline so that they do not have to wait
+    /// until our keepalives are missing. Returns when the keepalives have been sent.
+    pub fn announce_offline(&self, ips: Vec<String>) {
+        let ctx = self.context();
+        for ip in &ips {
+            Layers::send_keepalive(&ctx, ip, Presence::Offline);
+        }
+    }
+
+    /// Sends a keepalive with the given state to the ip if it supports keepalives.
+    fn send_keepalive(ctx: &Context, ip: &String, state: Presence) {
+
+        if ctx.keys.features().supports(ip, negotiation::FEATURE_PRESENCE) != Some(true) {
+            return;
+        }
+        // Keepalives have a fixed size and are not padded. Like messages they are signed.
+        if let Ok((_, buf)) = Layers::encrypt(ctx, ip, &state.encode(), Padding::Off, 0) {
+            let _ = ctx.network.send_keepalive(buf, ip.clone());
+        }
+    }
+
+    /// Sends the receipt for a received message with the given stamp to the ip.
+    fn send_receipt(ctx: &Context, ip: &String, stamp: &Stamp) {
+        Layers::send_encrypted_receipt(ctx, ip, receipt::encode(stamp));
+    }
+
+    fn send_encrypted_receipt(ctx: &Context, ip: &String, buf: Vec<u8>) {
+
+        // Older peers which do not support receipts would drop them anyway.
+        if ctx.keys.features().supports(ip, negotiation::FEATURE_RECEIPT) == Some(false) {
+            return;
+        }
+        // Receipts have a fixed size and are not padded.
+        if let Ok((_, buf)) = Layers::encrypt(ctx, ip, &buf, Padding::Off, 0) {
+            let _ = ctx.network.send_receipt(buf, ip.clone());
+        }
+    }
+
+    /// Starts the key check for the given ip if the key of the ip has not been checked yet.
+    /// If no valid response is received in time a warning is shown.
+    fn check_key(&self, ip: &String) {
+
+        let keycheck = match self.keycheck {
+            Some(ref k) => k.clone(),
+            None => return
+        };
+
+        // In a group the challenge can be answered by each member. The handshake, however, is
+        // only accepted if it has been signed with the key of the ip.
+        let challenge = keycheck.lock().unwrap().challenge(ip, self.keys.get(ip).primary());
+        if let Some(buf) = challenge {
+            if let Err(e) = self.network.send_key_challenge(buf, ip.clone()) {
+                self.status.error(format!("Could not send key check to {}: {}", ip, e));
+            }
+            let status = self.status.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_secs(KEYCHECK_TIMEOUT));
+                keycheck.lock().unwrap().expire(SystemTime::now(), &status);
+            });
+        }
+    }
+
+    /// Starts a handshake with the given ip if there is no session with the ip and no
+    /// handshake is in progress.
+    fn handshake(network: &Network, ip: &String, sessions: &Arc<Mutex<Sessions>>, keys: &Arc<PeerKeys>, status: &Status) {
+
+        let now = replay::now();
+        let hello = {
+            let mut s = sessions.lock().unwrap();
+            s.discard_old(now);
+            if !s.needs_handshake(ip, now) {
+                return;
+            }
+            if keys.features().supports(ip, negotiation::FEATURE_VERSION) == Some(true) {
+                s.set_versioned(ip);
+            }
+            s.initiate(ip, keys.get(ip).primary(), now)
+        };
+        Layers::send_hello(network, ip, hello, status);
+    }
+
+    /// Sends the first message of a handshake to the ip.
+    fn send_hello(network: &Network, ip: &String, hello: ResultVec, status: &Status) {
+
+        match hello {
+            Ok(buf) => if let Err(e) = network.send_handshake(buf, ip.clone()) {
+                status.error(format!("Could not send handshake to {}: {}", ip, e));
+            },
+            Err(e) => status.error(format!("Could not create handshake for {}: {}", ip, e))
+        }
+    }
+
+    /// Replaces the sessions with the given ips in background when they have expired even
+    /// if no messages are sent.
+    /// Checks the sessions with the peers periodically. Peers added at runtime are included.
+    fn start_rekey_timer(&self) {
+
+        let sessions = self.sessions.clone();
+        let keys = self.keys.clone();
+        let status = self.status.clone();
+        let events = self.events.clone();
+        let network = self.network.clone();
+        let shutdown = self.network.shutdown_signal();
+        shutdown.clone().spawn(move || { while shutdown.sleep(Duration::from_secs(REKEY_CHECK_INTERVAL)) {
+            for ip in &network.accepted() {
+                let due = {
+                    let s = sessions.lock().unwrap();
+                    s.is_established(ip) && s.needs_rekey(ip, replay::now())
+                };
+                // Also discards the keys of old sessions.
+                Layers::handshake(&network, ip, &sessions, &keys, &status);
+                if due {
+                    status.info(format!("Rekeying session with {}.", ip));
+                }
+            }
+            Layers::session_events(&sessions, &events);
+        }});
+    }
+
+    /// Sends dummy messages at random intervals in background. Each dummy message is sent
+    /// to one of the ips which support them.
+    fn start_cover_traffic(&self, cover: CoverTraffic, ips: Vec<String>) {
+
+        let ctx = self.context();
+        let shutdown = self.network.shutdown_signal();
+        shutdown.clone().spawn(move || { while shutdown.sleep(cover.interval()) {
+            let peers = ips.iter()
+                .filter(|ip| ctx.keys.features().supports(ip, negotiation::FEATURE_COVER) == Some(true))
+                .collect::<Vec<_>>();
+            if let Some(ip) = peers.choose(&mut rand::thread_rng()) {
+                let msg = Message::new((*ip).clone(), cover::decoy());
+                let n = ctx.network.current_size(ip);
+                let _ = Layers::encrypt_and_send(&ctx, msg, None, rand::random::<u64>(), n);
+            }
+        }});
+    }
+
+    /// Establishes sessions with the given ips in background.
+    fn start_handshakes(&self, ips: Vec<String>) {
+
+        for ip in ips {
+            let sessions = self.sessions.clone();
+            let keys = self.keys.clone();
+            let status = self.status.clone();
+            let network = self.network.clone();
+            thread::spawn(move || {
+                for _ in 0..HANDSHAKE_ATTEMPTS {
+                    Layers::handshake(&network, &ip, &sessions, &keys, &status);
+                    if !network.shutdown_signal().sleep(Duration::from_secs(HANDSHAKE_TIMEOUT as u64)) {
+                        break;
+                    }
+                    if sessions.lock().unwrap().is_established(&ip) {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    fn init(keys: PeerKeys, keycheck: bool, settings: Settings) -> Result<Layer, StealthyError> {
+
+        // network  tx1 --- incoming message ---> rx1 delivery
+        // delivery tx2 --- incoming message ---> rx2 layers
+        let (tx1, rx1) = channel();
+        let (tx2, rx2) = channel();
+        let keys = Arc::new(keys);
+        let ips = settings.accept.as_strings();
+        let status = settings.status.clone();
+        let l = Layers::new(keys.clone(),
+                       keycheck,
+                       Delivery::new(
+                           Network::new(&settings.transport, tx1, status.clone(), &settings.accept, keys)?,
+                           tx2,
+                           rx1,
+                           status,
+                       ),
+                       rx2,
+                       settings
+        );
+        l.layers.start_handshakes(ips);
+        l.layers.start_rekey_timer();
+        Ok(l)
+    }
+
+    fn new(keys: Arc<PeerKeys>, keycheck: bool, d: Delivery, rx_network: Receiver<IncomingMessage>, settings: Settings) -> Layer {
+
+        // tx is used to send received messages to the application via rx
+        let (tx, rx) = channel::<IncomingMessage>();
+
+        let l = Layers {
+            keys,
+            network: d.network(),
+            delivery_layer: Arc::new(Box::new(d)),
+            status: settings.status,
+            keycheck: if keycheck { Some(Arc::new(Mutex::new(KeyCheck::new()))) } else { None },
+            replay: ReplayWindow::new(settings.replay_window),
+            sessions: Arc::new(Mutex::new(Sessions::new(settings.rekey))),
+            signing: Arc::new(Mutex::new(settings.signing)),
+            sequencer: Arc::new(Mutex::new(Sequencer::new())),
+            reorder: Arc::new(Mutex::new(Reorder::new(DEFAULT_REORDER_TIMEOUT))),
+            receipts: Arc::new(Mutex::new(Receipts::new())),
+            padding: Padding::Off,
+            jitter: 0,
+            identity: vec![],
+            events: tx.clone(),
+            queues: Arc::new(Mutex::new(HashMap::new())),
+            group: Arc::new(Mutex::new(Group::new(settings.accept.as_strings()))),
+            blocked: Arc::new(Mutex::new(HashSet::new())),
+        };
+
+        l.recv_loop(tx, rx_network);
+        Layer {
+            rx: rx,
+            layers: l,
+        }
+    }
+
+    /// Listens for incoming messages and processes them. Stops when the layers are shut
+    /// down or the application has dropped the receiver of the incoming messages.
+    fn recv_loop(&self, tx: Sender<IncomingMessage>, rx: Receiver<IncomingMessage>) {
+
+        let ctx = self.context();
+        let keycheck = self.keycheck.clone();
+        let group = self.group.clone();
+        let counters = self.network.counters();
+
+        let shutdown = self.network.shutdown_signal();
+        shutdown.clone().spawn(move || {
+            let mut transfers = Transfers::new();
+            while !shutdown.is_stopped() {
+                let open = match rx.recv_timeout(Duration::from_millis(REORDER_INTERVAL)) {
+                    Ok(IncomingMessage::KeyChallenge(msg)) => {
+                        Layers::handle_key_challenge(&ctx.network, msg, &keycheck, &ctx.keys);
+                        true
+                    },
+                    Ok(IncomingMessage::KeyResponse(msg)) => {
+                        Layers::handle_key_response(msg, &keycheck, &ctx.status);
+                        true
+                    },
+                    Ok(IncomingMessage::Handshake(msg)) => {
+                        Layers::handle_handshake(&ctx.network, msg, &ctx.sessions, &ctx.keys, &ctx.status);
+                        true
+                    },
+                    Ok(IncomingMessage::Receipt(msg)) => Layers::handle_receipt(&ctx, msg, &tx),
+                    Ok(IncomingMessage::Keepalive(msg)) => Layers::handle_keepalive(&ctx, msg, &tx),
+                    Ok(IncomingMessage::Cancel(msg)) => Layers::handle_cancel(&ctx, msg, &mut transfers, &tx),
+                    Ok(IncomingMessage::Group(msg)) => Layers::handle_group(&ctx, msg, &group, &tx),
+                    Ok(msg) => {
+                        let peer = msg.message().map(|m| (m.get_ip(), m.id));
+                        match Layers::handle_message(&ctx, msg, &mut transfers) {
+                            // The vector is empty if the message has been rejected.
+                            Ok(v) => Layers::forward(v, &tx),
+                            Err(e) => {
+                                let e = match e.message() {
+                                    handshake::NO_SESSION => StealthyError::Protocol("Received a message of an unknown session. A new session is established."),
+                                    cryp::AUTHENTICATION_FAILED => {
+                                        counters.decryption_failed();
+                                        StealthyError::Crypto("Rejected a received message which has been modified or which has not been encrypted with your key.")
+                                    },
+                                    _ => {
+                                        counters.decryption_failed();
+                                        StealthyError::Crypto("Could not decrypt received message.")
+                                    }
+                                };
+                                match peer {
+                                    Some((ip, id)) => Layers::err(e.with_peer(&ip, id), &tx),
+                                    None => Layers::err(e, &tx)
+                                }
+                            }
+                        }
+                    },
+                    Err(RecvTimeoutError::Timeout) => true,
+                    _ => Layers::err(StealthyError::Transport("Could not receive message."), &tx)
+                };
+                // Messages which have waited too long for the messages before them.
+                let expired = ctx.reorder.lock().unwrap().expire(rate::now_millis());
+                // Nobody listens anymore if the application has dropped the receiver.
+                if !open || !Layers::forward(expired, &tx) || !Layers::session_events(&ctx.sessions, &tx) {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Answers the key check challenge of a peer. Challenges are only answered in hybrid mode.
+    fn handle_key_challenge(network: &Network, msg: Message, keycheck: &Option<Arc<Mutex<KeyCheck>>>, keys: &Arc<PeerKeys>) {
+
+        if let Some(k) = keycheck {
+            if k.lock().unwrap().is_own_challenge(&msg.buf) {
+                return;
+            }
+            if let Some(response) = KeyCheck::respond(keys.get(&msg.ip).primary(), &msg.buf) {
+                let _ = network.send_key_response(response, msg.get_ip());
+            }
+        }
+    }
+
+    fn handle_key_response(msg: Message, keycheck: &Option<Arc<Mutex<KeyCheck>>>, status: &Status) {
+
+        if let Some(k) = keycheck {
+            k.lock().unwrap().verify(&msg.ip, &msg.buf, status);
+        }
+    }
+
+    fn handle_handshake(network: &Network, msg: Message, sessions: &Arc<Mutex<Sessions>>, keys: &Arc<PeerKeys>, status: &Status) {
+
+        let mut s = sessions.lock().unwrap();
+        if keys.features().supports(&msg.ip, negotiation::FEATURE_VERSION) == Some(true) {
+            s.set_versioned(&msg.ip);
+        }
+        match s.handle(&msg.ip, &msg.buf, keys.get(&msg.ip).primary(), replay::now()) {
+            Ok(Some(reply)) => if let Err(e) = network.send_handshake(reply, msg.get_ip()) {
+                status.error(format!("Could not send handshake to {}: {}", msg.ip, e));
+            },
+            Ok(None) => { },
+            Err(e) => status.info(format!("{} (from {})", e, msg.ip))
+        }
+        // Warn once per version instead of failing later with messages which cannot be
+        // decoded.
+        if let Some(v) = s.take_version(&msg.ip) {
+            if keys.features().learn_version(&msg.ip, &v) {
+                if let Some(w) = v.warning(&msg.ip) {
+                    status.info(w);
+                }
+            }
+        }
+    }
+
+    /// Notifies the application about sessions which have been opened or closed. Returns
+    /// false if the application does not receive messages anymore.
+    fn session_events(sessions: &Arc<Mutex<Sessions>>, tx: &Sender<IncomingMessage>) -> bool {
+
+        let events = sessions.lock().unwrap().take_events();
+        let v = events.into_iter().map(|e| match e {
+            SessionEvent::Opened(s) => IncomingMessage::SessionOpened(s),
+            SessionEvent::Closed(s) => IncomingMessage::SessionClosed(s),
+        }).collect();
+        Layers::forward(v, tx)
+    }
+
+    /// Opens a message which is not shown itself, e.g. a receipt, and passes its stamp, its
+    /// content and the status of its signature to `handle`. The messages returned by
+    /// `handle` are sent to the application followed by the messages which the stamp
+    /// releases from the reorder buffer. Invalid messages are ignored. Returns false if the
+    /// application does not receive messages anymore.
+    fn handle_control<F>(ctx: &Context, msg: &Message, tx: &Sender<IncomingMessage>, handle: F) -> bool
+        where F: FnOnce(&Stamp, Vec<u8>, SignatureStatus) -> Vec<IncomingMessage> {
+
+        match Layers::open(ctx, msg) {
+            Ok(Some((stamp, buf, sig))) => {
+                let mut v = handle(&stamp, buf, sig);
+                v.extend(Layers::in_order(&ctx.reorder, &msg.ip, &stamp, vec![]));
+                Layers::forward(v, tx)
+            },
+            _ => true
+        }
+    }
+
+    /// Notifies the application if a receipt confirms the delivery of a message. Receipts
+    /// are best effort. Hence, invalid receipts are ignored.
+    fn handle_receipt(ctx: &Context, msg: Message, tx: &Sender<IncomingMessage>) -> bool {
+
+        Layers::handle_control(ctx, &msg, tx, |_, buf, _| {
+            ctx.receipts.lock().unwrap().confirm(&msg.ip, &buf)
+                .map(|c| match c {
+                    Confirmation::Delivered(id) => IncomingMessage::Delivered(id),
+                    Confirmation::Read(id) => IncomingMessage::Read(id)
+                })
+                .into_iter()
+                .collect()
+        })
+    }
+
+    /// Notifies the application about the state announced by a keepalive. Like receipts,
+    /// invalid keepalives are ignored.
+    fn handle_keepalive(ctx: &Context, msg: Message, tx: &Sender<IncomingMessage>) -> bool {
+
+        Layers::handle_control(ctx, &msg, tx, |_, buf, _| {
+            Presence::decode(&buf).map(|p| IncomingMessage::Presence(msg.get_ip(), p)).into_iter().collect()
+        })
+    }
+
+    /// Discards the parts of a file transfer which the peer has cancelled and tells the
+    /// application about the cancellation.
+    fn handle_cancel(ctx: &Context, msg: Message, transfers: &mut Transfers, tx: &Sender<IncomingMessage>) -> bool {
+
+        Layers::handle_control(ctx, &msg, tx, |_, buf, _| {
+            match transfer::decode_cancel(&buf) {
+                Ok(id) => {
+                    transfers.cancel(&msg.ip, id);
+                    vec![IncomingMessage::Cancelled(msg.get_ip(), id)]
+                },
+                Err(e) => {
+                    ctx.status.info(format!("{} (from {})", e, msg.ip));
+                    vec![]
+                }
+            }
+        })
+    }
+
+    /// Applies a change of the members of the group if it has been signed with a trusted
+    /// key. The default key is replaced by the key of the group.
+    fn handle_group(ctx: &Context, msg: Message, group: &Arc<Mutex<Group>>, tx: &Sender<IncomingMessage>) -> bool {
+
+        Layers::handle_control(ctx, &msg, tx, |stamp, buf, sig| {
+            Layers::send_receipt(ctx, &msg.ip, stamp);
+            let status = &ctx.status;
+            match (Membership::decode(&buf), sig) {
+                (Ok(m), SignatureStatus::Verified) => {
+                    if !group.lock().unwrap().apply(&m, Some(&msg.ip)) {
+                        status.info(format!("Ignored an outdated group change of {}.", msg.ip));
+                    } else {
+                        match ctx.keys.set_default_key(&tools::to_hex(m.key.as_slice())) {
+                            Ok(_) => status.info(Layers::describe_change(&msg.ip, &m.change, &group.lock().unwrap())),
+                            Err(e) => status.error(format!("Could not apply the group key of {}: {}", msg.ip, e))
+                        }
+                        Layers::save_group(&ctx.network, group, status);
+                    }
+                },
+                (Ok(_), _) => status.info(format!("Ignored the group change of {} because it is not signed with a trusted key.", msg.ip)),
+                (Err(e), _) => status.info(format!("{} (from {})", e, msg.ip))
+            }
+            vec![]
+        })
+    }
+
+    /// Returns the status which is shown when a peer has changed the group. The ip of a
+    /// change which is not one of our peers is our own.
+    fn describe_change(from: &str, c: &Change, group: &Group) -> String {
+        let (verb, ip) = match c {
+            Change::Invite(ip) => ("invited", ip),
+            Change::Remove(ip) => ("removed", ip),
+        };
+        let who = match group.is_peer(ip) {
+            true => ip.as_str(),
+            false => "you"
+        };
+        format!("{} has {} {}. Members: {}", from, verb, who, group.members().join(", "))
+    }
+
+    /// Passes the items of a received message with the given stamp to the reorder buffer
+    /// and returns the items which can be released (see `Reorder`).
+    fn in_order(reorder: &Arc<Mutex<Reorder<IncomingMessage>>>, ip: &str, stamp: &Stamp, items: Vec<IncomingMessage>) -> Vec<IncomingMessage> {
+        reorder.lock().unwrap().push(ip, stamp, items, rate::now_millis())
+    }
+
+    /// Notifies the application about an error. Returns false if the application does not
+    /// receive messages anymore.
+    fn err(e: StealthyError, tx: &Sender<IncomingMessage>) -> bool {
+        tx.send(IncomingMessage::Error(e)).is_ok()
+    }
+
+    /// Sends the messages to the application. Returns false if the application does not
+    /// receive messages anymore.
+    fn forward(v: Vec<IncomingMessage>, tx: &Sender<IncomingMessage>) -> bool {
+        v.into_iter().all(|m| tx.send(m).is_ok())
+    }
+
+    /// Decrypts incoming messages of type "new" or returns the message without
+    /// modification if it is not of type "new". A batch is unpacked into several messages.
+    /// Returns an empty vector if the message has been rejected as replay.
+    fn handle_message(ctx: &Context, m: IncomingMessage, transfers: &mut Transfers) -> Result<Vec<IncomingMessage>, StealthyError> {
+
+        let status = &ctx.status;
+        let reorder = &ctx.reorder;
+
+        #[cfg(feature="debugout")]
+            status.info(String::from("[Layers::handle_message()] decrypting message"));
+
+        match m {
+            IncomingMessage::New(msg) => {
+                #[cfg(feature="debugout")]
+                    status.info(format!("[Layers::handle_message()] new message {}", msg.buf.len()));
+
+                // Dummy messages are confirmed like real messages but not shown.
+                match Layers::open_and_confirm(ctx, &msg)? {
+                    Some((stamp, buf, sig)) => {
+                        let v = match cover::is_cover(&buf) {
+                            true => vec![],
+                            false => vec![IncomingMessage::New(msg.set_payload(buf).set_signature(sig).with_stamp(stamp))]
+                        };
+                        Ok(Layers::in_order(reorder, &msg.ip, &stamp, v))
+                    },
+                    None => Ok(vec![])
+                }
+            },
+            IncomingMessage::FileUpload(msg) => {
+                match Layers::open(ctx, &msg)? {
+                    // A part of a file whose metadata is encrypted (see `transfer`).
+                    Some((stamp, buf, sig)) if transfer::is_part(&buf) => {
+                        let v = match transfers.insert(&msg.ip, &buf, sig, replay::now()).map_err(StealthyError::Protocol)? {
+                            Some((name, data, sig)) => {
+                                Layers::send_receipt(ctx, &msg.ip, &stamp);
+                                vec![IncomingMessage::FileUpload(Message::file_upload(msg.get_ip(), name, &data).set_signature(sig).with_device(msg.device.clone()))]
+                            },
+                            None => vec![]
+                        };
+                        Ok(Layers::in_order(reorder, &msg.ip, &stamp, v))
+                    },
+                    Some((stamp, buf, sig)) => {
+                        status.info(format!("{} runs an older version of stealthy. The size of the received file has been visible.", msg.ip));
+                        Layers::send_receipt(ctx, &msg.ip, &stamp);
+                        let v = vec![IncomingMessage::FileUpload(msg.set_payload(buf).set_signature(sig))];
+                        Ok(Layers::in_order(reorder, &msg.ip, &stamp, v))
+                    },
+                    None => Ok(vec![])
+                }
+            },
+            IncomingMessage::Batch(msg) => {
+                match Layers::open_and_confirm(ctx, &msg)? {
+                    Some((stamp, buf, sig)) => {
+                        let v = batch::unpack(&buf)
+                            .ok_or(StealthyError::Protocol("Invalid batch."))?
+                            .into_iter()
+                            .map(|b| IncomingMessage::New(Message::new(msg.get_ip(), b).set_signature(sig).with_device(msg.device.clone()).with_stamp(stamp)))
+                            .collect();
+                        Ok(Layers::in_order(reorder, &msg.ip, &stamp, v))
+                    },
+                    None => Ok(vec![])
+                }
+            },
+            IncomingMessage::Ack(_) => Ok(vec![m]),
+            IncomingMessage::Error(_) => Ok(vec![m]),
+            IncomingMessage::AckProgress(_, _, _) => Ok(vec![m]),
+            IncomingMessage::Delivered(_) => Ok(vec![m]),
+            IncomingMessage::Read(_) => Ok(vec![m]),
+            IncomingMessage::Presence(_, _) => Ok(vec![m]),
+            IncomingMessage::SendFailed(_, _) => Ok(vec![m]),
+            IncomingMessage::Writable(_) => Ok(vec![m]),
+            IncomingMessage::Cancelled(_, _) => Ok(vec![m]),
+            IncomingMessage::KeyChanged(_, _) => Ok(vec![m]),
+            IncomingMessage::SessionOpened(_) => Ok(vec![m]),
+            IncomingMessage::SessionClosed(_) => Ok(vec![m]),
+            IncomingMessage::Rollover(msg) => {
+                match Layers::open(ctx, &msg)? {
+                    Some((stamp, buf, _)) => {
+                        Layers::send_receipt(ctx, &msg.ip, &stamp);
+                        let v = match Announcement::verify(&buf) {
+                            Ok(a) => {
+                                let r = ctx.signing.lock().unwrap().apply(&msg.ip, &a);
+                                match r {
+                                    Ok(true) => vec![IncomingMessage::KeyChanged(msg.get_ip(), a)],
+                                    Ok(false) => {
+                                        status.info(format!("Ignored the key rollover of {} because its old key is not trusted.", msg.ip));
+                                        vec![]
+                                    },
+                                    // The keys in memory have been updated nevertheless.
+                                    Err(e) => {
+                                        status.info(e.to_string());
+                                        vec![IncomingMessage::KeyChanged(msg.get_ip(), a)]
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                status.info(format!("{} (from {})", e, msg.ip));
+                                vec![]
+                            }
+                        };
+                        Ok(Layers::in_order(reorder, &msg.ip, &stamp, v))
+                    },
+                    None => Ok(vec![])
+                }
+            },
+            // Timeouts are handled by the delivery layer.
+            IncomingMessage::Timeout(_, _, _) | IncomingMessage::PacketFailed(_, _) => Ok(vec![]),
+            // Key checks, receipts, keepalives and group changes are handled in recv_loop.
+            IncomingMessage::KeyChallenge(_) | IncomingMessage::KeyResponse(_) | IncomingMessage::Handshake(_) | IncomingMessage::Receipt(_) |
+            IncomingMessage::Keepalive(_) | IncomingMessage::Group(_) | IncomingMessage::Cancel(_) => Ok(vec![])
+        }
+    }
+
+    /// Like `open` but sends a receipt to the sender if the message has been accepted.
+    fn open_and_confirm(ctx: &Context, msg: &Message) -> Result<Option<(Stamp, Vec<u8>, SignatureStatus)>, StealthyError> {
+
+        Ok(Layers::open(ctx, msg)?.map(|(stamp, buf, sig)| {
+            Layers::send_receipt(ctx, &msg.ip, &stamp);
+            (stamp, buf, sig)
+        }))
+    }
+
+    /// Decrypts the payload of a message, checks the signature and removes the stamp.
+    /// Returns None if the message is rejected by the replay window or has already been
+    /// received.
+    fn open(ctx: &Context, msg: &Message) -> Result<Option<(Stamp, Vec<u8>, SignatureStatus)>, StealthyError> {
+
+        let replay = &ctx.replay;
+        let plain = match msg.buf.first() {
+            Some(&cryp::CIPHER_SESSION) => {
+                let r = ctx.sessions.lock().unwrap().decrypt(&msg.ip, &msg.buf[1..], replay::now());
+                // The peer uses a session we do not know, e.g. because we have been restarted.
+                match r.as_ref().map_err(|e| e.message()) {
+                    Err(handshake::NO_SESSION) => Layers::handshake(&ctx.network, &msg.ip, &ctx.sessions, &ctx.keys, &ctx.status),
+                    // Retransmitted packets are dropped quietly.
+                    Err(session::REPLAYED) => {
+                        replay.count_dropped();
+                        return Ok(None);
+                    },
+                    _ => { }
+                }
+                r?
+            },
+            _ => ctx.keys.open(&msg.ip, &msg.buf)?
+        };
+        let plain = padding::unpad(plain)?;
+        let (plain, sig) = ctx.signing.lock().unwrap().verify(&msg.ip, &plain)?;
+        match replay.check(&plain, replay::now()) {
+            // Replays of messages which are still within the window are dropped quietly.
+            Ok((stamp, _)) if !replay.is_new(&msg.ip, &stamp) => Ok(None),
+            Ok((stamp, buf)) => Ok(Some((stamp, buf, sig))),
+            Err(reason) => {
+                ctx.status.info(format!("{} (from {})", reason, msg.ip));
+                Ok(None)
+            }
+        }
+    }
+}
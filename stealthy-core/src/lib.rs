@@ -0,0 +1,60 @@
+pub mod tools;
+pub mod rsatools;
+pub mod message;
+pub mod layer;
+pub mod cryp;
+mod delivery;
+pub mod binding;
+pub mod iptools;
+pub mod blowfish;
+mod packet;
+mod rsa;
+pub mod error;
+pub mod status;
+mod keycheck;
+pub mod replay;
+mod batch;
+pub mod handshake;
+mod session;
+pub mod signing;
+pub mod negotiation;
+pub mod kdf;
+pub mod peerkeys;
+pub mod keystore;
+pub mod keygen;
+mod pq;
+pub mod secret;
+mod receipt;
+mod transfer;
+pub mod padding;
+pub mod sas;
+pub mod gpg;
+pub mod sshkey;
+pub mod pkcs11;
+pub mod rollover;
+pub mod transport;
+pub mod link;
+pub mod memory;
+pub mod socks;
+pub mod cover;
+pub mod camouflage;
+mod window;
+mod rtt;
+pub mod rate;
+pub mod reorder;
+mod dedup;
+pub mod outbox;
+mod wire;
+pub mod presence;
+pub mod envelope;
+pub mod group;
+pub mod builder;
+mod shutdown;
+pub mod sendhandle;
+pub mod stats;
+pub mod asyncio;
+pub mod handlers;
+#[cfg(unix)]
+mod bpf;
+#[cfg(windows)]
+mod winsock;
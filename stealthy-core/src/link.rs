@@ -0,0 +1,50 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::StealthyError;
+
+/// Carries the packets of the network layer to the peers, e.g. the in-memory network of
+/// `MemoryNetwork` which connects the layers of tests without raw sockets. The network
+/// layer above still acknowledges and retransmits the packets and the layers encrypt them.
+///
+/// This is the only place where the layers can be replaced by a fake. There is no trait
+/// for the delivery layer on purpose: a fake of it would skip the splitting, the ACKs and
+/// the retransmissions which the tests over a `MemoryNetwork` exercise.
+pub trait PacketTransport: Send + Sync {
+
+    /// Sends the packet to the given ip. A packet which gets lost is not an error.
+    fn send(&self, ip: &str, buf: &[u8]) -> Result<(), StealthyError>;
+
+    /// Waits up to `timeout` for the next packet and returns it with the ip of its sender.
+    fn recv(&self, timeout: Duration) -> Option<(Vec<u8>, String)>;
+
+    /// Called on shutdown after the receiver has stopped.
+    fn close(&self) { }
+}
+
+/// Transport of the application which is used with `Transport::Custom`. Two of them are
+/// equal if they share the same transport.
+#[derive(Clone)]
+pub struct CustomTransport(pub Arc<dyn PacketTransport>);
+
+impl CustomTransport {
+
+    pub fn new<T: PacketTransport + 'static>(t: T) -> CustomTransport {
+        CustomTransport(Arc::new(t))
+    }
+}
+
+impl PartialEq for CustomTransport {
+
+    fn eq(&self, other: &CustomTransport) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for CustomTransport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CustomTransport")
+    }
+}
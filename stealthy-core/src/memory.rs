@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use crate::error::StealthyError;
+use crate::link::{CustomTransport, PacketTransport};
+use crate::transport::Transport;
+
+type Hosts = Arc<Mutex<HashMap<String, Sender<(Vec<u8>, String)>>>>;
+
+/// Network in memory which connects the layers of several hosts in one process, e.g. in
+/// tests. Packets are neither delayed nor reordered but a fraction of them can be dropped
+/// to exercise the retransmissions.
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    hosts: Hosts,
+    loss: Arc<Mutex<Loss>>,
+}
+
+/// Drops a fraction of the packets at regular intervals instead of randomly, so that tests
+/// do not depend on luck. A packet is dropped at most once: its retransmission, which has
+/// the same content, is delivered.
+struct Loss {
+    fraction: f64,
+    /// Sum of the fractions of the packets which have not been dropped yet.
+    credit: f64,
+    /// Hashes of the packets which have been dropped.
+    dropped: HashSet<u64>,
+}
+
+impl Loss {
+
+    fn new(fraction: f64) -> Loss {
+        Loss {
+            fraction,
+            credit: 0.0,
+            dropped: HashSet::new(),
+        }
+    }
+
+    /// Returns true if the packet is dropped.
+    fn drop_packet(&mut self, buf: &[u8]) -> bool {
+        let mut h = DefaultHasher::new();
+        buf.hash(&mut h);
+        if self.dropped.contains(&h.finish()) {
+            return false;
+        }
+        self.credit += self.fraction;
+        if self.credit >= 1.0 {
+            self.credit -= 1.0;
+            self.dropped.insert(h.finish());
+            return true;
+        }
+        false
+    }
+}
+
+impl MemoryNetwork {
+
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork {
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            loss: Arc::new(Mutex::new(Loss::new(0.0))),
+        }
+    }
+
+    /// Adds a host with the given ip and returns its transport for `LayersBuilder::with_transport`.
+    /// The host is removed when its layers are shut down.
+    pub fn host(&self, ip: &str) -> Transport {
+        let (tx, rx) = channel();
+        self.hosts.lock().unwrap().insert(ip.to_string(), tx);
+        Transport::Custom(CustomTransport::new(MemoryLink {
+            ip: ip.to_string(),
+            hosts: self.hosts.clone(),
+            loss: self.loss.clone(),
+            rx: Mutex::new(rx),
+        }))
+    }
+
+    /// Drops the given fraction of the packets between 0.0 and 1.0, e.g. every fifth packet
+    /// for 0.2. Retransmitted packets are not dropped again.
+    pub fn set_loss(&self, loss: f64) {
+        *self.loss.lock().unwrap() = Loss::new(loss);
+    }
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> MemoryNetwork {
+        MemoryNetwork::new()
+    }
+}
+
+/// Transport of a host of the `MemoryNetwork`.
+struct MemoryLink {
+    ip: String,
+    hosts: Hosts,
+    loss: Arc<Mutex<Loss>>,
+    rx: Mutex<Receiver<(Vec<u8>, String)>>,
+}
+
+impl PacketTransport for MemoryLink {
+
+    fn send(&self, ip: &str, buf: &[u8]) -> Result<(), StealthyError> {
+        if self.loss.lock().unwrap().drop_packet(buf) {
+            return Ok(());
+        }
+        // Like on a real network packets to hosts which do not exist get lost.
+        if let Some(tx) = self.hosts.lock().unwrap().get(ip) {
+            let _ = tx.send((buf.to_vec(), self.ip.clone()));
+        }
+        Ok(())
+    }
+
+    fn recv(&self, timeout: Duration) -> Option<(Vec<u8>, String)> {
+        self.rx.lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    fn close(&self) {
+        self.hosts.lock().unwrap().remove(&self.ip);
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+    use super::MemoryNetwork;
+    use crate::builder::LayersBuilder;
+    use crate::cryp::{Encryption, ResultVec, CIPHER_CUSTOM};
+    use crate::iptools::IpAddresses;
+    use crate::message::{IncomingMessage, Message};
+    use crate::secret::SecretBytes;
+
+    const KEY: &str = "11111111111111111111111111111111";
+
+    /// Toy cipher which shows that applications can plug in their own implementation.
+    struct Xor(u8);
+
+    impl Encryption for Xor {
+        fn encrypt(&self, v: &Vec<u8>) -> ResultVec { Ok(v.iter().map(|b| b ^ self.0).collect()) }
+        fn decrypt(&self, v: &Vec<u8>) -> ResultVec { self.encrypt(v) }
+        fn encryption_key(&self) -> SecretBytes { SecretBytes::new(vec![self.0]) }
+        fn cipher_id(&self) -> u8 { CIPHER_CUSTOM }
+        fn sign(&self, v: &[u8]) -> ResultVec { Ok(vec![v.iter().fold(self.0, |a, b| a.wrapping_add(*b))]) }
+        fn verify(&self, v: &[u8], sig: &[u8]) -> bool { self.sign(v).ok().as_deref() == Some(sig) }
+    }
+
+    #[test]
+    fn test_memory_network() {
+
+        let net = MemoryNetwork::new();
+        let layer = |ip: &str, peer: &str| LayersBuilder::new()
+            .with_key(KEY)
+            .with_transport(net.host(ip))
+            .with_accept(IpAddresses::from_comma_list(peer))
+            .build()
+            .unwrap();
+        let a = layer("10.0.0.1", "10.0.0.2");
+        let b = layer("10.0.0.2", "10.0.0.1");
+
+        // Some packets get lost and are retransmitted until they are acknowledged. As each
+        // packet is lost at most once, the message is acknowledged after a few seconds.
+        net.set_loss(0.2);
+        let handle = a.layers.send(Message::new("10.0.0.2".to_string(), b"hello".to_vec()), 1, false);
+        assert_eq!(handle.wait_timeout(Duration::from_secs(30)), Some(Ok(())));
+        let received = b.incoming().find_map(|m| match m {
+            IncomingMessage::New(m) => Some(m),
+            _ => None
+        });
+        assert_eq!(received.map(|m| (m.get_ip(), m.get_payload())), Some(("10.0.0.1".to_string(), b"hello".to_vec())));
+
+        a.shutdown();
+        b.shutdown();
+    }
+
+    #[test]
+    fn test_custom_encryption() {
+
+        let net = MemoryNetwork::new();
+        let layer = |ip: &str, peer: &str| LayersBuilder::new()
+            .with_encryption(Box::new(Xor(0x5a)))
+            .with_transport(net.host(ip))
+            .with_accept(IpAddresses::from_comma_list(peer))
+            .build()
+            .unwrap();
+        let a = layer("10.0.0.1", "10.0.0.2");
+        let b = layer("10.0.0.2", "10.0.0.1");
+
+        let handle = a.layers.send(Message::new("10.0.0.2".to_string(), b"hello".to_vec()), 1, false);
+        assert_eq!(handle.wait_timeout(Duration::from_secs(30)), Some(Ok(())));
+        let received = b.incoming().find_map(|m| match m {
+            IncomingMessage::New(m) => Some(m.get_payload()),
+            _ => None
+        });
+        assert_eq!(received, Some(b"hello".to_vec()));
+
+        a.shutdown();
+        b.shutdown();
+    }
+
+    #[test]
+    fn test_add_peer() {
+
+        let net = MemoryNetwork::new();
+        let a = LayersBuilder::new().with_key(KEY).with_transport(net.host("10.0.0.1")).build().unwrap();
+        let b = LayersBuilder::new().with_key(KEY).with_transport(net.host("10.0.0.2"))
+            .with_accept(IpAddresses::from_comma_list("10.0.0.1"))
+            .build()
+            .unwrap();
+
+        // a accepts the ACKs of b only after b has been added.
+        assert!(a.layers.add_peer("10.0.0.2"));
+        assert!(!a.layers.add_peer("10.0.0.2"));
+        assert_eq!(a.layers.list_peers(), vec!["10.0.0.2".to_string()]);
+        assert_eq!(a.layers.members(), vec!["10.0.0.2".to_string()]);
+        // The window of the new peer has room.
+        let handle = a.layers.try_send(Message::new("10.0.0.2".to_string(), b"hello".to_vec()), 1).unwrap();
+        assert_eq!(handle.wait_timeout(Duration::from_secs(30)), Some(Ok(())));
+
+        assert!(a.layers.remove_peer("10.0.0.2"));
+        assert!(a.layers.list_peers().is_empty());
+        assert!(a.layers.members().is_empty());
+
+        a.shutdown();
+        b.shutdown();
+    }
+}
@@ -0,0 +1,311 @@
+//use crypto::sha2::Sha256;
+//use crypto::digest::Digest;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::StealthyError;
+use crate::handshake::SessionInfo;
+use crate::presence::Presence;
+use crate::replay::Stamp;
+use crate::rollover::Announcement;
+use crate::signing::SignatureStatus;
+
+// Messages are passed between the threads of the layers. All fields are owned values, so
+// that the compiler derives Send and Sync. This fails to compile if a field breaks it.
+const _: fn() = || {
+    fn assert_thread_safe<T: Send + Sync>() { }
+    assert_thread_safe::<IncomingMessage>();
+    assert_thread_safe::<Message>();
+};
+
+pub enum IncomingMessage {
+    New(Message),
+    Ack(u64),
+    AckProgress(u64, usize, usize),
+    Error(StealthyError),
+    FileUpload(Message),
+    /// Several small messages packed into one message.
+    Batch(Message),
+    /// Challenge of a peer to check that its public key matches our private key.
+    KeyChallenge(Message),
+    /// Response of a peer to our key check challenge.
+    KeyResponse(Message),
+    /// Message of the handshake which establishes a session with a peer.
+    Handshake(Message),
+    /// Encrypted receipt of a peer for a message we have sent.
+    Receipt(Message),
+    /// The peer has confirmed with a valid receipt that it has received the message with
+    /// the given id.
+    Delivered(u64),
+    /// The peer has confirmed with a valid read receipt that the message with the given id
+    /// has been shown on its screen.
+    Read(u64),
+    /// Encrypted keepalive of a peer.
+    Keepalive(Message),
+    /// The peer with the given ip has announced its state with a valid keepalive.
+    Presence(String, Presence),
+    /// Announcement of a peer that it has replaced or revoked its signing key.
+    Rollover(Message),
+    /// Change of the members of the group by a peer.
+    Group(Message),
+    /// Encrypted cancellation of a message or file transfer by a peer.
+    Cancel(Message),
+    /// The trusted signing key of the peer with the given ip has been replaced or revoked.
+    KeyChanged(String, Announcement),
+    /// A session with a peer has been established and is used from now on.
+    SessionOpened(SessionInfo),
+    /// The keys of a session with a peer have been discarded.
+    SessionClosed(SessionInfo),
+    /// The packet with the given id to the given ip has been retransmitted the given number
+    /// of times as it has not been acknowledged. Only passed from the network to the
+    /// delivery layer.
+    Timeout(String, u64, u32),
+    /// A router or the peer has rejected the packet with the given id with an ICMP error
+    /// for the given reason. Only passed from the network to the delivery layer.
+    PacketFailed(u64, String),
+    /// The message with the given id cannot be delivered for the given reason.
+    SendFailed(u64, String),
+    /// The window of the peer with the given ip has room again after `Layers::try_send`
+    /// has returned `WouldBlock`.
+    Writable(String),
+    /// The message or file transfer with the given id to or from the peer with the given ip
+    /// has been cancelled, either by us (see `Layers::cancel`) or by the peer.
+    Cancelled(String, u64),
+}
+
+impl IncomingMessage {
+    /// Returns the message of a peer which is carried by this event, if any.
+    pub fn message(&self) -> Option<&Message> {
+        match self {
+            IncomingMessage::New(m) | IncomingMessage::FileUpload(m) | IncomingMessage::Batch(m) |
+            IncomingMessage::KeyChallenge(m) | IncomingMessage::KeyResponse(m) |
+            IncomingMessage::Handshake(m) | IncomingMessage::Receipt(m) |
+            IncomingMessage::Keepalive(m) | IncomingMessage::Rollover(m) |
+            IncomingMessage::Group(m) | IncomingMessage::Cancel(m) => Some(m),
+            _ => None
+        }
+    }
+}
+
+impl Clone for MessageType {
+    fn clone(&self) -> MessageType {
+        match *self {
+            MessageType::NewMessage => MessageType::NewMessage,
+            //MessageType::AckMessage => MessageType::AckMessage,
+            MessageType::FileUpload => MessageType::FileUpload,
+            MessageType::Batch => MessageType::Batch,
+            MessageType::Rollover => MessageType::Rollover,
+            MessageType::Group => MessageType::Group
+        }
+    }
+}
+
+/// A message to or from a peer. With the feature `serde` messages can be serialized, e.g.
+/// by frontends which store the received messages.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Message {
+    /// Contains the destination ip for outgoing messages, source ip from incoming messages.
+    pub ip : String,
+    /// Id of an outgoing message which is reported when the message is acknowledged.
+    pub id: Option<u64>,
+    pub typ: MessageType,
+    pub buf: Vec<u8>,
+    /// Result of the signature check for incoming messages.
+    pub signature: SignatureStatus,
+    /// Device on which an incoming message has been received. None for outgoing messages
+    /// and if the device is not known, e.g. for the device "any" or the UDP transport.
+    pub device: Option<String>,
+    /// Stamp of an incoming message which is confirmed with a read receipt when the
+    /// message is shown.
+    pub stamp: Option<Stamp>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq)]
+pub enum MessageType {
+    NewMessage,
+    //AckMessage,
+    FileUpload,
+    Batch,
+    Rollover,
+    Group
+}
+
+impl Message {
+    pub fn file_upload(ip: String, fname: String, data: &Vec<u8>) -> Message {
+        let mut buffer = Vec::from(fname.as_bytes());
+        buffer.push(0);
+        buffer.extend(data.iter());
+        Message::create(ip, buffer, MessageType::FileUpload)
+    }
+
+    pub fn new(ip: String, buf: Vec<u8>) -> Message {
+        Message::create(ip, buf, MessageType::NewMessage)
+    }
+
+    /// Creates a message which contains several small messages packed via `batch::pack`.
+    pub fn batch(ip: String, buf: Vec<u8>) -> Message {
+        Message::create(ip, buf, MessageType::Batch)
+    }
+
+    /// Creates a message which announces a new signing key (see `rollover`).
+    pub fn rollover(ip: String, buf: Vec<u8>) -> Message {
+        Message::create(ip, buf, MessageType::Rollover)
+    }
+
+    /// Creates a message which changes the members of the group (see `group`).
+    pub fn group(ip: String, buf: Vec<u8>) -> Message {
+        Message::create(ip, buf, MessageType::Group)
+    }
+
+    /*
+    pub fn ack(ip: String) -> Message {
+        Message::create(ip, vec![], MessageType::AckMessage)
+    }*/
+
+    pub fn set_payload(&self, buf: Vec<u8>) -> Message {
+        let mut m = Message::create(self.get_ip(), buf, self.get_type()).with_device(self.device.clone());
+        m.id = self.id;
+        m
+    }
+
+    /// Sets the id of an outgoing message.
+    pub fn with_id(mut self, id: u64) -> Message {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets the result of the signature check.
+    pub fn set_signature(mut self, s: SignatureStatus) -> Message {
+        self.signature = s;
+        self
+    }
+
+    /// Sets the stamp of a received message.
+    pub fn with_stamp(mut self, stamp: Stamp) -> Message {
+        self.stamp = Some(stamp);
+        self
+    }
+
+    /// Sets the device on which the message has been received.
+    pub fn with_device(mut self, device: Option<String>) -> Message {
+        self.device = device;
+        self
+    }
+
+    pub fn get_payload(&self) -> Vec<u8> { self.buf.clone() }
+
+    /// Returns the destination ip for outgoing messages or the source ip from incoming messages.
+    pub fn get_ip(&self) -> String { self.ip.clone() }
+
+    pub fn get_type(&self) -> MessageType { self.typ.clone() }
+
+    /// Returns the destination ip for outgoing messages or the source ip from incoming messages.
+    pub fn peer(&self) -> &str { &self.ip }
+
+    pub fn kind(&self) -> &MessageType { &self.typ }
+
+    /// Returns the time in seconds when an incoming message has been sent by the peer.
+    pub fn timestamp(&self) -> Option<i64> {
+        self.stamp.map(|s| s.time)
+    }
+
+    /// Returns the name and the size of the file of a file upload.
+    pub fn file(&self) -> Option<FileInfo> {
+        match self.typ {
+            MessageType::FileUpload => {
+                let pos = self.buf.iter().position(|x| *x == 0)?;
+                let name = String::from_utf8(self.buf[..pos].to_vec()).ok()?;
+                Some(FileInfo { name: sanitize_filename(name), size: self.buf.len() - pos - 1 })
+            },
+            _ => None
+        }
+    }
+
+    pub fn get_filename(&self) -> Option<String> {
+        let pos = self.get_payload().iter().position(|x| *x == 0 as u8);
+        if pos.is_none() {
+            // invalid format; TODO error
+            return None;
+        }
+        let payload = self.get_payload();
+        let (fname, _) = payload.split_at(pos.unwrap());
+        let filename = String::from_utf8(fname.to_vec()).expect("XXXXXXXX"); // TODO error
+        Some(sanitize_filename(filename))
+    }
+
+    pub fn get_filedata(&self) -> Option<Vec<u8>> {
+        let pos = self.get_payload().iter().position(|x| *x == 0 as u8);
+        if pos.is_none() {
+            // invalid format; TODO error
+            return None;
+        }
+        let payload = self.get_payload();
+        let (_, data) = payload.split_at(pos.unwrap() + 1);
+        Some(data.to_vec())
+    }
+
+    /*
+    pub fn sha2(&self) -> String {
+        let mut sha2 = Sha256::new();
+        sha2.input(&self.buf);
+        sha2.result_str()
+    }*/
+
+    fn create(ip: String, buf: Vec<u8>, typ: MessageType) -> Message {
+        Message {
+            ip: ip,
+            id: None,
+            buf: buf,
+            typ: typ,
+            signature: SignatureStatus::None,
+            device: None,
+            stamp: None,
+        }
+    }
+}
+
+/// Name and size of the file of a file upload (see `Message::file`).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: usize,
+}
+
+fn replace_char(c: char) -> char {
+    match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => c,
+        _ => '_'
+    }
+}
+
+fn sanitize_filename(s: String) -> String {
+    s.chars().map(|c| replace_char(c)).collect()
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{FileInfo, Message};
+    use crate::replay::Stamp;
+
+    #[test]
+    fn test_metadata() {
+
+        let m = Message::file_upload("1.2.3.4".to_string(), "a b.txt".to_string(), &vec![1, 2, 3]);
+        assert_eq!(m.peer(), "1.2.3.4");
+        assert_eq!(m.file(), Some(FileInfo { name: "a_b.txt".to_string(), size: 3 }));
+        assert_eq!(m.timestamp(), None);
+
+        let m = Message::new("1.2.3.4".to_string(), b"hi".to_vec()).with_stamp(Stamp { time: 1000, epoch: 1, seq: 2 }).with_id(7);
+        assert_eq!(m.file(), None);
+        assert_eq!(m.timestamp(), Some(1000));
+        assert_eq!(m.set_payload(vec![]).id, Some(7));
+    }
+}
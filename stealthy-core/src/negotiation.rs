@@ -0,0 +1,519 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::cryp::{self, Encryption, ResultVec, CIPHERS_BY_STRENGTH};
+use crate::error::StealthyError;
+use crate::tools::to_hex;
+use crate::wire::{push_value, push_slice, pop_value};
+
+/// Ciphers which we support and which the peers have advertised.
+///
+/// The supported ciphers are advertised in the PROBING ping which is sent at startup. For
+/// each peer the strongest cipher which is supported by both sides is used. A cipher which
+/// is weaker than the configured one is never used, so a forged advertisement cannot
+/// downgrade the encryption. As long as a peer has not advertised its ciphers (e.g.
+/// because it runs an older version) the configured cipher is used. Received messages are
+/// decrypted with whatever supported cipher they have been encrypted.
+pub struct Ciphers {
+    /// The configured cipher.
+    default: Arc<Box<dyn Encryption>>,
+    /// All supported ciphers including the configured one.
+    available: Vec<Arc<Box<dyn Encryption>>>,
+    /// Ciphers advertised by the peers.
+    peers: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Ciphers {
+
+    pub fn new(default: Arc<Box<dyn Encryption>>, alternatives: Vec<Box<dyn Encryption>>) -> Ciphers {
+
+        let mut available = vec![default.clone()];
+        available.extend(alternatives.into_iter().map(Arc::new));
+        available.sort_by_key(|e| CIPHERS_BY_STRENGTH.iter().position(|id| *id == e.cipher_id()));
+        Ciphers {
+            default,
+            available,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the ciphers for another key. The ciphers advertised by the peers are kept.
+    pub fn rekeyed(&self, default: Arc<Box<dyn Encryption>>, alternatives: Vec<Box<dyn Encryption>>) -> Ciphers {
+        let c = Ciphers::new(default, alternatives);
+        *c.peers.lock().unwrap() = self.peers.lock().unwrap().clone();
+        c
+    }
+
+    /// Returns the configured cipher.
+    pub fn primary(&self) -> &dyn Encryption {
+        &**self.default
+    }
+
+    /// Returns the identifiers of the supported ciphers, strongest first.
+    pub fn ids(&self) -> Vec<u8> {
+        self.available.iter().map(|e| e.cipher_id()).collect()
+    }
+
+    /// Returns the supported ciphers in the format used in the PROBING ping, e.g. "3,1".
+    pub fn advertisement(&self) -> String {
+        self.ids().iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+    }
+
+    /// Stores the ciphers advertised by a peer. Returns the cipher which is used for the
+    /// peer from now on if it has changed.
+    pub fn learn(&self, ip: &str, ciphers: Vec<u8>) -> Option<u8> {
+
+        let before = self.agreed(ip);
+        self.peers.lock().unwrap().insert(ip.to_string(), ciphers);
+        let after = self.agreed(ip);
+        match before != after {
+            true => Some(after),
+            false => None
+        }
+    }
+
+    /// Returns the identifier of the cipher which is used for messages to the given ip.
+    pub fn agreed(&self, ip: &str) -> u8 {
+        self.encryption_for(ip).cipher_id()
+    }
+
+    /// Encrypts a message for the given ip with the agreed cipher.
+    pub fn seal(&self, ip: &str, v: &Vec<u8>) -> ResultVec {
+        cryp::seal(&***self.encryption_for(ip), v)
+    }
+
+    /// Decrypts a message with the cipher whose identifier is prepended to the message.
+    pub fn open(&self, v: &Vec<u8>) -> ResultVec {
+        match v.first().and_then(|id| self.available.iter().find(|e| e.cipher_id() == *id)) {
+            Some(e) => cryp::open(&***e, v),
+            None => cryp::open(&**self.default, v)
+        }
+    }
+
+    /// Returns the maximum size of a sealed message among all supported ciphers.
+    pub fn max_sealed_len(&self, v: &Vec<u8>) -> Result<usize, StealthyError> {
+        let mut n = 0;
+        for e in &self.available {
+            n = n.max(cryp::seal(&***e, v)?.len());
+        }
+        Ok(n)
+    }
+
+    fn encryption_for(&self, ip: &str) -> &Arc<Box<dyn Encryption>> {
+        // The ciphers are sorted by strength, so the configured one is the minimum.
+        let min = self.available.iter().position(|e| Arc::ptr_eq(e, &self.default)).unwrap_or(0);
+        let peers = self.peers.lock().unwrap();
+        match peers.get(ip) {
+            Some(theirs) => self.available[..=min].iter()
+                .find(|e| theirs.contains(&e.cipher_id()))
+                .unwrap_or(&self.default),
+            None => &self.default
+        }
+    }
+}
+
+/// Revision of the wire format. It is increased if the format of the packets changes in
+/// a way which older versions cannot read, so that a peer detects that it is outdated.
+pub const PROTOCOL_REVISION: u32 = 1;
+/// Version of stealthy which is sent to the peers in the handshake.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Encrypted metadata of file transfers (see `transfer`).
+pub const FEATURE_FILE_METADATA: &str = "files2";
+/// Removal of the padding of messages (see `padding`).
+pub const FEATURE_PADDING: &str = "pad";
+/// Announcements of new signing keys (see `rollover`).
+pub const FEATURE_KEY_ROLLOVER: &str = "roll";
+/// Messages of sessions in the deniable format (see `session`).
+pub const FEATURE_DENIABLE: &str = "deny";
+/// Dropping of dummy messages (see `cover`).
+pub const FEATURE_COVER: &str = "cover";
+/// Packets with a tag in the header (see `packet`).
+pub const FEATURE_TAG: &str = "tag";
+/// Acknowledgement of several packets with one packet (see `binding`).
+pub const FEATURE_ACK_BATCH: &str = "acks";
+/// End-to-end receipts of messages (see `receipt`).
+pub const FEATURE_RECEIPT: &str = "rcpt";
+/// Packets with a checksum (see `packet`).
+pub const FEATURE_CHECKSUM: &str = "crc";
+/// Keepalives which announce the presence of a peer (see `presence`).
+pub const FEATURE_PRESENCE: &str = "pres";
+/// Version and revision of the wire format in the handshake (see `handshake`).
+pub const FEATURE_VERSION: &str = "ver";
+/// Options of text messages, e.g. expiration (see `envelope`).
+pub const FEATURE_ENVELOPE: &str = "env";
+/// Signed changes of the members of the group (see `group`).
+pub const FEATURE_GROUP: &str = "grp";
+/// Cancellation of messages and file transfers (see `Layers::cancel`).
+pub const FEATURE_CANCEL: &str = "cncl";
+
+/// The optional features which we support.
+const FEATURES: &[&str] = &[FEATURE_FILE_METADATA, FEATURE_PADDING, FEATURE_KEY_ROLLOVER, FEATURE_DENIABLE, FEATURE_COVER, FEATURE_TAG, FEATURE_ACK_BATCH, FEATURE_RECEIPT, FEATURE_CHECKSUM, FEATURE_PRESENCE, FEATURE_VERSION, FEATURE_ENVELOPE, FEATURE_GROUP, FEATURE_CANCEL];
+
+/// Optional features which the peers have advertised.
+///
+/// The features are advertised in the PROBING ping after the ciphers, followed by the
+/// revision of the wire format and a signature, e.g. "PROBING:        1234/3,1/files2/1/a1b2/"
+/// (see `advertise`). A peer which advertises its ciphers but no features runs an older
+/// version.
+pub struct Features {
+    peers: Mutex<HashMap<String, Vec<String>>>,
+    /// Peers which have sent an advertisement with a valid signature.
+    signed: Mutex<HashSet<String>>,
+    /// Revision of the wire format of each peer.
+    revisions: Mutex<HashMap<String, u32>>,
+    /// Version of each peer received in the handshake.
+    versions: Mutex<HashMap<String, PeerVersion>>,
+}
+
+impl Default for Features {
+    fn default() -> Features {
+        Features::new()
+    }
+}
+
+impl Features {
+
+    pub fn new() -> Features {
+        Features {
+            peers: Mutex::new(HashMap::new()),
+            signed: Mutex::new(HashSet::new()),
+            revisions: Mutex::new(HashMap::new()),
+            versions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the supported features in the format used in the PROBING ping.
+    pub fn advertisement() -> String {
+        format!("{}/{}", FEATURES.join(","), PROTOCOL_REVISION)
+    }
+
+    /// Decides whether the advertisement of a peer is used. `signed` is None if the
+    /// advertisement has no signature, e.g. because the peer runs an older version, and
+    /// otherwise whether the signature is valid (see `authenticate`). Advertisements with
+    /// an invalid signature are rejected. Advertisements without a signature are only
+    /// accepted as long as the peer has not sent a signed one, so that a forged
+    /// advertisement cannot disable features of a peer which runs a newer version.
+    pub fn accept(&self, ip: &str, signed: Option<bool>) -> bool {
+        let mut s = self.signed.lock().unwrap();
+        match signed {
+            Some(true) => { s.insert(ip.to_string()); true },
+            Some(false) => false,
+            None => !s.contains(ip)
+        }
+    }
+
+    /// Stores the features advertised by a peer. Returns true if the peer has not
+    /// advertised its features before.
+    pub fn learn(&self, ip: &str, features: Vec<String>) -> bool {
+        self.peers.lock().unwrap().insert(ip.to_string(), features).is_none()
+    }
+
+    /// Returns whether the peer supports the feature or None if the peer has not
+    /// advertised its features yet.
+    pub fn supports(&self, ip: &str, feature: &str) -> Option<bool> {
+        self.peers.lock().unwrap().get(ip).map(|v| v.iter().any(|f| f == feature))
+    }
+
+    /// Stores the revision of the wire format advertised by a peer. Returns true if the
+    /// peer has not advertised this revision before.
+    pub fn learn_revision(&self, ip: &str, revision: u32) -> bool {
+        self.revisions.lock().unwrap().insert(ip.to_string(), revision) != Some(revision)
+    }
+
+    /// Stores the version of a peer received in the handshake. Returns true if the peer
+    /// has not sent this version before.
+    pub fn learn_version(&self, ip: &str, v: &PeerVersion) -> bool {
+        self.versions.lock().unwrap().insert(ip.to_string(), v.clone()).as_ref() != Some(v)
+    }
+}
+
+/// Version of stealthy and revision of the wire format of a peer.
+///
+/// Format: revision (4) | length of the version (1) | version, e.g. "0.0.3"
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerVersion {
+    pub version: String,
+    pub revision: u32,
+}
+
+impl PeerVersion {
+
+    /// Returns our own version.
+    pub fn ours() -> PeerVersion {
+        PeerVersion {
+            version: VERSION.to_string(),
+            revision: PROTOCOL_REVISION,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let version = &self.version.as_bytes()[..self.version.len().min(255)];
+        let mut v = vec![];
+        push_value(&mut v, self.revision as u64, 4);
+        push_value(&mut v, version.len() as u64, 1);
+        push_slice(&mut v, version);
+        v
+    }
+
+    /// Returns the version at the start of the buffer and the number of bytes it occupies.
+    pub fn decode(buf: &[u8]) -> Option<(PeerVersion, usize)> {
+        let mut v = buf.get(..5)?.to_vec();
+        let revision = pop_value(&mut v, 4).ok()? as u32;
+        let n = pop_value(&mut v, 1).ok()? as usize;
+        let version = String::from_utf8(buf.get(5..5 + n)?.to_vec()).ok()?;
+        Some((PeerVersion { version, revision }, 5 + n))
+    }
+
+    /// Returns a warning if the peer with the ip uses another wire format or runs a much
+    /// older version, i.e. a version whose major or minor number is smaller than ours.
+    pub fn warning(&self, ip: &str) -> Option<String> {
+        if self.revision > PROTOCOL_REVISION {
+            return Some(format!("{} runs stealthy {} which uses a newer wire format (revision {}, ours is {}). Update stealthy if messages of {} cannot be read.",
+                                ip, self.version, self.revision, PROTOCOL_REVISION, ip));
+        }
+        if self.revision < PROTOCOL_REVISION {
+            return Some(format!("{} runs stealthy {} which uses an older wire format (revision {}, ours is {}). {} has to update stealthy to read all messages.",
+                                ip, self.version, self.revision, PROTOCOL_REVISION, ip));
+        }
+        let parse = |s: &str| s.split('.').take(2).map(|n| n.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>();
+        if parse(&self.version) < parse(VERSION) {
+            return Some(format!("{} runs stealthy {} which is much older than this version ({}).", ip, self.version, VERSION));
+        }
+        None
+    }
+}
+
+/// Returns the advertisement in a PROBING ping to a peer: the supported ciphers, the
+/// features and the revision of the wire format, followed by a signature of them which is
+/// created with the key of the peer (see `Encryption::sign`), e.g. "3,1/files2,pad/1/a1b2".
+pub fn advertise(c: &Ciphers) -> String {
+    let s = format!("{}/{}", c.advertisement(), Features::advertisement());
+    match c.primary().sign(s.as_bytes()) {
+        Ok(sig) => format!("{}/{}", s, to_hex(&sig)),
+        Err(_) => s
+    }
+}
+
+/// Verifies the signature of the advertisement in a PROBING ping with the key of the peer.
+/// Returns None if the ping does not contain a signature, e.g. if it has been sent by an
+/// older version.
+pub fn authenticate(buf: &[u8], offset: usize, c: &Ciphers) -> Option<bool> {
+
+    let rest = buf.get(offset..)?;
+    let mut fields = rest.split(|b| *b == b'/');
+    // Ciphers, features and revision are signed.
+    let signed = fields.by_ref().take(3).map(|f| f.len() + 1).sum::<usize>().checked_sub(1)?;
+    let sig = fields.next()?;
+    // The signature is terminated by a slash. The last field is the padding of the ping.
+    fields.next()?;
+    match cryp::from_hex(String::from_utf8(sig.to_vec()).ok()?) {
+        Ok(sig) => Some(c.primary().verify(&rest[..signed], &sig)),
+        Err(_) => Some(false)
+    }
+}
+
+/// Parses the ciphers advertised in a PROBING ping. The list follows the id of the ping
+/// and is terminated by a slash, e.g. "PROBING:        1234/3,1/". Returns None if the
+/// ping does not contain a list, e.g. if it has been sent by an older version.
+pub fn parse(buf: &[u8], offset: usize) -> Option<Vec<u8>> {
+
+    let rest = buf.get(offset..)?;
+    let end = rest.iter().position(|c| *c == b'/')?;
+    let s = String::from_utf8(rest[..end].to_vec()).ok()?;
+    s.split(',').map(|id| id.parse::<u8>().ok()).collect()
+}
+
+/// Parses the features advertised in a PROBING ping which follow the list of ciphers.
+/// Returns an empty list if the ping does not contain features, e.g. if it has been sent
+/// by an older version.
+pub fn parse_features(buf: &[u8], offset: usize) -> Vec<String> {
+
+    let rest = match buf.get(offset..) {
+        Some(r) => r,
+        None => return vec![]
+    };
+    let start = match rest.iter().position(|c| *c == b'/') {
+        Some(i) => i + 1,
+        None => return vec![]
+    };
+    let end = match rest[start..].iter().position(|c| *c == b'/') {
+        Some(i) => start + i,
+        None => return vec![]
+    };
+    match String::from_utf8(rest[start..end].to_vec()) {
+        Ok(s) => s.split(',').filter(|f| !f.is_empty()).map(|f| f.to_string()).collect(),
+        Err(_) => vec![]
+    }
+}
+
+/// Parses the revision of the wire format which follows the features in a PROBING ping.
+/// Returns None if the ping does not contain a revision, e.g. if it has been sent by an
+/// older version.
+pub fn parse_revision(buf: &[u8], offset: usize) -> Option<u32> {
+
+    let rest = buf.get(offset..)?;
+    let mut fields = rest.split(|c| *c == b'/');
+    let field = fields.nth(2)?;
+    // The last field is not terminated by a slash and is the padding of the ping.
+    fields.next()?;
+    String::from_utf8(field.to_vec()).ok()?.parse::<u32>().ok()
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::{Ciphers, Features, advertise, authenticate, parse, parse_features, parse_revision, PROTOCOL_REVISION, FEATURE_FILE_METADATA, FEATURE_PADDING, FEATURE_KEY_ROLLOVER, FEATURE_DENIABLE, FEATURE_COVER, FEATURE_TAG, FEATURE_ACK_BATCH, FEATURE_RECEIPT, FEATURE_CHECKSUM, FEATURE_PRESENCE, FEATURE_VERSION, FEATURE_ENVELOPE, FEATURE_GROUP, FEATURE_CANCEL, PeerVersion};
+    use crate::cryp::{self, CIPHER_BLOWFISH, CIPHER_CHACHA20_POLY1305};
+
+    const KEY: &str = "11111111111111111111111111111111";
+
+    fn ciphers(cipher: &str) -> Ciphers {
+        let k = KEY.to_string();
+        Ciphers::new(Arc::new(cryp::symmetric(cipher, &k).unwrap()), cryp::symmetric_alternatives(cipher, &k))
+    }
+
+    #[test]
+    fn test_advertisement() {
+
+        let c = ciphers("blowfish");
+        assert_eq!(c.ids(), vec![CIPHER_CHACHA20_POLY1305, CIPHER_BLOWFISH]);
+        assert_eq!(c.advertisement(), "3,1");
+        assert_eq!(parse(b"PROBING:        1234/3,1/\x01\x01", 21), Some(c.ids()));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+
+        assert_eq!(parse(b"PROBING:        1234/\x01\x01\x01", 21), None);
+        assert_eq!(parse(b"PROBING:        1234/3,1", 21), None);
+        assert_eq!(parse(b"PROBING:", 21), None);
+    }
+
+    #[test]
+    fn test_agree() {
+
+        let c = ciphers("blowfish");
+        // The configured cipher is used until the peer has advertised its ciphers.
+        assert_eq!(c.agreed("1.1.1.1"), CIPHER_BLOWFISH);
+        assert_eq!(c.learn("1.1.1.1", vec![CIPHER_BLOWFISH, CIPHER_CHACHA20_POLY1305]), Some(CIPHER_CHACHA20_POLY1305));
+        assert_eq!(c.learn("1.1.1.1", vec![CIPHER_CHACHA20_POLY1305]), None);
+        assert_eq!(c.agreed("1.1.1.1"), CIPHER_CHACHA20_POLY1305);
+        // Without a common cipher the configured one is used.
+        assert_eq!(c.learn("2.2.2.2", vec![42]), None);
+        assert_eq!(c.agreed("2.2.2.2"), CIPHER_BLOWFISH);
+
+        // A cipher weaker than the configured one is never used.
+        let c = ciphers("chacha20poly1305");
+        assert_eq!(c.learn("1.1.1.1", vec![CIPHER_BLOWFISH]), None);
+        assert_eq!(c.agreed("1.1.1.1"), CIPHER_CHACHA20_POLY1305);
+    }
+
+    #[test]
+    fn test_authenticate() {
+
+        let c = ciphers("blowfish");
+        let ping = format!("PROBING:        1234/{}/\x01\x01", advertise(&c));
+        assert_eq!(authenticate(ping.as_bytes(), 21, &c), Some(true));
+        assert_eq!(parse(ping.as_bytes(), 21), Some(c.ids()));
+        assert_eq!(parse_revision(ping.as_bytes(), 21), Some(PROTOCOL_REVISION));
+
+        // A modified advertisement or a signature with another key is rejected.
+        let forged = ping.replacen("3,1", "1", 1);
+        assert_eq!(authenticate(forged.as_bytes(), 21, &c), Some(false));
+        let other = Ciphers::new(Arc::new(cryp::symmetric("blowfish", &"22222222222222222222222222222222".to_string()).unwrap()), vec![]);
+        assert_eq!(authenticate(ping.as_bytes(), 21, &other), Some(false));
+        assert_eq!(authenticate(b"PROBING:        1234/3,1/pad/1/xyz/\x01", 21, &c), Some(false));
+        // Older versions do not sign their advertisement.
+        assert_eq!(authenticate(b"PROBING:        1234/3,1/pad/1/\x01\x01", 21, &c), None);
+        assert_eq!(authenticate(b"PROBING:        1234/3,1/pad/1/", 21, &c), None);
+
+        let f = Features::new();
+        assert!(f.accept("1.1.1.1", None));
+        assert!(!f.accept("1.1.1.1", Some(false)));
+        assert!(f.accept("1.1.1.1", Some(true)));
+        // Unsigned advertisements are not accepted anymore once the peer has signed one.
+        assert!(!f.accept("1.1.1.1", None));
+        assert!(f.accept("2.2.2.2", None));
+    }
+
+    #[test]
+    fn test_seal_open() {
+
+        let alice = ciphers("blowfish");
+        let bob = ciphers("chacha20poly1305");
+        alice.learn("2.2.2.2", bob.ids());
+
+        let data = b"hello".to_vec();
+        let sealed = alice.seal("2.2.2.2", &data).unwrap();
+        assert_eq!(sealed[0], CIPHER_CHACHA20_POLY1305);
+        assert_eq!(bob.open(&sealed).unwrap(), data);
+        // Messages encrypted with the configured cipher of an older peer are decrypted.
+        let old = cryp::seal(&*cryp::symmetric("blowfish", &KEY.to_string()).unwrap(), &data).unwrap();
+        assert_eq!(bob.open(&old).unwrap(), data);
+        assert!(bob.max_sealed_len(&data).unwrap() >= sealed.len());
+    }
+
+    #[test]
+    fn test_features() {
+
+        let ping = format!("PROBING:        1234/3,1/{}/\x01\x01", Features::advertisement());
+        assert_eq!(parse_features(ping.as_bytes(), 21), vec![FEATURE_FILE_METADATA.to_string(), FEATURE_PADDING.to_string(), FEATURE_KEY_ROLLOVER.to_string(), FEATURE_DENIABLE.to_string(), FEATURE_COVER.to_string(), FEATURE_TAG.to_string(), FEATURE_ACK_BATCH.to_string(), FEATURE_RECEIPT.to_string(), FEATURE_CHECKSUM.to_string(), FEATURE_PRESENCE.to_string(), FEATURE_VERSION.to_string(), FEATURE_ENVELOPE.to_string(), FEATURE_GROUP.to_string(), FEATURE_CANCEL.to_string()]);
+        // Older versions do not advertise features.
+        assert!(parse_features(b"PROBING:        1234/3,1/\x01\x01", 21).is_empty());
+        assert!(parse_features(b"PROBING:", 21).is_empty());
+
+        let f = Features::new();
+        assert_eq!(f.supports("1.1.1.1", FEATURE_FILE_METADATA), None);
+        assert!(f.learn("1.1.1.1", vec![]));
+        assert!(!f.learn("1.1.1.1", vec![]));
+        assert_eq!(f.supports("1.1.1.1", FEATURE_FILE_METADATA), Some(false));
+        f.learn("2.2.2.2", vec![FEATURE_FILE_METADATA.to_string()]);
+        assert_eq!(f.supports("2.2.2.2", FEATURE_FILE_METADATA), Some(true));
+    }
+
+    #[test]
+    fn test_revision() {
+
+        let ping = format!("PROBING:        1234/3,1/{}/\x01\x01", Features::advertisement());
+        assert_eq!(parse_revision(ping.as_bytes(), 21), Some(PROTOCOL_REVISION));
+        assert_eq!(parse_revision(b"PROBING:        1234/3,1/pad/7/", 21), Some(7));
+        // Older versions do not advertise a revision.
+        assert_eq!(parse_revision(b"PROBING:        1234/3,1/pad/\x01\x01", 21), None);
+        assert_eq!(parse_revision(b"PROBING:        1234/3,1/\x01\x01", 21), None);
+        assert_eq!(parse_revision(b"PROBING:        1234/3,1/pad/x/", 21), None);
+
+        let f = Features::new();
+        assert!(f.learn_revision("1.1.1.1", 1));
+        assert!(!f.learn_revision("1.1.1.1", 1));
+        assert!(f.learn_revision("1.1.1.1", 2));
+    }
+
+    #[test]
+    fn test_version() {
+
+        let ours = PeerVersion::ours();
+        let buf = ours.encode();
+        assert_eq!(PeerVersion::decode(&buf), Some((ours.clone(), buf.len())));
+        assert_eq!(PeerVersion::decode(&[buf.clone(), vec![1, 2]].concat()), Some((ours.clone(), buf.len())));
+        assert_eq!(PeerVersion::decode(&buf[..buf.len() - 1]), None);
+        assert_eq!(PeerVersion::decode(&[]), None);
+
+        assert_eq!(ours.warning("1.1.1.1"), None);
+        let newer = PeerVersion { version: "99.0.0".to_string(), revision: PROTOCOL_REVISION };
+        assert_eq!(newer.warning("1.1.1.1"), None);
+        assert!(PeerVersion { version: "0.0.0".to_string(), revision: PROTOCOL_REVISION + 1 }.warning("1.1.1.1").is_some());
+        assert!(PeerVersion { version: "99.0.0".to_string(), revision: PROTOCOL_REVISION - 1 }.warning("1.1.1.1").is_some());
+
+        let f = Features::new();
+        assert!(f.learn_version("1.1.1.1", &ours));
+        assert!(!f.learn_version("1.1.1.1", &ours));
+        assert!(f.learn_version("1.1.1.1", &newer));
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cryp::{self, ChaChaEncryption, Encryption, from_hex};
+use crate::wire::{push_value, pop_value};
+use crate::kdf::KdfParams;
+use crate::packet::Packet;
+use crate::tools::{read_file, to_hex};
+use crate::error::StealthyError;
+
+const KEY_LEN: usize = 32;
+
+/// Packets which have not been acknowledged yet. They are stored in a file so that they
+/// are retransmitted when stealthy is started again.
+///
+/// The file has the format of the keystore: the parameters of the KDF in the first line
+/// and the encrypted packets in hex in the second line. The key is derived from a
+/// passphrase via Argon2id when the outbox is opened. The payload of the packets is
+/// encrypted for the peers anyway but the outbox hides the peers and the number of
+/// pending packets as well.
+///
+/// The last change of the group (see `group`) is stored in the same format in a second
+/// file with the suffix `.group`, so that the key of the group is not lost after a restart.
+#[derive(Clone)]
+pub struct Outbox {
+    fname: String,
+    params: KdfParams,
+    cipher: Arc<ChaChaEncryption>,
+}
+
+impl Outbox {
+
+    /// Opens the outbox in the given file. If the file does not exist, it is created when
+    /// the outbox is saved.
+    pub fn open(fname: &str, passphrase: &str) -> Result<Outbox, StealthyError> {
+
+        // Both files use the same parameters, but only one of them might exist.
+        let group = format!("{}.group", fname);
+        let params = match [fname, &group].iter().find(|f| Path::new(f).exists()) {
+            Some(f) => KdfParams::parse(read_file(f)?.lines().next().unwrap_or(""))?,
+            None => KdfParams::generate()?
+        };
+        Outbox::with_params(fname, passphrase, params)
+    }
+
+    fn with_params(fname: &str, passphrase: &str, params: KdfParams) -> Result<Outbox, StealthyError> {
+        let cipher = ChaChaEncryption::new(&to_hex(&params.derive(passphrase, KEY_LEN)?))?;
+        Ok(Outbox {
+            fname: fname.to_string(),
+            params,
+            cipher: Arc::new(cipher),
+        })
+    }
+
+    /// Returns the packets which have been stored or an empty list if the outbox does not
+    /// exist yet.
+    pub fn load(&self) -> Result<Vec<Packet>, StealthyError> {
+        match self.read(&self.fname)? {
+            Some(plain) => decode(&plain),
+            None => Ok(vec![])
+        }
+    }
+
+    /// Replaces the content of the outbox with the given packets. The file is only
+    /// readable by the owner. It is replaced atomically, so a crash while saving keeps
+    /// either the old or the new content.
+    pub fn save(&self, packets: &[Packet]) -> Result<(), StealthyError> {
+        self.write(&self.fname, &encode(packets))
+    }
+
+    /// Returns the last change of the group which has been stored or None if there is none
+    /// (see `Group::encode_state`).
+    pub fn load_group(&self) -> Result<Option<Vec<u8>>, StealthyError> {
+        self.read(&self.group_fname())
+    }
+
+    /// Replaces the stored change of the group.
+    pub fn save_group(&self, state: &[u8]) -> Result<(), StealthyError> {
+        self.write(&self.group_fname(), state)
+    }
+
+    fn group_fname(&self) -> String {
+        format!("{}.group", self.fname)
+    }
+
+    fn read(&self, fname: &str) -> Result<Option<Vec<u8>>, StealthyError> {
+
+        if !Path::new(fname).exists() {
+            return Ok(None);
+        }
+        let data = read_file(fname)?;
+        let lines = data.lines().collect::<Vec<_>>();
+        if lines.len() != 2 {
+            return Err(StealthyError::Crypto("Invalid format of outbox."));
+        }
+        let plain = self.cipher
+            .decrypt(&from_hex(lines[1].trim().to_string())?)
+            .map_err(|e| match e.message() {
+                cryp::AUTHENTICATION_FAILED => StealthyError::Crypto("Wrong passphrase or damaged outbox."),
+                _ => e
+            })?;
+        Ok(Some(plain))
+    }
+
+    fn write(&self, fname: &str, plain: &[u8]) -> Result<(), StealthyError> {
+
+        let cipher = self.cipher.encrypt(&plain.to_vec())?;
+        let data = format!("{}\n{}\n", self.params.encode(), to_hex(&cipher));
+
+        // The data is written into a temporary file in the same directory which then
+        // replaces the old file, as the rename is atomic only within a file system.
+        let tmp = format!("{}.tmp", fname);
+        let mut o = OpenOptions::new();
+        o.write(true).create(true).truncate(true);
+        // On Windows the file inherits the permissions of its directory.
+        #[cfg(unix)]
+        o.mode(0o600);
+        let mut f = o.open(&tmp).map_err(|e| StealthyError::Io("Could not create outbox.", e))?;
+        f.write_all(data.as_bytes()).map_err(|e| StealthyError::Io("Could not write outbox.", e))?;
+        f.sync_all().map_err(|e| StealthyError::Io("Could not write outbox.", e))?;
+        drop(f);
+        fs::rename(&tmp, fname).map_err(|e| StealthyError::Io("Could not replace outbox.", e))?;
+
+        // The rename itself is only durable after the directory has been synced.
+        #[cfg(unix)]
+        {
+            let dir = match Path::new(fname).parent() {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => Path::new(".")
+            };
+            fs::File::open(dir)
+                .and_then(|d| d.sync_all())
+                .map_err(|e| StealthyError::Io("Could not write outbox.", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes each packet as length of the ip (1 byte), ip, length of the serialized packet
+/// (4 bytes) and serialized packet.
+fn encode(packets: &[Packet]) -> Vec<u8> {
+
+    let mut v = vec![];
+    for p in packets {
+        let buf = p.serialize();
+        push_value(&mut v, p.ip.len() as u64, 1);
+        v.extend_from_slice(p.ip.as_bytes());
+        push_value(&mut v, buf.len() as u64, 4);
+        v.extend_from_slice(&buf);
+    }
+    v
+}
+
+fn decode(buf: &[u8]) -> Result<Vec<Packet>, StealthyError> {
+
+    let mut v = buf.to_vec();
+    let mut packets = vec![];
+    while !v.is_empty() {
+        let n = pop_value(&mut v, 1).map_err(StealthyError::Crypto)? as usize;
+        if v.len() < n {
+            return Err(StealthyError::Crypto("Invalid format of outbox."));
+        }
+        let ip = String::from_utf8(v.drain(..n).collect()).map_err(|_| StealthyError::Crypto("Invalid format of outbox."))?;
+        let n = pop_value(&mut v, 4).map_err(StealthyError::Crypto)? as usize;
+        if v.len() < n {
+            return Err(StealthyError::Crypto("Invalid format of outbox."));
+        }
+        let p = Packet::deserialize(&v.drain(..n).collect::<Vec<_>>(), ip).ok_or(StealthyError::Crypto("Invalid packet in outbox."))?;
+        packets.push(p);
+    }
+    Ok(packets)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Outbox, decode, encode};
+    use crate::kdf::KdfParams;
+    use crate::packet::Packet;
+
+    fn params() -> KdfParams {
+        KdfParams::parse("argon2id$m=64,t=1,p=1$01010101010101010101010101010101").unwrap()
+    }
+
+    #[test]
+    fn test_outbox() {
+
+        let packets = vec![
+            Packet::new(vec![1, 2, 3], "1.2.3.4".to_string(), 7),
+            Packet::file_upload(vec![], "fe80::1".to_string(), u64::max_value()),
+        ];
+        let v = decode(&encode(&packets)).unwrap();
+        assert_eq!(v.len(), 2);
+        assert_eq!((v[0].id, v[0].ip.as_str(), v[0].data.clone()), (7, "1.2.3.4", vec![1, 2, 3]));
+        assert!(v[1].is_file_upload());
+        assert_eq!((v[1].id, v[1].ip.as_str()), (u64::max_value(), "fe80::1"));
+        assert!(decode(&encode(&packets)[..20]).is_err());
+
+        let fname = std::env::temp_dir().join(format!("stealthy-outbox-{}", std::process::id()));
+        let fname = fname.to_str().unwrap();
+        let o = Outbox::with_params(fname, "secret", params()).unwrap();
+        assert!(o.load().unwrap().is_empty());
+        o.save(&packets).unwrap();
+        assert_eq!(o.load().unwrap().len(), 2);
+        assert!(!std::path::Path::new(&format!("{}.tmp", fname)).exists());
+
+        // The KDF parameters are read from the file.
+        assert_eq!(Outbox::open(fname, "secret").unwrap().load().unwrap()[0].id, 7);
+        assert!(Outbox::open(fname, "wrong").unwrap().load().is_err());
+
+        // The state of the group is stored next to the packets.
+        assert_eq!(o.load_group().unwrap(), None);
+        o.save_group(&[1, 2, 3]).unwrap();
+        assert_eq!(o.load().unwrap().len(), 2);
+        let _ = std::fs::remove_file(fname);
+        assert_eq!(Outbox::open(fname, "secret").unwrap().load_group().unwrap(), Some(vec![1, 2, 3]));
+        let _ = std::fs::remove_file(format!("{}.group", fname));
+    }
+}
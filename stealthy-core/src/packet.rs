@@ -0,0 +1,445 @@
+//extern crate rand;
+//extern crate time;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use crate::wire::{self, PacketHeader, PACKET_HEADER_LEN};
+
+pub type IdType = (u64);
+
+/// Version of packets without a tag which is used for peers of older versions.
+const VERSION: u8 = 1;
+/// Version of packets whose header contains a tag.
+const VERSION_TAGGED: u8 = 2;
+/// Version of packets whose header contains a tag and which end with a checksum.
+const VERSION_CHECKED: u8 = 3;
+const TAG_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 4;
+const TAG_CONTEXT: &[u8] = b"stealthy packet tag\0";
+
+pub enum PacketType {
+    NewMessage = 16,
+    AckMessage = 17,
+	FileUpload = 18,
+	KeyChallenge = 19,
+	KeyResponse = 20,
+	Batch = 21,
+	Handshake = 22,
+	Receipt = 23,
+	Rollover = 24,
+	AckBatch = 25,
+	Keepalive = 26,
+	Group = 27,
+	Cancel = 28,
+}
+
+pub struct Packet {
+	// The id of the packet that is transmitted. It is used to identify
+	// the ack for that message.
+	pub id:      IdType,
+	pub data:    Vec<u8>,      // data packet from the caller
+	pub created: time::PreciseTime,
+	pub ip:      String,
+    pub typ:     u8,
+}
+
+impl Packet {
+
+    pub fn is_new_message(&self) -> bool {
+        self.typ == (PacketType::NewMessage as u8)
+    }
+
+    pub fn is_ack(&self) -> bool {
+        self.typ == (PacketType::AckMessage as u8)
+    }
+
+	pub fn is_ack_batch(&self) -> bool {
+		self.typ == (PacketType::AckBatch as u8)
+	}
+
+	pub fn is_file_upload(&self) -> bool {
+		self.typ == (PacketType::FileUpload as u8)
+	}
+
+	pub fn is_key_challenge(&self) -> bool {
+		self.typ == (PacketType::KeyChallenge as u8)
+	}
+
+	pub fn is_key_response(&self) -> bool {
+		self.typ == (PacketType::KeyResponse as u8)
+	}
+
+	pub fn is_batch(&self) -> bool {
+		self.typ == (PacketType::Batch as u8)
+	}
+
+	pub fn is_handshake(&self) -> bool {
+		self.typ == (PacketType::Handshake as u8)
+	}
+
+	pub fn is_receipt(&self) -> bool {
+		self.typ == (PacketType::Receipt as u8)
+	}
+
+	pub fn is_rollover(&self) -> bool {
+		self.typ == (PacketType::Rollover as u8)
+	}
+
+	pub fn is_keepalive(&self) -> bool {
+		self.typ == (PacketType::Keepalive as u8)
+	}
+
+	pub fn is_group(&self) -> bool {
+		self.typ == (PacketType::Group as u8)
+	}
+
+	pub fn is_cancel(&self) -> bool {
+		self.typ == (PacketType::Cancel as u8)
+	}
+
+	pub fn handshake(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Handshake as u8,
+		}
+	}
+
+	pub fn receipt(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Receipt as u8,
+		}
+	}
+
+	pub fn keepalive(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Keepalive as u8,
+		}
+	}
+
+	pub fn rollover(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Rollover as u8,
+		}
+	}
+
+	pub fn cancel(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Cancel as u8,
+		}
+	}
+
+	pub fn group(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Group as u8,
+		}
+	}
+
+	pub fn batch(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::Batch as u8,
+		}
+	}
+
+	pub fn key_challenge(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::KeyChallenge as u8,
+		}
+	}
+
+	pub fn key_response(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::KeyResponse as u8,
+		}
+	}
+
+	pub fn file_upload(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data,
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: PacketType::FileUpload as u8,
+		}
+	}
+
+	// data = message
+	pub fn new(data: Vec<u8>, ip: String, r: u64) -> Packet {
+		Packet {
+			data: data, 
+			id: r,
+			created: time::PreciseTime::now(),
+			ip: ip,
+            typ: PacketType::NewMessage as u8,
+		}
+	}
+
+	pub fn clone(&self) -> Packet {
+		Packet {
+			id: self.id,
+			data: self.data.clone(),
+			created: self.created.clone(),
+			ip: self.ip.clone(),
+            typ: self.typ,
+		}
+	}
+
+	/// Serializes the packet with a tag after the header (see `tag`). Only peers which
+	/// have advertised that they support tags can read these packets.
+	pub fn serialize_tagged(&self) -> Vec<u8> {
+
+		let mut v = self.serialize();
+		v[0] = VERSION_TAGGED;
+		let t = tag(self.typ, &v[2..PACKET_HEADER_LEN]);
+		v.splice(PACKET_HEADER_LEN..PACKET_HEADER_LEN, t.iter().cloned());
+		v
+	}
+
+	/// Serializes the packet with a tag like `serialize_tagged` and appends the CRC-32 of
+	/// the packet. Corrupted packets are dropped when they are deserialized instead of
+	/// failing in the decryption. Only peers which have advertised that they support
+	/// checksums can read these packets.
+	pub fn serialize_checked(&self) -> Vec<u8> {
+
+		let mut v = self.serialize_tagged();
+		v[0] = VERSION_CHECKED;
+		let crc = wire::crc32(&v);
+		wire::push_value(&mut v, crc as u64, CHECKSUM_LEN);
+		v
+	}
+
+	/// Returns true if the serialized packet has a valid tag.
+	pub fn is_tagged(buf: &[u8]) -> bool {
+		buf.len() >= PACKET_HEADER_LEN + TAG_LEN && (buf[0] == VERSION_TAGGED || buf[0] == VERSION_CHECKED) &&
+			buf[PACKET_HEADER_LEN..PACKET_HEADER_LEN + TAG_LEN] == tag(buf[1], &buf[2..PACKET_HEADER_LEN])
+	}
+
+	/// Returns true if the serialized packet ends with a valid checksum.
+	fn has_valid_checksum(buf: &[u8]) -> bool {
+		if buf.len() < PACKET_HEADER_LEN + TAG_LEN + CHECKSUM_LEN {
+			return false;
+		}
+		let (packet, crc) = buf.split_at(buf.len() - CHECKSUM_LEN);
+		wire::crc32(packet).to_le_bytes() == crc
+	}
+
+	pub fn serialize(&self) -> Vec<u8> {
+
+		// if you change someting check delivery::send_msg
+
+		let mut v = PacketHeader { version: VERSION, typ: self.typ, id: self.id }.encode();
+		v.extend_from_slice(&self.data);
+		v
+	}
+
+    pub fn create_ack(p: Packet) -> Packet {
+
+        Packet {
+            id: p.id,
+            data: vec![],
+            created: time::PreciseTime::now(),
+            ip: p.ip,
+            typ: PacketType::AckMessage as u8,
+        }
+  }
+
+	/// Returns a packet which acknowledges the packets with the given ids at once. The
+	/// payload contains the ids with eight bytes each.
+	pub fn ack_batch(ids: &[IdType], ip: String) -> Packet {
+
+		let mut data = vec![];
+		for id in ids {
+			let mut t = *id;
+			for _ in 0..8 {
+				data.push(t as u8);
+				t = t >> 8;
+			}
+		}
+		Packet {
+			id: rand::random::<u64>(),
+			data,
+			created: time::PreciseTime::now(),
+			ip,
+			typ: PacketType::AckBatch as u8,
+		}
+	}
+
+	/// Returns the ids of the packets which are acknowledged by an ACK batch.
+	pub fn acked_ids(&self) -> Vec<IdType> {
+		self.data.chunks(8)
+			.filter(|c| c.len() == 8)
+			.map(|c| c.iter().rev().fold(0, |id, b| (id << 8) + *b as u64))
+			.collect()
+	}
+
+    fn valid_type(typ: u8) -> bool {
+		typ == (PacketType::NewMessage as u8) ||
+			typ == (PacketType::AckMessage as u8) ||
+			typ == (PacketType::FileUpload as u8) ||
+			typ == (PacketType::KeyChallenge as u8) ||
+			typ == (PacketType::KeyResponse as u8) ||
+			typ == (PacketType::Batch as u8) ||
+			typ == (PacketType::Handshake as u8) ||
+			typ == (PacketType::Receipt as u8) ||
+			typ == (PacketType::Rollover as u8) ||
+			typ == (PacketType::AckBatch as u8) ||
+			typ == (PacketType::Keepalive as u8) ||
+			typ == (PacketType::Group as u8) ||
+			typ == (PacketType::Cancel as u8)
+    }
+
+	pub fn deserialize(buf: &[u8], ip: String) -> Option<Packet> {
+
+		let (h, rest) = PacketHeader::decode(buf).ok()?;
+		let data = match h.version {
+			VERSION => rest,
+			VERSION_TAGGED if Packet::is_tagged(buf) => &rest[TAG_LEN..],
+			VERSION_CHECKED if Packet::is_tagged(buf) && Packet::has_valid_checksum(buf) => &rest[TAG_LEN..rest.len() - CHECKSUM_LEN],
+			_ => return None
+		};
+		if !Packet::valid_type(h.typ) {
+			return None;
+		}
+		Some(Packet {
+			id: h.id,
+			data: data.to_vec(),
+			created: time::PreciseTime::now(),
+			ip: ip,
+			typ: h.typ,
+		})
+	}
+}
+
+/// Returns the tag of a packet with the given type and the serialized id.
+///
+/// The payload of an ordinary ping could be mistaken for a packet of stealthy. The tag
+/// marks the packets of stealthy so that foreign packets are dropped before they reach
+/// the decryption. It is derived from the header so that it is not a constant on the
+/// wire.
+fn tag(typ: u8, id: &[u8]) -> [u8; TAG_LEN] {
+
+	let mut h = Sha256::new();
+	h.input(TAG_CONTEXT);
+	h.input(&[VERSION_TAGGED, typ]);
+	h.input(id);
+	let mut out = [0; 32];
+	h.result(&mut out);
+	[out[0], out[1], out[2], out[3]]
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+	use super::Packet;
+
+	#[test]
+	fn test_tagged() {
+
+		let p = Packet::new(vec![1, 2, 3], "1.1.1.1".to_string(), 0x1122334455667788);
+		let v = p.serialize_tagged();
+		assert_eq!(v.len(), p.serialize().len() + 4);
+		assert!(Packet::is_tagged(&v));
+		assert!(!Packet::is_tagged(&p.serialize()));
+
+		let q = Packet::deserialize(&v, "1.1.1.1".to_string()).unwrap();
+		assert_eq!((q.id, q.typ, q.data), (p.id, p.typ, p.data.clone()));
+		let q = Packet::deserialize(&p.serialize(), "1.1.1.1".to_string()).unwrap();
+		assert_eq!(q.data, p.data);
+
+		// A modified header invalidates the tag.
+		let mut t = v.clone();
+		t[5] ^= 1;
+		assert!(Packet::deserialize(&t, "1.1.1.1".to_string()).is_none());
+		let mut t = v.clone();
+		t[11] ^= 1;
+		assert!(Packet::deserialize(&t, "1.1.1.1".to_string()).is_none());
+	}
+
+	#[test]
+	fn test_checked() {
+
+		let p = Packet::new(vec![1, 2, 3], "1.1.1.1".to_string(), 0x1122334455667788);
+		let v = p.serialize_checked();
+		assert_eq!(v.len(), p.serialize_tagged().len() + 4);
+		assert!(Packet::is_tagged(&v));
+		let q = Packet::deserialize(&v, "1.1.1.1".to_string()).unwrap();
+		assert_eq!((q.id, q.typ, q.data), (p.id, p.typ, p.data.clone()));
+		let q = Packet::deserialize(&Packet::new(vec![], "1.1.1.1".to_string(), 1).serialize_checked(), "1.1.1.1".to_string()).unwrap();
+		assert!(q.data.is_empty());
+
+		// Each corrupted byte is detected, even in the payload which is not covered by the tag.
+		for i in 0..v.len() {
+			let mut t = v.clone();
+			t[i] ^= 0x40;
+			assert!(Packet::deserialize(&t, "1.1.1.1".to_string()).is_none());
+		}
+		for i in 0..v.len() {
+			assert!(Packet::deserialize(&v[..i], "1.1.1.1".to_string()).is_none());
+		}
+	}
+
+	#[test]
+	fn test_truncated() {
+
+		let v = Packet::new(vec![1, 2, 3], "1.1.1.1".to_string(), 7).serialize_tagged();
+		for i in 0..10 {
+			assert!(Packet::deserialize(&v[..i], "1.1.1.1".to_string()).is_none());
+		}
+		// Without the complete tag the packet is not recognized as tagged.
+		for i in 10..14 {
+			assert!(Packet::deserialize(&v[..i], "1.1.1.1".to_string()).is_none());
+		}
+		assert_eq!(Packet::deserialize(&v[..14], "1.1.1.1".to_string()).unwrap().data, vec![]);
+	}
+
+	#[test]
+	fn test_ack_batch() {
+
+		let ids = vec![1, 0x1122334455667788, u64::max_value()];
+		let p = Packet::ack_batch(&ids, "1.1.1.1".to_string());
+		assert_eq!(p.data.len(), 24);
+		let q = Packet::deserialize(&p.serialize_tagged(), "1.1.1.1".to_string()).unwrap();
+		assert!(q.is_ack_batch());
+		assert_eq!(q.acked_ids(), ids);
+		assert!(Packet::ack_batch(&[], "1.1.1.1".to_string()).acked_ids().is_empty());
+	}
+}
@@ -0,0 +1,121 @@
+use crate::wire::{push_value, pop_value};
+use crate::error::StealthyError;
+
+/// Marks a padded plaintext. Unpadded plaintexts start with the marker of `Signing` which
+/// is 0 or 1.
+const PADDED: u8 = 2;
+/// Marker and length of the unpadded plaintext.
+pub const HEADER_LEN: usize = 1 + 4;
+/// Size of the smallest bucket.
+const MIN_BUCKET: usize = 64;
+
+/// Padding of outgoing messages before they are encrypted.
+///
+/// The length of an encrypted message reveals the length of its content, e.g. whether a
+/// short answer like "yes" or a long text has been sent. With padding the length of each
+/// message is increased so that messages of similar size cannot be distinguished. The
+/// receiver removes the padding transparently. Messages are only padded for peers which
+/// have advertised that they support padding.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Padding {
+    Off,
+    /// Pads to the next power of two.
+    Buckets,
+    /// Pads to the maximum payload size so that each packet has the same size.
+    Max,
+}
+
+impl Padding {
+
+    pub fn parse(s: &str) -> Result<Padding, StealthyError> {
+        match s {
+            "off" => Ok(Padding::Off),
+            "bucket" => Ok(Padding::Buckets),
+            "max" => Ok(Padding::Max),
+            _ => Err(StealthyError::Protocol("Unknown padding. Use off, bucket or max."))
+        }
+    }
+
+    /// Returns the length to which a plaintext with len bytes is padded. `capacity` is the
+    /// number of bytes of a packet which are available for the ciphertext and `overhead`
+    /// the maximum number of bytes which are added by the encryption.
+    pub fn target(&self, len: usize, capacity: usize, overhead: usize) -> usize {
+        let len = len + HEADER_LEN;
+        match *self {
+            Padding::Off => len,
+            Padding::Buckets => len.next_power_of_two().max(MIN_BUCKET),
+            Padding::Max if capacity > overhead => {
+                // Number of packets which are required for the padded message.
+                let n = (len + overhead + capacity - 1) / capacity;
+                n * capacity - overhead
+            },
+            Padding::Max => len
+        }
+    }
+}
+
+/// Pads the plaintext to the given length. If the plaintext is larger, only the header is
+/// added.
+pub fn pad(buf: &[u8], target: usize) -> Vec<u8> {
+    let mut v = Vec::with_capacity(target.max(buf.len() + HEADER_LEN));
+    v.push(PADDED);
+    push_value(&mut v, buf.len() as u64, 4);
+    v.extend_from_slice(buf);
+    while v.len() < target {
+        v.push(0);
+    }
+    v
+}
+
+/// Removes the padding of a decrypted message. Messages which are not padded are returned
+/// without modification.
+pub fn unpad(buf: Vec<u8>) -> Result<Vec<u8>, StealthyError> {
+    if buf.first() != Some(&PADDED) {
+        return Ok(buf);
+    }
+    let mut v = buf[1..].to_vec();
+    let len = pop_value(&mut v, 4).map_err(StealthyError::Protocol)? as usize;
+    if len > v.len() {
+        return Err(StealthyError::Protocol("Invalid padding."));
+    }
+    v.truncate(len);
+    Ok(v)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Padding, pad, unpad, MIN_BUCKET};
+
+    #[test]
+    fn test_target() {
+
+        assert_eq!(Padding::Buckets.target(1, 500, 50), MIN_BUCKET);
+        assert_eq!(Padding::Buckets.target(100, 500, 50), 128);
+        assert_eq!(Padding::Buckets.target(123, 500, 50), 128);
+        assert_eq!(Padding::Buckets.target(124, 500, 50), 256);
+        // The ciphertext fills whole packets.
+        assert_eq!(Padding::Max.target(1, 500, 50), 450);
+        assert_eq!(Padding::Max.target(445, 500, 50), 450);
+        assert_eq!(Padding::Max.target(446, 500, 50), 950);
+        assert_eq!(Padding::Max.target(1, 50, 50), 6);
+        assert_eq!(Padding::parse("bucket").map_err(|e| e.message()), Ok(Padding::Buckets));
+        assert!(Padding::parse("none").is_err());
+    }
+
+    #[test]
+    fn test_pad() {
+
+        let v = pad(b"hello", 64);
+        assert_eq!(v.len(), 64);
+        assert_eq!(unpad(v).unwrap(), b"hello".to_vec());
+        assert_eq!(pad(b"hello", 2).len(), 10);
+        // Unpadded messages are not modified.
+        assert_eq!(unpad(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+        assert!(unpad(vec![2, 10, 0, 0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::cryp::{self, ResultVec};
+use crate::error::StealthyError;
+use crate::iptools;
+use crate::negotiation::{Ciphers, Features};
+
+/// The symmetric keys of the peers.
+///
+/// By default all peers share the same key. With a key file each peer can get its own key
+/// so that a peer cannot read the conversations with other peers. Each line of the file
+/// contains the ip of a peer and its key in hexadecimal separated by whitespace. Lines
+/// which start with '#' are ignored. Peers which are not listed use the default key.
+///
+/// The default key is replaced when the members of the group change (see `group`).
+///
+/// Next to the ciphers the optional features advertised by the peers are kept here.
+pub struct PeerKeys {
+    default: RwLock<Arc<Ciphers>>,
+    peers: HashMap<String, Arc<Ciphers>>,
+    features: Features,
+}
+
+impl PeerKeys {
+
+    pub fn new(default: Ciphers) -> PeerKeys {
+        PeerKeys {
+            default: RwLock::new(Arc::new(default)),
+            peers: HashMap::new(),
+            features: Features::new(),
+        }
+    }
+
+    /// Adds the keys in the format of the key file. The keys are used with the given cipher.
+    pub fn with_keys(mut self, content: &str, cipher: &str) -> Result<PeerKeys, &'static str> {
+
+        for (ip, key) in parse(content)? {
+            let c = Ciphers::new(Arc::new(cryp::symmetric(cipher, &key).map_err(|e| e.message())?), cryp::symmetric_alternatives(cipher, &key));
+            self = self.with_peer(&iptools::canonical(&ip), c);
+        }
+        Ok(self)
+    }
+
+    /// Uses the given ciphers for the ip instead of the default ciphers.
+    pub fn with_peer(mut self, ip: &str, c: Ciphers) -> PeerKeys {
+        self.peers.insert(ip.to_string(), Arc::new(c));
+        self
+    }
+
+    /// Returns the ciphers which are used for the given ip.
+    pub fn get(&self, ip: &str) -> Arc<Ciphers> {
+        match self.peers.get(ip) {
+            Some(c) => c.clone(),
+            None => self.default.read().unwrap().clone()
+        }
+    }
+
+    /// Replaces the default key by the given key in hexadecimal. The configured cipher is
+    /// kept. Peers with their own key are not affected.
+    pub fn set_default_key(&self, hexkey: &String) -> Result<(), &'static str> {
+        let old = self.default.read().unwrap().clone();
+        let cipher = cryp::cipher_name(old.primary().cipher_id());
+        let c = old.rekeyed(Arc::new(cryp::symmetric(cipher, hexkey).map_err(|e| e.message())?), cryp::symmetric_alternatives(cipher, hexkey));
+        *self.default.write().unwrap() = Arc::new(c);
+        Ok(())
+    }
+
+    /// Returns the optional features advertised by the peers.
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    /// Returns the maximum size of a sealed message among all keys.
+    pub fn max_sealed_len(&self, v: &Vec<u8>) -> Result<usize, StealthyError> {
+        let mut n = self.default.read().unwrap().max_sealed_len(v)?;
+        for c in self.peers.values() {
+            n = n.max(c.max_sealed_len(v)?);
+        }
+        Ok(n)
+    }
+
+    /// Encrypts a message for the given ip with its key.
+    pub fn seal(&self, ip: &str, v: &Vec<u8>) -> ResultVec {
+        self.get(ip).seal(ip, v)
+    }
+
+    /// Decrypts a message of the given ip with its key.
+    pub fn open(&self, ip: &str, v: &Vec<u8>) -> ResultVec {
+        self.get(ip).open(v)
+    }
+}
+
+/// Returns the pairs of ip and key in hexadecimal of a key file. The keys are not
+/// validated.
+pub fn parse(content: &str) -> Result<Vec<(String, String)>, &'static str> {
+
+    let mut v = vec![];
+    for line in content.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with("#")) {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        if parts.len() != 2 {
+            return Err("Invalid line in key file.");
+        }
+        v.push((parts[0].to_string(), parts[1].to_string()));
+    }
+    Ok(v)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::{PeerKeys, parse};
+    use crate::cryp;
+    use crate::negotiation::Ciphers;
+
+    fn keys() -> PeerKeys {
+        let e = cryp::symmetric("blowfish", &"11111111111111111111111111111111".to_string()).unwrap();
+        PeerKeys::new(Ciphers::new(Arc::new(e), vec![]))
+    }
+
+    #[test]
+    fn test_peer_keys() {
+
+        let k = keys().with_keys("# comment\n1.1.1.1 22222222222222222222222222222222\n\n2.2.2.2  33333333333333333333333333333333\n", "blowfish").unwrap();
+        let data = b"hello".to_vec();
+
+        let sealed = k.seal("1.1.1.1", &data).unwrap();
+        assert_eq!(k.open("1.1.1.1", &sealed).unwrap(), data);
+        // Other peers cannot decrypt the message.
+        assert!(k.open("2.2.2.2", &sealed).is_err());
+        assert!(k.open("3.3.3.3", &sealed).is_err());
+
+        // Peers which are not listed use the default key.
+        let sealed = k.seal("3.3.3.3", &data).unwrap();
+        assert_eq!(keys().open("3.3.3.3", &sealed).unwrap(), data);
+        assert!(k.open("1.1.1.1", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+
+        let v = parse("# comment\n\n 1.1.1.1\t22 \n").unwrap();
+        assert_eq!(v, vec![("1.1.1.1".to_string(), "22".to_string())]);
+        assert!(parse("1.1.1.1\n").is_err());
+        assert!(parse("1.1.1.1 22 33\n").is_err());
+    }
+
+    #[test]
+    fn test_set_default_key() {
+
+        let k = keys().with_keys("1.1.1.1 22222222222222222222222222222222", "blowfish").unwrap();
+        let data = b"hello".to_vec();
+        let sealed = k.seal("3.3.3.3", &data).unwrap();
+        let own = k.seal("1.1.1.1", &data).unwrap();
+
+        k.set_default_key(&"44444444444444444444444444444444".to_string()).unwrap();
+        assert!(k.open("3.3.3.3", &sealed).is_err());
+        assert_eq!(k.open("3.3.3.3", &k.seal("3.3.3.3", &data).unwrap()).unwrap(), data);
+        // Peers with their own key still use it.
+        assert_eq!(k.open("1.1.1.1", &own).unwrap(), data);
+        assert!(k.set_default_key(&"abc".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_key_file() {
+
+        assert!(keys().with_keys("1.1.1.1", "blowfish").is_err());
+        assert!(keys().with_keys("1.1.1.1 abc", "blowfish").is_err());
+        assert!(keys().with_keys("1.1.1.1 22222222222222222222222222222222 x", "blowfish").is_err());
+    }
+}
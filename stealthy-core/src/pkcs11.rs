@@ -0,0 +1,476 @@
+#[cfg(unix)]
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Mutex;
+
+#[cfg(unix)]
+use libc::c_char;
+use libc::{c_ulong, c_void};
+
+use crate::rsa::Padding;
+use crate::error::StealthyError;
+
+/// Prefix of a PKCS#11 URI (RFC 7512) which refers to a private key on a token, e.g.
+/// `pkcs11:object=stealthy?module-path=/usr/lib/x86_64-linux-gnu/libykcs11.so`.
+pub const PREFIX: &str = "pkcs11:";
+
+type Rv = c_ulong;
+type Handle = c_ulong;
+
+const CKR_OK: Rv = 0;
+const CKR_USER_ALREADY_LOGGED_IN: Rv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: Rv = 0x191;
+const CKF_SERIAL_SESSION: c_ulong = 1 << 2;
+const CKU_USER: c_ulong = 1;
+const CKO_PRIVATE_KEY: c_ulong = 3;
+const CKK_RSA: c_ulong = 0;
+const CKA_CLASS: c_ulong = 0;
+const CKA_LABEL: c_ulong = 3;
+const CKA_KEY_TYPE: c_ulong = 0x100;
+const CKA_ID: c_ulong = 0x102;
+const CKA_MODULUS: c_ulong = 0x120;
+const CKM_RSA_PKCS_OAEP: c_ulong = 9;
+const CKM_SHA256_RSA_PKCS: c_ulong = 0x40;
+const CKM_SHA_1: c_ulong = 0x220;
+const CKM_SHA256: c_ulong = 0x250;
+const CKG_MGF1_SHA1: c_ulong = 1;
+const CKG_MGF1_SHA256: c_ulong = 2;
+const CKZ_DATA_SPECIFIED: c_ulong = 1;
+
+const TOKEN_FAILED: &str = "Operation of the PKCS#11 token failed.";
+const LOAD_FAILED: &str = "Could not load the PKCS#11 module.";
+
+type Unused = *const c_void;
+
+/// Functions of a PKCS#11 module in the order of the specification. Only the functions
+/// which are used have a signature. They are unsafe as the module dereferences the
+/// pointers which are passed to it.
+#[repr(C)]
+struct FunctionList {
+    version: [u8; 2],
+    initialize: unsafe extern "C" fn(*mut c_void) -> Rv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> Rv,
+    _get_info: Unused,
+    _get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(u8, *mut c_ulong, *mut c_ulong) -> Rv,
+    _get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(c_ulong, *mut TokenInfo) -> Rv,
+    _get_mechanism_list: Unused,
+    _get_mechanism_info: Unused,
+    _init_token: Unused,
+    _init_pin: Unused,
+    _set_pin: Unused,
+    open_session: unsafe extern "C" fn(c_ulong, c_ulong, *mut c_void, *mut c_void, *mut Handle) -> Rv,
+    close_session: unsafe extern "C" fn(Handle) -> Rv,
+    _close_all_sessions: Unused,
+    _get_session_info: Unused,
+    _get_operation_state: Unused,
+    _set_operation_state: Unused,
+    login: unsafe extern "C" fn(Handle, c_ulong, *const u8, c_ulong) -> Rv,
+    _logout: Unused,
+    _create_object: Unused,
+    _copy_object: Unused,
+    _destroy_object: Unused,
+    _get_object_size: Unused,
+    get_attribute_value: unsafe extern "C" fn(Handle, Handle, *mut Attribute, c_ulong) -> Rv,
+    _set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(Handle, *mut Attribute, c_ulong) -> Rv,
+    find_objects: unsafe extern "C" fn(Handle, *mut Handle, c_ulong, *mut c_ulong) -> Rv,
+    find_objects_final: unsafe extern "C" fn(Handle) -> Rv,
+    _encrypt_init: Unused,
+    _encrypt: Unused,
+    _encrypt_update: Unused,
+    _encrypt_final: Unused,
+    decrypt_init: unsafe extern "C" fn(Handle, *mut Mechanism, Handle) -> Rv,
+    decrypt: unsafe extern "C" fn(Handle, *const u8, c_ulong, *mut u8, *mut c_ulong) -> Rv,
+    _decrypt_update: Unused,
+    _decrypt_final: Unused,
+    _digest_init: Unused,
+    _digest: Unused,
+    _digest_update: Unused,
+    _digest_key: Unused,
+    _digest_final: Unused,
+    sign_init: unsafe extern "C" fn(Handle, *mut Mechanism, Handle) -> Rv,
+    sign: unsafe extern "C" fn(Handle, *const u8, c_ulong, *mut u8, *mut c_ulong) -> Rv,
+}
+
+#[repr(C)]
+struct TokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: c_ulong,
+    counters: [c_ulong; 10],
+    hardware_version: [u8; 2],
+    firmware_version: [u8; 2],
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct Attribute {
+    typ: c_ulong,
+    value: *mut c_void,
+    len: c_ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    mechanism: c_ulong,
+    parameter: *mut c_void,
+    len: c_ulong,
+}
+
+#[repr(C)]
+struct OaepParams {
+    hash: c_ulong,
+    mgf: c_ulong,
+    source: c_ulong,
+    source_data: *mut c_void,
+    source_data_len: c_ulong,
+}
+
+/// The parts of a PKCS#11 URI which are used to find the key.
+#[derive(Debug, PartialEq)]
+struct Uri {
+    module: String,
+    token: Option<String>,
+    object: Option<String>,
+    id: Option<Vec<u8>>,
+    pin: Option<String>,
+}
+
+impl Uri {
+
+    fn parse(s: &str) -> Result<Uri, StealthyError> {
+
+        if !s.starts_with(PREFIX) {
+            return Err(StealthyError::Crypto("Invalid PKCS#11 URI."));
+        }
+        let mut parts = s[PREFIX.len()..].splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let query = parts.next().unwrap_or("");
+
+        let mut uri = Uri { module: String::new(), token: None, object: None, id: None, pin: None };
+        let attrs = path.split(';').chain(query.split('&')).filter(|a| !a.is_empty());
+        for a in attrs {
+            let mut kv = a.splitn(2, '=');
+            let k = kv.next().unwrap_or("");
+            let v = percent_decode(kv.next().ok_or(StealthyError::Crypto("Invalid PKCS#11 URI."))?)?;
+            let text = || String::from_utf8(v.clone()).map_err(|_| StealthyError::Crypto("Invalid PKCS#11 URI."));
+            match k {
+                "module-path" => uri.module = text()?,
+                "token" => uri.token = Some(text()?),
+                "object" => uri.object = Some(text()?),
+                "id" => uri.id = Some(v.clone()),
+                "pin-value" => uri.pin = Some(text()?),
+                // Other attributes like the type of the object are not required.
+                _ => { }
+            }
+        }
+        if uri.module.is_empty() {
+            return Err(StealthyError::Crypto("The PKCS#11 URI requires the module-path of the PKCS#11 library."));
+        }
+        Ok(uri)
+    }
+}
+
+fn percent_decode(s: &str) -> Result<Vec<u8>, StealthyError> {
+    let b = s.as_bytes();
+    let mut r = vec![];
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let h = b.get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or(StealthyError::Crypto("Invalid PKCS#11 URI."))?;
+            r.push(h);
+            i += 3;
+        } else {
+            r.push(b[i]);
+            i += 1;
+        }
+    }
+    Ok(r)
+}
+
+/// Returns true if the URI does not contain the PIN so that it has to be asked for.
+pub fn requires_pin(s: &str) -> bool {
+    Uri::parse(s).map(|u| u.pin.is_none()).unwrap_or(false)
+}
+
+/// A RSA private key which is stored on a PKCS#11 token, e.g. a YubiKey or a smartcard.
+/// The key never leaves the token. The wrapped keys of messages are decrypted and
+/// signatures are created by the token.
+pub struct TokenKey {
+    lib: *mut c_void,
+    functions: *const FunctionList,
+    /// Sessions of PKCS#11 must not be used by several threads at the same time.
+    session: Mutex<Handle>,
+    key: Handle,
+    /// Size of the modulus in bytes.
+    siz: usize,
+}
+
+// The module is only accessed via the session which is protected by the mutex.
+unsafe impl Send for TokenKey { }
+unsafe impl Sync for TokenKey { }
+
+impl TokenKey {
+
+    /// Loads the PKCS#11 module given in the URI, logs in with the PIN and looks up the
+    /// RSA private key given by its label (`object`) or `id`.
+    pub fn open(uri: &str, pin: Option<&String>) -> Result<TokenKey, StealthyError> {
+
+        let uri = Uri::parse(uri)?;
+        let pin = pin.cloned().or(uri.pin.clone());
+
+        let (lib, sym) = load(&uri.module)?;
+        // SAFETY: C_GetFunctionList has this signature in every PKCS#11 module.
+        let get_function_list: unsafe extern "C" fn(*mut *const FunctionList) -> Rv = unsafe { std::mem::transmute(sym) };
+        let mut functions = ptr::null();
+        // SAFETY: The module writes the address of its function list into `functions`.
+        if unsafe { get_function_list(&mut functions) } != CKR_OK || functions.is_null() {
+            unload(lib);
+            return Err(StealthyError::Crypto(LOAD_FAILED));
+        }
+
+        let mut k = TokenKey {
+            lib,
+            functions,
+            session: Mutex::new(0),
+            key: 0,
+            siz: 0
+        };
+        // SAFETY: The function list has been checked for NULL and is owned by the module
+        // which stays loaded until `k` is dropped.
+        let f = unsafe { &*functions };
+        // SAFETY: NULL selects the default arguments.
+        match unsafe { (f.initialize)(ptr::null_mut()) } {
+            CKR_OK | CKR_CRYPTOKI_ALREADY_INITIALIZED => { },
+            _ => return Err(StealthyError::Crypto("Could not initialize the PKCS#11 module."))
+        }
+
+        let slot = k.find_slot(uri.token.as_ref())?;
+        let mut session = 0;
+        // SAFETY: The session handle is written into `session`. No callback is given.
+        check(unsafe { (f.open_session)(slot, CKF_SERIAL_SESSION, ptr::null_mut(), ptr::null_mut(), &mut session) })?;
+        *k.session.lock().unwrap() = session;
+
+        if let Some(p) = pin {
+            // SAFETY: The PIN is read with the given length and is not kept by the module.
+            match unsafe { (f.login)(session, CKU_USER, p.as_ptr(), p.len() as c_ulong) } {
+                CKR_OK | CKR_USER_ALREADY_LOGGED_IN => { },
+                _ => return Err(StealthyError::Crypto("Login to the PKCS#11 token failed. Wrong PIN?"))
+            }
+        }
+
+        k.key = k.find_key(session, uri.object.as_ref(), uri.id.as_ref())?;
+        let mut attr = Attribute { typ: CKA_MODULUS, value: ptr::null_mut(), len: 0 };
+        // SAFETY: With a NULL value only the length of the modulus is written into `attr`.
+        check(unsafe { (f.get_attribute_value)(session, k.key, &mut attr, 1) })?;
+        k.siz = attr.len as usize;
+        Ok(k)
+    }
+
+    fn f(&self) -> &FunctionList {
+        // SAFETY: `functions` has been checked for NULL in `open` and the module is unloaded
+        // only when `self` is dropped.
+        unsafe { &*self.functions }
+    }
+
+    /// Returns the first slot with a token whose label matches.
+    fn find_slot(&self, label: Option<&String>) -> Result<c_ulong, StealthyError> {
+
+        let f = self.f();
+        let mut n = 0;
+        // SAFETY: With NULL only the number of slots is written into `n`. Afterwards the
+        // module writes at most `n` slots into `slots` which has room for `n`.
+        check(unsafe { (f.get_slot_list)(1, ptr::null_mut(), &mut n) })?;
+        let mut slots = vec![0; n as usize];
+        check(unsafe { (f.get_slot_list)(1, slots.as_mut_ptr(), &mut n) })?;
+        slots.truncate(n as usize);
+
+        for slot in slots {
+            let label = match label {
+                Some(l) => l,
+                None => return Ok(slot)
+            };
+            // SAFETY: TokenInfo consists of integers only, for which zero is valid, and the
+            // module fills it.
+            let mut info: TokenInfo = unsafe { std::mem::zeroed() };
+            if unsafe { (f.get_token_info)(slot, &mut info) } == CKR_OK
+                    && String::from_utf8_lossy(&info.label).trim_end() == label.as_str() {
+                return Ok(slot);
+            }
+        }
+        Err(StealthyError::Crypto("Could not find the PKCS#11 token."))
+    }
+
+    fn find_key(&self, session: Handle, label: Option<&String>, id: Option<&Vec<u8>>) -> Result<Handle, StealthyError> {
+
+        let f = self.f();
+        let mut class = CKO_PRIVATE_KEY;
+        let mut typ = CKK_RSA;
+        let ulong = std::mem::size_of::<c_ulong>() as c_ulong;
+        let mut template = vec![
+            Attribute { typ: CKA_CLASS, value: &mut class as *mut c_ulong as *mut c_void, len: ulong },
+            Attribute { typ: CKA_KEY_TYPE, value: &mut typ as *mut c_ulong as *mut c_void, len: ulong },
+        ];
+        if let Some(l) = label {
+            template.push(Attribute { typ: CKA_LABEL, value: l.as_ptr() as *mut c_void, len: l.len() as c_ulong });
+        }
+        if let Some(i) = id {
+            template.push(Attribute { typ: CKA_ID, value: i.as_ptr() as *mut c_void, len: i.len() as c_ulong });
+        }
+
+        // SAFETY: The values of the template point to `class`, `typ`, `label` and `id` which
+        // outlive the search. At most one handle is written into `key`.
+        check(unsafe { (f.find_objects_init)(session, template.as_mut_ptr(), template.len() as c_ulong) })?;
+        let mut key = 0;
+        let mut n = 0;
+        let r = unsafe { (f.find_objects)(session, &mut key, 1, &mut n) };
+        unsafe { (f.find_objects_final)(session) };
+        check(r)?;
+        match n {
+            1 => Ok(key),
+            _ => Err(StealthyError::Crypto("Could not find the RSA private key on the PKCS#11 token."))
+        }
+    }
+
+    /// Decrypts a key which has been encrypted via RSA-OAEP.
+    pub fn decrypt(&self, cipher: &[u8], padding: Padding) -> Result<Vec<u8>, StealthyError> {
+
+        let (hash, mgf) = match padding {
+            Padding::OaepSha1 => (CKM_SHA_1, CKG_MGF1_SHA1),
+            Padding::OaepSha256 => (CKM_SHA256, CKG_MGF1_SHA256)
+        };
+        let mut params = OaepParams {
+            hash,
+            mgf,
+            source: CKZ_DATA_SPECIFIED,
+            source_data: ptr::null_mut(),
+            source_data_len: 0
+        };
+        let mut mech = Mechanism {
+            mechanism: CKM_RSA_PKCS_OAEP,
+            parameter: &mut params as *mut OaepParams as *mut c_void,
+            len: std::mem::size_of::<OaepParams>() as c_ulong
+        };
+
+        let f = self.f();
+        let session = self.session.lock().unwrap();
+        // SAFETY: `mech` and `params` outlive the operation. The plaintext is shorter than
+        // the modulus, so it fits into `buf` whose length is passed in `len`.
+        check(unsafe { (f.decrypt_init)(*session, &mut mech, self.key) })?;
+        let mut buf = vec![0; self.siz];
+        let mut len = buf.len() as c_ulong;
+        check(unsafe { (f.decrypt)(*session, cipher.as_ptr(), cipher.len() as c_ulong, buf.as_mut_ptr(), &mut len) })?;
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+
+    /// Signs the data via PKCS#1 v1.5 with SHA-256 like `RSA::sign`.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+        let mut mech = Mechanism {
+            mechanism: CKM_SHA256_RSA_PKCS,
+            parameter: ptr::null_mut(),
+            len: 0
+        };
+        let f = self.f();
+        let session = self.session.lock().unwrap();
+        // SAFETY: The signature has the length of the modulus, so it fits into `buf` whose
+        // length is passed in `len`.
+        check(unsafe { (f.sign_init)(*session, &mut mech, self.key) })?;
+        let mut buf = vec![0; self.siz];
+        let mut len = buf.len() as c_ulong;
+        check(unsafe { (f.sign)(*session, data.as_ptr(), data.len() as c_ulong, buf.as_mut_ptr(), &mut len) })?;
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+}
+
+impl Drop for TokenKey {
+
+    fn drop(&mut self) {
+        let f = self.f();
+        let session = *self.session.lock().unwrap();
+        // SAFETY: The session is not used anymore. NULL is the only valid argument of
+        // C_Finalize.
+        if session != 0 {
+            unsafe { (f.close_session)(session) };
+        }
+        unsafe { (f.finalize)(ptr::null_mut()) };
+        unload(self.lib);
+    }
+}
+
+/// Loads the module and returns its handle and the address of `C_GetFunctionList`.
+#[cfg(unix)]
+fn load(module: &str) -> Result<(*mut c_void, *mut c_void), StealthyError> {
+
+    let path = CString::new(module).map_err(|_| StealthyError::Crypto("Invalid PKCS#11 URI."))?;
+    let lib = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW) };
+    if lib.is_null() {
+        return Err(StealthyError::Crypto(LOAD_FAILED));
+    }
+    let sym = unsafe { libc::dlsym(lib, b"C_GetFunctionList\0".as_ptr() as *const c_char) };
+    if sym.is_null() {
+        unload(lib);
+        return Err(StealthyError::Crypto(LOAD_FAILED));
+    }
+    Ok((lib, sym))
+}
+
+#[cfg(unix)]
+fn unload(lib: *mut c_void) {
+    unsafe { libc::dlclose(lib); }
+}
+
+/// The structures of PKCS#11 are packed on Windows which is not supported so far.
+#[cfg(windows)]
+fn load(_module: &str) -> Result<(*mut c_void, *mut c_void), StealthyError> {
+    Err(StealthyError::Crypto("PKCS#11 is not supported on Windows so far."))
+}
+
+#[cfg(windows)]
+fn unload(_lib: *mut c_void) { }
+
+fn check(rv: Rv) -> Result<(), StealthyError> {
+    match rv {
+        CKR_OK => Ok(()),
+        _ => Err(StealthyError::Crypto(TOKEN_FAILED))
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Uri, requires_pin, TokenKey};
+
+    #[test]
+    fn test_uri() {
+
+        let u = Uri::parse("pkcs11:token=YubiKey%20PIV;object=my%20key;id=%01%02;type=private?module-path=/usr/lib/libykcs11.so&pin-value=123456").unwrap();
+        assert_eq!(u, Uri {
+            module: "/usr/lib/libykcs11.so".to_string(),
+            token: Some("YubiKey PIV".to_string()),
+            object: Some("my key".to_string()),
+            id: Some(vec![1, 2]),
+            pin: Some("123456".to_string())
+        });
+        assert!(!requires_pin("pkcs11:object=a?module-path=/lib.so&pin-value=1"));
+        assert!(requires_pin("pkcs11:object=a?module-path=/lib.so"));
+
+        assert!(Uri::parse("pkcs11:object=a").is_err());
+        assert!(Uri::parse("pkcs11:object=%zz?module-path=/lib.so").is_err());
+        assert!(Uri::parse("object=a?module-path=/lib.so").is_err());
+        assert!(TokenKey::open("pkcs11:object=a?module-path=/does/not/exist.so", None).is_err());
+    }
+}
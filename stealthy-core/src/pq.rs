@@ -0,0 +1,98 @@
+/// True if stealthy has been built with support for post-quantum key encapsulation.
+pub const ENABLED: bool = cfg!(feature = "pq");
+
+/// Size of an encapsulation key.
+pub const PUBLIC_KEY_LEN: usize = 1184;
+/// Size of the ciphertext of an encapsulated secret.
+pub const CIPHERTEXT_LEN: usize = 1088;
+
+pub const UNSUPPORTED: &str = "Post-quantum key encapsulation is not supported. Build with --features pq.";
+
+/// An ML-KEM-768 (Kyber) key pair for post-quantum key encapsulation.
+///
+/// If stealthy is built with the feature `pq` the handshake encapsulates a second secret
+/// with ML-KEM in addition to the X25519 key exchange. The session key is derived from
+/// both secrets so that recorded sessions remain confidential as long as one of both is
+/// not broken. Without the feature all functions return `UNSUPPORTED`.
+pub struct KemKeyPair {
+    #[cfg_attr(not(feature = "pq"), allow(dead_code))]
+    secret: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+#[cfg(feature = "pq")]
+impl KemKeyPair {
+
+    pub fn generate() -> Result<KemKeyPair, &'static str> {
+        use pqcrypto_traits::kem::{PublicKey, SecretKey};
+
+        let (public, secret) = pqcrypto_mlkem::mlkem768::keypair();
+        Ok(KemKeyPair {
+            secret: secret.as_bytes().to_vec(),
+            public: public.as_bytes().to_vec(),
+        })
+    }
+
+    /// Returns the secret which has been encapsulated by the peer.
+    pub fn decapsulate(&self, ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        use pqcrypto_mlkem::mlkem768;
+        use pqcrypto_traits::kem::{Ciphertext, SecretKey, SharedSecret};
+
+        let ct = mlkem768::Ciphertext::from_bytes(ciphertext).map_err(|_| "Invalid ciphertext of key encapsulation.")?;
+        let sk = mlkem768::SecretKey::from_bytes(&self.secret).map_err(|_| "Invalid key for key encapsulation.")?;
+        Ok(mlkem768::decapsulate(&ct, &sk).as_bytes().to_vec())
+    }
+}
+
+/// Encapsulates a new secret for the peer. Returns the ciphertext for the peer and the
+/// secret.
+#[cfg(feature = "pq")]
+pub fn encapsulate(public: &[u8]) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+    use pqcrypto_mlkem::mlkem768;
+    use pqcrypto_traits::kem::{Ciphertext, PublicKey, SharedSecret};
+
+    let pk = mlkem768::PublicKey::from_bytes(public).map_err(|_| "Invalid key for key encapsulation.")?;
+    let (secret, ct) = mlkem768::encapsulate(&pk);
+    Ok((ct.as_bytes().to_vec(), secret.as_bytes().to_vec()))
+}
+
+#[cfg(not(feature = "pq"))]
+impl KemKeyPair {
+
+    pub fn generate() -> Result<KemKeyPair, &'static str> {
+        Err(UNSUPPORTED)
+    }
+
+    pub fn decapsulate(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        Err(UNSUPPORTED)
+    }
+}
+
+#[cfg(not(feature = "pq"))]
+pub fn encapsulate(_public: &[u8]) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+    Err(UNSUPPORTED)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "pq"))]
+mod tests {
+
+    use super::{KemKeyPair, encapsulate, PUBLIC_KEY_LEN, CIPHERTEXT_LEN};
+
+    #[test]
+    fn test_encapsulate() {
+
+        let k = KemKeyPair::generate().unwrap();
+        assert_eq!(k.public.len(), PUBLIC_KEY_LEN);
+
+        let (ct, secret) = encapsulate(&k.public).unwrap();
+        assert_eq!(ct.len(), CIPHERTEXT_LEN);
+        assert_eq!(k.decapsulate(&ct).unwrap(), secret);
+        // Another key pair gets another secret.
+        assert_ne!(KemKeyPair::generate().unwrap().decapsulate(&ct).unwrap(), secret);
+        assert!(encapsulate(&[1, 2, 3]).is_err());
+    }
+}
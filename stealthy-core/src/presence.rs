@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+/// Interval in seconds in which a keepalive is sent to each peer.
+pub const KEEPALIVE_INTERVAL: u64 = 30;
+/// Time in seconds without a keypress after which we are away.
+pub const AWAY_TIMEOUT: u64 = 300;
+/// Time in seconds without a keepalive after which a peer is offline. Two keepalives may
+/// be lost.
+const OFFLINE_TIMEOUT: i64 = 3 * KEEPALIVE_INTERVAL as i64 + 10;
+
+const ONLINE: u8 = 1;
+const AWAY: u8 = 2;
+const OFFLINE: u8 = 3;
+
+/// State of a peer which is announced in its keepalives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Online,
+    /// The user has not pressed a key for `AWAY_TIMEOUT` seconds.
+    Away,
+    Offline,
+}
+
+impl Presence {
+
+    /// Returns the content of a keepalive which announces the state.
+    pub fn encode(&self) -> Vec<u8> {
+        vec![match *self {
+            Presence::Online => ONLINE,
+            Presence::Away => AWAY,
+            Presence::Offline => OFFLINE,
+        }]
+    }
+
+    /// Returns the state announced in the decrypted keepalive or None if the keepalive is
+    /// invalid.
+    pub fn decode(buf: &[u8]) -> Option<Presence> {
+        match buf {
+            [ONLINE] => Some(Presence::Online),
+            [AWAY] => Some(Presence::Away),
+            [OFFLINE] => Some(Presence::Offline),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Presence::Online => "online",
+            Presence::Away => "away",
+            Presence::Offline => "offline",
+        }
+    }
+}
+
+/// State of the peers learned from their keepalives.
+///
+/// Each peer sends an encrypted keepalive every `KEEPALIVE_INTERVAL` seconds and whenever
+/// its state changes. A peer from which no keepalive has been received for
+/// `OFFLINE_TIMEOUT` seconds is offline. Peers which run an older version never send
+/// keepalives and are shown as offline.
+pub struct Peers {
+    /// The ips in the order in which they are shown.
+    ips: Vec<String>,
+    /// Announced state and time in seconds of the last keepalive of each peer.
+    seen: HashMap<String, (Presence, i64)>,
+}
+
+impl Default for Peers {
+    fn default() -> Peers {
+        Peers::new()
+    }
+}
+
+impl Peers {
+
+    pub fn new() -> Peers {
+        Peers {
+            ips: vec![],
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Sets the peers which are shown even if they have never been seen.
+    pub fn set_ips(&mut self, ips: Vec<String>) {
+        self.ips = ips;
+    }
+
+    /// Is called when a keepalive with the given state has been received from the ip.
+    pub fn update(&mut self, ip: &str, p: Presence, now: i64) {
+        if !self.ips.iter().any(|i| i == ip) {
+            self.ips.push(ip.to_string());
+        }
+        self.seen.insert(ip.to_string(), (p, now));
+    }
+
+    /// Returns the state of the ip and the time of its last keepalive.
+    pub fn get(&self, ip: &str, now: i64) -> (Presence, Option<i64>) {
+        match self.seen.get(ip) {
+            Some(&(_, t)) if now - t >= OFFLINE_TIMEOUT => (Presence::Offline, Some(t)),
+            Some(&(p, t)) => (p, Some(t)),
+            None => (Presence::Offline, None)
+        }
+    }
+
+    /// Returns the ip, the state and the time of the last keepalive of each peer.
+    pub fn list(&self, now: i64) -> Vec<(String, Presence, Option<i64>)> {
+        self.ips.iter()
+            .map(|ip| {
+                let (p, t) = self.get(ip, now);
+                (ip.clone(), p, t)
+            })
+            .collect()
+    }
+}
+
+/// Returns a human readable description of the time since `t`, e.g. "5m ago".
+pub fn last_seen(t: Option<i64>, now: i64) -> String {
+    match t {
+        None => String::from("never"),
+        Some(t) => {
+            let d = (now - t).max(0);
+            match d {
+                0..=59 => format!("{}s ago", d),
+                60..=3599 => format!("{}m ago", d / 60),
+                3600..=86399 => format!("{}h ago", d / 3600),
+                _ => format!("{}d ago", d / 86400),
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Peers, Presence, last_seen, OFFLINE_TIMEOUT};
+
+    #[test]
+    fn test_encode() {
+
+        for p in &[Presence::Online, Presence::Away, Presence::Offline] {
+            assert_eq!(Presence::decode(&p.encode()), Some(*p));
+        }
+        assert_eq!(Presence::decode(&[]), None);
+        assert_eq!(Presence::decode(&[0]), None);
+        assert_eq!(Presence::decode(&[1, 1]), None);
+    }
+
+    #[test]
+    fn test_peers() {
+
+        let mut p = Peers::new();
+        p.set_ips(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(p.get("a", 100), (Presence::Offline, None));
+
+        p.update("a", Presence::Online, 100);
+        p.update("c", Presence::Away, 110);
+        assert_eq!(p.get("a", 120), (Presence::Online, Some(100)));
+        assert_eq!(p.list(120), vec![
+            ("a".to_string(), Presence::Online, Some(100)),
+            ("b".to_string(), Presence::Offline, None),
+            ("c".to_string(), Presence::Away, Some(110)),
+        ]);
+
+        // Without keepalives the peer is offline after the timeout.
+        assert_eq!(p.get("a", 100 + OFFLINE_TIMEOUT - 1).0, Presence::Online);
+        assert_eq!(p.get("a", 100 + OFFLINE_TIMEOUT), (Presence::Offline, Some(100)));
+        p.update("a", Presence::Offline, 200);
+        assert_eq!(p.get("a", 201), (Presence::Offline, Some(200)));
+    }
+
+    #[test]
+    fn test_last_seen() {
+        assert_eq!(last_seen(None, 100), "never");
+        assert_eq!(last_seen(Some(90), 100), "10s ago");
+        assert_eq!(last_seen(Some(100), 220), "2m ago");
+        assert_eq!(last_seen(Some(0), 7200), "2h ago");
+        assert_eq!(last_seen(Some(0), 200000), "2d ago");
+    }
+}
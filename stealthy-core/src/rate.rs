@@ -0,0 +1,194 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::StealthyError;
+
+/// Initial rate of file uploads in bytes per second.
+const INITIAL_RATE: f64 = 16384.0;
+const MIN_RATE: f64 = 1024.0;
+const MAX_RATE: f64 = 16.0 * 1024.0 * 1024.0;
+/// Increase of the rate in bytes per second for each acknowledged packet.
+const RATE_INCREASE: f64 = 1024.0;
+/// Minimum time in milliseconds between two reductions of the rate so that the
+/// retransmissions of a single burst of losses halve the rate only once.
+const DECREASE_INTERVAL: i64 = 1000;
+
+/// Parses a rate in bytes per second with an optional suffix k or m for KiB or MiB per
+/// second, e.g. "64k".
+pub fn parse_rate(s: &str) -> Result<u64, StealthyError> {
+    let s = s.trim().to_lowercase();
+    let (n, factor) = match s.chars().last() {
+        Some('k') => (&s[..s.len() - 1], 1024),
+        Some('m') => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (&s[..], 1)
+    };
+    match n.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n.saturating_mul(factor)),
+        _ => Err(StealthyError::Transport("Invalid rate. Use bytes per second, e.g. 2048 or 64k."))
+    }
+}
+
+/// Returns the current time in milliseconds.
+pub fn now_millis() -> i64 {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    t.as_millis() as i64
+}
+
+/// Controls the rate at which the packets of file uploads are sent so that a large upload
+/// does not flood the path and delay interactive messages.
+///
+/// The rate is controlled by additive increase and multiplicative decrease (AIMD): each
+/// ACK increases the rate by a constant and each retransmission halves it. The packets
+/// are paced, i.e. each packet is delayed until the previous packets have been sent with
+/// the current rate.
+///
+/// Independent of the rate of file uploads the packets of all messages can be limited to a
+/// maximum rate so that stealthy does not cause a conspicuous burst of ICMP packets.
+#[derive(Clone, Debug)]
+pub struct RateControl {
+    /// Rate in bytes per second.
+    rate: f64,
+    /// Time in milliseconds at which the next packet may be sent.
+    next: i64,
+    /// Time in milliseconds of the last reduction.
+    reduced: i64,
+    /// Maximum rate of all packets in bytes per second.
+    max: Option<u64>,
+    /// Time in milliseconds at which the next packet may be sent with the maximum rate.
+    max_next: i64,
+}
+
+impl Default for RateControl {
+    fn default() -> RateControl {
+        RateControl::new()
+    }
+}
+
+impl RateControl {
+
+    pub fn new() -> RateControl {
+        RateControl {
+            rate: INITIAL_RATE,
+            next: 0,
+            reduced: i64::min_value(),
+            max: None,
+            max_next: 0,
+        }
+    }
+
+    /// Limits the rate of all packets to the given number of bytes per second.
+    pub fn set_max(&mut self, max: Option<u64>) {
+        self.max = max;
+    }
+
+    /// Returns the maximum rate of all packets in bytes per second.
+    pub fn max(&self) -> Option<u64> {
+        self.max
+    }
+
+    /// Returns the rate in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.rate as u64
+    }
+
+    /// Increases the rate as a packet has been acknowledged.
+    pub fn on_ack(&mut self) {
+        self.rate = (self.rate + RATE_INCREASE).min(MAX_RATE);
+    }
+
+    /// Halves the rate as a packet has been retransmitted.
+    pub fn on_loss(&mut self, now: i64) {
+        if now < self.reduced + DECREASE_INTERVAL {
+            return;
+        }
+        self.rate = (self.rate / 2.0).max(MIN_RATE);
+        self.reduced = now;
+    }
+
+    /// Reserves the time to send a packet with the given size and returns the number of
+    /// milliseconds the packet has to be delayed. Only the packets of file uploads are paced
+    /// with the adaptive rate.
+    pub fn delay(&mut self, bytes: usize, now: i64, upload: bool) -> u64 {
+        let mut start = now;
+        if upload {
+            start = start.max(self.next);
+        }
+        if let Some(max) = self.max {
+            start = start.max(self.max_next);
+            self.max_next = start + (bytes as f64 * 1000.0 / max as f64) as i64;
+        }
+        if upload {
+            self.next = start + (bytes as f64 * 1000.0 / self.rate) as i64;
+        }
+        (start - now) as u64
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{RateControl, parse_rate};
+
+    #[test]
+    fn test_rate_control() {
+
+        let mut r = RateControl::new();
+        assert_eq!(r.rate(), 16384);
+
+        // 16 KiB per second: a packet of 8 KiB takes half a second.
+        assert_eq!(r.delay(8192, 1000, true), 0);
+        assert_eq!(r.delay(8192, 1000, true), 500);
+        assert_eq!(r.delay(8192, 1200, true), 800);
+        // After a pause the packet is sent immediately.
+        assert_eq!(r.delay(8192, 5000, true), 0);
+        // Other messages are not paced without a maximum rate.
+        assert_eq!(r.delay(8192, 5000, false), 0);
+
+        for _ in 0..16 {
+            r.on_ack();
+        }
+        assert_eq!(r.rate(), 32768);
+
+        // Retransmissions of a burst halve the rate only once.
+        r.on_loss(10000);
+        r.on_loss(10500);
+        assert_eq!(r.rate(), 16384);
+        r.on_loss(11000);
+        assert_eq!(r.rate(), 8192);
+
+        for i in 0..100 {
+            r.on_loss(20000 + i * 1000);
+        }
+        assert_eq!(r.rate(), 1024);
+    }
+
+    #[test]
+    fn test_max_rate() {
+
+        let mut r = RateControl::new();
+        r.set_max(Some(1024));
+        assert_eq!(r.max(), Some(1024));
+        assert_eq!(r.delay(512, 1000, false), 0);
+        assert_eq!(r.delay(512, 1000, false), 500);
+        // An upload waits for the maximum rate and for its own rate.
+        assert_eq!(r.delay(1024, 1000, true), 1000);
+        assert_eq!(r.delay(16384, 3000, true), 0);
+        assert_eq!(r.delay(16, 3000, true), 16000);
+
+        r.set_max(None);
+        assert_eq!(r.delay(512, 3000, false), 0);
+    }
+
+    #[test]
+    fn test_parse_rate() {
+
+        assert_eq!(parse_rate("2048").map_err(|e| e.message()), Ok(2048));
+        assert_eq!(parse_rate("64k").map_err(|e| e.message()), Ok(65536));
+        assert_eq!(parse_rate(" 2M ").map_err(|e| e.message()), Ok(2097152));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("k").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+}
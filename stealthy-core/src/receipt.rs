@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::wire::{push_value, pop_value};
+use crate::replay::Stamp;
+
+/// Time in seconds a receipt is expected. Afterwards the message is forgotten.
+const RECEIPT_TIMEOUT: i64 = 3600;
+
+const RECEIPT_LEN: usize = 16;
+/// Flag which follows the stamp in a read receipt.
+const READ: u8 = 1;
+
+/// Returns the content of the receipt for a received message with the given stamp.
+pub fn encode(s: &Stamp) -> Vec<u8> {
+    let mut v = Vec::with_capacity(RECEIPT_LEN + 1);
+    push_value(&mut v, s.epoch, 8);
+    push_value(&mut v, s.seq, 8);
+    v
+}
+
+/// Returns the content of the read receipt for a received message with the given stamp.
+/// The read receipt has one byte more than a receipt so that older versions ignore it.
+pub fn encode_read(s: &Stamp) -> Vec<u8> {
+    let mut v = encode(s);
+    v.push(READ);
+    v
+}
+
+/// Confirmation of a message by a receipt.
+#[derive(Debug, PartialEq)]
+pub enum Confirmation {
+    /// The message with the given id has been decrypted by the recipient.
+    Delivered(u64),
+    /// The message with the given id has been shown to the recipient.
+    Read(u64),
+}
+
+/// End-to-end acknowledgements of received messages.
+///
+/// The ACKs of the network layer are plain ICMP echo replies which can be forged by
+/// everyone on the path. Therefore, the recipient of a message additionally sends a
+/// receipt as soon as it has decrypted and authenticated the message. The receipt contains
+/// the epoch and the sequence number of the message (see `replay::Stamp`) and is encrypted
+/// like a message. Hence, only the recipient can create a valid receipt. Receipts are sent
+/// only once and are not acknowledged so that older versions which do not know receipts
+/// can just ignore them.
+///
+/// A second receipt, the read receipt, is sent when the message is shown on the screen
+/// of the recipient. A read receipt confirms the delivery as well.
+pub struct Receipts {
+    /// Id, send time and whether the delivery has been confirmed of the messages for which
+    /// a receipt is expected by ip, epoch and sequence number.
+    pending: HashMap<(String, u64, u64), (u64, i64, bool)>,
+}
+
+impl Receipts {
+
+    pub fn new() -> Receipts {
+        Receipts {
+            pending: HashMap::new()
+        }
+    }
+
+    /// Remembers that a receipt is expected for the message with the given stamp and id.
+    pub fn expect(&mut self, ip: &str, s: &Stamp, id: u64) {
+        self.pending.retain(|_, (_, t, _)| s.time - *t < RECEIPT_TIMEOUT);
+        self.pending.insert((ip.to_string(), s.epoch, s.seq), (id, s.time, false));
+    }
+
+    /// Returns the confirmation of the message by the decrypted receipt of the given ip or
+    /// None if no receipt for the message is expected. Each message is confirmed as
+    /// delivered and as read only once.
+    pub fn confirm(&mut self, ip: &str, buf: &[u8]) -> Option<Confirmation> {
+        let read = match (buf.len(), buf.last()) {
+            (RECEIPT_LEN, _) => false,
+            (n, Some(&READ)) if n == RECEIPT_LEN + 1 => true,
+            _ => return None
+        };
+        let mut v = buf[..RECEIPT_LEN].to_vec();
+        let epoch = pop_value(&mut v, 8).ok()?;
+        let seq = pop_value(&mut v, 8).ok()?;
+        let key = (ip.to_string(), epoch, seq);
+        if read {
+            return self.pending.remove(&key).map(|(id, _, _)| Confirmation::Read(id));
+        }
+        match self.pending.get_mut(&key) {
+            Some((id, _, delivered)) if !*delivered => {
+                *delivered = true;
+                Some(Confirmation::Delivered(*id))
+            },
+            _ => None
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Receipts, Confirmation, encode, encode_read, RECEIPT_TIMEOUT};
+    use crate::replay::Stamp;
+
+    const NOW: i64 = 1_500_000_000;
+
+    fn stamp(seq: u64, time: i64) -> Stamp {
+        Stamp { time, epoch: 7, seq }
+    }
+
+    #[test]
+    fn test_confirm() {
+
+        let mut r = Receipts::new();
+        r.expect("1.1.1.1", &stamp(1, NOW), 100);
+        r.expect("1.1.1.1", &stamp(2, NOW), 200);
+
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(2, NOW))), Some(Confirmation::Delivered(200)));
+        // Each message is confirmed only once.
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(2, NOW))), None);
+        // The receipt has to be sent by the recipient of the message.
+        assert_eq!(r.confirm("2.2.2.2", &encode(&stamp(1, NOW))), None);
+        assert_eq!(r.confirm("1.1.1.1", &encode(&Stamp { time: NOW, epoch: 8, seq: 1 })), None);
+        assert_eq!(r.confirm("1.1.1.1", &[1, 2, 3]), None);
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(1, NOW))), Some(Confirmation::Delivered(100)));
+    }
+
+    #[test]
+    fn test_read() {
+
+        let mut r = Receipts::new();
+        r.expect("1.1.1.1", &stamp(1, NOW), 100);
+        r.expect("1.1.1.1", &stamp(2, NOW), 200);
+
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(1, NOW))), Some(Confirmation::Delivered(100)));
+        assert_eq!(r.confirm("1.1.1.1", &encode_read(&stamp(1, NOW))), Some(Confirmation::Read(100)));
+        assert_eq!(r.confirm("1.1.1.1", &encode_read(&stamp(1, NOW))), None);
+        // A read receipt confirms the delivery as well.
+        assert_eq!(r.confirm("1.1.1.1", &encode_read(&stamp(2, NOW))), Some(Confirmation::Read(200)));
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(2, NOW))), None);
+
+        let mut v = encode_read(&stamp(1, NOW));
+        v[16] = 2;
+        r.expect("1.1.1.1", &stamp(1, NOW), 100);
+        assert_eq!(r.confirm("1.1.1.1", &v), None);
+    }
+
+    #[test]
+    fn test_timeout() {
+
+        let mut r = Receipts::new();
+        r.expect("1.1.1.1", &stamp(1, NOW), 100);
+        r.expect("1.1.1.1", &stamp(2, NOW + RECEIPT_TIMEOUT), 200);
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(1, NOW))), None);
+        assert_eq!(r.confirm("1.1.1.1", &encode(&stamp(2, NOW))), Some(Confirmation::Delivered(200)));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::replay::Stamp;
+
+/// Time in milliseconds a message waits for the messages sent before it if
+/// --reorder-timeout is not given.
+pub const DEFAULT_REORDER_TIMEOUT: u64 = 3000;
+
+struct PeerQueue<T> {
+    epoch: u64,
+    /// Sequence number of the next message which is released.
+    next: u64,
+    /// Messages which wait for the messages sent before them together with the time in
+    /// milliseconds when they have been received.
+    held: BTreeMap<u64, (i64, Vec<T>)>,
+}
+
+impl<T> PeerQueue<T> {
+
+    fn new(s: &Stamp) -> PeerQueue<T> {
+        PeerQueue {
+            epoch: s.epoch,
+            next: s.seq,
+            held: BTreeMap::new(),
+        }
+    }
+
+    /// Releases the messages which are next in order.
+    fn release(&mut self, out: &mut Vec<T>) {
+        while let Some((_, items)) = self.held.remove(&self.next) {
+            out.extend(items);
+            self.next += 1;
+        }
+    }
+
+    /// Releases all messages up to the given sequence number even if messages before them
+    /// are missing.
+    fn skip_to(&mut self, seq: u64, out: &mut Vec<T>) {
+        let rest = self.held.split_off(&(seq + 1));
+        for (_, (_, items)) in std::mem::replace(&mut self.held, rest) {
+            out.extend(items);
+        }
+        self.next = seq + 1;
+        self.release(out);
+    }
+}
+
+/// Brings the messages of each peer into the order in which they have been sent.
+///
+/// The order is given by the sequence numbers of the stamps (see `Sequencer`). Every
+/// message with a stamp must be pushed, even if it is not shown, so that the messages
+/// behind it do not wait for it. A message is held back until the messages sent before it
+/// have arrived. If they do not arrive within the timeout, e.g. because they are lost, the
+/// message is released nevertheless. Messages which arrive after they have been skipped
+/// are released immediately.
+pub struct Reorder<T> {
+    /// Timeout in milliseconds. If zero messages are not reordered.
+    timeout: u64,
+    peers: HashMap<String, PeerQueue<T>>,
+}
+
+impl<T> Reorder<T> {
+
+    pub fn new(timeout: u64) -> Reorder<T> {
+        Reorder {
+            timeout,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: u64) {
+        self.timeout = timeout;
+    }
+
+    /// Adds the items of a message with the given stamp which has been received from the
+    /// ip and returns the items which can be released.
+    pub fn push(&mut self, ip: &str, s: &Stamp, items: Vec<T>, now: i64) -> Vec<T> {
+
+        if self.timeout == 0 {
+            return items;
+        }
+
+        let mut out = vec![];
+        let q = self.peers.entry(ip.to_string()).or_insert_with(|| PeerQueue::new(s));
+        if s.epoch < q.epoch {
+            return items;
+        }
+        if s.epoch > q.epoch {
+            // The peer has been restarted. Messages of its previous run are not waited for.
+            for (_, (_, v)) in std::mem::replace(q, PeerQueue::new(s)).held {
+                out.extend(v);
+            }
+        }
+        if s.seq < q.next {
+            out.extend(items);
+            return out;
+        }
+        q.held.insert(s.seq, (now, items));
+        q.release(&mut out);
+        out
+    }
+
+    /// Returns the messages which have waited longer than the timeout and the messages
+    /// which are next in order behind them.
+    pub fn expire(&mut self, now: i64) -> Vec<T> {
+
+        let mut out = vec![];
+        let timeout = self.timeout as i64;
+        for q in self.peers.values_mut() {
+            let expired = q.held.iter()
+                .filter(|(_, (t, _))| now - *t >= timeout)
+                .map(|(seq, _)| *seq)
+                .max();
+            if let Some(seq) = expired {
+                q.skip_to(seq, &mut out);
+            }
+        }
+        out
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Reorder;
+    use crate::replay::Stamp;
+
+    fn stamp(epoch: u64, seq: u64) -> Stamp {
+        Stamp { time: 0, epoch, seq }
+    }
+
+    #[test]
+    fn test_reorder() {
+
+        let mut r = Reorder::new(1000);
+
+        // The first message of a peer is released immediately.
+        assert_eq!(r.push("a", &stamp(1, 5), vec![5], 0), vec![5]);
+        assert_eq!(r.push("a", &stamp(1, 7), vec![7], 0), vec![]);
+        assert_eq!(r.push("a", &stamp(1, 8), vec![], 0), vec![]);
+        assert_eq!(r.push("a", &stamp(1, 9), vec![9, 9], 0), vec![]);
+        assert_eq!(r.push("b", &stamp(1, 1), vec![1], 0), vec![1]);
+        assert_eq!(r.push("a", &stamp(1, 6), vec![6], 10), vec![6, 7, 9, 9]);
+
+        // A lost message is skipped after the timeout.
+        assert_eq!(r.push("a", &stamp(1, 12), vec![12], 100), vec![]);
+        assert_eq!(r.push("a", &stamp(1, 14), vec![14], 500), vec![]);
+        assert_eq!(r.push("a", &stamp(1, 11), vec![11], 600), vec![]);
+        assert_eq!(r.expire(1099), Vec::<u32>::new());
+        assert_eq!(r.expire(1100), vec![11, 12]);
+        assert_eq!(r.push("a", &stamp(1, 10), vec![10], 1200), vec![10]);
+        assert_eq!(r.expire(1600), vec![14]);
+
+        // A restart of the peer releases the waiting messages.
+        assert_eq!(r.push("a", &stamp(1, 17), vec![17], 2000), vec![]);
+        assert_eq!(r.push("a", &stamp(2, 3), vec![3], 2000), vec![17, 3]);
+        assert_eq!(r.push("a", &stamp(1, 16), vec![16], 2000), vec![16]);
+        assert_eq!(r.push("a", &stamp(2, 4), vec![4], 2000), vec![4]);
+
+        // Without timeout messages are not reordered.
+        r.set_timeout(0);
+        assert_eq!(r.push("a", &stamp(2, 9), vec![9], 2000), vec![9]);
+    }
+}
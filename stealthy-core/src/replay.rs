@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::wire::{push_value, pop_value};
+
+/// Tolerated clock difference in seconds between the clocks of the sender and the receiver.
+/// Messages with a timestamp which is more than this value in the future are rejected.
+pub const CLOCK_SKEW_TOLERANCE: i64 = 30;
+
+/// Number of sequence numbers below the highest received one which are tracked per peer.
+/// Older messages are dropped.
+pub const SEQUENCE_WINDOW: u64 = 64;
+
+/// Number of bytes which are added to each message by `stamp`.
+pub const STAMP_LEN: usize = 24;
+
+/// Returns the current time in seconds since the epoch.
+pub fn now() -> i64 {
+    time::get_time().sec
+}
+
+/// Information which is encrypted together with each message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Stamp {
+    /// Time in seconds when the message has been sent.
+    pub time: i64,
+    /// Identifies the run of the sender. It increases each time the sender is started.
+    pub epoch: u64,
+    /// Sequence number of the message for the recipient within the epoch.
+    pub seq: u64,
+}
+
+/// Prepends the stamp to the plaintext of a message. As the stamp is encrypted together
+/// with the message it cannot be modified by an attacker without being detected.
+pub fn stamp(buf: &[u8], s: &Stamp) -> Vec<u8> {
+    let mut v = Vec::with_capacity(STAMP_LEN + buf.len());
+    push_value(&mut v, s.time as u64, 8);
+    push_value(&mut v, s.epoch, 8);
+    push_value(&mut v, s.seq, 8);
+    v.extend_from_slice(buf);
+    v
+}
+
+/// Assigns the stamps to outgoing messages.
+pub struct Sequencer {
+    epoch: u64,
+    next: HashMap<String, u64>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Sequencer {
+        Sequencer::new()
+    }
+}
+
+impl Sequencer {
+
+    /// Creates a new instance. The epoch is the time in microseconds when we were started.
+    pub fn new() -> Sequencer {
+        let t = time::get_time();
+        Sequencer {
+            epoch: t.sec as u64 * 1_000_000 + t.nsec as u64 / 1000,
+            next: HashMap::new(),
+        }
+    }
+
+    /// Returns the stamp for the next message to the given ip.
+    pub fn next(&mut self, ip: &str, now: i64) -> Stamp {
+        let seq = self.next.entry(ip.to_string()).or_insert(0);
+        *seq += 1;
+        Stamp {
+            time: now,
+            epoch: self.epoch,
+            seq: *seq,
+        }
+    }
+}
+
+/// Rejects messages which are older than the configured window or which have already
+/// been received.
+#[derive(Clone)]
+pub struct ReplayWindow {
+    /// Maximum age of a message in seconds. If None, messages of any age are accepted.
+    window: Option<u64>,
+    sequences: Arc<Mutex<SequenceWindows>>,
+    /// Number of replayed messages which have been dropped.
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ReplayWindow {
+
+    pub fn new(window: Option<u64>) -> ReplayWindow {
+        ReplayWindow {
+            window,
+            sequences: Arc::new(Mutex::new(SequenceWindows::new())),
+            dropped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns true if the message with the given stamp has not been received from the ip
+    /// before. Otherwise the message is counted as dropped replay.
+    pub fn is_new(&self, ip: &str, s: &Stamp) -> bool {
+        let r = self.sequences.lock().unwrap().check(ip, s);
+        if !r {
+            self.count_dropped();
+        }
+        r
+    }
+
+    /// Counts a replayed message which has been detected elsewhere, e.g. by a session.
+    pub fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the number of replayed messages which have been dropped.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Removes the stamp from the decrypted message and checks that the message has been
+    /// sent within the window. Returns the stamp and the message without the stamp or the
+    /// reason why the message has been rejected.
+    pub fn check(&self, buf: &[u8], now: i64) -> Result<(Stamp, Vec<u8>), String> {
+
+        let mut v = buf.to_vec();
+        let t = pop_value(&mut v, 8)? as i64;
+        let epoch = pop_value(&mut v, 8)?;
+        let seq = pop_value(&mut v, 8)?;
+
+        if let Some(w) = self.window {
+            if t > now + CLOCK_SKEW_TOLERANCE {
+                return Err(format!("Rejected message with a timestamp {} seconds in the future.", t - now));
+            }
+            if now - t > w as i64 {
+                return Err(format!("Rejected message which is {} seconds old. Possible replay.", now - t));
+            }
+        }
+        Ok((Stamp { time: t, epoch, seq }, v))
+    }
+}
+
+struct PeerWindow {
+    epoch: u64,
+    highest: u64,
+    /// Bit i is set if the message with the sequence number `highest - i` has been received.
+    received: u64,
+}
+
+/// Detects replayed messages via the sequence numbers of each peer.
+///
+/// For each peer the highest sequence number and which of the previous `SEQUENCE_WINDOW`
+/// messages have been received are tracked. Messages which have already been received or
+/// which are older than the window are dropped. Messages of a previous epoch are dropped
+/// as well. Note that the state is lost when we are restarted.
+pub struct SequenceWindows {
+    peers: HashMap<String, PeerWindow>,
+}
+
+impl Default for SequenceWindows {
+    fn default() -> SequenceWindows {
+        SequenceWindows::new()
+    }
+}
+
+impl SequenceWindows {
+
+    pub fn new() -> SequenceWindows {
+        SequenceWindows {
+            peers: HashMap::new()
+        }
+    }
+
+    /// Returns true if the message has not been received before. The stamp must have been
+    /// authenticated.
+    pub fn check(&mut self, ip: &str, s: &Stamp) -> bool {
+
+        let w = self.peers.entry(ip.to_string()).or_insert(PeerWindow {
+            epoch: s.epoch,
+            highest: 0,
+            received: 0,
+        });
+
+        if s.epoch < w.epoch {
+            return false;
+        }
+        if s.epoch > w.epoch {
+            // The peer has been restarted.
+            *w = PeerWindow { epoch: s.epoch, highest: 0, received: 0 };
+        }
+
+        if s.seq > w.highest {
+            let shift = s.seq - w.highest;
+            w.received = if shift >= SEQUENCE_WINDOW { 1 } else { (w.received << shift) | 1 };
+            w.highest = s.seq;
+            return true;
+        }
+
+        let age = w.highest - s.seq;
+        if age >= SEQUENCE_WINDOW || w.received & (1 << age) != 0 {
+            return false;
+        }
+        w.received |= 1 << age;
+        true
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Stamp, ReplayWindow, SequenceWindows, Sequencer, CLOCK_SKEW_TOLERANCE, SEQUENCE_WINDOW};
+
+    const NOW: i64 = 1_500_000_000;
+
+    fn stamp(buf: &[u8], time: i64) -> Vec<u8> {
+        super::stamp(buf, &Stamp { time, epoch: 1, seq: 1 })
+    }
+
+    fn seq(epoch: u64, seq: u64) -> Stamp {
+        Stamp { time: NOW, epoch, seq }
+    }
+
+    #[test]
+    fn test_in_window() {
+
+        let w = ReplayWindow::new(Some(60));
+        let data = "hello".to_string().into_bytes();
+
+        assert_eq!(w.check(&stamp(&data, NOW), NOW).unwrap().1, data);
+        assert_eq!(w.check(&stamp(&data, NOW - 60), NOW).unwrap().1, data);
+        // Small clock differences are tolerated.
+        assert_eq!(w.check(&stamp(&data, NOW + CLOCK_SKEW_TOLERANCE), NOW).unwrap().1, data);
+    }
+
+    #[test]
+    fn test_too_old() {
+
+        let w = ReplayWindow::new(Some(60));
+        assert!(w.check(&stamp(&[1, 2, 3], NOW - 61), NOW).is_err());
+        assert!(w.check(&stamp(&[1, 2, 3], 0), NOW).is_err());
+    }
+
+    #[test]
+    fn test_future() {
+
+        let w = ReplayWindow::new(Some(60));
+        assert!(w.check(&stamp(&[1, 2, 3], NOW + CLOCK_SKEW_TOLERANCE + 1), NOW).is_err());
+    }
+
+    #[test]
+    fn test_disabled() {
+
+        let w = ReplayWindow::new(None);
+        assert_eq!(w.check(&stamp(&[1, 2, 3], 0), NOW).unwrap().1, vec![1, 2, 3]);
+        assert_eq!(w.check(&stamp(&[1, 2, 3], NOW + 3600), NOW).unwrap().1, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_too_short() {
+
+        let w = ReplayWindow::new(None);
+        assert!(w.check(&[1, 2, 3], NOW).is_err());
+    }
+
+    #[test]
+    fn test_stamp() {
+
+        let s = Stamp { time: NOW, epoch: 7, seq: 42 };
+        let v = super::stamp(b"hello", &s);
+        assert_eq!(v.len(), super::STAMP_LEN + 5);
+        assert_eq!(ReplayWindow::new(None).check(&v, NOW).unwrap(), (s, b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_sequencer() {
+
+        let mut s = Sequencer::new();
+        assert_eq!(s.next("1.1.1.1", NOW).seq, 1);
+        assert_eq!(s.next("1.1.1.1", NOW).seq, 2);
+        assert_eq!(s.next("2.2.2.2", NOW).seq, 1);
+    }
+
+    #[test]
+    fn test_sequence_window() {
+
+        let mut w = SequenceWindows::new();
+        assert!(w.check("1.1.1.1", &seq(5, 1)));
+        assert!(!w.check("1.1.1.1", &seq(5, 1)));
+        // Messages may arrive out of order.
+        assert!(w.check("1.1.1.1", &seq(5, 3)));
+        assert!(w.check("1.1.1.1", &seq(5, 2)));
+        assert!(!w.check("1.1.1.1", &seq(5, 2)));
+        assert!(!w.check("1.1.1.1", &seq(5, 3)));
+        // Each peer has its own window.
+        assert!(w.check("2.2.2.2", &seq(5, 1)));
+
+        // Messages older than the window are dropped.
+        assert!(w.check("1.1.1.1", &seq(5, 3 + SEQUENCE_WINDOW)));
+        assert!(!w.check("1.1.1.1", &seq(5, 3)));
+        assert!(w.check("1.1.1.1", &seq(5, 4)));
+    }
+
+    #[test]
+    fn test_sequence_epoch() {
+
+        let mut w = SequenceWindows::new();
+        assert!(w.check("1.1.1.1", &seq(5, 10)));
+        // After a restart of the peer the sequence numbers start again.
+        assert!(w.check("1.1.1.1", &seq(6, 1)));
+        // Messages of the previous run are dropped.
+        assert!(!w.check("1.1.1.1", &seq(5, 11)));
+    }
+
+    #[test]
+    fn test_dropped() {
+
+        let w = ReplayWindow::new(None);
+        assert!(w.is_new("1.1.1.1", &seq(5, 1)));
+        assert!(!w.is_new("1.1.1.1", &seq(5, 1)));
+        w.count_dropped();
+        assert_eq!(w.dropped(), 2);
+        // Clones share the state.
+        assert!(!w.clone().is_new("1.1.1.1", &seq(5, 1)));
+        assert_eq!(w.dropped(), 3);
+    }
+}
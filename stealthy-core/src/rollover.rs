@@ -0,0 +1,108 @@
+use crypto::ed25519;
+use crate::error::StealthyError;
+
+const VERSION: u8 = 1;
+const ROLLOVER: u8 = 1;
+const REVOCATION: u8 = 2;
+const KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+/// Prefix of the signed data so that the signature cannot be confused with the signature
+/// of a message.
+const CONTEXT: &[u8] = b"stealthy key rollover\0";
+
+const INVALID_ANNOUNCEMENT: &str = "Invalid key rollover.";
+
+/// Announcement of a peer that it does not use its signing key anymore.
+///
+/// The announcement is signed with the old key. Peers which trust the old key replace it
+/// by the new key or stop trusting it in case of a revocation. Someone who has stolen the
+/// old key can also create a valid announcement. Hence, a compromised key should be
+/// revoked and the new key should be exchanged via a trusted channel.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Announcement {
+    /// The old key is replaced by the new key.
+    Rollover { old: Vec<u8>, new: Vec<u8> },
+    /// The old key is withdrawn without a replacement.
+    Revocation { old: Vec<u8> },
+}
+
+impl Announcement {
+
+    /// Returns the key which is replaced or revoked.
+    pub fn old(&self) -> &[u8] {
+        match self {
+            Announcement::Rollover { old, .. } => old,
+            Announcement::Revocation { old } => old,
+        }
+    }
+
+    /// Returns the announcement signed with the secret key which belongs to the old key.
+    pub fn sign(&self, secret: &[u8]) -> Vec<u8> {
+        let mut v = self.data();
+        let sig = ed25519::signature(&[CONTEXT, &v[..]].concat(), secret);
+        v.extend_from_slice(&sig);
+        v
+    }
+
+    /// Parses a signed announcement and checks that it has been signed with the old key.
+    pub fn verify(buf: &[u8]) -> Result<Announcement, StealthyError> {
+
+        if buf.len() < 2 + KEY_LEN + SIGNATURE_LEN || buf[0] != VERSION {
+            return Err(StealthyError::Crypto(INVALID_ANNOUNCEMENT));
+        }
+        let (data, sig) = buf.split_at(buf.len() - SIGNATURE_LEN);
+        let old = data[2..2 + KEY_LEN].to_vec();
+        let a = match (data[1], data.len() - 2 - KEY_LEN) {
+            (ROLLOVER, KEY_LEN) => Announcement::Rollover { old, new: data[2 + KEY_LEN..].to_vec() },
+            (REVOCATION, 0) => Announcement::Revocation { old },
+            _ => return Err(StealthyError::Crypto(INVALID_ANNOUNCEMENT))
+        };
+        match ed25519::verify(&[CONTEXT, data].concat(), a.old(), sig) {
+            true => Ok(a),
+            false => Err(StealthyError::Crypto("The signature of the key rollover is invalid."))
+        }
+    }
+
+    /// The signed part of the announcement.
+    fn data(&self) -> Vec<u8> {
+        match self {
+            Announcement::Rollover { old, new } => [&[VERSION, ROLLOVER][..], &old[..], &new[..]].concat(),
+            Announcement::Revocation { old } => [&[VERSION, REVOCATION][..], &old[..]].concat(),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Announcement;
+    use crypto::ed25519;
+
+    #[test]
+    fn test_rollover() {
+
+        let (secret, old) = ed25519::keypair(&[1; 32]);
+        let (_, new) = ed25519::keypair(&[2; 32]);
+
+        let a = Announcement::Rollover { old: old.to_vec(), new: new.to_vec() };
+        let buf = a.sign(&secret);
+        assert_eq!(Announcement::verify(&buf).map_err(|e| e.message()), Ok(a));
+
+        let r = Announcement::Revocation { old: old.to_vec() };
+        assert_eq!(Announcement::verify(&r.sign(&secret)).map_err(|e| e.message()), Ok(r));
+
+        // The announcement has to be signed with the old key.
+        let (mallory, _) = ed25519::keypair(&[3; 32]);
+        assert!(Announcement::verify(&Announcement::Rollover { old: old.to_vec(), new: new.to_vec() }.sign(&mallory)).is_err());
+
+        let mut t = buf.clone();
+        t[40] ^= 1;
+        assert!(Announcement::verify(&t).is_err());
+        assert!(Announcement::verify(&buf[..buf.len() - 1]).is_err());
+        assert!(Announcement::verify(&[]).is_err());
+    }
+}
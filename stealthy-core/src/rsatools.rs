@@ -13,3 +13,14 @@ pub fn key_as_der(pem: &String) -> Vec<u8> {
     (&s).from_base64().unwrap()
 }
 
+pub fn key_as_pem(der: &[u8]) -> String {
+
+    let b64 = der.to_base64(Config {
+        char_set: CharacterSet::Standard,
+        newline: Newline::LF,
+        pad: true,
+        line_length: Some(64)
+    });
+    format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n", b64)
+}
+
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+/// Retransmission timeout in milliseconds until the first round-trip time has been measured.
+const INITIAL_RTO: i64 = 3000;
+/// Lower bound of the retransmission timeout in milliseconds.
+const MIN_RTO: i64 = 200;
+/// Upper bound of the retransmission timeout in milliseconds.
+const MAX_RTO: i64 = 60000;
+
+/// Estimates the round-trip time to a peer and computes the retransmission timeout from it
+/// as in RFC 6298: the smoothed round-trip time plus four times its variation.
+///
+/// Only packets which have not been retransmitted must be measured as the ACK of a
+/// retransmitted packet cannot be assigned to one of the transmissions (Karn's algorithm).
+#[derive(Clone, Debug)]
+pub struct Rtt {
+    /// Smoothed round-trip time in milliseconds.
+    srtt: Option<f64>,
+    /// Variation of the round-trip time in milliseconds.
+    rttvar: f64,
+}
+
+impl Rtt {
+
+    pub fn new() -> Rtt {
+        Rtt {
+            srtt: None,
+            rttvar: 0.0,
+        }
+    }
+
+    /// Adds the round-trip time of an acknowledged packet in milliseconds.
+    pub fn sample(&mut self, millis: i64) {
+        let r = millis.max(0) as f64;
+        match self.srtt {
+            None => {
+                self.srtt = Some(r);
+                self.rttvar = r / 2.0;
+            },
+            Some(srtt) => {
+                self.rttvar = 0.75 * self.rttvar + 0.25 * (srtt - r).abs();
+                self.srtt = Some(0.875 * srtt + 0.125 * r);
+            }
+        }
+    }
+
+    /// Returns the smoothed round-trip time if one has been measured.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt.map(|ms| Duration::from_micros((ms * 1000.0) as u64))
+    }
+
+    /// Returns the retransmission timeout in milliseconds.
+    pub fn rto(&self) -> i64 {
+        match self.srtt {
+            None => INITIAL_RTO,
+            Some(srtt) => ((srtt + 4.0 * self.rttvar) as i64).max(MIN_RTO).min(MAX_RTO)
+        }
+    }
+
+    /// Returns the timeout in milliseconds after a packet has been retransmitted the given
+    /// number of times. The timeout is doubled with each retransmission.
+    pub fn backoff(&self, retries: u32) -> i64 {
+        self.rto().saturating_mul(1 << retries.min(16)).min(MAX_RTO)
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::Rtt;
+
+    #[test]
+    fn test_rtt() {
+
+        let mut r = Rtt::new();
+        assert_eq!(r.rto(), 3000);
+        assert_eq!(r.srtt(), None);
+
+        r.sample(100);
+        assert_eq!(r.rto(), 300);
+        assert_eq!(r.srtt(), Some(Duration::from_millis(100)));
+        r.sample(100);
+        assert_eq!(r.rto(), 250);
+        for _ in 0..100 {
+            r.sample(100);
+        }
+        assert_eq!(r.rto(), 200);
+
+        // A slower link increases the timeout.
+        for _ in 0..100 {
+            r.sample(2000);
+        }
+        assert!(r.rto() >= 2000 && r.rto() < 2100);
+
+        let mut r = Rtt::new();
+        r.sample(100000);
+        assert_eq!(r.rto(), 60000);
+        r.sample(-5);
+        assert!(r.rto() > 0);
+
+        let r = Rtt::new();
+        assert_eq!(r.backoff(0), 3000);
+        assert_eq!(r.backoff(1), 6000);
+        assert_eq!(r.backoff(3), 24000);
+        assert_eq!(r.backoff(5), 60000);
+        assert_eq!(r.backoff(u32::max_value()), 60000);
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use crate::tools::{read_file, to_hex, write_data};
+use crate::error::StealthyError;
+
+/// Name of the file in ~/.stealthy where the verified peers are stored.
+const VERIFIED_FILE: &str = "verified";
+/// Number of words of a short authentication string.
+const SAS_WORDS: usize = 5;
+
+/// Words for the short authentication string. Each byte of the fingerprint selects one word.
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alien", "alpha", "amber",
+    "angel", "anvil", "apple", "apron", "arena", "armor", "arrow", "atlas", "attic", "audio",
+    "avian", "award", "bacon", "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil",
+    "beach", "beard", "berry", "bison", "blade", "blaze", "bloom", "board", "bonus", "boot",
+    "brass", "bread", "brick", "bride", "broom", "brush", "bugle", "cabin", "cable", "cactus",
+    "camel", "candy", "canoe", "canyon", "cargo", "carrot", "castle", "cedar", "chalk", "chart",
+    "cheese", "cherry", "chess", "chief", "cider", "cigar", "civic", "clamp", "clock", "cloud",
+    "clown", "cobra", "cocoa", "comet", "coral", "couch", "crane", "crate", "crown", "cube",
+    "curry", "daisy", "dance", "delta", "denim", "depot", "diary", "dingo", "disco", "dolphin",
+    "donkey", "dough", "dove", "dragon", "drum", "eagle", "easel", "ebony", "echo", "eclipse",
+    "elbow", "elder", "ember", "emerald", "engine", "envoy", "epoch", "fable", "falcon", "fancy",
+    "feast", "fern", "ferry", "fiber", "fiddle", "flame", "flute", "forest", "fossil", "fox",
+    "frost", "fudge", "galaxy", "garlic", "gecko", "genie", "geyser", "ghost", "giant", "ginger",
+    "glacier", "globe", "goose", "grape", "gravel", "guitar", "habit", "hammer", "harbor", "harp",
+    "hazel", "helmet", "heron", "hippo", "honey", "hornet", "hotel", "husky", "igloo", "index",
+    "ink", "iris", "island", "ivory", "jacket", "jaguar", "jelly", "jewel", "jigsaw", "jockey",
+    "judge", "juice", "jungle", "kayak", "kebab", "kernel", "kettle", "kiosk", "kite", "koala",
+    "ladder", "lagoon", "lamp", "laser", "lemon", "lens", "lily", "lime", "llama", "lobster",
+    "locket", "lotus", "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon", "mercury",
+    "meteor", "mint", "mirror", "mocha", "monkey", "moose", "mosaic", "motor", "mural", "nectar",
+    "needle", "nickel", "ninja", "noodle", "nova", "oasis", "ocean", "olive", "omega", "onion",
+    "opal", "orbit", "orchid", "otter", "oyster", "paddle", "panda", "paper", "parrot", "peach",
+    "pearl", "pepper", "piano", "pilot", "pirate", "plaza", "polar", "pony", "poppy", "potato",
+    "prism", "puzzle", "quartz", "quill", "rabbit", "radar", "raven", "reef", "rhino", "ribbon",
+    "robin", "rocket", "rodeo", "ruby", "saddle", "salmon", "sandal", "saturn", "scarf", "shark",
+    "shovel", "silver", "sketch", "sloth", "snail", "sonar",
+];
+
+/// Returns the fingerprint of the keys which are used for the communication with a peer.
+/// In hybrid mode these are both public keys, with a symmetric key it is the shared key.
+/// The keys are sorted so that both peers get the same fingerprint.
+pub fn fingerprint(own: &[u8], peer: &[u8]) -> Vec<u8> {
+
+    let (a, b) = if own <= peer { (own, peer) } else { (peer, own) };
+    let mut h = Sha256::new();
+    h.input(b"stealthy sas");
+    for k in &[a, b] {
+        h.input(&(k.len() as u64).to_le_bytes());
+        h.input(k);
+    }
+    let mut v = vec![0; h.output_bytes()];
+    h.result(&mut v);
+    v
+}
+
+/// Returns the short authentication string of a fingerprint which can be compared by the
+/// users via phone or in person.
+pub fn words(fingerprint: &[u8]) -> String {
+    fingerprint.iter()
+        .take(SAS_WORDS)
+        .map(|b| WORDS[*b as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Peers whose short authentication string has been confirmed by the user.
+///
+/// For each peer the fingerprint of the keys is stored in ~/.stealthy/verified, one line
+/// with IP and fingerprint in hexadecimal per peer. If the keys change, the peer is no
+/// longer verified.
+pub struct VerifiedPeers {
+    peers: HashMap<String, String>,
+}
+
+impl VerifiedPeers {
+
+    /// Parses the content of the file.
+    pub fn parse(content: &str) -> VerifiedPeers {
+        VerifiedPeers {
+            peers: content.lines()
+                .map(|l| l.split_whitespace().collect::<Vec<_>>())
+                .filter(|p| p.len() == 2)
+                .map(|p| (p[0].to_string(), p[1].to_string()))
+                .collect()
+        }
+    }
+
+    /// Loads the verified peers. Returns an empty list if no peer has been verified yet.
+    pub fn load() -> VerifiedPeers {
+        match VerifiedPeers::path().and_then(|p| read_file(&p)) {
+            Ok(s) => VerifiedPeers::parse(&s),
+            Err(_) => VerifiedPeers::parse("")
+        }
+    }
+
+    /// Returns true if the peer has been verified with the given fingerprint.
+    pub fn is_verified(&self, ip: &str, fingerprint: &[u8]) -> bool {
+        self.peers.get(ip) == Some(&to_hex(fingerprint))
+    }
+
+    /// Returns true if the peer has been verified with another fingerprint, i.e. the keys
+    /// have changed since then.
+    pub fn has_changed(&self, ip: &str, fingerprint: &[u8]) -> bool {
+        self.peers.contains_key(ip) && !self.is_verified(ip, fingerprint)
+    }
+
+    pub fn verify(&mut self, ip: &str, fingerprint: &[u8]) {
+        self.peers.insert(ip.to_string(), to_hex(fingerprint));
+    }
+
+    pub fn revoke(&mut self, ip: &str) {
+        self.peers.remove(ip);
+    }
+
+    /// Returns the content of the file.
+    pub fn encode(&self) -> String {
+        let mut v = self.peers.iter().map(|(ip, f)| format!("{} {}\n", ip, f)).collect::<Vec<_>>();
+        v.sort();
+        v.concat()
+    }
+
+    pub fn save(&self) -> Result<(), StealthyError> {
+        let fname = VerifiedPeers::path()?;
+        if let Some(dir) = std::path::Path::new(&fname).parent() {
+            fs::create_dir_all(dir).map_err(|e| StealthyError::Io("Could not create ~/.stealthy.", e))?;
+        }
+        match write_data(&fname, self.encode().into_bytes()) {
+            true => Ok(()),
+            false => Err(StealthyError::Crypto("Could not store verified peers."))
+        }
+    }
+
+    fn path() -> Result<String, StealthyError> {
+        let mut path = dirs::home_dir().ok_or(StealthyError::Crypto("Could not find home directory."))?;
+        path.push(".stealthy");
+        path.push(VERIFIED_FILE);
+        path.to_str().map(|s| s.to_string()).ok_or(StealthyError::Crypto("Invalid path."))
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{fingerprint, words, VerifiedPeers};
+
+    #[test]
+    fn test_fingerprint() {
+
+        // Both peers get the same words.
+        let f = fingerprint(b"alice", b"bob");
+        assert_eq!(f, fingerprint(b"bob", b"alice"));
+        assert_ne!(f, fingerprint(b"alice", b"eve"));
+        assert_ne!(fingerprint(b"ab", b"c"), fingerprint(b"a", b"bc"));
+        assert_eq!(words(&f).split(' ').count(), 5);
+        assert_eq!(words(&[0, 1, 255, 0, 0, 7]), "acid acorn sonar acid acid");
+    }
+
+    #[test]
+    fn test_verified_peers() {
+
+        let f = fingerprint(b"alice", b"bob");
+        let mut v = VerifiedPeers::parse("");
+        assert!(!v.is_verified("1.1.1.1", &f));
+        assert!(!v.has_changed("1.1.1.1", &f));
+        v.verify("1.1.1.1", &f);
+
+        let v = VerifiedPeers::parse(&v.encode());
+        assert!(v.is_verified("1.1.1.1", &f));
+        assert!(!v.is_verified("2.2.2.2", &f));
+        // The keys of the peer have changed.
+        let g = fingerprint(b"alice", b"eve");
+        assert!(!v.is_verified("1.1.1.1", &g));
+        assert!(v.has_changed("1.1.1.1", &g));
+    }
+}
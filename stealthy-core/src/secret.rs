@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Number of instances whose memory is on a locked page, indexed by the number of the
+/// page. Locks do not nest, i.e. a single munlock unlocks a page no matter how often it
+/// has been locked. Small secrets usually share a page, so a page is only unlocked when
+/// the last instance on it is dropped.
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Memory which contains secret key material.
+///
+/// The memory is locked via mlock (VirtualLock on Windows) so that it is not written to
+/// the swap and it is overwritten with zeros when the value is dropped. Locking can fail,
+/// e.g. if the limit for locked memory (ulimit -l) has been reached. In this case the
+/// memory is still zeroized. Clones get their own locked memory.
+///
+/// The memory is not copied when a vector is moved into an instance. Secrets which are
+/// held in other memory before should be zeroized by the caller.
+pub struct SecretBytes {
+    data: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+
+    /// Takes ownership of the vector. Its memory is not copied.
+    pub fn new(data: Vec<u8>) -> SecretBytes {
+        let locked = !data.is_empty() && lock(data.as_ptr(), data.capacity());
+        SecretBytes { data, locked }
+    }
+
+    pub fn from_string(s: String) -> SecretBytes {
+        SecretBytes::new(s.into_bytes())
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Allows to write the secret directly into the locked memory.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Clone for SecretBytes {
+
+    fn clone(&self) -> SecretBytes {
+        let mut v = Vec::with_capacity(self.data.len());
+        v.extend_from_slice(&self.data);
+        SecretBytes::new(v)
+    }
+}
+
+impl Drop for SecretBytes {
+
+    fn drop(&mut self) {
+        // Also the unused capacity is overwritten as it can contain parts of the secret,
+        // e.g. if the vector has been truncated.
+        unsafe {
+            zeroize(std::slice::from_raw_parts_mut(self.data.as_mut_ptr(), self.data.capacity()));
+        }
+        if self.locked {
+            unlock(self.data.as_ptr(), self.data.capacity());
+        }
+    }
+}
+
+/// Locks the pages of the memory and counts the references to them. Returns false if
+/// locking failed.
+fn lock(p: *const u8, len: usize) -> bool {
+    // The map is held while locking so that a page is not unlocked concurrently between
+    // the lock and the update of its count.
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    if !unsafe { sys_lock(p, len) } {
+        return false;
+    }
+    for page in pages(p, len) {
+        *locked.entry(page).or_insert(0) += 1;
+    }
+    true
+}
+
+/// Releases the references to the pages of the memory and unlocks the pages which are
+/// not referenced anymore.
+fn unlock(p: *const u8, len: usize) {
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let size = page_size();
+    for page in pages(p, len) {
+        if let Some(n) = locked.get_mut(&page) {
+            *n -= 1;
+            if *n == 0 {
+                locked.remove(&page);
+                unsafe { sys_unlock((page * size) as *const u8, size); }
+            }
+        }
+    }
+}
+
+/// Returns the numbers of the pages which contain the memory.
+fn pages(p: *const u8, len: usize) -> std::ops::RangeInclusive<usize> {
+    let size = page_size();
+    p as usize / size ..= (p as usize + len - 1) / size
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(unix)]
+unsafe fn sys_lock(p: *const u8, len: usize) -> bool {
+    libc::mlock(p as *const libc::c_void, len) == 0
+}
+
+#[cfg(unix)]
+unsafe fn sys_unlock(p: *const u8, len: usize) {
+    libc::munlock(p as *const libc::c_void, len);
+}
+
+#[cfg(windows)]
+fn page_size() -> usize {
+    unsafe {
+        let mut info: winapi::um::sysinfoapi::SYSTEM_INFO = std::mem::zeroed();
+        winapi::um::sysinfoapi::GetSystemInfo(&mut info);
+        info.dwPageSize as usize
+    }
+}
+
+#[cfg(windows)]
+unsafe fn sys_lock(p: *const u8, len: usize) -> bool {
+    winapi::um::memoryapi::VirtualLock(p as *mut _, len) != 0
+}
+
+#[cfg(windows)]
+unsafe fn sys_unlock(p: *const u8, len: usize) {
+    winapi::um::memoryapi::VirtualUnlock(p as *mut _, len);
+}
+
+/// Does not print the secret.
+impl fmt::Debug for SecretBytes {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes({} bytes)", self.data.len())
+    }
+}
+
+/// Overwrites the memory with zeros. Volatile writes are used so that the compiler does
+/// not remove the writes although the memory is not read afterwards.
+pub fn zeroize(v: &mut [u8]) {
+    for b in v.iter_mut() {
+        unsafe {
+            ptr::write_volatile(b, 0);
+        }
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{LOCKED_PAGES, SecretBytes, pages, zeroize};
+
+    #[test]
+    fn test_secret_bytes() {
+
+        let s = SecretBytes::new(vec![1, 2, 3]);
+        assert_eq!(s.as_slice(), &[1, 2, 3]);
+        assert_eq!(s.len(), 3);
+        assert!(!s.is_empty());
+        let c = s.clone();
+        drop(s);
+        assert_eq!(c.as_slice(), &[1, 2, 3]);
+        assert_eq!(format!("{:?}", c), "SecretBytes(3 bytes)");
+        assert_eq!(SecretBytes::from_string("ab".to_string()).as_slice(), b"ab");
+    }
+
+    #[test]
+    fn test_shared_pages() {
+
+        // Small allocations usually end up on the same page.
+        let a = SecretBytes::new(vec![1; 16]);
+        let b = SecretBytes::new(vec![2; 16]);
+        if !a.locked || !b.locked {
+            return; // e.g. ulimit -l is too small
+        }
+        let counted = |s: &SecretBytes| pages(s.data.as_ptr(), s.data.capacity())
+            .all(|p| LOCKED_PAGES.lock().unwrap().get(&p).is_some_and(|n| *n > 0));
+        assert!(counted(&a));
+        drop(a);
+        assert!(counted(&b));
+    }
+
+    #[test]
+    fn test_zeroize() {
+
+        let mut v = vec![1, 2, 3];
+        zeroize(&mut v);
+        assert_eq!(v, vec![0, 0, 0]);
+    }
+}
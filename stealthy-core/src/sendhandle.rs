@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::message::Message;
+
+/// Result of a sent message. The error contains the reason why the message has been given
+/// up.
+pub type SendResult = Result<(), String>;
+
+/// Returned by `Layers::try_send` if the window of the peer is full. Contains the message
+/// which has not been sent so that it can be sent again when `IncomingMessage::Writable`
+/// is received.
+pub struct WouldBlock(pub Message);
+
+impl fmt::Debug for WouldBlock {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WouldBlock({})", self.0.ip)
+    }
+}
+
+struct Tracked {
+    result: Option<SendResult>,
+    /// Number of retransmissions of the packets of the message.
+    retries: u32,
+    waker: Option<Waker>,
+}
+
+/// Messages for which a `SendHandle` exists. The delivery layer reports the
+/// retransmissions and the result of these messages.
+#[derive(Clone)]
+pub struct SendTracker {
+    sends: Arc<(Mutex<HashMap<u64, Tracked>>, Condvar)>,
+}
+
+impl Default for SendTracker {
+    fn default() -> SendTracker {
+        SendTracker::new()
+    }
+}
+
+impl SendTracker {
+
+    pub fn new() -> SendTracker {
+        SendTracker {
+            sends: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+        }
+    }
+
+    /// Returns the handle of the message with the given id. Must be called before the
+    /// message is sent as the ACK could arrive before the handle has been created otherwise.
+    pub fn register(&self, id: u64) -> SendHandle {
+        self.sends.0.lock().unwrap().insert(id, Tracked { result: None, retries: 0, waker: None });
+        SendHandle { id, tracker: self.clone() }
+    }
+
+    /// Counts a retransmission of a packet of the message.
+    pub fn retransmitted(&self, id: u64) {
+        if let Some(t) = self.sends.0.lock().unwrap().get_mut(&id) {
+            t.retries += 1;
+        }
+    }
+
+    /// Sets the result of the message and wakes up everyone who waits for it. Only the
+    /// first result counts.
+    pub fn resolve(&self, id: u64, r: SendResult) {
+        let mut sends = self.sends.0.lock().unwrap();
+        if let Some(t) = sends.get_mut(&id) {
+            if t.result.is_none() {
+                t.result = Some(r);
+            }
+            if let Some(w) = t.waker.take() {
+                w.wake();
+            }
+            self.sends.1.notify_all();
+        }
+    }
+
+    /// Gives up all messages which have no result yet for the given reason.
+    pub fn resolve_all(&self, reason: &str) {
+        let ids = self.sends.0.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        for id in ids {
+            self.resolve(id, Err(reason.to_string()));
+        }
+    }
+}
+
+/// Handle of a message sent with `Layers::send`. It can be used to wait until the message
+/// has been acknowledged or given up, either blocking with `wait` and `wait_timeout` or by
+/// awaiting the handle.
+pub struct SendHandle {
+    id: u64,
+    tracker: SendTracker,
+}
+
+impl SendHandle {
+
+    /// Returns the id of the message which is reported in `IncomingMessage::Ack`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns how often the packets of the message have been retransmitted so far.
+    pub fn retries(&self) -> u32 {
+        self.tracker.sends.0.lock().unwrap().get(&self.id).map(|t| t.retries).unwrap_or(0)
+    }
+
+    /// Returns the result of the message without waiting for it.
+    pub fn result(&self) -> Option<SendResult> {
+        self.tracker.sends.0.lock().unwrap().get(&self.id).and_then(|t| t.result.clone())
+    }
+
+    /// Waits until the message has been acknowledged or given up.
+    pub fn wait(&self) -> SendResult {
+        let (lock, cvar) = &*self.tracker.sends;
+        let mut sends = lock.lock().unwrap();
+        loop {
+            if let Some(r) = sends.get(&self.id).and_then(|t| t.result.clone()) {
+                return r;
+            }
+            sends = cvar.wait(sends).unwrap();
+        }
+    }
+
+    /// Waits like `wait` but at most for the given time. Returns None if the message has
+    /// neither been acknowledged nor given up in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<SendResult> {
+        let end = Instant::now() + timeout;
+        let (lock, cvar) = &*self.tracker.sends;
+        let mut sends = lock.lock().unwrap();
+        loop {
+            if let Some(r) = sends.get(&self.id).and_then(|t| t.result.clone()) {
+                return Some(r);
+            }
+            let now = Instant::now();
+            if now >= end {
+                return None;
+            }
+            sends = cvar.wait_timeout(sends, end - now).unwrap().0;
+        }
+    }
+}
+
+impl Future for SendHandle {
+    type Output = SendResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<SendResult> {
+        let mut sends = self.tracker.sends.0.lock().unwrap();
+        match sends.get_mut(&self.id) {
+            Some(Tracked { result: Some(r), .. }) => Poll::Ready(r.clone()),
+            Some(t) => {
+                t.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+            None => Poll::Pending
+        }
+    }
+}
+
+impl Drop for SendHandle {
+    fn drop(&mut self) {
+        self.tracker.sends.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::thread;
+    use std::time::Duration;
+
+    use super::SendTracker;
+
+    #[test]
+    fn test_send_handle() {
+
+        let t = SendTracker::new();
+        let h = t.register(1);
+        let failed = t.register(2);
+        assert_eq!(h.id(), 1);
+        assert_eq!(h.result(), None);
+        assert_eq!(h.wait_timeout(Duration::from_millis(10)), None);
+
+        t.retransmitted(1);
+        t.retransmitted(1);
+        t.retransmitted(3);
+        assert_eq!(h.retries(), 2);
+        assert_eq!(failed.retries(), 0);
+
+        let t2 = t.clone();
+        let s = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            t2.resolve(1, Ok(()));
+            t2.resolve(1, Err("late".to_string()));
+            t2.resolve(2, Err("timeout".to_string()));
+        });
+        assert_eq!(h.wait(), Ok(()));
+        assert_eq!(failed.wait_timeout(Duration::from_secs(10)), Some(Err("timeout".to_string())));
+        s.join().unwrap();
+
+        let open = t.register(4);
+        t.resolve_all("shut down");
+        assert_eq!(open.result(), Some(Err("shut down".to_string())));
+        assert_eq!(h.result(), Some(Ok(())));
+
+        // The message is forgotten when its handle is dropped.
+        drop(h);
+        t.retransmitted(1);
+        assert!(t.sends.0.lock().unwrap().get(&1).is_none());
+    }
+}
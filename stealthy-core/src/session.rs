@@ -0,0 +1,515 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use crypto::chacha20::ChaCha20;
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::aead::{AeadEncryptor, AeadDecryptor};
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::curve25519::{curve25519, curve25519_base};
+use crypto::hkdf::{hkdf_extract, hkdf_expand};
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::cryp::AUTHENTICATION_FAILED;
+use crate::wire::{push_value, push_slice};
+use crate::error::StealthyError;
+
+pub const KEY_LEN: usize = 32;
+
+/// Number of bytes which are added to each message by a session.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+/// Maximum number of bytes which are added to a message in the deniable format.
+pub const MAX_OVERHEAD: usize = OVERHEAD + 1 + MAX_REVEALED * KEY_LEN;
+
+/// Error which is returned by `Ratchet::decrypt` if the message has already been received.
+pub const REPLAYED: &str = "Message has already been received.";
+
+const HEADER_LEN: usize = KEY_LEN + 4 + 4;
+const TAG_LEN: usize = 16;
+/// Maximum number of messages of a chain which may be missing.
+const MAX_SKIP: u32 = 1000;
+/// Number of previous ratchet keys of the peer which are remembered to detect replays.
+const MAX_OLD_KEYS: usize = 8;
+/// Flag in the length of the previous sending chain which marks the deniable format.
+const DENIABLE: u32 = 1 << 31;
+/// Maximum number of MAC keys which are published in one message. As the receipt for a
+/// message is also sent via the session, usually at most one key is pending.
+const MAX_REVEALED: usize = 2;
+/// Maximum number of MAC keys which wait to be published. If the peer sends many messages
+/// without getting an answer, the oldest keys are dropped and not published.
+const MAX_PENDING_REVEALED: usize = 64;
+
+type Key = [u8; KEY_LEN];
+
+/// A X25519 key pair.
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: Key,
+    pub public: Key,
+}
+
+impl KeyPair {
+
+    pub fn generate() -> Result<KeyPair, StealthyError> {
+        let mut secret = [0u8; KEY_LEN];
+        OsRng::new().map_err(|_| StealthyError::Crypto("Could not get OsRng."))?.fill_bytes(&mut secret);
+        Ok(KeyPair {
+            public: curve25519_base(&secret),
+            secret,
+        })
+    }
+
+    /// Computes the shared secret with the public key of the peer.
+    pub fn dh(&self, peer: &[u8]) -> Result<Key, StealthyError> {
+        let shared = curve25519(&self.secret, peer);
+        // A peer could send a point of small order which results in a known secret.
+        if fixed_time_eq(&shared, &[0u8; KEY_LEN]) {
+            return Err(StealthyError::Crypto("Invalid public key of peer."));
+        }
+        Ok(shared)
+    }
+}
+
+/// Derives key material with HKDF-SHA256.
+pub fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) {
+    let mut prk = [0u8; 32];
+    hkdf_extract(Sha256::new(), salt, ikm, &mut prk);
+    hkdf_expand(Sha256::new(), &prk, info, okm);
+}
+
+/// The Double Ratchet of a session (see https://signal.org/docs/specifications/doubleratchet/).
+///
+/// Each message is encrypted with its own message key which is derived from the sending
+/// chain. The chains are replaced with new chains derived from a new X25519 key pair each
+/// time the direction of the conversation changes. Hence, a compromised state does not
+/// reveal previous messages and the session recovers from a compromise as soon as the
+/// peers have exchanged new ratchet keys.
+///
+/// Message format: ratchet key (32) | length of the previous sending chain (4) |
+///                 message number (4) | ciphertext | tag (16)
+///
+/// In the deniable format the message is encrypted with ChaCha20 and authenticated with a
+/// HMAC-SHA256 whose key is derived separately from the message key. After a message has
+/// been verified the receiver publishes the MAC key in one of its next messages. Then
+/// everyone can create messages with valid tags for the transcript, so that a transcript
+/// does not prove who has written a message, while the peers still know during the
+/// session that a message is authentic as each message key is accepted only once.
+///
+/// Deniable format: ratchet key (32) | length of the previous sending chain with the
+///                  highest bit set (4) | message number (4) | number of published
+///                  MAC keys (1) | published MAC keys (32 each) | ciphertext | tag (16)
+#[derive(Clone)]
+pub struct Ratchet {
+    /// Our ratchet key pair.
+    dhs: KeyPair,
+    /// The ratchet key of the peer.
+    dhr: Option<Key>,
+    /// Previous ratchet keys of the peer.
+    old: Vec<Key>,
+    /// Root key.
+    rk: Key,
+    /// Chain keys for sending and receiving.
+    cks: Option<Key>,
+    ckr: Option<Key>,
+    /// Message numbers for sending and receiving.
+    ns: u32,
+    nr: u32,
+    /// Number of messages in the previous sending chain.
+    pn: u32,
+    /// Message keys of messages which have not been received yet.
+    skipped: HashMap<(Key, u32), Key>,
+    /// MAC keys of received messages in the deniable format which have not been
+    /// published yet.
+    revealed: Vec<Key>,
+}
+
+impl Ratchet {
+
+    /// Creates the ratchet of the peer which has initiated the handshake. `sk` is the key
+    /// negotiated by the handshake and `peer` is the ratchet key of the responder.
+    pub fn initiator(sk: &[u8], peer: &[u8]) -> Result<Ratchet, StealthyError> {
+
+        let dhs = KeyPair::generate()?;
+        let (rk, ck) = kdf_rk(sk, &dhs.dh(peer)?);
+        Ok(Ratchet {
+            dhs,
+            dhr: Some(to_key(peer)),
+            old: vec![],
+            rk,
+            cks: Some(ck),
+            ckr: None,
+            ns: 0,
+            nr: 0,
+            pn: 0,
+            skipped: HashMap::new(),
+            revealed: vec![],
+        })
+    }
+
+    /// Creates the ratchet of the responder. `dhs` is the key pair whose public key has
+    /// been sent to the initiator. The responder can send messages not before it has
+    /// received the first message of the initiator.
+    pub fn responder(sk: &[u8], dhs: KeyPair) -> Ratchet {
+        Ratchet {
+            dhs,
+            dhr: None,
+            old: vec![],
+            rk: to_key(sk),
+            cks: None,
+            ckr: None,
+            ns: 0,
+            nr: 0,
+            pn: 0,
+            skipped: HashMap::new(),
+            revealed: vec![],
+        }
+    }
+
+    /// Returns true if messages can be sent.
+    pub fn can_send(&self) -> bool {
+        self.cks.is_some()
+    }
+
+    /// Encrypts a message. The deniable format is used if `deniable` is true or if there
+    /// are MAC keys of the peer which have to be published.
+    pub fn encrypt(&mut self, plain: &[u8], deniable: bool) -> Result<Vec<u8>, StealthyError> {
+
+        let (ck, mk) = kdf_ck(&self.cks.ok_or(StealthyError::Crypto("Session cannot be used for sending yet."))?);
+        self.cks = Some(ck);
+
+        let deniable = deniable || !self.revealed.is_empty();
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        push_slice(&mut header, &self.dhs.public);
+        push_value(&mut header, (self.pn | if deniable { DENIABLE } else { 0 }) as u64, 4);
+        push_value(&mut header, self.ns as u64, 4);
+        self.ns += 1;
+
+        if deniable {
+            let n = cmp::min(self.revealed.len(), MAX_REVEALED);
+            header.push(n as u8);
+            for k in self.revealed.drain(..n) {
+                push_slice(&mut header, &k);
+            }
+            let (ek, ak) = kdf_mk(&mk);
+            let mut cipher = vec![0; plain.len()];
+            ChaCha20::new(&ek, &[0u8; 8]).process(plain, &mut cipher);
+            push_slice(&mut header, &cipher);
+            let tag = deniable_tag(&ak, &header);
+            push_slice(&mut header, &tag);
+            return Ok(header);
+        }
+
+        let mut cipher = vec![0; plain.len()];
+        let mut tag = [0u8; TAG_LEN];
+        // A message key is used only once so that the nonce can be fixed.
+        ChaCha20Poly1305::new(&mk, &[0u8; 8], &header).encrypt(plain, &mut cipher, &mut tag);
+
+        push_slice(&mut header, &cipher);
+        push_slice(&mut header, &tag);
+        Ok(header)
+    }
+
+    /// Decrypts a message of the peer. The state is only changed if the message is
+    /// authentic.
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, StealthyError> {
+        let mut r = self.clone();
+        let plain = r.try_decrypt(data)?;
+        *self = r;
+        Ok(plain)
+    }
+
+    fn try_decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+        if data.len() < OVERHEAD {
+            return Err(StealthyError::Crypto(AUTHENTICATION_FAILED));
+        }
+        let header = &data[..HEADER_LEN];
+        let dh = to_key(&header[..KEY_LEN]);
+        let pn = read_u32(&header[KEY_LEN..KEY_LEN + 4]) & !DENIABLE;
+        let n = read_u32(&header[KEY_LEN + 4..]);
+
+        if let Some(mk) = self.skipped.remove(&(dh, n)) {
+            return self.open(&mk, data);
+        }
+        if self.dhr == Some(dh) {
+            if n < self.nr {
+                return Err(StealthyError::Crypto(REPLAYED));
+            }
+        } else {
+            if self.old.contains(&dh) {
+                return Err(StealthyError::Crypto(REPLAYED));
+            }
+            self.skip(pn)?;
+            self.dh_ratchet(dh)?;
+        }
+        self.skip(n)?;
+
+        let (ck, mk) = kdf_ck(&self.ckr.ok_or(StealthyError::Crypto(AUTHENTICATION_FAILED))?);
+        self.ckr = Some(ck);
+        self.nr += 1;
+        self.open(&mk, data)
+    }
+
+    /// Decrypts a message with its message key. The MAC key of a message in the deniable
+    /// format is remembered so that it is published with the next message.
+    fn open(&mut self, mk: &Key, data: &[u8]) -> Result<Vec<u8>, StealthyError> {
+
+        let header = &data[..HEADER_LEN];
+        if read_u32(&header[KEY_LEN..KEY_LEN + 4]) & DENIABLE == 0 {
+            let (cipher, tag) = data[HEADER_LEN..].split_at(data.len() - HEADER_LEN - TAG_LEN);
+            let mut plain = vec![0; cipher.len()];
+            return match ChaCha20Poly1305::new(mk, &[0u8; 8], header).decrypt(cipher, &mut plain, tag) {
+                true => Ok(plain),
+                false => Err(StealthyError::Crypto(AUTHENTICATION_FAILED))
+            };
+        }
+
+        // The published MAC keys are authenticated but not needed by us.
+        let start = HEADER_LEN + 1 + data[HEADER_LEN] as usize * KEY_LEN;
+        if data.len() < start + TAG_LEN {
+            return Err(StealthyError::Crypto(AUTHENTICATION_FAILED));
+        }
+        let (signed, tag) = data.split_at(data.len() - TAG_LEN);
+        let (ek, ak) = kdf_mk(mk);
+        if !fixed_time_eq(&deniable_tag(&ak, signed), tag) {
+            return Err(StealthyError::Crypto(AUTHENTICATION_FAILED));
+        }
+        let cipher = &signed[start..];
+        let mut plain = vec![0; cipher.len()];
+        ChaCha20::new(&ek, &[0u8; 8]).process(cipher, &mut plain);
+        self.revealed.push(ak);
+        if self.revealed.len() > MAX_PENDING_REVEALED {
+            self.revealed.remove(0);
+        }
+        Ok(plain)
+    }
+
+    /// Stores the message keys of the current receiving chain up to the given message.
+    fn skip(&mut self, until: u32) -> Result<(), StealthyError> {
+
+        if let (Some(mut ck), Some(dh)) = (self.ckr, self.dhr) {
+            if until > self.nr + MAX_SKIP || self.skipped.len() as u32 > 2 * MAX_SKIP {
+                return Err(StealthyError::Crypto("Too many missing messages."));
+            }
+            while self.nr < until {
+                let (next, mk) = kdf_ck(&ck);
+                self.skipped.insert((dh, self.nr), mk);
+                ck = next;
+                self.nr += 1;
+            }
+            self.ckr = Some(ck);
+        }
+        Ok(())
+    }
+
+    /// Replaces the chains after the peer has sent a new ratchet key.
+    fn dh_ratchet(&mut self, dh: Key) -> Result<(), StealthyError> {
+
+        if let Some(old) = self.dhr {
+            self.old.push(old);
+            if self.old.len() > MAX_OLD_KEYS {
+                self.old.remove(0);
+            }
+        }
+        self.pn = self.ns;
+        self.ns = 0;
+        self.nr = 0;
+        self.dhr = Some(dh);
+
+        let (rk, ck) = kdf_rk(&self.rk, &self.dhs.dh(&dh)?);
+        self.rk = rk;
+        self.ckr = Some(ck);
+
+        self.dhs = KeyPair::generate()?;
+        let (rk, ck) = kdf_rk(&self.rk, &self.dhs.dh(&dh)?);
+        self.rk = rk;
+        self.cks = Some(ck);
+        Ok(())
+    }
+}
+
+/// Derives the key for the encryption and the MAC key of a message in the deniable format
+/// from the message key.
+fn kdf_mk(mk: &Key) -> (Key, Key) {
+    let mut okm = [0u8; 2 * KEY_LEN];
+    hkdf(&[], mk, b"stealthy deniable", &mut okm);
+    (to_key(&okm[..KEY_LEN]), to_key(&okm[KEY_LEN..]))
+}
+
+fn deniable_tag(ak: &Key, data: &[u8]) -> Vec<u8> {
+    let mut h = Hmac::new(Sha256::new(), ak);
+    h.input(data);
+    h.result().code()[..TAG_LEN].to_vec()
+}
+
+/// Derives a new root key and a new chain key.
+fn kdf_rk(rk: &[u8], dh: &[u8]) -> (Key, Key) {
+    let mut okm = [0u8; 2 * KEY_LEN];
+    hkdf(rk, dh, b"stealthy ratchet", &mut okm);
+    (to_key(&okm[..KEY_LEN]), to_key(&okm[KEY_LEN..]))
+}
+
+/// Derives the next chain key and a message key.
+fn kdf_ck(ck: &Key) -> (Key, Key) {
+    let mac = |b: u8| {
+        let mut h = Hmac::new(Sha256::new(), ck);
+        h.input(&[b]);
+        to_key(h.result().code())
+    };
+    (mac(2), mac(1))
+}
+
+fn to_key(v: &[u8]) -> Key {
+    let mut k = [0u8; KEY_LEN];
+    k.copy_from_slice(&v[..KEY_LEN]);
+    k
+}
+
+fn read_u32(v: &[u8]) -> u32 {
+    v.iter().rev().fold(0, |acc, b| (acc << 8) + *b as u32)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{KeyPair, Ratchet, REPLAYED, OVERHEAD, HEADER_LEN, KEY_LEN, TAG_LEN, MAX_PENDING_REVEALED, deniable_tag, to_key};
+    use crate::cryp::AUTHENTICATION_FAILED;
+
+    const SK: [u8; 32] = [7; 32];
+
+    fn pair() -> (Ratchet, Ratchet) {
+        let bob = KeyPair::generate().unwrap();
+        let a = Ratchet::initiator(&SK, &bob.public).unwrap();
+        let b = Ratchet::responder(&SK, bob);
+        (a, b)
+    }
+
+    #[test]
+    fn test_conversation() {
+
+        let (mut a, mut b) = pair();
+        assert!(a.can_send());
+        assert!(!b.can_send());
+
+        let c1 = a.encrypt(b"hello", false).unwrap();
+        let c2 = a.encrypt(b"hello", false).unwrap();
+        assert_eq!(c1.len(), 5 + OVERHEAD);
+        // Each message uses a fresh message key.
+        assert!(c1[OVERHEAD - 16..] != c2[OVERHEAD - 16..]);
+
+        assert_eq!(b.decrypt(&c1).unwrap(), b"hello".to_vec());
+        assert_eq!(b.decrypt(&c2).unwrap(), b"hello".to_vec());
+        assert!(b.can_send());
+
+        // The ratchet keys are replaced when the direction changes.
+        for i in 0..3 {
+            let key_b = b.dhs.public;
+            let c = b.encrypt(format!("answer {}", i).as_bytes(), false).unwrap();
+            assert_eq!(a.decrypt(&c).unwrap(), format!("answer {}", i).into_bytes());
+            let c = a.encrypt(b"question", false).unwrap();
+            assert_eq!(b.decrypt(&c).unwrap(), b"question".to_vec());
+            assert!(b.dhs.public != key_b);
+        }
+    }
+
+    #[test]
+    fn test_out_of_order() {
+
+        let (mut a, mut b) = pair();
+        let c = (0..4).map(|i| a.encrypt(&[i], false).unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(b.decrypt(&c[2]).unwrap(), vec![2]);
+        assert_eq!(b.decrypt(&c[0]).unwrap(), vec![0]);
+        let answer = b.encrypt(b"x", false).unwrap();
+        assert_eq!(a.decrypt(&answer).unwrap(), b"x".to_vec());
+        // Messages of the previous chain can still be received after the ratchet step.
+        let next = a.encrypt(b"y", false).unwrap();
+        assert_eq!(b.decrypt(&next).unwrap(), b"y".to_vec());
+        assert_eq!(b.decrypt(&c[3]).unwrap(), vec![3]);
+        assert_eq!(b.decrypt(&c[1]).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_replay() {
+
+        let (mut a, mut b) = pair();
+        let c = a.encrypt(b"hello", false).unwrap();
+        b.decrypt(&c).unwrap();
+        assert_eq!(b.decrypt(&c).map_err(|e| e.message()), Err(REPLAYED));
+
+        // Replays of previous chains are detected as well.
+        a.decrypt(&b.encrypt(b"x", false).unwrap()).unwrap();
+        b.decrypt(&a.encrypt(b"y", false).unwrap()).unwrap();
+        assert_eq!(b.decrypt(&c).map_err(|e| e.message()), Err(REPLAYED));
+    }
+
+    #[test]
+    fn test_tampered() {
+
+        let (mut a, mut b) = pair();
+        let c = a.encrypt(b"hello", false).unwrap();
+        for i in 0..c.len() {
+            let mut t = c.clone();
+            t[i] ^= 1;
+            assert!(b.decrypt(&t).is_err());
+        }
+        assert_eq!(b.decrypt(&c[..10]).map_err(|e| e.message()), Err(AUTHENTICATION_FAILED));
+        // Rejected messages do not change the state.
+        assert_eq!(b.decrypt(&c).unwrap(), b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_deniable() {
+
+        let (mut a, mut b) = pair();
+        let c = a.encrypt(b"hello", true).unwrap();
+        assert_eq!(c.len(), 5 + OVERHEAD + 1);
+        for i in 0..c.len() {
+            let mut t = c.clone();
+            t[i] ^= 1;
+            assert!(b.decrypt(&t).is_err());
+        }
+        assert_eq!(b.decrypt(&c).unwrap(), b"hello".to_vec());
+
+        // The MAC key is published with the next message of the receiver.
+        let r = b.encrypt(b"x", false).unwrap();
+        assert_eq!(r.len(), 1 + OVERHEAD + 1 + KEY_LEN);
+        assert_eq!(a.decrypt(&r).unwrap(), b"x".to_vec());
+        assert!(b.revealed.is_empty());
+        let ak = &r[HEADER_LEN + 1..HEADER_LEN + 1 + KEY_LEN];
+
+        // Everyone can create a message with a valid tag with the published key, but it
+        // is not accepted in the session anymore.
+        let mut forged = c[..c.len() - TAG_LEN].to_vec();
+        forged[HEADER_LEN + 1] ^= 1;
+        let tag = deniable_tag(&to_key(ak), &forged);
+        forged.extend_from_slice(&tag);
+        assert_eq!(b.decrypt(&forged).map_err(|e| e.message()), Err(REPLAYED));
+
+        // The receiver of the answer publishes its MAC key as well.
+        let c = a.encrypt(b"y", false).unwrap();
+        assert_eq!(c.len(), 1 + OVERHEAD + 1 + KEY_LEN);
+        assert_eq!(b.decrypt(&c).unwrap(), b"y".to_vec());
+    }
+
+    #[test]
+    fn test_pending_revealed() {
+
+        // The MAC keys of a peer which never gets an answer are not kept forever.
+        let (mut a, mut b) = pair();
+        let keys = (0..MAX_PENDING_REVEALED + 3).map(|_| {
+            b.decrypt(&a.encrypt(b"x", true).unwrap()).unwrap();
+            *b.revealed.last().unwrap()
+        }).collect::<Vec<_>>();
+        assert_eq!(b.revealed.len(), MAX_PENDING_REVEALED);
+        assert_eq!(b.revealed[0], keys[3]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Signal which stops the background threads of the layers.
+///
+/// Threads which run as long as the layers are started with `spawn`. They have to check the
+/// signal regularly, e.g. by waiting with `sleep` which returns as soon as the signal has
+/// been given. `join` waits until these threads have finished.
+#[derive(Clone)]
+pub struct Shutdown {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Shutdown {
+
+    pub fn new() -> Shutdown {
+        Shutdown {
+            stopped: Arc::new((Mutex::new(false), Condvar::new())),
+            threads: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Starts a thread which is joined by `join`.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let t = thread::spawn(f);
+        self.threads.lock().unwrap().push(t);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.0.lock().unwrap()
+    }
+
+    /// Waits for the given time. Returns false if the signal has been given before.
+    pub fn sleep(&self, d: Duration) -> bool {
+
+        let end = Instant::now() + d;
+        let (lock, cvar) = &*self.stopped;
+        let mut stopped = lock.lock().unwrap();
+        while !*stopped {
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            stopped = cvar.wait_timeout(stopped, end - now).unwrap().0;
+        }
+        false
+    }
+
+    pub fn signal(&self) {
+        *self.stopped.0.lock().unwrap() = true;
+        self.stopped.1.notify_all();
+    }
+
+    /// Waits for the threads started with `spawn`. Must not be called by one of these
+    /// threads.
+    pub fn join(&self) {
+        // Threads may start other threads while they are stopping.
+        loop {
+            let threads = mem::replace(&mut *self.threads.lock().unwrap(), vec![]);
+            if threads.is_empty() {
+                break;
+            }
+            for t in threads {
+                let _ = t.join();
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use super::Shutdown;
+
+    #[test]
+    fn test_shutdown() {
+
+        let s = Shutdown::new();
+        assert!(s.sleep(Duration::from_millis(10)));
+
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let (t, r) = (s.clone(), rounds.clone());
+        s.spawn(move || while t.sleep(Duration::from_secs(60)) {
+            r.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // The thread is woken up and joined immediately.
+        let start = Instant::now();
+        s.signal();
+        s.join();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(rounds.load(Ordering::SeqCst), 0);
+        assert!(s.is_stopped());
+        assert!(!s.sleep(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,414 @@
+use std::fs;
+use std::net::IpAddr;
+
+use crypto::ed25519;
+
+use crate::cryp::from_hex;
+use crate::tools::{read_file, to_hex};
+use crate::rollover::Announcement;
+use crate::secret::SecretBytes;
+use crate::sshkey;
+use crate::error::StealthyError;
+
+const SEED_LEN: usize = 32;
+const PUBLIC_KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+const UNSIGNED: u8 = 0;
+const SIGNED: u8 = 1;
+
+/// Result of the signature check of a received message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SignatureStatus {
+    /// The message is not signed and no keys for verification are configured.
+    None,
+    /// The message has been signed with a trusted key of the sender.
+    Verified,
+    /// The message is not signed or the signature could not be verified although keys for
+    /// verification are configured.
+    Unverified,
+}
+
+/// Optional Ed25519 signatures of the sender.
+///
+/// With a symmetric key every holder of the key can send messages in the name of another
+/// peer. If a signing key is configured each outgoing message is signed. The signature is
+/// encrypted together with the message so that it does not reveal the sender to others.
+///
+/// A trusted key can be bound to the IP address of a peer. The signature of a message is
+/// only verified with the keys bound to the sender. Keys without an IP address are used
+/// for peers which have no bound key, so a peer with a bound key cannot be impersonated
+/// by the holder of another trusted key.
+#[derive(Clone)]
+pub struct Signing {
+    secret: Option<[u8; 64]>,
+    public: Option<[u8; PUBLIC_KEY_LEN]>,
+    /// Public keys of the peers whose signatures are accepted, each with the IP address of
+    /// the peer if the key is bound to it.
+    trusted: Vec<(Option<String>, Vec<u8>)>,
+    /// File from which the trusted keys have been loaded. It is updated when a peer rolls
+    /// over its key.
+    store: Option<String>,
+}
+
+impl Signing {
+
+    /// Creates an instance which neither signs nor verifies messages.
+    pub fn disabled() -> Signing {
+        Signing {
+            secret: None,
+            public: None,
+            trusted: vec![],
+            store: None,
+        }
+    }
+
+    /// Loads the keys from files. `sign_key` contains the seed of our secret key in
+    /// hexadecimal or an Ed25519 private key of OpenSSH which is decrypted with the
+    /// passphrase if required. `verify_keys` contains the public keys of the peers, one
+    /// per line, either in hexadecimal or in the format of OpenSSH like in
+    /// `~/.ssh/id_ed25519.pub`. A key which is preceded by an IP address is bound to the
+    /// peer with this address. Lines which start with '#' are ignored.
+    pub fn from_files(sign_key: Option<&String>, passphrase: Option<&String>, verify_keys: Option<&String>) -> Result<Signing, StealthyError> {
+
+        let mut s = Signing::disabled();
+        if let Some(f) = sign_key {
+            s = s.with_seed(read_seed(f, passphrase)?.as_slice())?;
+        }
+        if let Some(f) = verify_keys {
+            for line in read_file(f)?.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with("#")) {
+                s = match parse_line(line)? {
+                    (Some(ip), k) => s.trust_peer(&ip, &k)?,
+                    (None, k) => s.trust(&k)?
+                };
+            }
+            s.store = Some(f.clone());
+        }
+        Ok(s)
+    }
+
+    /// Signs outgoing messages with the key derived from the given seed.
+    pub fn with_seed(mut self, seed: &[u8]) -> Result<Signing, StealthyError> {
+        if seed.len() != SEED_LEN {
+            return Err(StealthyError::Crypto("Invalid length of signing key."));
+        }
+        let (secret, public) = ed25519::keypair(seed);
+        self.secret = Some(secret);
+        self.public = Some(public);
+        Ok(self)
+    }
+
+    /// Accepts signatures created with the given public key from all peers which have no
+    /// bound key.
+    pub fn trust(self, public: &[u8]) -> Result<Signing, StealthyError> {
+        self.add_trusted(None, public)
+    }
+
+    /// Accepts signatures created with the given public key only from the peer with the
+    /// given IP address.
+    pub fn trust_peer(self, ip: &str, public: &[u8]) -> Result<Signing, StealthyError> {
+        self.add_trusted(Some(ip.to_string()), public)
+    }
+
+    fn add_trusted(mut self, ip: Option<String>, public: &[u8]) -> Result<Signing, StealthyError> {
+        if public.len() != PUBLIC_KEY_LEN {
+            return Err(StealthyError::Crypto("Invalid length of public key for verification."));
+        }
+        self.trusted.push((ip, public.to_vec()));
+        Ok(self)
+    }
+
+    /// Returns the positions of the trusted keys which are used for the peer with the given
+    /// IP address: its bound keys or, if there are none, the keys without an address.
+    fn keys_of(&self, ip: &str) -> Vec<usize> {
+        let bound = self.trusted.iter().any(|(i, _)| i.as_deref() == Some(ip));
+        (0..self.trusted.len())
+            .filter(|&n| match self.trusted[n].0 {
+                Some(ref i) => i == ip,
+                None => !bound
+            })
+            .collect()
+    }
+
+    /// Returns our public key in hexadecimal if messages are signed.
+    pub fn public_key(&self) -> Option<String> {
+        self.public.map(|k| to_hex(&k))
+    }
+
+    /// Replaces our key by the key derived from the given seed or stops signing if no seed
+    /// is given. Returns the announcement for the peers signed with the old key (see
+    /// `rollover`).
+    pub fn roll_over(&mut self, seed: Option<&[u8]>) -> Result<Vec<u8>, StealthyError> {
+
+        let (secret, public) = match (self.secret, self.public) {
+            (Some(s), Some(p)) => (s, p),
+            _ => return Err(StealthyError::Crypto("There is no signing key which could be replaced."))
+        };
+        let a = match seed {
+            Some(seed) if seed.len() != SEED_LEN => return Err(StealthyError::Crypto("Invalid length of signing key.")),
+            Some(seed) => Announcement::Rollover { old: public.to_vec(), new: ed25519::keypair(seed).1.to_vec() },
+            None => Announcement::Revocation { old: public.to_vec() }
+        };
+        let buf = a.sign(&secret);
+        match seed {
+            Some(seed) => {
+                let (secret, public) = ed25519::keypair(seed);
+                self.secret = Some(secret);
+                self.public = Some(public);
+            },
+            None => {
+                self.secret = None;
+                self.public = None;
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Applies the verified announcement of the peer with the given IP address to the
+    /// trusted keys and updates the file of the trusted keys. Returns false if the old key
+    /// is not trusted for this peer. In this case nothing is changed.
+    pub fn apply(&mut self, ip: &str, a: &Announcement) -> Result<bool, StealthyError> {
+
+        let pos = match self.keys_of(ip).into_iter().find(|&n| &self.trusted[n].1[..] == a.old()) {
+            Some(pos) => pos,
+            None => return Ok(false)
+        };
+        let bound = self.trusted[pos].0.clone();
+        match a {
+            Announcement::Rollover { new, .. } => self.trusted[pos].1 = new.clone(),
+            Announcement::Revocation { .. } => { self.trusted.remove(pos); }
+        }
+        if let Some(ref f) = self.store {
+            let content = update_store(&read_file(f)?, bound.as_deref(), a);
+            fs::write(f, content).map_err(|e| StealthyError::Io("Could not update the file of the keys for verification.", e))?;
+        }
+        Ok(true)
+    }
+
+    /// Returns the number of bytes which are added to each message.
+    pub fn overhead(&self) -> usize {
+        match self.secret {
+            Some(_) => 1 + SIGNATURE_LEN,
+            None => 1
+        }
+    }
+
+    /// Prepends the signature to the message if a signing key is configured.
+    pub fn sign(&self, buf: &[u8]) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.overhead() + buf.len());
+        match self.secret {
+            Some(ref k) => {
+                v.push(SIGNED);
+                v.extend_from_slice(&ed25519::signature(buf, k));
+            },
+            None => v.push(UNSIGNED)
+        }
+        v.extend_from_slice(buf);
+        v
+    }
+
+    /// Removes the signature from a message of the peer with the given IP address and
+    /// verifies it with the keys which are trusted for this peer.
+    pub fn verify(&self, ip: &str, buf: &[u8]) -> Result<(Vec<u8>, SignatureStatus), StealthyError> {
+
+        let unverified = match self.trusted.is_empty() {
+            true => SignatureStatus::None,
+            false => SignatureStatus::Unverified
+        };
+        match buf.first() {
+            Some(&UNSIGNED) => Ok((buf[1..].to_vec(), unverified)),
+            Some(&SIGNED) if buf.len() > SIGNATURE_LEN => {
+                let (sig, data) = buf[1..].split_at(SIGNATURE_LEN);
+                let valid = self.keys_of(ip).into_iter().any(|n| ed25519::verify(data, &self.trusted[n].1, sig));
+                match valid {
+                    true => Ok((data.to_vec(), SignatureStatus::Verified)),
+                    false => Ok((data.to_vec(), SignatureStatus::Unverified))
+                }
+            },
+            _ => Err(StealthyError::Crypto("Invalid format of signature."))
+        }
+    }
+}
+
+/// Reads the seed of a signing key from a file which contains the seed in hexadecimal or
+/// an Ed25519 private key of OpenSSH.
+pub fn read_seed(f: &str, passphrase: Option<&String>) -> Result<SecretBytes, StealthyError> {
+    let content = read_file(f)?;
+    match sshkey::is_private_key(&content) {
+        true => sshkey::ed25519_seed(&content, passphrase.map(|p| p.as_str())),
+        false => Ok(SecretBytes::new(from_hex(content.trim().to_string())?))
+    }
+}
+
+/// Parses a public key for verification in hexadecimal or in the format of OpenSSH.
+fn parse_public_key(line: &str) -> Result<Vec<u8>, StealthyError> {
+    match sshkey::is_public_key(line) {
+        true => sshkey::ed25519_public_key(line),
+        false => from_hex(line.to_string())
+    }
+}
+
+/// Parses a line of the file of the trusted keys, i.e. a public key which is optionally
+/// preceded by the IP address of the peer to which it is bound.
+fn parse_line(line: &str) -> Result<(Option<String>, Vec<u8>), StealthyError> {
+    let mut parts = line.splitn(2, char::is_whitespace);
+    match (parts.next().map(|p| p.parse::<IpAddr>()), parts.next()) {
+        (Some(Ok(ip)), Some(key)) => Ok((Some(ip.to_string()), parse_public_key(key.trim())?)),
+        _ => Ok((None, parse_public_key(line)?))
+    }
+}
+
+/// Returns the content of the file of the trusted keys after the announcement has been
+/// applied to the key which is bound to the given IP address or, if there is none, to the
+/// key without an address. A replaced key is kept as comment so that the history remains
+/// visible.
+fn update_store(content: &str, ip: Option<&str>, a: &Announcement) -> String {
+
+    let mut s = String::new();
+    for line in content.lines() {
+        let t = line.trim();
+        let matches = !t.is_empty() && !t.starts_with("#") && match parse_line(t) {
+            Ok((i, k)) => i.as_deref() == ip && &k[..] == a.old(),
+            Err(_) => false
+        };
+        match a {
+            Announcement::Rollover { new, .. } if matches => {
+                let prefix = ip.map(|i| format!("{} ", i)).unwrap_or_default();
+                s += &format!("# rolled over: {}\n{}{}\n", t, prefix, to_hex(new));
+            },
+            Announcement::Revocation { .. } if matches => {
+                s += &format!("# revoked: {}\n", t);
+            },
+            _ => {
+                s += line;
+                s += "\n";
+            }
+        }
+    }
+    s
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Signing, SignatureStatus, parse_line, update_store};
+    use crate::rollover::Announcement;
+    use crate::tools::to_hex;
+    use crypto::ed25519;
+
+    const SEED: [u8; 32] = [5; 32];
+    const ALICE: &str = "1.2.3.4";
+
+    fn public(seed: &[u8]) -> Vec<u8> {
+        ed25519::keypair(seed).1.to_vec()
+    }
+
+    #[test]
+    fn test_sign_verify() {
+
+        let alice = Signing::disabled().with_seed(&SEED).unwrap();
+        let bob = Signing::disabled().trust(&public(&SEED)).unwrap();
+
+        let signed = alice.sign(b"hello");
+        assert_eq!(signed.len(), 5 + alice.overhead());
+        assert_eq!(bob.verify(ALICE, &signed).unwrap(), (b"hello".to_vec(), SignatureStatus::Verified));
+
+        // A modified message is not verified.
+        let mut t = signed.clone();
+        *t.last_mut().unwrap() ^= 1;
+        assert_eq!(bob.verify(ALICE, &t).unwrap().1, SignatureStatus::Unverified);
+
+        // A signature of an unknown key is not verified.
+        let mallory = Signing::disabled().with_seed(&[6; 32]).unwrap();
+        assert_eq!(bob.verify(ALICE, &mallory.sign(b"hello")).unwrap().1, SignatureStatus::Unverified);
+    }
+
+    #[test]
+    fn test_bound_keys() {
+
+        let alice = Signing::disabled().with_seed(&SEED).unwrap();
+        let mallory = Signing::disabled().with_seed(&[6; 32]).unwrap();
+        let bob = Signing::disabled()
+            .trust_peer(ALICE, &public(&SEED)).unwrap()
+            .trust(&public(&[6; 32])).unwrap();
+
+        assert_eq!(bob.verify(ALICE, &alice.sign(b"hello")).unwrap().1, SignatureStatus::Verified);
+        // Another trusted key cannot sign in the name of a peer with a bound key.
+        assert_eq!(bob.verify(ALICE, &mallory.sign(b"hello")).unwrap().1, SignatureStatus::Unverified);
+        // A bound key is not accepted from other peers.
+        assert_eq!(bob.verify("5.6.7.8", &alice.sign(b"hello")).unwrap().1, SignatureStatus::Unverified);
+        assert_eq!(bob.verify("5.6.7.8", &mallory.sign(b"hello")).unwrap().1, SignatureStatus::Verified);
+    }
+
+    #[test]
+    fn test_unsigned() {
+
+        let plain = Signing::disabled().sign(b"hello");
+        assert_eq!(plain.len(), 6);
+        assert_eq!(Signing::disabled().verify(ALICE, &plain).unwrap(), (b"hello".to_vec(), SignatureStatus::None));
+
+        // Unsigned messages are unverified if keys for verification are configured.
+        let bob = Signing::disabled().trust(&public(&SEED)).unwrap();
+        assert_eq!(bob.verify(ALICE, &plain).unwrap().1, SignatureStatus::Unverified);
+
+        assert!(bob.verify(ALICE, &[]).is_err());
+        assert!(bob.verify(ALICE, &[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(Signing::disabled().with_seed(&[1, 2, 3]).is_err());
+        assert!(Signing::disabled().trust(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_roll_over() {
+
+        let mut alice = Signing::disabled().with_seed(&SEED).unwrap();
+        let mut bob = Signing::disabled().trust(&public(&SEED)).unwrap();
+
+        let a = Announcement::verify(&alice.roll_over(Some(&[6; 32])).unwrap()).unwrap();
+        assert_eq!(a, Announcement::Rollover { old: public(&SEED), new: public(&[6; 32]) });
+        assert_eq!(alice.public_key(), Some(to_hex(&public(&[6; 32]))));
+
+        // Messages signed with the new key are verified after the rollover.
+        let signed = alice.sign(b"hello");
+        assert_eq!(bob.verify(ALICE, &signed).unwrap().1, SignatureStatus::Unverified);
+        assert_eq!(bob.apply(ALICE, &a).map_err(|e| e.message()), Ok(true));
+        assert_eq!(bob.verify(ALICE, &signed).unwrap().1, SignatureStatus::Verified);
+        // The old key is not trusted anymore.
+        assert_eq!(bob.apply(ALICE, &a).map_err(|e| e.message()), Ok(false));
+
+        let r = Announcement::verify(&alice.roll_over(None).unwrap()).unwrap();
+        assert_eq!(alice.public_key(), None);
+        assert_eq!(bob.apply(ALICE, &r).map_err(|e| e.message()), Ok(true));
+        assert_eq!(bob.verify(ALICE, &signed).unwrap().1, SignatureStatus::Unverified);
+        assert!(alice.roll_over(None).is_err());
+    }
+
+    #[test]
+    fn test_update_store() {
+
+        let old = public(&SEED);
+        let new = public(&[6; 32]);
+        let content = format!("# alice\n{}\n{}\n", to_hex(&old), to_hex(&public(&[7; 32])));
+
+        let s = update_store(&content, None, &Announcement::Rollover { old: old.clone(), new: new.clone() });
+        assert_eq!(s, format!("# alice\n# rolled over: {}\n{}\n{}\n", to_hex(&old), to_hex(&new), to_hex(&public(&[7; 32]))));
+
+        let s = update_store(&content, None, &Announcement::Revocation { old: old.clone() });
+        assert_eq!(s, format!("# alice\n# revoked: {}\n{}\n", to_hex(&old), to_hex(&public(&[7; 32]))));
+
+        // Only the key which is bound to the peer is replaced and it stays bound.
+        let content = format!("{}\n{} {}\n", to_hex(&old), ALICE, to_hex(&old));
+        let s = update_store(&content, Some(ALICE), &Announcement::Rollover { old: old.clone(), new: new.clone() });
+        assert_eq!(s, format!("{}\n# rolled over: {} {}\n{} {}\n", to_hex(&old), ALICE, to_hex(&old), ALICE, to_hex(&new)));
+        assert_eq!(parse_line(&format!("{} {}", ALICE, to_hex(&new))).unwrap(), (Some(ALICE.to_string()), new.clone()));
+        assert_eq!(parse_line(&to_hex(&new)).unwrap(), (None, new));
+    }
+}
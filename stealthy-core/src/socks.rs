@@ -0,0 +1,205 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use crate::error::StealthyError;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+const PREFIX: &str = "socks5://";
+const PROXY_FAILED: &str = "Could not connect via the proxy.";
+
+/// SOCKS5 proxy (RFC 1928) through which TCP connections are established, e.g. the SOCKS
+/// port of Tor.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Proxy {
+    host: String,
+    port: u16,
+    /// Username and password (RFC 1929). Tor uses different circuits for different
+    /// credentials.
+    auth: Option<(String, String)>,
+}
+
+impl Proxy {
+
+    /// Parses a proxy of the form socks5://[user:password@]host:port.
+    pub fn parse(url: &str) -> Result<Proxy, StealthyError> {
+
+        if !url.starts_with(PREFIX) {
+            return Err(StealthyError::Transport("Only SOCKS5 proxies are supported, e.g. socks5://127.0.0.1:9050."));
+        }
+        let s = url[PREFIX.len()..].trim_end_matches('/');
+        let (auth, hostport) = match s.rfind('@') {
+            Some(i) => {
+                let (user, pass) = s[..i].split_at(s[..i].find(':').ok_or(StealthyError::Transport("Invalid credentials of the proxy."))?);
+                (Some((user.to_string(), pass[1..].to_string())), &s[i + 1..])
+            },
+            None => (None, s)
+        };
+        let i = hostport.rfind(':').ok_or(StealthyError::Transport("The port of the proxy is missing."))?;
+        let host = hostport[..i].trim_start_matches('[').trim_end_matches(']');
+        let port = hostport[i + 1..].parse::<u16>().map_err(|_| StealthyError::Transport("Invalid port of the proxy."))?;
+        if host.is_empty() {
+            return Err(StealthyError::Transport("The host of the proxy is missing."));
+        }
+        if let Some((ref user, ref pass)) = auth {
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(StealthyError::Transport("Invalid credentials of the proxy."));
+            }
+        }
+        Ok(Proxy { host: host.to_string(), port, auth })
+    }
+
+    /// Returns the address of the proxy, e.g. for the welcome screen.
+    pub fn address(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Connects to the destination via the proxy.
+    pub fn connect(&self, dst: &SocketAddr, timeout: Duration) -> Result<TcpStream, StealthyError> {
+
+        let addr = (self.host.as_str(), self.port).to_socket_addrs()
+            .map_err(|e| StealthyError::Io("Could not resolve the proxy.", e))?
+            .next()
+            .ok_or(StealthyError::Transport("Could not resolve the proxy."))?;
+        let mut s = TcpStream::connect_timeout(&addr, timeout).map_err(|e| StealthyError::Io("Could not connect to the proxy.", e))?;
+        s.set_read_timeout(Some(timeout)).map_err(|e| StealthyError::Io(PROXY_FAILED, e))?;
+        self.handshake(&mut s, dst)?;
+        s.set_read_timeout(None).map_err(|e| StealthyError::Io(PROXY_FAILED, e))?;
+        Ok(s)
+    }
+
+    fn handshake<S: Read + Write>(&self, s: &mut S, dst: &SocketAddr) -> Result<(), StealthyError> {
+
+        let method = match self.auth {
+            Some(_) => USER_PASS,
+            None => NO_AUTH
+        };
+        write(s, &[VERSION, 1, method])?;
+        match read(s, 2)?[..] {
+            [VERSION, NO_ACCEPTABLE_METHOD] => return Err(StealthyError::Transport("The proxy requires an unsupported authentication.")),
+            [VERSION, m] if m == method => { },
+            _ => return Err(StealthyError::Transport(PROXY_FAILED))
+        }
+
+        if let Some((ref user, ref pass)) = self.auth {
+            let mut v = vec![1, user.len() as u8];
+            v.extend_from_slice(user.as_bytes());
+            v.push(pass.len() as u8);
+            v.extend_from_slice(pass.as_bytes());
+            write(s, &v)?;
+            if read(s, 2)?[1] != 0 {
+                return Err(StealthyError::Transport("The proxy rejected the credentials."));
+            }
+        }
+
+        let mut v = vec![VERSION, CONNECT, 0];
+        match dst.ip() {
+            IpAddr::V4(a) => {
+                v.push(ATYP_IPV4);
+                v.extend_from_slice(&a.octets());
+            },
+            IpAddr::V6(a) => {
+                v.push(ATYP_IPV6);
+                v.extend_from_slice(&a.octets());
+            }
+        }
+        v.extend_from_slice(&dst.port().to_be_bytes());
+        write(s, &v)?;
+
+        let reply = read(s, 4)?;
+        if reply[0] != VERSION {
+            return Err(StealthyError::Transport(PROXY_FAILED));
+        }
+        if reply[1] != 0 {
+            return Err(StealthyError::Transport("The proxy could not connect to the peer."));
+        }
+        // The address to which the proxy has bound the connection is not needed.
+        let n = match reply[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => read(s, 1)?[0] as usize,
+            _ => return Err(StealthyError::Transport(PROXY_FAILED))
+        };
+        read(s, n + 2)?;
+        Ok(())
+    }
+}
+
+fn write<S: Write>(s: &mut S, buf: &[u8]) -> Result<(), StealthyError> {
+    s.write_all(buf).map_err(|e| StealthyError::Io(PROXY_FAILED, e))
+}
+
+fn read<S: Read>(s: &mut S, n: usize) -> Result<Vec<u8>, StealthyError> {
+    let mut v = vec![0; n];
+    s.read_exact(&mut v).map_err(|e| StealthyError::Io(PROXY_FAILED, e))?;
+    Ok(v)
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{self, Read, Write};
+    use super::Proxy;
+
+    /// Replays the answers of a proxy and records what has been sent to it.
+    struct Fake {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Fake {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.input.read(buf) }
+    }
+
+    impl Write for Fake {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.output.write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_parse() {
+
+        let p = Proxy::parse("socks5://127.0.0.1:9050").unwrap();
+        assert_eq!(p.address(), "127.0.0.1:9050");
+        assert_eq!(p.auth, None);
+        let p = Proxy::parse("socks5://alice:se:cret@[::1]:1080/").unwrap();
+        assert_eq!(p.address(), "[::1]:1080");
+        assert_eq!(p.auth, Some(("alice".to_string(), "se:cret".to_string())));
+        assert!(Proxy::parse("http://127.0.0.1:8080").is_err());
+        assert!(Proxy::parse("socks5://127.0.0.1").is_err());
+        assert!(Proxy::parse("socks5://:9050").is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+
+        let dst = "1.2.3.4:4000".parse().unwrap();
+        let mut f = Fake { input: io::Cursor::new(vec![5, 0, 5, 0, 0, 1, 0, 0, 0, 0, 0, 0]), output: vec![] };
+        assert_eq!(Proxy::parse("socks5://localhost:9050").unwrap().handshake(&mut f, &dst).map_err(|e| e.message()), Ok(()));
+        assert_eq!(f.output, vec![5, 1, 0, 5, 1, 0, 1, 1, 2, 3, 4, 0x0f, 0xa0]);
+
+        let mut f = Fake { input: io::Cursor::new(vec![5, 2, 1, 0, 5, 0, 0, 3, 3, b'a', b'b', b'c', 0, 0]), output: vec![] };
+        assert_eq!(Proxy::parse("socks5://u:p@localhost:9050").unwrap().handshake(&mut f, &dst).map_err(|e| e.message()), Ok(()));
+        assert_eq!(&f.output[..8], &[5, 1, 2, 1, 1, b'u', 1, b'p']);
+
+        // The proxy cannot reach the peer.
+        let mut f = Fake { input: io::Cursor::new(vec![5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]), output: vec![] };
+        assert!(Proxy::parse("socks5://localhost:9050").unwrap().handshake(&mut f, &dst).is_err());
+        let mut f = Fake { input: io::Cursor::new(vec![5, 0xff]), output: vec![] };
+        assert!(Proxy::parse("socks5://localhost:9050").unwrap().handshake(&mut f, &dst).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Statistics of the layers (see `Layers::stats`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub retransmissions: u64,
+    /// Packets which have been dropped because their ip is not accepted.
+    pub dropped_unaccepted: u64,
+    /// Received messages which could not be decrypted or which have been modified.
+    pub decryption_failures: u64,
+    /// Received messages which have been dropped because they have been replayed.
+    pub dropped_replays: u64,
+    pub peers: BTreeMap<String, PeerStats>,
+    /// Mean of the round-trip times of the peers for which one has been measured.
+    pub mean_rtt: Option<Duration>,
+    /// Current rate of file uploads in bytes per second.
+    pub upload_rate: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Smoothed round-trip time (see `Rtt`).
+    pub rtt: Option<Duration>,
+}
+
+/// Counts the packets and bytes while the layers are running. Clones share the counters.
+#[derive(Clone)]
+pub struct Counters {
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Default for Counters {
+    fn default() -> Counters {
+        Counters::new()
+    }
+}
+
+impl Counters {
+
+    pub fn new() -> Counters {
+        Counters {
+            stats: Arc::new(Mutex::new(Stats::default())),
+        }
+    }
+
+    pub fn sent(&self, ip: &str, bytes: usize) {
+        let mut s = self.stats.lock().unwrap();
+        s.packets_sent += 1;
+        s.peers.entry(ip.to_string()).or_default().bytes_sent += bytes as u64;
+    }
+
+    pub fn received(&self, ip: &str, bytes: usize) {
+        let mut s = self.stats.lock().unwrap();
+        s.packets_received += 1;
+        s.peers.entry(ip.to_string()).or_default().bytes_received += bytes as u64;
+    }
+
+    pub fn retransmitted(&self) {
+        self.stats.lock().unwrap().retransmissions += 1;
+    }
+
+    pub fn dropped_unaccepted(&self) {
+        self.stats.lock().unwrap().dropped_unaccepted += 1;
+    }
+
+    pub fn decryption_failed(&self) {
+        self.stats.lock().unwrap().decryption_failures += 1;
+    }
+
+    /// Returns the current counters together with the given round-trip times of the peers.
+    pub fn snapshot(&self, rtts: Vec<(String, Duration)>) -> Stats {
+        let mut s = self.stats.lock().unwrap().clone();
+        s.mean_rtt = mean(&rtts.iter().map(|(_, d)| *d).collect::<Vec<_>>());
+        for (ip, rtt) in rtts {
+            s.peers.entry(ip).or_default().rtt = Some(rtt);
+        }
+        s
+    }
+}
+
+fn mean(v: &[Duration]) -> Option<Duration> {
+    match v.len() {
+        0 => None,
+        n => Some(v.iter().sum::<Duration>() / n as u32)
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::Counters;
+
+    #[test]
+    fn test_counters() {
+
+        let c = Counters::new();
+        c.sent("1.2.3.4", 100);
+        c.clone().sent("1.2.3.4", 50);
+        c.sent("5.6.7.8", 10);
+        c.received("1.2.3.4", 80);
+        c.retransmitted();
+        c.dropped_unaccepted();
+        c.decryption_failed();
+
+        let s = c.snapshot(vec![]);
+        assert_eq!((s.packets_sent, s.packets_received, s.retransmissions), (3, 1, 1));
+        assert_eq!((s.dropped_unaccepted, s.decryption_failures), (1, 1));
+        assert_eq!((s.peers["1.2.3.4"].bytes_sent, s.peers["1.2.3.4"].bytes_received), (150, 80));
+        assert_eq!(s.peers["5.6.7.8"].bytes_sent, 10);
+        assert_eq!(s.mean_rtt, None);
+
+        let rtts = vec![("1.2.3.4".to_string(), Duration::from_millis(100)), ("9.9.9.9".to_string(), Duration::from_millis(300))];
+        let s = c.snapshot(rtts);
+        assert_eq!(s.mean_rtt, Some(Duration::from_millis(200)));
+        assert_eq!(s.peers["1.2.3.4"].rtt, Some(Duration::from_millis(100)));
+        assert_eq!(s.peers["9.9.9.9"].bytes_sent, 0);
+        assert_eq!(s.peers["5.6.7.8"].rtt, None);
+    }
+}
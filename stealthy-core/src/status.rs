@@ -0,0 +1,40 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+/// Message of the layers for the user, e.g. that a session has been established or that a
+/// received message could not be decrypted. The application decides how to show it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatusMessage {
+    Info(String),
+    Error(String),
+}
+
+/// Passes the status messages of the layers to the application.
+#[derive(Clone)]
+pub struct Status {
+    tx: Sender<StatusMessage>,
+}
+
+impl Status {
+
+    pub fn new(tx: Sender<StatusMessage>) -> Status {
+        Status { tx }
+    }
+
+    /// Returns a status which discards all messages, e.g. for the layers of an application
+    /// which does not show them.
+    pub fn discard() -> Status {
+        let (tx, rx) = channel();
+        thread::spawn(move || for _ in rx { });
+        Status::new(tx)
+    }
+
+    pub fn info(&self, s: String) {
+        // The application may have stopped listening, e.g. while it exits.
+        let _ = self.tx.send(StatusMessage::Info(s));
+    }
+
+    pub fn error(&self, s: String) {
+        let _ = self.tx.send(StatusMessage::Error(s));
+    }
+}
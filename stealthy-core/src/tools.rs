@@ -6,6 +6,8 @@ use std::io::Write;
 use rand::{thread_rng, Rng};
 use std::fs::{File, OpenOptions};
 
+use crate::error::StealthyError;
+
 #[allow(dead_code)]
 pub fn log_to_file(s: String) {
     match OpenOptions::new().append(true).create(true).open("/tmp/stealthy.log") {
@@ -65,7 +67,7 @@ pub fn read_bin_file(fname: &str) -> Result<Vec<u8>, String> {
 }
 
 
-pub fn read_file(fname: &str) -> Result<String, &'static str> {
+pub fn read_file(fname: &str) -> Result<String, StealthyError> {
 
     let r = File::open(fname);
     match r {
@@ -73,10 +75,10 @@ pub fn read_file(fname: &str) -> Result<String, &'static str> {
             let mut s = String::new();
             match file.read_to_string(&mut s) {
                 Ok(_) => Ok(s),
-                _     => Err("Could not read file")
+                Err(e) => Err(StealthyError::Io("Could not read file", e))
             }
         }
-        _ => Err("Could not open file for reading.")
+        Err(e) => Err(StealthyError::Io("Could not open file for reading.", e))
     }
 }
 
@@ -108,6 +110,11 @@ pub fn random_str(n: usize) -> String {
     ).unwrap()
 }
 
+/// Returns the bytes as lowercase hexadecimal string.
+pub fn to_hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 pub fn sha1(s: &[u8]) -> String {
     let mut h = Sha1::new();
     h.input(s);
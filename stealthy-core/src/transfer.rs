@@ -0,0 +1,250 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use crate::wire::{push_slice, push_value, pop_value};
+use crate::signing::SignatureStatus;
+
+/// Marks the decrypted payload of a part of a file transfer. Older versions put the name of
+/// the file in front of the data which never starts with a zero.
+const MAGIC: [u8; 4] = [0, b'S', b'F', 2];
+/// Marks the decrypted payload of a cancellation (see `encode_cancel`).
+const CANCEL_MAGIC: [u8; 4] = [0, b'S', b'C', 1];
+/// Magic, id of the transfer, index of the part and number of parts.
+pub const HEADER_LEN: usize = 4 + 8 + 4 + 4;
+/// Length of the name and size of the file which are contained in the first part.
+const INFO_LEN: usize = 2 + 8;
+/// Time in seconds after which an incomplete transfer is discarded.
+const TRANSFER_TIMEOUT: i64 = 3600;
+
+pub const INVALID_PART: &str = "Invalid part of a file transfer.";
+
+/// Returns true if the decrypted payload is a part of a file transfer.
+pub fn is_part(buf: &[u8]) -> bool {
+    buf.starts_with(&MAGIC)
+}
+
+/// Splits a file into parts with at most `maxsiz` bytes each.
+///
+/// Older versions send a file as one message with the name in front of the data. The
+/// message is encrypted but the delivery layer splits the ciphertext into chunks whose
+/// indices, and hence the size of the file, are visible on the wire. In the new format each
+/// part is encrypted on its own and fits into one packet. The name and size of the file as
+/// well as the indices of the parts are only contained in the encrypted payload. The id of
+/// the transfer is the id of the message so that the transfer can be cancelled.
+pub fn split(id: u64, name: &str, data: &[u8], maxsiz: usize) -> Result<Vec<Vec<u8>>, &'static str> {
+
+    if name.len() > u16::max_value() as usize || maxsiz <= HEADER_LEN + INFO_LEN + name.len() {
+        return Err("The name of the file is too long.");
+    }
+
+    let mut info = vec![];
+    push_value(&mut info, name.len() as u64, 2);
+    push_slice(&mut info, name.as_bytes());
+    push_value(&mut info, data.len() as u64, 8);
+
+    // The first part contains the name and size of the file.
+    let first = cmp::min(data.len(), maxsiz - HEADER_LEN - info.len());
+    let mut chunks = vec![&data[..first]];
+    chunks.extend(data[first..].chunks(maxsiz - HEADER_LEN));
+
+    let n = chunks.len();
+    Ok(chunks.into_iter().enumerate().map(|(i, chunk)| {
+        let mut v = MAGIC.to_vec();
+        push_value(&mut v, id, 8);
+        push_value(&mut v, i as u64, 4);
+        push_value(&mut v, n as u64, 4);
+        if i == 0 {
+            push_slice(&mut v, &info);
+        }
+        push_slice(&mut v, chunk);
+        v
+    }).collect())
+}
+
+/// Returns the payload which tells the peer that the message or file transfer with the
+/// given id has been cancelled.
+pub fn encode_cancel(id: u64) -> Vec<u8> {
+    let mut v = CANCEL_MAGIC.to_vec();
+    push_value(&mut v, id, 8);
+    v
+}
+
+/// Returns the id of the cancelled message or file transfer.
+pub fn decode_cancel(buf: &[u8]) -> Result<u64, &'static str> {
+    if buf.len() != CANCEL_MAGIC.len() + 8 || buf[..CANCEL_MAGIC.len()] != CANCEL_MAGIC {
+        return Err("Invalid cancellation.");
+    }
+    pop_value(&mut buf[CANCEL_MAGIC.len()..].to_vec(), 8)
+}
+
+/// A file which is received.
+struct Incoming {
+    /// Name and size of the file which are known as soon as the first part is received.
+    info: Option<(String, u64)>,
+    n: u32,
+    parts: HashMap<u32, Vec<u8>>,
+    /// The weakest signature status of all parts.
+    signature: SignatureStatus,
+    time: i64,
+}
+
+/// Collects the parts of incoming file transfers.
+pub struct Transfers {
+    incoming: HashMap<(String, u64), Incoming>,
+}
+
+impl Transfers {
+
+    pub fn new() -> Transfers {
+        Transfers {
+            incoming: HashMap::new()
+        }
+    }
+
+    /// Adds a decrypted part received from the ip. Returns the name, the data and the
+    /// signature status of the file as soon as all parts have been received.
+    pub fn insert(&mut self, ip: &str, buf: &[u8], sig: SignatureStatus, now: i64) -> Result<Option<(String, Vec<u8>, SignatureStatus)>, &'static str> {
+
+        if !is_part(buf) || buf.len() < HEADER_LEN {
+            return Err(INVALID_PART);
+        }
+        self.incoming.retain(|_, t| now - t.time < TRANSFER_TIMEOUT);
+
+        let mut v = buf[MAGIC.len()..].to_vec();
+        let id = pop_value(&mut v, 8)?;
+        let idx = pop_value(&mut v, 4)? as u32;
+        let n = pop_value(&mut v, 4)? as u32;
+        if idx >= n {
+            return Err(INVALID_PART);
+        }
+
+        let key = (ip.to_string(), id);
+        let t = self.incoming.entry(key.clone()).or_insert(Incoming {
+            info: None,
+            n,
+            parts: HashMap::new(),
+            signature: sig,
+            time: now,
+        });
+        if t.n != n {
+            return Err(INVALID_PART);
+        }
+        if idx == 0 {
+            let len = pop_value(&mut v, 2)? as usize;
+            if v.len() < len + 8 {
+                return Err(INVALID_PART);
+            }
+            let name = String::from_utf8(v.drain(..len).collect()).map_err(|_| INVALID_PART)?;
+            if name.contains('\0') {
+                return Err(INVALID_PART);
+            }
+            t.info = Some((name, pop_value(&mut v, 8)?));
+        }
+        if sig != SignatureStatus::Verified {
+            t.signature = sig;
+        }
+        t.parts.insert(idx, v);
+
+        if t.parts.len() < n as usize {
+            return Ok(None);
+        }
+
+        let mut t = self.incoming.remove(&key).unwrap();
+        let (name, size) = t.info.take().ok_or(INVALID_PART)?;
+        let data = (0..n).flat_map(|i| t.parts[&i].iter().cloned()).collect::<Vec<u8>>();
+        if data.len() as u64 != size {
+            return Err("The size of the received file does not match.");
+        }
+        Ok(Some((name, data, t.signature)))
+    }
+
+    /// Discards the parts of a transfer of the ip which has been cancelled. Returns false if
+    /// no part has been received.
+    pub fn cancel(&mut self, ip: &str, id: u64) -> bool {
+        self.incoming.remove(&(ip.to_string(), id)).is_some()
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Transfers, split, is_part, encode_cancel, decode_cancel, HEADER_LEN, TRANSFER_TIMEOUT};
+    use crate::signing::SignatureStatus;
+
+    const NOW: i64 = 1_500_000_000;
+
+    #[test]
+    fn test_split_and_insert() {
+
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut parts = split(7, "test.bin", &data, 100).unwrap();
+        assert!(parts.len() > 10);
+        assert!(parts.iter().all(|p| p.len() <= 100 && is_part(p)));
+        // The name is not visible in the other parts.
+        assert!(!parts[1].windows(4).any(|w| w == b"test"));
+
+        // Parts can arrive in any order.
+        parts.reverse();
+        let mut t = Transfers::new();
+        let last = parts.pop().unwrap();
+        for p in &parts {
+            assert_eq!(t.insert("1.1.1.1", p, SignatureStatus::Verified, NOW), Ok(None));
+        }
+        // Parts of another peer belong to another transfer.
+        assert_eq!(t.insert("2.2.2.2", &last, SignatureStatus::Verified, NOW), Ok(None));
+        assert_eq!(t.insert("1.1.1.1", &last, SignatureStatus::Unverified, NOW),
+                   Ok(Some(("test.bin".to_string(), data, SignatureStatus::Unverified))));
+    }
+
+    #[test]
+    fn test_empty_file() {
+
+        let parts = split(7, "empty", &[], 100).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(Transfers::new().insert("1.1.1.1", &parts[0], SignatureStatus::None, NOW),
+                   Ok(Some(("empty".to_string(), vec![], SignatureStatus::None))));
+    }
+
+    #[test]
+    fn test_invalid() {
+
+        assert!(split(7, "name", &[1, 2, 3], HEADER_LEN + 10).is_err());
+
+        let mut t = Transfers::new();
+        assert!(t.insert("1.1.1.1", b"name\0data", SignatureStatus::None, NOW).is_err());
+
+        // The size of the file is checked.
+        let mut parts = split(7, "name", &[1, 2, 3], 100).unwrap();
+        parts[0].pop();
+        assert!(t.insert("1.1.1.1", &parts[0], SignatureStatus::None, NOW).is_err());
+
+        // Incomplete transfers are discarded.
+        let parts = split(7, "name", &[0; 300], 100).unwrap();
+        t.insert("1.1.1.1", &parts[0], SignatureStatus::None, NOW).unwrap();
+        for p in &parts[1..] {
+            assert_eq!(t.insert("1.1.1.1", p, SignatureStatus::None, NOW + TRANSFER_TIMEOUT), Ok(None));
+        }
+        assert!(t.incoming.values().all(|i| i.info.is_none()));
+    }
+
+    #[test]
+    fn test_cancel() {
+
+        assert_eq!(decode_cancel(&encode_cancel(42)), Ok(42));
+        assert!(decode_cancel(&encode_cancel(42)[1..]).is_err());
+        assert!(decode_cancel(&split(42, "name", &[1], 100).unwrap()[0]).is_err());
+
+        // The parts received so far are discarded.
+        let parts = split(42, "name", &[0; 300], 100).unwrap();
+        let mut t = Transfers::new();
+        t.insert("1.1.1.1", &parts[0], SignatureStatus::None, NOW).unwrap();
+        assert!(!t.cancel("2.2.2.2", 42));
+        assert!(t.cancel("1.1.1.1", 42));
+        assert!(!t.cancel("1.1.1.1", 42));
+        assert!(t.incoming.is_empty());
+    }
+}
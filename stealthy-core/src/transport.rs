@@ -0,0 +1,294 @@
+use std::net::Ipv4Addr;
+
+use crate::socks::Proxy;
+use crate::camouflage::Camouflage;
+use crate::link::CustomTransport;
+use crate::error::StealthyError;
+
+/// Type of the ICMP packets which carry the packets of stealthy. Some networks limit the
+/// rate of echo requests but not of other ICMP packets.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Carrier {
+    EchoRequest,
+    /// Echo replies are not echoed by the peer so that the maximum payload size cannot be
+    /// probed.
+    EchoReply,
+    /// Timestamp requests (IPv4 only). The payload follows the timestamps.
+    Timestamp,
+    /// Address mask requests (IPv4 only). The payload follows the address mask.
+    AddressMask,
+}
+
+impl Carrier {
+
+    /// Parses a comma separated list of carriers, e.g. "timestamp,echo".
+    pub fn parse_list(s: &str) -> Result<Vec<Carrier>, StealthyError> {
+        let v = s.split(',')
+            .map(|c| match c.trim() {
+                "echo" => Ok(Carrier::EchoRequest),
+                "echo-reply" => Ok(Carrier::EchoReply),
+                "timestamp" => Ok(Carrier::Timestamp),
+                "address-mask" => Ok(Carrier::AddressMask),
+                _ => Err(StealthyError::Transport("Unknown ICMP type. Use echo, echo-reply, timestamp or address-mask."))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(v)
+    }
+
+    /// Returns the ICMP type of IPv4.
+    pub fn icmp_type(&self) -> u8 {
+        match self {
+            Carrier::EchoRequest => 8,
+            Carrier::EchoReply => 0,
+            Carrier::Timestamp => 13,
+            Carrier::AddressMask => 17,
+        }
+    }
+
+    /// Returns the number of bytes between the ICMP header and the payload.
+    pub fn offset(&self) -> usize {
+        match self {
+            Carrier::Timestamp => 12,
+            Carrier::AddressMask => 4,
+            _ => 0
+        }
+    }
+}
+
+/// Settings of the ICMP transport.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IcmpOptions {
+    /// Echo requests of other programs are answered by stealthy. Used if the kernel does
+    /// not answer pings so that the echo requests of stealthy are not echoed.
+    pub echo_replies: bool,
+    /// Packets are sent with the first carrier and received with any of them.
+    pub carriers: Vec<Carrier>,
+    /// Time to live (hop limit for IPv6) of the packets. The default of the OS if None.
+    pub ttl: Option<u8>,
+    /// Whether the DF bit is set, i.e. whether the packets must not be fragmented. The
+    /// default of the OS if None.
+    pub dont_fragment: Option<bool>,
+    /// The echo requests look like the pings of a ping utility.
+    pub camouflage: Option<Camouflage>,
+    /// Source address of the sent packets instead of the address of the host. The peers
+    /// cannot answer, hence this is only useful for one-way broadcasts.
+    pub spoof: Option<Ipv4Addr>,
+}
+
+impl Default for IcmpOptions {
+    fn default() -> IcmpOptions {
+        IcmpOptions {
+            echo_replies: false,
+            carriers: vec![Carrier::EchoRequest],
+            ttl: None,
+            dont_fragment: None,
+            camouflage: None,
+            spoof: None,
+        }
+    }
+}
+
+impl IcmpOptions {
+
+    /// Parses a TTL between 1 and 255.
+    pub fn parse_ttl(s: &str) -> Result<u8, StealthyError> {
+        match s.trim().parse::<u8>() {
+            Ok(ttl) if ttl > 0 => Ok(ttl),
+            _ => Err(StealthyError::Transport("The TTL has to be between 1 and 255."))
+        }
+    }
+
+    /// Parses the setting of the DF bit which is "on" or "off".
+    pub fn parse_df(s: &str) -> Result<bool, StealthyError> {
+        match s.trim() {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(StealthyError::Transport("The DF bit has to be on or off."))
+        }
+    }
+}
+
+/// Transport which carries the packets of stealthy.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Transport {
+    /// Packets are sent as payload of ICMP echo requests and received on the given devices
+    /// or on all devices with "any". Requires the permission to open raw sockets. If a port
+    /// is given, packets are sent via TCP to this port for peers which do not acknowledge
+    /// the packets sent via ICMP.
+    Icmp(Vec<String>, Option<u16>, IcmpOptions),
+    /// Packets are sent as UDP datagrams to the given port on which the peers listen as
+    /// well. Can be used if ICMP is blocked or raw sockets are not permitted.
+    Udp(u16),
+    /// Packets are sent via TCP connections to the given port on which the peers listen as
+    /// well. The connections are established via the proxy if one is given.
+    Tcp(u16, Option<Proxy>),
+    /// Packets are sent and received via a transport of the application, e.g. the
+    /// `MemoryNetwork` in tests. No sockets are opened.
+    Custom(CustomTransport),
+}
+
+impl Transport {
+
+    /// Parses the transport. `devices` is a comma separated list of devices for ICMP.
+    pub fn parse(name: &str, devices: &str, port: Option<&str>, fallback: Option<&str>) -> Result<Transport, StealthyError> {
+        match (name, port, fallback) {
+            ("icmp", None, f) => Ok(Transport::Icmp(parse_devices(devices)?, f.map(parse_port).transpose()?, IcmpOptions::default())),
+            ("icmp", Some(_), _) => Err(StealthyError::Transport("--port can only be used with --transport udp or tcp.")),
+            (_, _, Some(_)) => Err(StealthyError::Transport("--tcp-fallback can only be used with --transport icmp.")),
+            ("udp", Some(p), None) => Ok(Transport::Udp(parse_port(p)?)),
+            ("tcp", Some(p), None) => Ok(Transport::Tcp(parse_port(p)?, None)),
+            ("udp", None, None) | ("tcp", None, None) => Err(StealthyError::Transport("--transport udp and tcp require --port.")),
+            _ => Err(StealthyError::Transport("Unknown transport. Use icmp, udp or tcp."))
+        }
+    }
+
+    /// Establishes the connections to the peers via the proxy. Only TCP connections can be
+    /// routed through a proxy like Tor.
+    pub fn with_proxy(self, proxy: Proxy) -> Result<Transport, StealthyError> {
+        match self {
+            Transport::Tcp(port, _) => Ok(Transport::Tcp(port, Some(proxy))),
+            Transport::Icmp(_, _, _) => Err(StealthyError::Transport("--proxy requires --transport tcp. ICMP packets cannot be sent via a proxy.")),
+            Transport::Udp(_) => Err(StealthyError::Transport("--proxy requires --transport tcp. UDP is not supported by Tor and most other proxies.")),
+            Transport::Custom(_) => Err(StealthyError::Transport("A proxy cannot be used with a custom transport."))
+        }
+    }
+
+    /// Sets the options of the ICMP transport. Other transports only accept the defaults.
+    pub fn with_icmp(self, options: IcmpOptions) -> Result<Transport, StealthyError> {
+        match self {
+            Transport::Icmp(devs, fallback, _) => Ok(Transport::Icmp(devs, fallback, options)),
+            t if options == IcmpOptions::default() => Ok(t),
+            _ => Err(StealthyError::Transport("The options of ICMP packets require --transport icmp."))
+        }
+    }
+
+    /// Returns where packets are received, e.g. "device lo".
+    pub fn description(&self) -> String {
+        match self {
+            Transport::Icmp(devs, None, _) => format!("{} {}", plural("device", devs.len()), devs.join(", ")),
+            Transport::Icmp(devs, Some(port), _) => format!("{} {}, TCP port {}", plural("device", devs.len()), devs.join(", "), port),
+            Transport::Udp(port) => format!("UDP port {}", port),
+            Transport::Tcp(port, None) => format!("TCP port {}", port),
+            Transport::Tcp(port, Some(proxy)) => format!("TCP port {} via {}", port, proxy.address()),
+            Transport::Custom(_) => "custom transport".to_string(),
+        }
+    }
+}
+
+fn plural(s: &str, n: usize) -> String {
+    match n {
+        1 => s.to_string(),
+        _ => format!("{}s", s)
+    }
+}
+
+fn parse_devices(s: &str) -> Result<Vec<String>, StealthyError> {
+    let devs = s.split(',')
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+        .collect::<Vec<_>>();
+    match devs.len() {
+        0 => Err(StealthyError::Transport("No device given.")),
+        // Packets would be received twice.
+        n if n > 1 && devs.iter().any(|d| d == "any") => Err(StealthyError::Transport("The device any cannot be combined with other devices.")),
+        _ => Ok(devs)
+    }
+}
+
+fn parse_port(s: &str) -> Result<u16, StealthyError> {
+    match s.parse::<u16>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(StealthyError::Transport("Invalid port."))
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Transport, IcmpOptions, Carrier};
+    use crate::socks::Proxy;
+
+    #[test]
+    fn test_parse() {
+
+        assert_eq!(Transport::parse("icmp", "eth0", None, None).map_err(|e| e.message()), Ok(Transport::Icmp(vec!["eth0".to_string()], None, IcmpOptions::default())));
+        assert_eq!(Transport::parse("udp", "eth0", Some("4000"), None).map_err(|e| e.message()), Ok(Transport::Udp(4000)));
+        assert_eq!(Transport::Udp(4000).description(), "UDP port 4000");
+        assert!(Transport::parse("udp", "eth0", None, None).is_err());
+        assert!(Transport::parse("udp", "eth0", Some("0"), None).is_err());
+        assert!(Transport::parse("udp", "eth0", Some("65536"), None).is_err());
+        assert!(Transport::parse("icmp", "eth0", Some("4000"), None).is_err());
+        assert!(Transport::parse("sctp", "eth0", None, None).is_err());
+    }
+
+    #[test]
+    fn test_tcp() {
+
+        assert_eq!(Transport::parse("tcp", "eth0", Some("4000"), None).map_err(|e| e.message()), Ok(Transport::Tcp(4000, None)));
+        assert_eq!(Transport::parse("icmp", "eth0", None, Some("4000")).map_err(|e| e.message()), Ok(Transport::Icmp(vec!["eth0".to_string()], Some(4000), IcmpOptions::default())));
+        assert_eq!(Transport::Icmp(vec!["lo".to_string()], Some(4000), IcmpOptions::default()).description(), "device lo, TCP port 4000");
+        assert!(Transport::parse("icmp", "eth0", None, Some("x")).is_err());
+        assert!(Transport::parse("udp", "eth0", Some("4000"), Some("4000")).is_err());
+    }
+
+    #[test]
+    fn test_proxy() {
+
+        let proxy = Proxy::parse("socks5://127.0.0.1:9050").unwrap();
+        let t = Transport::Tcp(4000, None).with_proxy(proxy.clone()).unwrap();
+        assert_eq!(t, Transport::Tcp(4000, Some(proxy.clone())));
+        assert_eq!(t.description(), "TCP port 4000 via 127.0.0.1:9050");
+        assert!(Transport::Udp(4000).with_proxy(proxy.clone()).is_err());
+        assert!(Transport::Icmp(vec!["lo".to_string()], Some(4000), IcmpOptions::default()).with_proxy(proxy).is_err());
+    }
+
+    #[test]
+    fn test_devices() {
+
+        let t = Transport::parse("icmp", "eth0, wlan0,", None, None).unwrap();
+        assert_eq!(t, Transport::Icmp(vec!["eth0".to_string(), "wlan0".to_string()], None, IcmpOptions::default()));
+        assert_eq!(t.description(), "devices eth0, wlan0");
+        assert!(Transport::parse("icmp", "any", None, None).is_ok());
+        assert!(Transport::parse("icmp", "eth0,any", None, None).is_err());
+        assert!(Transport::parse("icmp", " , ", None, None).is_err());
+    }
+
+    #[test]
+    fn test_icmp_options() {
+
+        let options = IcmpOptions { echo_replies: true, ..IcmpOptions::default() };
+        let t = Transport::parse("icmp", "eth0", None, None).unwrap().with_icmp(options.clone()).unwrap();
+        assert_eq!(t, Transport::Icmp(vec!["eth0".to_string()], None, options.clone()));
+        assert!(Transport::Udp(4000).with_icmp(options).is_err());
+        assert_eq!(Transport::Udp(4000).with_icmp(IcmpOptions::default()).map_err(|e| e.message()), Ok(Transport::Udp(4000)));
+    }
+
+    #[test]
+    fn test_carriers() {
+
+        assert_eq!(Carrier::parse_list("timestamp, echo").map_err(|e| e.message()), Ok(vec![Carrier::Timestamp, Carrier::EchoRequest]));
+        assert_eq!(Carrier::parse_list("echo-reply,address-mask").map_err(|e| e.message()), Ok(vec![Carrier::EchoReply, Carrier::AddressMask]));
+        assert!(Carrier::parse_list("echo,").is_err());
+        assert!(Carrier::parse_list("redirect").is_err());
+        assert_eq!(IcmpOptions::default().carriers, vec![Carrier::EchoRequest]);
+    }
+
+    #[test]
+    fn test_ttl_and_df() {
+
+        assert_eq!(IcmpOptions::parse_ttl("64").map_err(|e| e.message()), Ok(64));
+        assert_eq!(IcmpOptions::parse_ttl("255").map_err(|e| e.message()), Ok(255));
+        assert!(IcmpOptions::parse_ttl("0").is_err());
+        assert!(IcmpOptions::parse_ttl("256").is_err());
+        assert_eq!(IcmpOptions::parse_df("on").map_err(|e| e.message()), Ok(true));
+        assert_eq!(IcmpOptions::parse_df("off").map_err(|e| e.message()), Ok(false));
+        assert!(IcmpOptions::parse_df("yes").is_err());
+
+        let options = IcmpOptions { ttl: Some(1), ..IcmpOptions::default() };
+        assert!(Transport::Tcp(4000, None).with_icmp(options).is_err());
+    }
+}
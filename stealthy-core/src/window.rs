@@ -0,0 +1,100 @@
+/// Initial size of the window which is the former fixed limit of pending packets.
+const INITIAL_SIZE: f64 = 8.0;
+const MIN_SIZE: f64 = 1.0;
+/// Upper bound so that a fast peer does not lead to a flood of echo requests.
+const MAX_SIZE: f64 = 256.0;
+
+/// Sliding window which limits the number of packets to a peer which have not been
+/// acknowledged yet.
+///
+/// Like the congestion window of TCP the window grows by one packet for each ACK until the
+/// first packet is lost (slow start) and by one packet per window afterwards. If a packet
+/// is lost the window is halved. Packets which have been sent before the last reduction
+/// do not reduce the window again so that a burst of losses halves it only once.
+#[derive(Clone, Debug)]
+pub struct Window {
+    size: f64,
+    threshold: f64,
+    /// Time in milliseconds of the last reduction.
+    reduced: i64,
+}
+
+impl Window {
+
+    pub fn new() -> Window {
+        Window {
+            size: INITIAL_SIZE,
+            threshold: MAX_SIZE,
+            reduced: i64::min_value(),
+        }
+    }
+
+    /// Returns the number of packets which may be pending.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Grows the window as a packet has been acknowledged.
+    pub fn on_ack(&mut self) {
+        self.size += match self.size < self.threshold {
+            true => 1.0,
+            false => 1.0 / self.size
+        };
+        self.size = self.size.min(MAX_SIZE);
+    }
+
+    /// Shrinks the window as a packet sent at the given time has not been acknowledged.
+    pub fn on_loss(&mut self, sent: i64, now: i64) {
+        if sent <= self.reduced {
+            return;
+        }
+        self.threshold = (self.size / 2.0).max(MIN_SIZE);
+        self.size = self.threshold;
+        self.reduced = now;
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Window;
+
+    #[test]
+    fn test_window() {
+
+        let mut w = Window::new();
+        assert_eq!(w.size(), 8);
+        for _ in 0..8 {
+            w.on_ack();
+        }
+        assert_eq!(w.size(), 16);
+
+        // Losses of packets sent before the reduction are ignored.
+        w.on_loss(100, 200);
+        assert_eq!(w.size(), 8);
+        w.on_loss(150, 250);
+        assert_eq!(w.size(), 8);
+
+        // After a loss the window grows by about one packet per window.
+        for _ in 0..8 {
+            w.on_ack();
+        }
+        assert_eq!(w.size(), 8);
+        w.on_ack();
+        assert_eq!(w.size(), 9);
+
+        for i in 0..10 {
+            w.on_loss(300 + i, 300 + i);
+        }
+        assert_eq!(w.size(), 1);
+
+        for _ in 0..100000 {
+            w.on_ack();
+        }
+        assert_eq!(w.size(), 256);
+    }
+}
@@ -0,0 +1,157 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Once;
+
+use winapi::shared::inaddr::IN_ADDR;
+use winapi::shared::mstcpip::{RCVALL_ON, SIO_RCVALL};
+use winapi::shared::ws2def::{AF_INET, IPPROTO_ICMP, IPPROTO_IP, SOCKADDR, SOCKADDR_IN, SOL_SOCKET, SO_RCVTIMEO};
+use winapi::shared::ws2ipdef::{IP_DONTFRAGMENT, IP_TTL};
+use winapi::um::winsock2::{self, INVALID_SOCKET, SOCKET, SOCK_RAW, WSADATA};
+
+use crate::binding::{io_error, strip_ipv4_header};
+use crate::error::StealthyError;
+
+const IPV6_NOT_SUPPORTED: &str = "IPv6 is not supported on Windows.";
+
+static WSA_STARTUP: Once = Once::new();
+
+/// Raw socket which sends and receives ICMP packets on Windows. Raw sockets require
+/// administrator privileges. Only IPv4 is supported.
+pub struct RawSocket {
+	fd: SOCKET,
+	pub v6: bool,
+}
+
+impl RawSocket {
+
+	pub fn new(v6: bool) -> Result<RawSocket, StealthyError> {
+
+		if v6 {
+			return Err(StealthyError::Transport(IPV6_NOT_SUPPORTED));
+		}
+		WSA_STARTUP.call_once(|| {
+			let mut data: WSADATA = unsafe { mem::zeroed() };
+			unsafe { winsock2::WSAStartup(0x202, &mut data); }
+		});
+		let fd = unsafe { winsock2::socket(AF_INET, SOCK_RAW, IPPROTO_ICMP as i32) };
+		if fd == INVALID_SOCKET {
+			return Err(StealthyError::Io("Could not open raw socket. Stealthy has to be run as administrator.", io::Error::last_os_error()));
+		}
+		Ok(RawSocket { fd, v6 })
+	}
+
+	/// Opens a raw socket which receives the packets of the given device. On Windows the
+	/// device is the IPv4 address of the interface as a raw socket receives all packets of
+	/// an interface (SIO_RCVALL) only if it is bound to the address of the interface.
+	pub fn open(dev: &str, v6: bool) -> Result<RawSocket, StealthyError> {
+
+		let sock = RawSocket::new(v6)?;
+		let ip: Ipv4Addr = dev.parse().map_err(|_| StealthyError::Transport("On Windows the device has to be the IPv4 address of an interface."))?;
+		let addr = sockaddr(&ip);
+		let r = unsafe {
+			winsock2::bind(sock.fd, &addr as *const SOCKADDR_IN as *const SOCKADDR, mem::size_of::<SOCKADDR_IN>() as i32)
+		};
+		if r != 0 {
+			return Err(StealthyError::Io("Could not bind raw socket to the device.", io::Error::last_os_error()));
+		}
+		let on: u32 = RCVALL_ON;
+		let mut n: u32 = 0;
+		let r = unsafe {
+			winsock2::WSAIoctl(sock.fd, SIO_RCVALL, &on as *const u32 as *mut _, mem::size_of::<u32>() as u32,
+				ptr::null_mut(), 0, &mut n, ptr::null_mut(), None)
+		};
+		if r != 0 {
+			return Err(StealthyError::Io("Could not receive the packets of the device.", io::Error::last_os_error()));
+		}
+		Ok(sock)
+	}
+
+	/// Sets the TTL and the DF bit of the sent packets. Options which are None keep the
+	/// default of Windows.
+	pub fn set_header_options(&self, ttl: Option<u8>, dont_fragment: Option<bool>) -> Result<(), StealthyError> {
+
+		let options = ttl.map(|ttl| (IP_TTL, ttl as u32)).into_iter()
+			.chain(dont_fragment.map(|df| (IP_DONTFRAGMENT, df as u32)));
+		for (name, value) in options {
+			let r = unsafe {
+				winsock2::setsockopt(self.fd, IPPROTO_IP as i32, name, &value as *const u32 as *const i8, mem::size_of::<u32>() as i32)
+			};
+			if r != 0 {
+				return Err(StealthyError::Io("Could not set the TTL or the DF bit.", io::Error::last_os_error()));
+			}
+		}
+		Ok(())
+	}
+
+	/// Lets `recv` return an error if no packet has been received within the given number
+	/// of milliseconds.
+	pub fn set_recv_timeout(&self, millis: u64) -> Result<(), StealthyError> {
+
+		let value = millis as u32;
+		let r = unsafe {
+			winsock2::setsockopt(self.fd, SOL_SOCKET, SO_RCVTIMEO, &value as *const u32 as *const i8, mem::size_of::<u32>() as i32)
+		};
+		match r != 0 {
+			true => Err(StealthyError::Io("Could not set the receive timeout.", io::Error::last_os_error())),
+			false => Ok(())
+		}
+	}
+
+	/// Sends the ICMP packet to the given address.
+	pub fn send(&self, ip: &IpAddr, packet: &[u8]) -> Result<(), StealthyError> {
+
+		let addr = match ip {
+			IpAddr::V4(a) => sockaddr(a),
+			IpAddr::V6(_) => return Err(StealthyError::Transport(IPV6_NOT_SUPPORTED))
+		};
+		let r = unsafe {
+			winsock2::sendto(self.fd, packet.as_ptr() as *const i8, packet.len() as i32, 0,
+				&addr as *const SOCKADDR_IN as *const SOCKADDR, mem::size_of::<SOCKADDR_IN>() as i32)
+		};
+		if r < 0 {
+			let e = io::Error::last_os_error();
+			return Err(match e.kind() {
+				io::ErrorKind::PermissionDenied => StealthyError::Io("No permissions to send data. Stealthy has to be run as administrator.", e),
+				_ => io_error(e, "Could not send packet.")
+			});
+		}
+		Ok(())
+	}
+
+	/// Waits for the next packet and returns its ICMP part and the source address. The
+	/// socket receives all IP packets of the interface, hence other protocols are rejected.
+	pub fn recv<'a>(&self, buf: &'a mut [u8]) -> Result<(&'a [u8], String), StealthyError> {
+
+		let n = unsafe { winsock2::recv(self.fd, buf.as_mut_ptr() as *mut i8, buf.len() as i32, 0) };
+		if n < 0 {
+			return Err(StealthyError::Io("Could not receive packet.", io::Error::last_os_error()));
+		}
+		let buf = &buf[..n as usize];
+		if buf.len() < 20 || buf[9] != IPPROTO_ICMP as u8 {
+			return Err(StealthyError::Transport("Received packet which is not an ICMP packet."));
+		}
+		let ip = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]).to_string();
+		strip_ipv4_header(buf).map(|icmp| (icmp, ip)).ok_or(StealthyError::Transport("Received invalid IP packet."))
+	}
+}
+
+impl Drop for RawSocket {
+
+	fn drop(&mut self) {
+		unsafe { winsock2::closesocket(self.fd); }
+	}
+}
+
+fn sockaddr(ip: &Ipv4Addr) -> SOCKADDR_IN {
+
+	let mut s: SOCKADDR_IN = unsafe { mem::zeroed() };
+	s.sin_family = AF_INET as u16;
+	s.sin_addr = unsafe {
+		let mut a: IN_ADDR = mem::zeroed();
+		*a.S_un.S_addr_mut() = u32::from(*ip).to_be();
+		a
+	};
+	s
+}
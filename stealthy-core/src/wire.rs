@@ -0,0 +1,219 @@
+/// Length of the header of a packet: version (1 byte), type (1 byte) and id (8 bytes).
+pub const PACKET_HEADER_LEN: usize = 10;
+/// Length of the header of a part of a message: version (1 byte), id of the message
+/// (8 bytes), number of parts (4 bytes) and sequence number of the part (4 bytes).
+pub const FRAGMENT_HEADER_LEN: usize = 17;
+/// Version of the header of a part of a message.
+pub const FRAGMENT_VERSION: u8 = 1;
+
+/// Header of a packet (see `Packet`). All values are little endian.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PacketHeader {
+    pub version: u8,
+    pub typ: u8,
+    pub id: u64,
+}
+
+impl PacketHeader {
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut v = vec![self.version, self.typ];
+        push_value(&mut v, self.id, 8);
+        v
+    }
+
+    /// Returns the header and the rest of the buffer or an error if the buffer is too short.
+    pub fn decode(buf: &[u8]) -> Result<(PacketHeader, &[u8]), &'static str> {
+
+        if buf.len() < PACKET_HEADER_LEN {
+            return Err("Packet is too short.");
+        }
+        let h = PacketHeader {
+            version: buf[0],
+            typ: buf[1],
+            id: read_value(&buf[2..PACKET_HEADER_LEN]),
+        };
+        Ok((h, &buf[PACKET_HEADER_LEN..]))
+    }
+}
+
+/// Header of a part of a message which has been split into several packets (see
+/// `Delivery`). All values are little endian.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FragmentHeader {
+    pub id: u64,
+    /// Number of parts of the message.
+    pub n: u32,
+    /// Sequence number of the part starting with 1.
+    pub seq: u32,
+}
+
+impl FragmentHeader {
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut v = vec![FRAGMENT_VERSION];
+        push_value(&mut v, self.id, 8);
+        push_value(&mut v, self.n as u64, 4);
+        push_value(&mut v, self.seq as u64, 4);
+        v
+    }
+
+    /// Returns the header and the rest of the buffer. Fails if the buffer is too short or
+    /// if the header has an unknown version.
+    pub fn decode(buf: &[u8]) -> Result<(FragmentHeader, &[u8]), &'static str> {
+
+        if buf.len() < FRAGMENT_HEADER_LEN {
+            return Err("Part of message is too short.");
+        }
+        if buf[0] != FRAGMENT_VERSION {
+            return Err("Part of message has an unknown version.");
+        }
+        let h = FragmentHeader {
+            id: read_value(&buf[1..9]),
+            n: read_value(&buf[9..13]) as u32,
+            seq: read_value(&buf[13..FRAGMENT_HEADER_LEN]) as u32,
+        };
+        Ok((h, &buf[FRAGMENT_HEADER_LEN..]))
+    }
+}
+
+/// Returns the CRC-32 (IEEE 802.3) of the buffer.
+pub fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in buf {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+/// Reads a little endian value of up to eight bytes.
+fn read_value(buf: &[u8]) -> u64 {
+    buf.iter().rev().fold(0, |r, b| (r << 8) + *b as u64)
+}
+
+pub fn push_slice(v: &mut Vec<u8>, arr: &[u8]) {
+    v.extend_from_slice(arr);
+}
+
+/// Appends the lowest n bytes of the value in little endian.
+pub fn push_value(dst: &mut Vec<u8>, val: u64, n: usize) {
+    let mut v = val;
+    for _ in 0..n {
+        dst.push(v as u8);
+        v = v >> 8;
+    }
+}
+
+/// Removes a little endian value of n bytes from the front of the vector.
+pub fn pop_value(src: &mut Vec<u8>, n: usize) -> Result<u64, &'static str> {
+    if src.len() < n {
+        return Err("Could not pop value from vector because vector is too short.");
+    }
+    Ok(read_value(&src.drain(..n).collect::<Vec<_>>()))
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{FragmentHeader, PacketHeader, crc32, push_slice, push_value, pop_value, FRAGMENT_HEADER_LEN, PACKET_HEADER_LEN};
+
+    #[test]
+    fn test_packet_header() {
+
+        for &(version, typ, id) in &[(1, 16, 0), (2, 17, 0x1122334455667788), (255, 255, u64::max_value())] {
+            let h = PacketHeader { version, typ, id };
+            let mut v = h.encode();
+            assert_eq!(v.len(), PACKET_HEADER_LEN);
+            v.extend_from_slice(b"data");
+            let (d, rest) = PacketHeader::decode(&v).unwrap();
+            assert_eq!((d, rest), (h, &b"data"[..]));
+        }
+        assert_eq!(PacketHeader { version: 1, typ: 16, id: 0x0102 }.encode(), vec![1, 16, 2, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_fragment_header() {
+
+        for &(id, n, seq) in &[(0, 0, 0), (7, 3, 2), (u64::max_value(), u32::max_value(), u32::max_value())] {
+            let h = FragmentHeader { id, n, seq };
+            let v = h.encode();
+            assert_eq!(v.len(), FRAGMENT_HEADER_LEN);
+            assert_eq!(FragmentHeader::decode(&v).unwrap(), (h, &[][..]));
+        }
+        let mut v = FragmentHeader { id: 1, n: 1, seq: 1 }.encode();
+        v[0] = 2;
+        assert!(FragmentHeader::decode(&v).is_err());
+    }
+
+    #[test]
+    fn test_malformed() {
+
+        // Truncated or garbled headers are rejected without a panic.
+        let p = PacketHeader { version: 1, typ: 16, id: 42 }.encode();
+        let f = FragmentHeader { id: 42, n: 2, seq: 1 }.encode();
+        for i in 0..p.len() {
+            assert!(PacketHeader::decode(&p[..i]).is_err());
+        }
+        for i in 0..f.len() {
+            assert!(FragmentHeader::decode(&f[..i]).is_err());
+        }
+        for b in 0..=255u8 {
+            let v = vec![b; 20];
+            let _ = PacketHeader::decode(&v);
+            let _ = FragmentHeader::decode(&v);
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+    }
+
+    #[test]
+    fn test_push_slice() {
+        let mut v: Vec<u8> = Vec::new();
+        push_slice(&mut v, &[1, 2, 3]);
+        assert_eq!(v.len(), 3);
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_push_value() {
+        let mut v: Vec<u8> = Vec::new();
+
+        push_value(&mut v, 123, 2);
+        assert_eq!(v, vec![123, 0]);
+
+        v.clear();
+        push_value(&mut v, 23 * 256 + 78, 2);
+        assert_eq!(v, vec![78, 23]);
+    }
+
+    #[test]
+    fn test_pop_value() {
+        let mut v: Vec<u8> = vec![1, 2, 3];
+
+        let mut i = pop_value(&mut v, 4);
+        assert!(i.is_err());
+
+        v.clear();
+        push_value(&mut v, 17 * 256 + 19, 2);
+        push_value(&mut v, 34, 1);
+        i = pop_value(&mut v, 2);
+        assert_eq!(i.unwrap(), 17 * 256 + 19);
+        assert_eq!(v.len(), 1);
+
+        i = pop_value(&mut v, 1);
+        assert_eq!(i.unwrap(), 34);
+        assert_eq!(v.len(), 0);
+    }
+}
@@ -0,0 +1,59 @@
+/* Sends a message to a peer and prints the received messages.
+ *
+ * cargo build -p stealthy-ffi
+ * gcc stealthy-ffi/examples/client.c -Istealthy-ffi/include -Ltarget/debug -lstealthy_ffi -lcrypto -o client
+ * LD_LIBRARY_PATH=target/debug ./client 4000 10.0.0.2 hello
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "stealthy.h"
+
+#define KEY "11111111111111111111111111111111"
+
+int main(int argc, char **argv) {
+
+    if (argc != 4) {
+        fprintf(stderr, "usage: %s <udp port> <peer ip> <message>\n", argv[0]);
+        return 1;
+    }
+
+    stealthy_t *s = stealthy_init(KEY, "udp", argv[1], argv[2]);
+    if (!s) {
+        fprintf(stderr, "could not initialize stealthy: %s\n", stealthy_last_error());
+        return 1;
+    }
+
+    uint64_t id = stealthy_send(s, argv[2], (const uint8_t *) argv[3], strlen(argv[3]));
+    if (!id) {
+        fprintf(stderr, "could not send message: %s\n", stealthy_last_error());
+    }
+
+    stealthy_event_t e;
+    for (;;) {
+        int r = stealthy_poll(s, &e, 1000);
+        if (r < 0) {
+            break;
+        }
+        if (r == 0) {
+            continue;
+        }
+        switch (e.kind) {
+        case STEALTHY_MESSAGE:
+            printf("%s: %.*s\n", e.ip, (int) e.len, (const char *) e.data);
+            break;
+        case STEALTHY_ACK:
+            printf("message %llu acknowledged\n", (unsigned long long) e.id);
+            break;
+        case STEALTHY_FAILED:
+            printf("message %llu failed: %.*s\n", (unsigned long long) e.id, (int) e.len, (const char *) e.data);
+            break;
+        case STEALTHY_ERROR:
+            printf("error: %.*s\n", (int) e.len, (const char *) e.data);
+            break;
+        }
+    }
+
+    stealthy_shutdown(s);
+    return 0;
+}
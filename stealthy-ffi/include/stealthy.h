@@ -0,0 +1,64 @@
+#ifndef STEALTHY_H
+#define STEALTHY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Layers of stealthy. Created with stealthy_init and freed with stealthy_shutdown. */
+typedef struct Stealthy stealthy_t;
+
+/* A message of a peer has been received. */
+#define STEALTHY_MESSAGE 1
+/* The peer has acknowledged the message with the id of the event. */
+#define STEALTHY_ACK     2
+/* The message with the id of the event has been given up. The data is the reason. */
+#define STEALTHY_FAILED  3
+/* The layers have reported an error. The data is its description. */
+#define STEALTHY_ERROR   4
+/* An event which cannot be passed to C yet, e.g. a received file. ip is the peer if the
+ * event has one. */
+#define STEALTHY_UNSUPPORTED 5
+
+/* Event filled by stealthy_poll. ip and data are valid until the next call of
+ * stealthy_poll with the same layers. ip is empty for events without a peer. */
+typedef struct {
+    int kind;
+    uint64_t id;
+    const char *ip;
+    const uint8_t *data;
+    size_t len;
+} stealthy_event_t;
+
+/* Opens the network and starts the handshakes with the peers.
+ *
+ * key       - hexadecimal key for symmetric encryption
+ * transport - "icmp", "udp" or "tcp"
+ * port      - port of UDP and TCP, NULL for ICMP
+ * accept    - comma separated ips of the peers whose packets are accepted
+ *
+ * Returns NULL on error, see stealthy_last_error. */
+stealthy_t *stealthy_init(const char *key, const char *transport, const char *port, const char *accept);
+
+/* Sends the data to the ip in background. Returns the id of the message which is
+ * reported when the message is acknowledged or given up, or 0 on error. */
+uint64_t stealthy_send(stealthy_t *s, const char *ip, const uint8_t *data, size_t len);
+
+/* Waits up to timeout_ms milliseconds for the next event. Returns 1 if the event has
+ * been filled, 0 on timeout and -1 on error or if the layers have been shut down. */
+int stealthy_poll(stealthy_t *s, stealthy_event_t *event, uint32_t timeout_ms);
+
+/* Stops the layers and frees them. NULL is ignored. */
+void stealthy_shutdown(stealthy_t *s);
+
+/* Returns the last error of the calling thread or NULL. */
+const char *stealthy_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use libc::{c_char, c_int};
+
+use stealthy_core::builder::LayersBuilder;
+use stealthy_core::iptools::IpAddresses;
+use stealthy_core::layer::Layer;
+use stealthy_core::message::{IncomingMessage, Message};
+use stealthy_core::transport::Transport;
+
+/// A message of a peer has been received.
+pub const STEALTHY_MESSAGE: c_int = 1;
+/// The peer has acknowledged the message with the id of the event.
+pub const STEALTHY_ACK: c_int = 2;
+/// The message with the id of the event has been given up. The data is the reason.
+pub const STEALTHY_FAILED: c_int = 3;
+/// The layers have reported an error. The data is its description.
+pub const STEALTHY_ERROR: c_int = 4;
+/// An event which cannot be passed to C yet, e.g. a received file. The ip is the peer if
+/// the event has one.
+pub const STEALTHY_UNSUPPORTED: c_int = 5;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(s: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(s).ok());
+}
+
+/// Calls `f` and returns `err` if it panics, as a panic must not unwind into C.
+fn guard<T>(err: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|p| {
+        let reason = p.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| p.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        set_error(format!("Internal error: {}", reason));
+        err
+    })
+}
+
+/// Layers of stealthy which are used from C. The ip and the data of the event returned by
+/// `stealthy_poll` are kept until the next call.
+pub struct Stealthy {
+    layer: Layer,
+    ip: CString,
+    data: Vec<u8>,
+}
+
+impl Stealthy {
+    fn into_raw(layer: Layer) -> *mut Stealthy {
+        Box::into_raw(Box::new(Stealthy { layer, ip: CString::default(), data: vec![] }))
+    }
+}
+
+/// Event which is filled by `stealthy_poll`. See stealthy.h.
+#[repr(C)]
+pub struct StealthyEvent {
+    pub kind: c_int,
+    pub id: u64,
+    pub ip: *const c_char,
+    pub data: *const u8,
+    pub len: usize,
+}
+
+/// # Safety
+///
+/// `s` must be NULL or point to a NUL-terminated string.
+unsafe fn string(s: *const c_char) -> Option<String> {
+    match s.is_null() {
+        true => None,
+        false => CStr::from_ptr(s).to_str().ok().map(|s| s.to_string())
+    }
+}
+
+/// Opens the network and starts the handshakes with the peers. Returns NULL on error.
+///
+/// # Safety
+///
+/// Each argument must be NULL or point to a NUL-terminated string. The returned layers
+/// must be freed with `stealthy_shutdown`.
+#[no_mangle]
+pub unsafe extern "C" fn stealthy_init(key: *const c_char, transport: *const c_char, port: *const c_char, accept: *const c_char) -> *mut Stealthy {
+    guard(ptr::null_mut(), || init(key, transport, port, accept))
+}
+
+unsafe fn init(key: *const c_char, transport: *const c_char, port: *const c_char, accept: *const c_char) -> *mut Stealthy {
+
+    let (key, name, accept) = match (string(key), string(transport), string(accept)) {
+        (Some(k), Some(t), Some(a)) => (k, t, a),
+        _ => {
+            set_error("The key, the transport and the accepted ips are required.".to_string());
+            return ptr::null_mut();
+        }
+    };
+    let port = string(port);
+    let t = match Transport::parse(&name, "any", port.as_deref(), None) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
+    let r = LayersBuilder::new()
+        .with_key(&key)
+        .with_transport(t)
+        .with_accept(IpAddresses::from_comma_list(&accept))
+        .build();
+    match r {
+        Ok(layer) => Stealthy::into_raw(layer),
+        Err(e) => {
+            set_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Sends the data to the ip in background. Returns the id of the message which is reported
+/// when the message is acknowledged or given up, or 0 on error.
+///
+/// # Safety
+///
+/// `s` must be NULL or returned by `stealthy_init` and not yet shut down. `ip` must be NULL
+/// or point to a NUL-terminated string. `data` must point to `len` readable bytes unless
+/// `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn stealthy_send(s: *mut Stealthy, ip: *const c_char, data: *const u8, len: usize) -> u64 {
+    guard(0, || send(s, ip, data, len))
+}
+
+unsafe fn send(s: *mut Stealthy, ip: *const c_char, data: *const u8, len: usize) -> u64 {
+
+    let (s, ip) = match (s.as_ref(), string(ip)) {
+        (Some(s), Some(ip)) if !data.is_null() || len == 0 => (s, ip),
+        _ => {
+            set_error("Invalid arguments.".to_string());
+            return 0;
+        }
+    };
+    let buf = match len {
+        0 => vec![],
+        _ => slice::from_raw_parts(data, len).to_vec()
+    };
+    let id = loop {
+        match rand::random::<u64>() {
+            0 => continue,
+            id => break id
+        }
+    };
+    s.layer.layers.send(Message::new(ip, buf), id, true);
+    id
+}
+
+/// Waits up to `timeout_ms` milliseconds for the next event. Returns 1 if the event has
+/// been filled, 0 on timeout and -1 on error or if the layers have been shut down.
+///
+/// # Safety
+///
+/// `s` must be NULL or returned by `stealthy_init` and not yet shut down. `event` must be
+/// NULL or point to a writable `StealthyEvent`. The layers must not be polled by several
+/// threads at the same time.
+#[no_mangle]
+pub unsafe extern "C" fn stealthy_poll(s: *mut Stealthy, event: *mut StealthyEvent, timeout_ms: u32) -> c_int {
+    guard(-1, || poll(s, event, timeout_ms))
+}
+
+unsafe fn poll(s: *mut Stealthy, event: *mut StealthyEvent, timeout_ms: u32) -> c_int {
+
+    let (s, event) = match (s.as_mut(), event.as_mut()) {
+        (Some(s), Some(e)) => (s, e),
+        _ => {
+            set_error("Invalid arguments.".to_string());
+            return -1;
+        }
+    };
+    let (kind, id, ip, data) = match s.layer.rx.recv_timeout(Duration::from_millis(timeout_ms as u64)) {
+        Ok(IncomingMessage::New(m)) => (STEALTHY_MESSAGE, 0, m.get_ip(), m.get_payload()),
+        Ok(IncomingMessage::Ack(id)) => (STEALTHY_ACK, id, String::new(), vec![]),
+        Ok(IncomingMessage::SendFailed(id, reason)) => (STEALTHY_FAILED, id, String::new(), reason.into_bytes()),
+        Ok(IncomingMessage::Error(e)) => (STEALTHY_ERROR, 0, String::new(), e.to_string().into_bytes()),
+        Ok(m) => (STEALTHY_UNSUPPORTED, 0, m.message().map(|m| m.get_ip()).unwrap_or_default(), vec![]),
+        Err(RecvTimeoutError::Timeout) => return 0,
+        Err(RecvTimeoutError::Disconnected) => {
+            set_error("The layers have been shut down.".to_string());
+            return -1;
+        }
+    };
+    s.ip = CString::new(ip).unwrap_or_default();
+    s.data = data;
+    event.kind = kind;
+    event.id = id;
+    event.ip = s.ip.as_ptr();
+    event.data = s.data.as_ptr();
+    event.len = s.data.len();
+    1
+}
+
+/// Stops the layers and frees them. NULL is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or returned by `stealthy_init` and not yet shut down. It must not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn stealthy_shutdown(s: *mut Stealthy) {
+    if !s.is_null() {
+        guard((), || Box::from_raw(s).layer.shutdown());
+    }
+}
+
+/// Returns the last error of the calling thread or NULL. The string is valid until the next
+/// error of the thread.
+#[no_mangle]
+pub extern "C" fn stealthy_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    use stealthy_core::builder::LayersBuilder;
+    use stealthy_core::iptools::IpAddresses;
+    use stealthy_core::memory::MemoryNetwork;
+
+    use super::*;
+
+    const KEY: &str = "11111111111111111111111111111111";
+
+    fn event() -> StealthyEvent {
+        StealthyEvent { kind: 0, id: 0, ip: ptr::null(), data: ptr::null(), len: 0 }
+    }
+
+    /// Polls until an event of the given kind arrives and skips the unsupported events.
+    unsafe fn poll_kind(s: *mut Stealthy, kind: c_int) -> StealthyEvent {
+        let mut e = event();
+        for _ in 0..300 {
+            if stealthy_poll(s, &mut e, 100) == 1 && e.kind == kind {
+                return e;
+            }
+        }
+        panic!("No event of kind {} received.", kind);
+    }
+
+    #[test]
+    fn test_round_trip() {
+
+        let net = MemoryNetwork::new();
+        let layer = |ip: &str, peer: &str| Stealthy::into_raw(LayersBuilder::new()
+            .with_key(KEY)
+            .with_transport(net.host(ip))
+            .with_accept(IpAddresses::from_comma_list(peer))
+            .build()
+            .unwrap());
+        let a = layer("10.0.0.1", "10.0.0.2");
+        let b = layer("10.0.0.2", "10.0.0.1");
+
+        unsafe {
+            let ip = CString::new("10.0.0.2").unwrap();
+            let id = stealthy_send(a, ip.as_ptr(), b"hello".as_ptr(), 5);
+            assert_ne!(id, 0);
+
+            let e = poll_kind(b, STEALTHY_MESSAGE);
+            assert_eq!(CStr::from_ptr(e.ip).to_str(), Ok("10.0.0.1"));
+            assert_eq!(std::slice::from_raw_parts(e.data, e.len), b"hello");
+            assert_eq!(poll_kind(a, STEALTHY_ACK).id, id);
+
+            // Invalid arguments are reported instead of dereferenced.
+            assert_eq!(stealthy_send(a, ptr::null(), ptr::null(), 0), 0);
+            assert_eq!(stealthy_poll(ptr::null_mut(), &mut event(), 0), -1);
+            assert_eq!(CStr::from_ptr(stealthy_last_error()).to_str(), Ok("Invalid arguments."));
+
+            stealthy_shutdown(a);
+            stealthy_shutdown(b);
+        }
+    }
+
+    #[test]
+    fn test_guard() {
+        assert_eq!(guard(-1, || panic!("boom")), -1);
+        let e = unsafe { CStr::from_ptr(stealthy_last_error()) };
+        assert_eq!(e.to_str(), Ok("Internal error: boom"));
+        assert_eq!(guard(-1, || 1), 1);
+    }
+}
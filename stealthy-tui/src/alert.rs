@@ -0,0 +1,147 @@
+use std::io::{stdout, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Events for which the user can be alerted.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AlertEvent {
+    Message,
+    FileUpload,
+    Error,
+}
+
+/// What is done to alert the user.
+#[derive(Clone, PartialEq, Debug)]
+pub enum AlertAction {
+    /// Writes the terminal BEL character.
+    Bell,
+    /// Runs the given command in background.
+    Sound(String),
+}
+
+#[derive(Clone)]
+pub struct Alerts {
+    /// Events for which the BEL character is written.
+    pub bell: Vec<AlertEvent>,
+    pub message_cmd: Option<String>,
+    pub upload_cmd: Option<String>,
+    pub error_cmd: Option<String>,
+}
+
+impl Alerts {
+
+    /// Returns the actions which are executed for the given event.
+    pub fn actions(&self, event: AlertEvent) -> Vec<AlertAction> {
+
+        let mut v = vec![];
+        if self.bell.contains(&event) {
+            v.push(AlertAction::Bell);
+        }
+        let cmd = match event {
+            AlertEvent::Message => &self.message_cmd,
+            AlertEvent::FileUpload => &self.upload_cmd,
+            AlertEvent::Error => &self.error_cmd,
+        };
+        if let Some(c) = cmd {
+            v.push(AlertAction::Sound(c.clone()));
+        }
+        v
+    }
+
+    /// Alerts the user about the given event.
+    pub fn fire(&self, event: AlertEvent) {
+        for a in self.actions(event) {
+            match a {
+                AlertAction::Bell => bell(),
+                AlertAction::Sound(cmd) => run(cmd),
+            }
+        }
+    }
+}
+
+/// Parses a comma separated list of events, e.g. "message,upload,error".
+pub fn parse_events(s: &str) -> Result<Vec<AlertEvent>, String> {
+    s.split(",")
+        .map(|x| x.trim())
+        .filter(|x| x.len() > 0)
+        .map(|x| match x {
+            "message" => Ok(AlertEvent::Message),
+            "upload" => Ok(AlertEvent::FileUpload),
+            "error" => Ok(AlertEvent::Error),
+            _ => Err(format!("Unknown event '{}'. Valid events are: message, upload, error.", x))
+        })
+        .collect()
+}
+
+fn bell() {
+    let mut o = stdout();
+    // Errors are ignored as an alert is not important enough to disturb the user.
+    let _ = o.write_all(b"\x07");
+    let _ = o.flush();
+}
+
+fn run(cmd: String) {
+    // The command is executed in background so that a slow command does not block the
+    // receiver. Failures are ignored quietly.
+    thread::spawn(move || {
+        let parts = cmd.split_whitespace().collect::<Vec<_>>();
+        if parts.is_empty() {
+            return;
+        }
+        let child = Command::new(parts[0])
+            .args(&parts[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut c) = child {
+            let _ = c.wait();
+        }
+    });
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Alerts, AlertAction, AlertEvent, parse_events};
+
+    #[test]
+    fn test_actions() {
+
+        let a = Alerts {
+            bell: vec![AlertEvent::Message, AlertEvent::Error],
+            message_cmd: Some(String::from("paplay message.wav")),
+            upload_cmd: None,
+            error_cmd: Some(String::from("paplay error.wav")),
+        };
+
+        assert_eq!(a.actions(AlertEvent::Message),
+                   vec![AlertAction::Bell, AlertAction::Sound(String::from("paplay message.wav"))]);
+        assert_eq!(a.actions(AlertEvent::FileUpload), vec![]);
+        assert_eq!(a.actions(AlertEvent::Error),
+                   vec![AlertAction::Bell, AlertAction::Sound(String::from("paplay error.wav"))]);
+    }
+
+    #[test]
+    fn test_no_actions() {
+
+        let a = Alerts { bell: vec![], message_cmd: None, upload_cmd: None, error_cmd: None };
+        assert!(a.actions(AlertEvent::Message).is_empty());
+        assert!(a.actions(AlertEvent::FileUpload).is_empty());
+        assert!(a.actions(AlertEvent::Error).is_empty());
+    }
+
+    #[test]
+    fn test_parse_events() {
+
+        assert_eq!(parse_events("message").unwrap(), vec![AlertEvent::Message]);
+        assert_eq!(parse_events("message, upload,error").unwrap(),
+                   vec![AlertEvent::Message, AlertEvent::FileUpload, AlertEvent::Error]);
+        assert!(parse_events("").unwrap().is_empty());
+        assert!(parse_events("message,foo").is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::env;
+use getopts::Options;
+
+use crate::alert::{Alerts, AlertEvent, parse_events};
+use stealthy_core::kdf::{self, KdfParams};
+use stealthy_core::keygen::{self, KeyType};
+use stealthy_core::keystore::Keystore;
+use stealthy_core::blowfish;
+use stealthy_core::gpg;
+use stealthy_core::iptools;
+use stealthy_core::pkcs11;
+use stealthy_core::padding::Padding;
+use stealthy_core::peerkeys;
+use stealthy_core::cover::CoverTraffic;
+use stealthy_core::rate;
+use stealthy_core::rsatools;
+use stealthy_core::sshkey;
+use stealthy_core::tools::{self, read_file, to_hex};
+use stealthy_core::socks::Proxy;
+use stealthy_core::transport::{Transport, IcmpOptions, Carrier};
+use stealthy_core::camouflage::Camouflage;
+use stealthy_core::error::StealthyError;
+use crate::broadcast::BroadcastLists;
+
+pub struct Arguments {
+    pub transport: Transport,
+    /// True if the device has not been given and the device of the default route is used.
+    pub device_detected: bool,
+    pub dstip: String,
+    pub hybrid_mode: bool,
+    pub secret_key: String,
+    pub cipher: String,
+    /// Keys of the peers in the format of the file given with --peer-keys.
+    pub peer_keys: Option<String>,
+    pub rcpt_pubkey_file: String,
+    pub privkey_file: String,
+    pub pubkey_file: String,
+    /// PIN of the PKCS#11 token if the private key is stored on a token.
+    pub token_pin: Option<String>,
+    pub alerts: Alerts,
+    pub replay_window: Option<u64>,
+    /// File with the seed of the Ed25519 key which is used to sign messages.
+    pub sign_key_file: Option<String>,
+    /// Passphrase if the signing key is an encrypted key of OpenSSH.
+    pub sign_key_passphrase: Option<String>,
+    /// File with the Ed25519 public keys of the peers.
+    pub verify_keys_file: Option<String>,
+    /// Parameters of the KDF if the key has been derived from a passphrase.
+    pub kdf_params: Option<String>,
+    /// Lifetime of a session in minutes.
+    pub rekey_interval: Option<u64>,
+    /// Number of messages after which a session is replaced.
+    pub rekey_messages: Option<u64>,
+    /// Maximum delay of outgoing packets in milliseconds.
+    pub jitter: Option<u64>,
+    /// Number of retransmissions after which a packet is given up.
+    pub max_retries: Option<u64>,
+    /// Maximum rate of outgoing packets in bytes per second.
+    pub max_rate: Option<u64>,
+    /// Time in milliseconds a received message waits for the messages sent before it.
+    pub reorder_timeout: Option<u64>,
+    /// File in which the pending packets are stored.
+    pub outbox_file: Option<String>,
+    /// Passphrase of the outbox.
+    pub outbox_passphrase: Option<String>,
+    /// Expected hashes of the public keys of the peers.
+    pub fingerprints: Vec<(String, String)>,
+    /// Padding of outgoing messages.
+    pub padding: Padding,
+    /// True if messages are authenticated with MAC keys which are published after use.
+    pub deniable: bool,
+    /// Dummy messages which hide when messages are sent.
+    pub cover: Option<CoverTraffic>,
+    /// Named lists of peers for /broadcast.
+    pub lists: BroadcastLists,
+}
+
+/// Reads a passphrase from the terminal without echoing it.
+fn read_passphrase(prompt: &str) -> Result<String, String> {
+
+    use termion::input::TermRead;
+
+    print!("{}", prompt);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let passphrase = io::stdin()
+        .read_passwd(&mut io::stdout())
+        .map_err(|e| e.to_string())?
+        .ok_or("No passphrase given.")?;
+    println!();
+    Ok(passphrase)
+}
+
+/// Asks for the passphrase and derives the key for the given cipher from it.
+fn key_from_passphrase(params: &KdfParams, cipher: &str) -> Result<String, String> {
+
+    let passphrase = read_passphrase("Passphrase: ")?;
+    let len = match cipher {
+        "chacha20poly1305" => 32,
+        _ => blowfish::KEY_LEN
+    };
+    Ok(to_hex(&params.derive(&passphrase, len).map_err(|e| e.to_string())?))
+}
+
+/// Stores the keys given via the other arguments in a new keystore. In hybrid mode the
+/// hash of the public key of the recipient is stored as fingerprint for each destination.
+fn create_keystore(fname: &str, a: &Arguments) -> Result<(), String> {
+
+    let mut ks = Keystore::new();
+    if a.hybrid_mode {
+        // Keys of GnuPG and PKCS#11 tokens are stored as given.
+        let path = |f: &String| match gpg::key_id(f).is_some() || f.starts_with(pkcs11::PREFIX) {
+            true => Ok(f.clone()),
+            false => std::fs::canonicalize(f)
+                .map(|p| p.to_string_lossy().to_string())
+                .map_err(|_| format!("Could not find file {}.", f))
+        };
+        let recipients = a.rcpt_pubkey_file.split(',').map(|f| f.trim().to_string()).collect::<Vec<_>>();
+        ks.rsa_recipient = Some(recipients.iter().map(path).collect::<Result<Vec<_>, _>>()?.join(","));
+        ks.rsa_private = Some(path(&a.privkey_file)?);
+        ks.rsa_public = Some(path(&a.pubkey_file)?);
+        // With several recipient keys each destination has its own key.
+        let ips = a.dstip.split(',').map(iptools::canonical).collect::<Vec<_>>();
+        for (i, ip) in ips.into_iter().enumerate() {
+            let f = recipients.get(i).unwrap_or(&recipients[0]);
+            ks.fingerprints.push((ip, tools::sha1(&rsatools::key_as_der(&gpg::read_public_key(f).map_err(|e| e.to_string())?))));
+        }
+    } else {
+        ks.key = Some(a.secret_key.clone());
+    }
+    if let Some(ref c) = a.peer_keys {
+        ks.peer_keys = peerkeys::parse(c)?;
+    }
+
+    let passphrase = read_passphrase("New passphrase for keystore: ")?;
+    if read_passphrase("Repeat passphrase: ")? != passphrase {
+        return Err("Passphrases do not match.".to_string());
+    }
+    ks.save(fname, &passphrase).map_err(|e| e.to_string())
+}
+
+/// Replaces the keys in the arguments with the keys from the keystore.
+fn apply_keystore(fname: &str, a: &mut Arguments) -> Result<(), String> {
+
+    let ks = Keystore::load(fname, &read_passphrase("Passphrase for keystore: ")?).map_err(|e| e.to_string())?;
+    if !ks.peer_keys.is_empty() {
+        a.peer_keys = Some(ks.peer_keys_content());
+    }
+    if let Some(k) = ks.key {
+        a.secret_key = k;
+    }
+    match (ks.rsa_recipient, ks.rsa_private, ks.rsa_public) {
+        (Some(r), Some(p), Some(q)) => {
+            a.hybrid_mode = true;
+            a.rcpt_pubkey_file = r;
+            a.privkey_file = p;
+            a.pubkey_file = q;
+        },
+        (None, None, None) => { },
+        _ => return Err("Keystore contains incomplete RSA keys.".to_string())
+    }
+    a.fingerprints = ks.fingerprints;
+    Ok(())
+}
+
+fn get_key_from_home() -> Option<String> {
+    match dirs::home_dir() {
+        Some(mut path) => {
+            path.push(".stealthy/key");
+            match File::open(path) {
+                Ok(f) => {
+                    let mut reader = BufReader::new(f);
+                    let mut key = String::new();
+                    match reader.read_line(&mut key) {
+                        Ok(_) => Some(key.trim().to_string()),
+                        _ => None
+                    }
+                }
+                _ => None
+            }
+        },
+        None => None
+    }
+}
+
+pub fn parse_arguments() -> Option<Arguments> {
+
+    static DEFAULT_SECRET_KEY: &'static str = "11111111111111111111111111111111";
+
+    // parse comand line options
+    let args : Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optopt("i", "dev", "set the devices where to listen for messages, separated by commas, or any for all devices", "device");
+    opts.optopt("", "transport", "transport of the packets: icmp (default), udp, tcp", "name");
+    opts.optopt("", "port", "UDP or TCP port on which messages are received and to which they are sent (requires --transport udp or tcp)", "port");
+    opts.optopt("", "proxy", "establish the TCP connections via a SOCKS5 proxy like Tor, e.g. socks5://127.0.0.1:9050 (requires --transport tcp)", "url");
+    opts.optflag("", "echo-replies", "answer the pings of other programs like the kernel (use with net.ipv4.icmp_echo_ignore_all=1)");
+    opts.optopt("", "icmp-type", "ICMP types which carry the packets, separated by commas: echo (default), echo-reply, timestamp, address-mask; packets are sent with the first one", "types");
+    opts.optopt("", "ttl", "TTL (IPv4) or hop limit (IPv6) of the ICMP packets (default: the one of the OS)", "hops");
+    opts.optopt("", "df", "set (on) or clear (off) the DF bit of the ICMP packets (default: the setting of the OS)", "on|off");
+    opts.optopt("", "camouflage", "make the pings look like the ones of the ping utility of linux or windows", "os");
+    opts.optopt("", "spoof", "send the ICMP packets with the given source address; peers cannot answer, use only for one-way broadcasts (IPv4, requires root)", "IP");
+    opts.optopt("", "tcp-fallback", "send packets via TCP to the given port to peers which do not acknowledge packets sent via ICMP", "port");
+    opts.optopt("d", "dst", "set the IPv4 or IPv6 addresses where messages are sent to, separated by commas", "IP");
+    opts.optopt("e", "enc", "set the encryption key", "key");
+    opts.optopt("", "peer-keys", "file with a key for each peer, one line with IP and key per peer", "filename");
+    opts.optopt("", "lists", "file with named broadcast lists, one line like 'friends = [IP, IP]' per list", "filename");
+    opts.optopt("", "keystore", "read the keys from the given passphrase-encrypted keystore", "filename");
+    opts.optopt("", "outbox", "store unacknowledged packets in the given passphrase-encrypted file and resend them after a restart", "filename");
+    opts.optflag("", "keystore-create", "store the keys given with the other options in the keystore and exit");
+    opts.optflag("", "passphrase", "ask for a passphrase and derive the encryption key from it via Argon2id");
+    opts.optopt("", "kdf", "parameters for the key derivation (default: from ~/.stealthy/kdf or new ones)", "params");
+    opts.optopt("c", "cipher", "cipher for symmetric encryption: blowfish (default), chacha20poly1305", "name");
+    opts.optopt("r", "recipient", "recipient's public key in PEM format or gpg:<keyid> used for encryption; several keys separated by commas, one for each IP of -d", "filename");
+    opts.optopt("p", "priv", "your private key in PEM format, gpg:<keyid> or a pkcs11: URI used for decryption", "filename");
+    opts.optopt("q", "pub", "your public key in PEM format or gpg:<keyid>", "filename");
+    opts.optflagopt("", "bell", "ring the terminal bell for the given comma separated events: message, upload, error (default: message)", "events");
+    opts.optopt("", "sound-cmd", "command which is executed for new messages, e.g. 'paplay alert.wav'", "command");
+    opts.optopt("", "upload-sound-cmd", "command which is executed for received files", "command");
+    opts.optopt("", "error-sound-cmd", "command which is executed for errors", "command");
+    opts.optopt("", "replay-window", "reject messages which are older than the given number of seconds", "seconds");
+    opts.optopt("", "rekey-interval", "replace sessions after the given number of minutes (default: 60)", "minutes");
+    opts.optopt("", "rekey-messages", "replace sessions after the given number of messages (default: 1000)", "n");
+    opts.optopt("", "sign-key", "sign messages with the Ed25519 key whose seed is stored in hex in the file or with an Ed25519 key of OpenSSH", "filename");
+    opts.optopt("", "verify-keys", "verify signatures with the Ed25519 public keys stored in hex or in the format of OpenSSH in the file, one per line, optionally preceded by the IP of the peer", "filename");
+    opts.optopt("", "genkey", "generate a new key pair, write it into files with the given prefix and exit", "prefix");
+    opts.optopt("", "pad", "pad messages to hide their length: off (default), bucket, max", "mode");
+    opts.optflag("", "deniable", "publish the MAC keys of received messages so that transcripts do not prove who has written a message");
+    opts.optopt("", "jitter", "delay each outgoing packet by a random time up to the given number of milliseconds", "ms");
+    opts.optopt("", "max-rate", "limit outgoing packets to the given number of bytes per second, e.g. 64k", "rate");
+    opts.optopt("", "reorder-timeout", "wait up to the given number of milliseconds for messages which have been sent before a received message (default: 3000, 0 = off)", "ms");
+    opts.optopt("", "max-retries", "give up a message if a packet has not been acknowledged after the given number of retransmissions (default: 8)", "n");
+    opts.optopt("", "cover-traffic", "send dummy messages to the peers at random intervals with the given mean so that idle periods cannot be told apart from chatting", "seconds");
+    opts.optopt("", "key-type", "type of the key pair for --genkey: rsa (default), ed25519", "type");
+    opts.optflag("h", "help", "print this message");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!(f.to_string()) }
+    };
+
+    if let Some(prefix) = matches.opt_str("genkey") {
+        let r = KeyType::parse(&matches.opt_str("key-type").unwrap_or("rsa".to_string()))
+            .and_then(|typ| keygen::generate(typ, &prefix));
+        match r {
+            Ok(k) => {
+                println!("Private key written to {}.", k.private_file);
+                println!("Public key written to {}.", k.public_file);
+                println!("Fingerprint: {}", k.fingerprint);
+            },
+            Err(e) => println!("Could not generate key: {}", e)
+        }
+        return None;
+    }
+
+    let hybrid_mode = matches.opt_present("r") || matches.opt_present("p");
+
+    if matches.opt_present("h") ||
+        (hybrid_mode && !(matches.opt_present("r") && matches.opt_present("p") && matches.opt_present("q"))) {
+
+        let brief = format!("Usage: {} [options]", args[0]);
+        println!("{}", opts.usage(&brief));
+        return None;
+    }
+
+    let bell = match matches.opt_str("bell") {
+        Some(events) => match parse_events(&events) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            }
+        },
+        None if matches.opt_present("bell") => vec![AlertEvent::Message],
+        None => vec![]
+    };
+
+    let mut numbers = vec![];
+    for name in &["replay-window", "rekey-interval", "rekey-messages", "jitter", "max-retries", "reorder-timeout"] {
+        numbers.push(match matches.opt_str(name) {
+            Some(s) => match s.parse::<u64>() {
+                Ok(n) if n > 0 || *name == "replay-window" || *name == "reorder-timeout" => Some(n),
+                _ => {
+                    println!("Invalid value for --{}: {}", name, s);
+                    return None;
+                }
+            },
+            None => None
+        });
+    }
+
+    let max_rate = match matches.opt_str("max-rate").map(|s| rate::parse_rate(&s)) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(e)) => {
+            println!("{}", e);
+            return None;
+        },
+        None => None
+    };
+
+    let cipher = matches.opt_str("c").unwrap_or("blowfish".to_string());
+
+    let padding = match Padding::parse(&matches.opt_str("pad").unwrap_or("off".to_string())) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+
+    // Without a device the device of the default route is used.
+    let detected = match matches.opt_str("i") {
+        Some(_) => None,
+        None => iptools::default_device()
+    };
+    let transport = Transport::parse(
+        &matches.opt_str("transport").unwrap_or("icmp".to_string()),
+        &matches.opt_str("i").or(detected.clone()).unwrap_or("lo".to_string()),
+        matches.opt_str("port").as_ref().map(|p| p.as_str()),
+        matches.opt_str("tcp-fallback").as_ref().map(|p| p.as_str())
+    );
+    let icmp = || -> Result<IcmpOptions, StealthyError> {
+        let options = IcmpOptions {
+            echo_replies: matches.opt_present("echo-replies"),
+            carriers: match matches.opt_str("icmp-type") {
+                Some(types) => Carrier::parse_list(&types)?,
+                None => IcmpOptions::default().carriers
+            },
+            ttl: matches.opt_str("ttl").map(|s| IcmpOptions::parse_ttl(&s)).transpose()?,
+            dont_fragment: matches.opt_str("df").map(|s| IcmpOptions::parse_df(&s)).transpose()?,
+            camouflage: matches.opt_str("camouflage").map(|s| Camouflage::parse(&s)).transpose()?,
+            spoof: matches.opt_str("spoof")
+                .map(|s| s.trim().parse().map_err(|_| StealthyError::Transport("--spoof requires an IPv4 address.")))
+                .transpose()?,
+        };
+        match options.camouflage.is_some() && options.carriers != [Carrier::EchoRequest] {
+            true => Err(StealthyError::Transport("--camouflage can only be used with --icmp-type echo.")),
+            false => Ok(options)
+        }
+    };
+    let transport = transport.and_then(|t| t.with_icmp(icmp()?));
+    let transport = match matches.opt_str("proxy") {
+        Some(url) => transport.and_then(|t| t.with_proxy(Proxy::parse(&url)?)),
+        None => transport
+    };
+    let transport = match transport {
+        Ok(t) => t,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+    let device_detected = match transport {
+        Transport::Icmp(_, _, _) => detected.is_some(),
+        _ => false
+    };
+
+    // A signature would prove who has written a message.
+    let deniable = matches.opt_present("deniable");
+    if deniable && matches.opt_present("sign-key") {
+        println!("--deniable cannot be used together with --sign-key.");
+        return None;
+    }
+
+    let cover = match matches.opt_str("cover-traffic").map(|s| CoverTraffic::parse(&s)).transpose() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+
+    // If a passphrase is used, the peer needs the same parameters to derive the same key.
+    let kdf_params = match (matches.opt_present("passphrase"), matches.opt_str("kdf")) {
+        (false, _) => None,
+        (true, Some(s)) => Some(KdfParams::parse(&s)),
+        (true, None) => Some(kdf::load_or_create())
+    };
+    let kdf_params = match kdf_params {
+        Some(Ok(p)) => Some(p),
+        Some(Err(e)) => {
+            println!("{}", e);
+            return None;
+        },
+        None => None
+    };
+
+    // 1) If a passphrase is used derive the key from the passphrase.
+    // 2) If option -e is given use this key.
+    // 3) If key exists in home directory use this key.
+    // 4) Use default key.
+    let key = match kdf_params {
+        Some(ref p) => match key_from_passphrase(p, &cipher) {
+            Ok(k) => k,
+            Err(e) => {
+                println!("Could not derive key: {}", e);
+                return None;
+            }
+        },
+        None => matches.opt_str("e")
+            .unwrap_or(get_key_from_home().unwrap_or(DEFAULT_SECRET_KEY.to_string()))
+    };
+
+    let peer_keys = match matches.opt_str("peer-keys") {
+        Some(f) => match read_file(&f) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            }
+        },
+        None => None
+    };
+
+    let lists = match matches.opt_str("lists").map(|f| read_file(&f).map_err(|e| e.to_string()).and_then(|c| BroadcastLists::parse(&c).map_err(String::from))) {
+        Some(Ok(l)) => l,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return None;
+        },
+        None => BroadcastLists::new()
+    };
+
+    // The passphrase of an encrypted key of OpenSSH is asked for before the user interface
+    // is started.
+    let sign_key_passphrase = match matches.opt_str("sign-key").map(|f| read_file(&f)) {
+        Some(Ok(ref s)) if sshkey::is_private_key(s) && sshkey::is_encrypted(s).ok() == Some(true) => {
+            match read_passphrase("Passphrase for OpenSSH key: ") {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    println!("{}", e);
+                    return None;
+                }
+            }
+        },
+        _ => None
+    };
+
+    let outbox_passphrase = match matches.opt_present("outbox") {
+        true => match read_passphrase("Passphrase for outbox: ") {
+            Ok(p) => Some(p),
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            }
+        },
+        false => None
+    };
+
+    let mut a = Arguments {
+        device_detected,
+        transport,
+        dstip:        matches.opt_str("d").unwrap_or("127.0.0.1".to_string()),
+        secret_key:   key,
+        cipher:       cipher,
+        peer_keys,
+        hybrid_mode:  hybrid_mode,
+        rcpt_pubkey_file:  matches.opt_str("r").unwrap_or("".to_string()),
+        privkey_file: matches.opt_str("p").unwrap_or("".to_string()),
+        pubkey_file:  matches.opt_str("q").unwrap_or("".to_string()),
+        token_pin: None,
+        alerts: Alerts {
+            bell,
+            message_cmd: matches.opt_str("sound-cmd"),
+            upload_cmd:  matches.opt_str("upload-sound-cmd"),
+            error_cmd:   matches.opt_str("error-sound-cmd"),
+        },
+        replay_window: numbers[0],
+        sign_key_file: matches.opt_str("sign-key"),
+        sign_key_passphrase,
+        verify_keys_file: matches.opt_str("verify-keys"),
+        kdf_params: kdf_params.map(|p| p.encode()),
+        rekey_interval: numbers[1],
+        rekey_messages: numbers[2],
+        jitter: numbers[3],
+        max_retries: numbers[4],
+        max_rate,
+        reorder_timeout: numbers[5],
+        outbox_file: matches.opt_str("outbox"),
+        outbox_passphrase,
+        fingerprints: vec![],
+        padding,
+        deniable,
+        cover,
+        lists,
+    };
+
+    match (matches.opt_str("keystore"), matches.opt_present("keystore-create")) {
+        (Some(f), true) => {
+            match create_keystore(&f, &a) {
+                Ok(_) => println!("Keystore written to {}.", f),
+                Err(e) => println!("Could not create keystore: {}", e)
+            }
+            return None;
+        },
+        (Some(f), false) => if let Err(e) = apply_keystore(&f, &mut a) {
+            println!("Could not open keystore: {}", e);
+            return None;
+        },
+        (None, true) => {
+            println!("--keystore-create requires --keystore.");
+            return None;
+        },
+        (None, false) => { }
+    }
+
+    // The PIN of a PKCS#11 token is asked for before the user interface is started.
+    if a.hybrid_mode && a.privkey_file.starts_with(pkcs11::PREFIX) && pkcs11::requires_pin(&a.privkey_file) {
+        match read_passphrase("PIN for PKCS#11 token: ") {
+            Ok(p) => a.token_pin = Some(p),
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            }
+        }
+    }
+    Some(a)
+}
@@ -0,0 +1,86 @@
+use std::net::IpAddr;
+
+/// Named lists of peers to which a message can be sent at once.
+///
+/// Each line of the file contains the name of a list and its ips, e.g.
+/// `friends = [1.2.3.4, 2001:db8::1]`. The brackets are optional. Lines which start with
+/// '#' are ignored.
+pub struct BroadcastLists {
+    lists: Vec<(String, Vec<String>)>,
+}
+
+impl BroadcastLists {
+
+    pub fn new() -> BroadcastLists {
+        BroadcastLists {
+            lists: vec![],
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<BroadcastLists, &'static str> {
+
+        let mut lists: Vec<(String, Vec<String>)> = vec![];
+        for line in content.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with("#")) {
+            let mut parts = line.splitn(2, '=');
+            let (name, ips) = match (parts.next(), parts.next()) {
+                (Some(n), Some(ips)) if !n.trim().is_empty() && !n.trim().contains(char::is_whitespace) => (n.trim(), ips.trim()),
+                _ => return Err("Invalid line in file of broadcast lists.")
+            };
+            if lists.iter().any(|(n, _)| n == name) {
+                return Err("Broadcast list is defined twice.");
+            }
+            let ips = ips.trim_start_matches('[').trim_end_matches(']')
+                .split(',')
+                .map(|ip| ip.trim())
+                .filter(|ip| !ip.is_empty())
+                .map(|ip| ip.parse::<IpAddr>().map(|a| a.to_string()).map_err(|_| "Invalid IP address in broadcast list."))
+                .collect::<Result<Vec<_>, _>>()?;
+            if ips.is_empty() {
+                return Err("Broadcast list is empty.");
+            }
+            lists.push((name.to_string(), ips));
+        }
+        Ok(BroadcastLists { lists })
+    }
+
+    /// Returns the ips of the list with the given name.
+    pub fn get(&self, name: &str) -> Option<&Vec<String>> {
+        self.lists.iter().find(|(n, _)| n == name).map(|(_, ips)| ips)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.lists.iter().map(|(n, _)| n.clone()).collect()
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::BroadcastLists;
+
+    #[test]
+    fn test_parse() {
+
+        let l = BroadcastLists::parse("# lists\nfriends = [1.2.3.4, 2001:DB8::1]\n\nwork=5.6.7.8\n").unwrap();
+        assert_eq!(l.names(), vec!["friends".to_string(), "work".to_string()]);
+        assert_eq!(l.get("friends"), Some(&vec!["1.2.3.4".to_string(), "2001:db8::1".to_string()]));
+        assert_eq!(l.get("work"), Some(&vec!["5.6.7.8".to_string()]));
+        assert_eq!(l.get("family"), None);
+        assert!(BroadcastLists::parse("").unwrap().names().is_empty());
+    }
+
+    #[test]
+    fn test_invalid() {
+
+        assert!(BroadcastLists::parse("friends").is_err());
+        assert!(BroadcastLists::parse("= [1.2.3.4]").is_err());
+        assert!(BroadcastLists::parse("my friends = [1.2.3.4]").is_err());
+        assert!(BroadcastLists::parse("friends = []").is_err());
+        assert!(BroadcastLists::parse("friends = [1.2.3.x]").is_err());
+        assert!(BroadcastLists::parse("a = 1.2.3.4\na = 5.6.7.8").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+use crate::ConsoleMessage;
+use crate::Item;
+use crate::Layers;
+use crate::IpAddresses;
+use stealthy_core::iptools;
+use crate::ItemType;
+use crate::Message;
+use crate::Source;
+use crate::uptime;
+use crate::send_messages;
+use crate::send_envelope;
+use crate::send_broadcast;
+use stealthy_core::envelope::{self, Envelope};
+use crate::outputs::help_message;
+use crate::Console;
+use crate::ArcModel;
+use stealthy_core::keygen::{self, KeyType};
+use stealthy_core::sas::{self, VerifiedPeers};
+use crate::qrcode::QrCode;
+use stealthy_core::rsatools;
+use stealthy_core::rollover::Announcement;
+use stealthy_core::group::Change;
+use stealthy_core::signing;
+use stealthy_core::tools;
+use stealthy_core::rate;
+
+use stealthy_core::tools::{read_file, read_bin_file, decode_uptime, without_dirs};
+
+fn parse_command_set(txt: String, o: Console) -> bool {
+    let txt_parts = txt.split(' ').collect::<Vec<_>>();
+    if !(txt_parts.len() != 3 && txt_parts[1] != "scramble") {
+        let n = txt_parts[2].parse::<u32>();
+        if n.is_ok() {
+            let val = n.unwrap();
+            o.send(ConsoleMessage::SetScrambleTimeout(val));
+            o.send(ConsoleMessage::TextMessage(Item::new_system(&format!("Value set to {} seconds.", val))));
+            return true;
+        }
+    }
+    false
+}
+
+pub fn parse_command(txt: String, o: Console, l: &Layers, dstips: &IpAddresses, model: &ArcModel) {
+    // New messages and files are only sent to the peer of the current tab.
+    let to = model.lock().unwrap().recipients(dstips);
+
+    // TODO: find more elegant solution for this
+    if txt.starts_with("/cat ") {
+        // TODO split_at works on bytes not characters
+        let (_, b) = txt.as_str().split_at(5);
+        match read_file(b) {
+            Ok(data) => {
+                o.msg(String::from("Transmitting data ..."), ItemType::Info, Source::System);
+                let lines = data.as_str()
+                    .split("\n")
+                    .map(|line| line.trim_end().to_string())
+                    .collect::<Vec<_>>();
+                send_messages(lines, o.clone(), l, &to);
+            },
+            _ => {
+                o.msg(String::from("Could not read file."), ItemType::Error, Source::System);
+            }
+        }
+        return;
+    }
+
+    if txt.starts_with("/set ") {
+        if !parse_command_set(txt, o.clone()) {
+            o.send(ConsoleMessage::TextMessage(Item::new_system("Command not understood.")));
+        }
+        return;
+    }
+
+    if txt.starts_with("/upload ") {
+        let (_, b) = txt.as_str().split_at(8);
+        match read_bin_file(b) {
+            Ok(data) => {
+                send_file(data, b.to_string(), o, l, &to);
+            },
+            Err(s) => {
+                o.msg(String::from(s), ItemType::Error, Source::System);
+            }
+        }
+        return;
+    }
+
+    if txt.starts_with("/genkey ") {
+        genkey(&txt[8..], o);
+        return;
+    }
+
+    if txt.starts_with("/verify ") {
+        verify(&txt[8..], o, l);
+        return;
+    }
+
+    if txt == "/qr" || txt.starts_with("/qr ") {
+        qr(&txt[3..], o, l);
+        return;
+    }
+
+    if txt == "/rate" || txt.starts_with("/rate ") {
+        set_rate(&txt[5..], o, l);
+        return;
+    }
+
+    if txt.starts_with("/send ") {
+        send(&txt[6..], o, l, &to);
+        return;
+    }
+
+    if txt.starts_with("/edit ") {
+        edit(&txt[6..], o, l, dstips, model);
+        return;
+    }
+
+    if txt.starts_with("/delete ") {
+        delete(&txt[8..], o, l, dstips, model);
+        return;
+    }
+
+    if txt == "/broadcast" || txt.starts_with("/broadcast ") {
+        broadcast(&txt[10..], o, l, dstips, model);
+        return;
+    }
+
+    if txt.starts_with("/invite ") {
+        change_group(Change::Invite(iptools::canonical(&txt[8..])), o, l);
+        return;
+    }
+
+    if txt.starts_with("/remove ") {
+        change_group(Change::Remove(iptools::canonical(&txt[8..])), o, l);
+        return;
+    }
+
+    if txt.starts_with("/rollover ") {
+        rollover(&txt[10..], o, l, dstips);
+        return;
+    }
+
+    match txt.as_str() {
+        "/help" => {
+            help_message(o.clone());
+        },
+        "/uptime" | "/up" => {
+            o.msg(format!("up {}", decode_uptime(uptime())), ItemType::Info, Source::System);
+        },
+        "/rekey" => {
+            o.msg(String::from("Starting new sessions ..."), ItemType::Info, Source::System);
+            l.rekey(dstips.as_strings());
+        },
+        "/who" => {
+            o.send(ConsoleMessage::Who);
+        },
+        "/group" => {
+            o.msg(format!("Members: {}", l.members().join(", ")), ItemType::Info, Source::System);
+        },
+        "/pending" => {
+            show_pending(o.clone(), l);
+        },
+        "/cancel" => {
+            cancel_pending(o.clone(), l);
+        },
+        "/stats" => {
+            show_stats(o.clone(), l);
+        },
+        _ => {
+            o.msg(String::from("Unknown command. Type /help to see a list of commands."), ItemType::Info, Source::System);
+        }
+    };
+}
+
+/// Shows the statistics of the layers.
+fn show_stats(o: Console, l: &Layers) {
+
+    let s = l.stats();
+    let mut lines = vec![
+        format!("Packets sent: {}, received: {}, retransmitted: {}", s.packets_sent, s.packets_received, s.retransmissions),
+        format!("Dropped packets from unknown ips: {}", s.dropped_unaccepted),
+        format!("Decryption failures: {}", s.decryption_failures),
+        format!("Dropped replays: {}", s.dropped_replays),
+        format!("Upload rate: {} bytes/s", s.upload_rate),
+    ];
+    if let Some(rtt) = s.mean_rtt {
+        lines.push(format!("Mean round-trip time: {} ms", rtt.as_millis()));
+    }
+    for (ip, p) in &s.peers {
+        let rtt = p.rtt.map(|d| format!(", round-trip time: {} ms", d.as_millis())).unwrap_or_default();
+        lines.push(format!("{}: {} bytes sent, {} bytes received{}", ip, p.bytes_sent, p.bytes_received, rtt));
+    }
+    for l in lines {
+        o.msg(l, ItemType::Info, Source::System);
+    }
+}
+
+/// Cancels the messages and file uploads which have not been acknowledged yet.
+fn cancel_pending(o: Console, l: &Layers) {
+
+    let n = l.unacknowledged().into_iter().filter(|id| l.cancel(*id)).count();
+    o.msg(format!("Cancelled {} message(s).", n), ItemType::Info, Source::System);
+}
+
+/// Lists the packets which have not been acknowledged yet.
+fn show_pending(o: Console, l: &Layers) {
+
+    let pending = l.pending();
+    if pending.is_empty() {
+        o.msg(String::from("All packets have been acknowledged."), ItemType::Info, Source::System);
+    }
+    for p in pending {
+        let txt = format!("Packet {:016x} to {}: {}s old, {} retransmissions", p.id, p.ip, p.age.as_secs(), p.retries);
+        o.msg(txt, ItemType::Info, Source::System);
+    }
+}
+
+/// Shows the maximum rate of outgoing packets or sets it if a rate or "off" is given.
+fn set_rate(args: &str, o: Console, l: &Layers) {
+
+    let r = match args.trim() {
+        "" => Ok(l.max_rate()),
+        "off" => Ok(None),
+        s => rate::parse_rate(s).map(Some)
+    };
+    match r {
+        Ok(max) => {
+            l.set_max_rate(max);
+            let txt = match max {
+                Some(n) => format!("Maximum rate: {} bytes/s", n),
+                None => String::from("The rate is not limited.")
+            };
+            o.msg(txt, ItemType::Info, Source::System);
+        },
+        Err(e) => {
+            o.msg(e.to_string(), ItemType::Error, Source::System);
+        }
+    }
+}
+
+/// Sends a message with options, e.g. "--expire 10m hello" sends a message which the
+/// receivers remove ten minutes after they have received it.
+fn send(args: &str, o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let mut expire = None;
+    let mut rest = args.trim_start();
+    while rest.starts_with("--") {
+        let mut parts = rest.splitn(3, ' ');
+        let r = match (parts.next(), parts.next()) {
+            (Some("--expire"), Some(t)) => envelope::parse_duration(t).map(|secs| expire = Some(secs)).map_err(|e| e.message()),
+            _ => Err("Usage: /send [--expire <time>] <text>")
+        };
+        if let Err(e) = r {
+            o.msg(String::from(e), ItemType::Error, Source::System);
+            return;
+        }
+        rest = parts.next().unwrap_or("").trim_start();
+    }
+    if rest.is_empty() {
+        o.msg(String::from("Usage: /send [--expire <time>] <text>"), ItemType::Error, Source::System);
+        return;
+    }
+    let env = Envelope::new(rest.as_bytes().to_vec());
+    send_envelope(match expire {
+        Some(secs) => env.with_expire(secs),
+        None => env
+    }, o, l, dstips);
+}
+
+/// Replaces the text of the last message we have sent.
+fn edit(args: &str, o: Console, l: &Layers, dstips: &IpAddresses, model: &ArcModel) {
+
+    let txt = args.trim();
+    if txt.is_empty() {
+        o.msg(String::from("Usage: /edit <text>"), ItemType::Error, Source::System);
+        return;
+    }
+    let id = model.lock().unwrap().sent(1);
+    match id {
+        Some(id) => send_envelope(Envelope::new(txt.as_bytes().to_vec()).with_edit(id), o, l, dstips),
+        None => o.msg(String::from("There is no message which could be edited."), ItemType::Error, Source::System)
+    }
+}
+
+/// Deletes the n-th last message we have sent at the receivers. The last message is 1.
+fn delete(args: &str, o: Console, l: &Layers, dstips: &IpAddresses, model: &ArcModel) {
+
+    let n = match args.trim().parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            o.msg(String::from("Usage: /delete <n>, e.g. /delete 1 deletes your last message"), ItemType::Error, Source::System);
+            return;
+        }
+    };
+    let id = model.lock().unwrap().sent(n);
+    match id {
+        Some(id) => send_envelope(Envelope::new(vec![]).with_delete(id), o, l, dstips),
+        None => o.msg(String::from("There is no such message."), ItemType::Error, Source::System)
+    }
+}
+
+/// Sends a message to the peers of a broadcast list, e.g. "friends hello". Without
+/// arguments the names of the lists are shown.
+fn broadcast(args: &str, o: Console, l: &Layers, dstips: &IpAddresses, model: &ArcModel) {
+
+    let mut parts = args.trim_start().splitn(2, ' ');
+    let (name, txt) = match (parts.next(), parts.next()) {
+        (Some(""), _) | (None, _) => {
+            let names = model.lock().unwrap().lists.names();
+            match names.is_empty() {
+                true => o.msg(String::from("There are no broadcast lists. They are read from the file given with --lists."), ItemType::Info, Source::System),
+                false => o.msg(format!("Broadcast lists: {}", names.join(", ")), ItemType::Info, Source::System)
+            }
+            return;
+        },
+        (Some(name), Some(txt)) if !txt.trim().is_empty() => (name, txt.trim()),
+        _ => {
+            o.msg(String::from("Usage: /broadcast <list> <text>"), ItemType::Error, Source::System);
+            return;
+        }
+    };
+    let ips = match model.lock().unwrap().lists.get(name) {
+        Some(ips) => ips.clone(),
+        None => {
+            o.msg(format!("Unknown broadcast list {}.", name), ItemType::Error, Source::System);
+            return;
+        }
+    };
+    // Packets of other ips are dropped, so they could not acknowledge the message.
+    let available = dstips.only(&ips);
+    for ip in ips.iter().filter(|ip| !available.as_strings().contains(ip)) {
+        o.msg(format!("{} is skipped because it is not a member of the group.", ip), ItemType::Error, Source::System);
+    }
+    send_broadcast(name, txt.to_string(), o, l, &available);
+}
+
+/// Invites a peer to the group or removes it from the group.
+fn change_group(c: Change, o: Console, l: &Layers) {
+    match l.change_group(c) {
+        Ok(_) => o.msg(format!("Members: {}", l.members().join(", ")), ItemType::Info, Source::System),
+        Err(e) => o.msg(e.to_string(), ItemType::Error, Source::System)
+    }
+}
+
+/// Generates a key pair. The argument is the prefix of the files optionally preceded by
+/// the type of the key, e.g. "ed25519 mykey".
+fn genkey(args: &str, o: Console) {
+
+    let parts = args.split_whitespace().collect::<Vec<_>>();
+    let r = match parts.len() {
+        1 => keygen::generate(KeyType::Rsa, parts[0]).map_err(|e| e.to_string()),
+        2 => KeyType::parse(parts[0]).and_then(|typ| keygen::generate(typ, parts[1])).map_err(|e| e.to_string()),
+        _ => Err(String::from("Usage: /genkey [rsa|ed25519] <prefix>"))
+    };
+    match r {
+        Ok(k) => {
+            o.msg(format!("Keys written to {} and {}.", k.private_file, k.public_file), ItemType::Info, Source::System);
+            o.msg(format!("Fingerprint: {}", k.fingerprint), ItemType::Info, Source::System);
+        },
+        Err(e) => o.msg(e, ItemType::Error, Source::System)
+    }
+}
+
+/// Shows the short authentication string of the keys used for a peer. With "confirm" the
+/// peer is marked as verified, with "revoke" the mark is removed, e.g. "1.2.3.4 confirm".
+fn verify(args: &str, o: Console, l: &Layers) {
+
+    let parts = args.split_whitespace().collect::<Vec<_>>();
+    let (ip, action) = match parts.len() {
+        1 => (parts[0], None),
+        2 => (parts[0], Some(parts[1])),
+        _ => {
+            o.msg(String::from("Usage: /verify <ip> [confirm|revoke]"), ItemType::Error, Source::System);
+            return;
+        }
+    };
+
+    let ip = &iptools::canonical(ip);
+    let fingerprint = l.key_fingerprint(ip);
+    let mut peers = VerifiedPeers::load();
+    let verified = match action {
+        None => {
+            o.msg(format!("Short authentication string for {}: {}", ip, sas::words(&fingerprint)), ItemType::Info, Source::System);
+            o.msg(format!("Compare it with {} via a trusted channel, e.g. by phone. If it matches type /verify {} confirm.", ip, ip), ItemType::Info, Source::System);
+            return;
+        },
+        Some("confirm") => {
+            peers.verify(ip, &fingerprint);
+            true
+        },
+        Some("revoke") => {
+            peers.revoke(ip);
+            false
+        },
+        Some(_) => {
+            o.msg(String::from("Usage: /verify <ip> [confirm|revoke]"), ItemType::Error, Source::System);
+            return;
+        }
+    };
+
+    match peers.save() {
+        Ok(_) => {
+            o.send(ConsoleMessage::PeerVerified(ip.to_string(), verified));
+            let s = if verified { "is marked as verified" } else { "is no longer marked as verified" };
+            o.msg(format!("{} {}.", ip, s), ItemType::Info, Source::System);
+        },
+        Err(e) => o.msg(e.to_string(), ItemType::Error, Source::System)
+    }
+}
+
+/// Shows the hash of our public key as QR code so that a peer can scan it, e.g. with a
+/// phone. With "key" the public key itself is shown. Without hybrid mode the public key of
+/// the signing key is shown.
+fn qr(args: &str, o: Console, l: &Layers) {
+
+    let public = l.public_key();
+    let full = match args.trim() {
+        "" => false,
+        "key" => true,
+        _ => {
+            o.msg(String::from("Usage: /qr [key]"), ItemType::Error, Source::System);
+            return;
+        }
+    };
+    let (label, data) = match (public.is_empty(), l.signing_key()) {
+        (false, _) if full => ("Your public key", rsatools::key_as_pem(&public)),
+        (false, _) => ("Hash of your public key", tools::sha1(&public)),
+        (true, Some(k)) => ("Your signing key", k),
+        (true, None) => {
+            o.msg(String::from("There is no public key. Use hybrid encryption or --sign-key."), ItemType::Error, Source::System);
+            return;
+        }
+    };
+
+    match QrCode::encode(data.as_bytes()) {
+        Ok(q) => {
+            o.msg(format!("{}:", label), ItemType::Info, Source::System);
+            for line in q.lines() {
+                o.raw(line, ItemType::Code, Source::Raw);
+            }
+        },
+        Err(e) => o.msg(String::from(e), ItemType::Error, Source::System)
+    }
+}
+
+/// Replaces our signing key by the key in the given file or revokes it with "revoke". The
+/// peers are notified with an announcement signed with the old key.
+fn rollover(args: &str, o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let r = match args.trim() {
+        "" => Err(String::from("Usage: /rollover <filename>|revoke")),
+        "revoke" => l.roll_over(None, dstips.as_strings()).map_err(|e| e.to_string()),
+        f => signing::read_seed(f, None)
+            .map_err(|e| e.to_string())
+            .and_then(|seed| l.roll_over(Some(seed.as_slice()), dstips.as_strings()).map_err(|e| e.to_string()))
+    };
+    match (r, l.signing_key()) {
+        (Ok(_), Some(k)) => {
+            o.msg(format!("Your new signing key: {}", k), ItemType::Info, Source::System);
+            o.msg(String::from("Use the new key with --sign-key when you start stealthy the next time."), ItemType::Info, Source::System);
+        },
+        (Ok(_), None) => o.msg(String::from("Your signing key has been revoked. Messages are not signed anymore."), ItemType::Info, Source::System),
+        (Err(e), _) => o.msg(e, ItemType::Error, Source::System)
+    }
+}
+
+/// Shows that a peer has replaced or revoked its signing key.
+pub fn key_changed(o: Console, ip: &str, a: &Announcement) {
+
+    match a {
+        Announcement::Rollover { old, new } => {
+            o.msg(format!("{} has replaced its signing key {} by {}.", ip, tools::to_hex(old), tools::to_hex(new)), ItemType::Info, Source::System);
+            o.msg(format!("Messages of {} signed with the new key are verified from now on.", ip), ItemType::Info, Source::System);
+        },
+        Announcement::Revocation { old } => {
+            o.msg(format!("{} has revoked its signing key {}. Its messages cannot be verified anymore.", ip, tools::to_hex(old)), ItemType::Error, Source::System);
+        }
+    }
+}
+
+/// Shows the peers which have been verified via /verify and warns if their keys have
+/// changed since then.
+pub fn load_verified(o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let peers = VerifiedPeers::load();
+    for ip in dstips.as_strings() {
+        let fingerprint = l.key_fingerprint(&ip);
+        if peers.is_verified(&ip, &fingerprint) {
+            o.send(ConsoleMessage::PeerVerified(ip, true));
+        } else if peers.has_changed(&ip, &fingerprint) {
+            o.msg(format!("WARNING: The keys for {} have changed since they have been verified. Use /verify {} to compare them again.", ip, ip), ItemType::Error, Source::System);
+        }
+    }
+}
+
+fn create_upload_data(dstip: String, fname: &String, data: &Vec<u8>) -> (Message, u64) {
+    (
+        Message::file_upload(dstip, without_dirs(fname), data),
+        rand::random::<u64>()
+    )
+}
+
+/// Sends a file in background.
+///
+/// # Arguments
+///
+/// * `data` - Content of the file (binary data).
+/// * `fname` - Name of the file.
+/// * `o` - Sender object to which messages are sent to.
+fn send_file(data: Vec<u8>, fname: String, console: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let n = data.len();
+
+    // This is sent to the console to show the user information about the file upload.
+    let mut item = Item::new(
+        format!("sending file '{}' with {} bytes...", fname, n),
+        ItemType::UploadMessage,
+        Source::You
+    ).add_size(n);
+
+    // Create a tuple (Message, u64) for each destination IP. For each IP a unique ID is created.
+    let v = dstips.as_strings()
+        .iter()
+        .map(|dstip| create_upload_data(dstip.clone(), &fname, &data))
+        .collect::<Vec<_>>();
+
+    // Add the file upload id to the item which is shown to the user. This ID allows us to
+    // update the status of this item, e.g. once the file upload is finished.
+    for (_, id) in &v {
+        item = item.add_id(*id);
+    }
+    if let [(ref msg, _)] = v.as_slice() {
+        item = item.to(&msg.ip);
+    }
+
+    // Show the message.
+    console.msg_item(item);
+
+    // Now, start the file transfer in the background for each given IP.
+    for (msg, id) in v {
+        l.send(msg, id, true);
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use stealthy_core::message::{FileInfo, Message};
+use crate::model::ItemType;
+use crate::model::Item;
+use crate::model::Source;
+use stealthy_core::presence::Presence;
+use stealthy_core::envelope::Envelope;
+use stealthy_core::status::{Status, StatusMessage};
+
+#[cfg(not(feature = "no_notify"))]
+use std::process::Command;
+
+pub enum ConsoleMessage {
+    TextMessage(Item),
+    Ack(u64),
+    /// A verified receipt for the message with the given id has been received.
+    Delivered(u64),
+    /// A verified read receipt for the message with the given id has been received.
+    Read(u64),
+    /// The message with the given id cannot be delivered.
+    Failed(u64),
+    /// The user has confirmed (true) or revoked (false) the short authentication string of
+    /// the peer.
+    PeerVerified(String, bool),
+    /// The peer with the given ip has announced its state with a keepalive.
+    Presence(String, Presence),
+    /// Shows the state of each peer.
+    Who,
+    /// The text of the message with the given envelope id is replaced by the text of the
+    /// item.
+    Edit(u64, Item),
+    /// The message with the given envelope id has been deleted by its sender.
+    Delete(u64, Source),
+    AckProgress(u64, usize, usize),
+    SetScrambleTimeout(u32),
+    ScrambleTick,
+    Exit,
+}
+
+#[derive(Clone)]
+pub struct Console {
+    console: Sender<ConsoleMessage>,
+}
+
+impl Console {
+    pub fn new(sender: Sender<ConsoleMessage>) -> Console {
+        Console {
+            console: sender
+        }
+    }
+
+    /// Returns a status for the layers whose messages are shown on this console.
+    pub fn to_status(&self) -> Status {
+        let (tx, rx) = channel();
+        let o = self.console.clone();
+        thread::spawn(move || for m in rx {
+            match m {
+                StatusMessage::Info(s) => status(o.clone(), s),
+                StatusMessage::Error(s) => error(o.clone(), s),
+            }
+        });
+        Status::new(tx)
+    }
+
+    pub fn new_file(&self, m: Message, file: FileInfo) {
+        new_file(self.console.clone(), m, file);
+    }
+
+    pub fn ack_msg(&self, id: u64) {
+        ack_msg(self.console.clone(), id);
+    }
+
+    pub fn delivered_msg(&self, id: u64) {
+        delivered_msg(self.console.clone(), id);
+    }
+
+    pub fn read_msg(&self, id: u64) {
+        read_msg(self.console.clone(), id);
+    }
+
+    pub fn failed_msg(&self, id: u64) {
+        failed_msg(self.console.clone(), id);
+    }
+
+    pub fn presence(&self, ip: String, p: Presence) {
+        presence(self.console.clone(), ip, p);
+    }
+
+    pub fn ack_msg_progress(&self, id: u64, done: usize, total: usize) {
+        ack_msg_progress(self.console.clone(), id, done, total);
+    }
+
+    pub fn error(&self, s: String) {
+        error(self.console.clone(), s);
+    }
+
+    pub fn status(&self, s: String) {
+        status(self.console.clone(), s);
+    }
+
+    pub fn new_msg(&self, m: Message) {
+        new_msg(self.console.clone(), m);
+    }
+
+    pub fn raw(&self, s: String, typ: ItemType, from: Source) {
+        raw(self.console.clone(), s, typ, from);
+    }
+
+    pub fn send(&self, msg: ConsoleMessage) {
+        self.console.send(msg).unwrap();
+    }
+
+    pub fn msg_item(&self, i: Item) {
+        msg_item(self.console.clone(), i);
+    }
+
+    pub fn msg(&self, s: String, typ: ItemType, from: Source) {
+        msg(self.console.clone(), s, typ, from);
+    }
+}
+
+pub fn raw_item(o: Sender<ConsoleMessage>, i: Item) {
+    o.send(ConsoleMessage::TextMessage(i)).expect("Error in console::msg");
+}
+
+pub fn raw(o: Sender<ConsoleMessage>, s: String, typ: ItemType, from: Source) {
+    raw_item(o, Item::new(format!("{}", s), typ, from));
+}
+
+pub fn msg_item(o: Sender<ConsoleMessage>, i: Item) {
+    raw_item(o, i);
+}
+
+pub fn msg(o: Sender<ConsoleMessage>, s: String, typ: ItemType, from: Source) {
+    raw(o, format!("{}", s), typ, from);
+}
+
+pub fn error(o: Sender<ConsoleMessage>, s: String) {
+    msg(o, s, ItemType::Error, Source::System);
+}
+
+pub fn status(o: Sender<ConsoleMessage>, s: String) {
+    msg(o, s, ItemType::Info, Source::System);
+}
+
+pub fn new_file(o: Sender<ConsoleMessage>, m: Message, file: FileInfo) {
+    msg(o, format!("received file '{}' ({} bytes)", file.name, file.size), ItemType::NewFile, Source::Ip(m.peer().to_string()));
+}
+
+pub fn ack_msg(o: Sender<ConsoleMessage>, id: u64) {
+    o.send(ConsoleMessage::Ack(id)).expect("Error");
+}
+
+pub fn delivered_msg(o: Sender<ConsoleMessage>, id: u64) {
+    o.send(ConsoleMessage::Delivered(id)).expect("Error");
+}
+
+pub fn read_msg(o: Sender<ConsoleMessage>, id: u64) {
+    o.send(ConsoleMessage::Read(id)).expect("Error");
+}
+
+pub fn failed_msg(o: Sender<ConsoleMessage>, id: u64) {
+    o.send(ConsoleMessage::Failed(id)).expect("Error");
+}
+
+pub fn presence(o: Sender<ConsoleMessage>, ip: String, p: Presence) {
+    o.send(ConsoleMessage::Presence(ip, p)).expect("Error");
+}
+
+pub fn ack_msg_progress(o: Sender<ConsoleMessage>, id: u64, done: usize, total: usize) {
+    // TODO: "done" actually is number of pending acks
+    o.send(ConsoleMessage::AckProgress(id, done, total)).expect("Error");
+}
+
+#[cfg(not(feature = "no_notify"))]
+fn notify(ip: String, o: Sender<ConsoleMessage>) {
+    // TODO configure the command
+    if Command::new("notify-send")
+        .arg("-t")
+        .arg("3000")
+        .arg(format!("new message from {}", ip))
+        .status().is_err() {
+        msg(o, format!("calling notify-send failed"), ItemType::Error, Source::System);
+    }
+}
+
+pub fn new_msg(o: Sender<ConsoleMessage>, m: Message) {
+
+    let ip = m.get_ip();
+    let env = Envelope::decode(&m.buf);
+
+    match env.and_then(|e| String::from_utf8(e.text.clone()).ok().map(|s| (s, e))) {
+        Some((s, env)) => {
+            let mut item = Item::new(format!("{}", s), ItemType::Received, Source::Ip(ip.clone())).signature(m.signature);
+            if let Some(stamp) = m.stamp {
+                item = item.read_receipt(ip.clone(), stamp);
+            }
+            if let Some(secs) = env.expire {
+                item = item.expire_after(secs);
+            }
+            if let Some(id) = env.id {
+                item = item.envelope_id(id);
+            }
+            match (env.edit, env.delete) {
+                (_, Some(id)) => {
+                    o.send(ConsoleMessage::Delete(id, Source::Ip(ip))).expect("Error in console::new_msg");
+                    return;
+                },
+                (Some(id), _) => o.send(ConsoleMessage::Edit(id, item)).expect("Error in console::new_msg"),
+                _ => raw_item(o.clone(), item)
+            }
+
+            #[cfg(not(feature = "no_notify"))]
+            notify(ip, o);
+        }
+        _ => {
+            msg(o, format!("error: could not decode message"), ItemType::Error, Source::Ip(ip));
+        }
+    }
+}
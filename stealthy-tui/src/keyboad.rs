@@ -19,6 +19,9 @@ pub enum UserInput {
     PageUp,
     CtrlR,
     CtrlS,
+    CtrlF,
+    Tab,
+    ShiftTab,
 }
 
 /// Use to receive user input.
@@ -78,6 +81,12 @@ impl InputKeyboard {
             Some(UserInput::CtrlR)
         } else if buf == vec![19] {          // Ctrl + S
             Some(UserInput::CtrlS)
+        } else if buf == vec![6] {           // Ctrl + F
+            Some(UserInput::CtrlF)
+        } else if buf == vec![9] {           // Tab
+            Some(UserInput::Tab)
+        } else if buf == vec![27, 91, 90] {  // Shift + Tab
+            Some(UserInput::ShiftTab)
         } else if buf.len() < 3 {            // Some character
             Some(UserInput::Character(buf))
         } else if buf == vec![27, 91, 53, 126] { // Page up
@@ -0,0 +1,581 @@
+mod outputs;
+mod arguments;
+mod console;
+mod view;
+mod model;
+mod keyboad;
+mod commands;
+mod upload;
+mod alert;
+mod qrcode;
+mod broadcast;
+
+use std::thread;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use stealthy_core::message::{Message, IncomingMessage};
+use stealthy_core::layer::{Layers, Layer};
+use stealthy_core::builder::LayersBuilder;
+use stealthy_core::tools::write_data;
+use stealthy_core::iptools::IpAddresses;
+use crate::arguments::{parse_arguments, Arguments};
+use crate::console::ConsoleMessage;
+use crate::view::View;
+use crate::keyboad::{InputKeyboard, UserInput};
+use crate::model::{ItemType, Model, Item};
+use crate::model::Source;
+use crate::console::Console;
+use crate::outputs::WelcomeData;
+use crate::alert::{Alerts, AlertEvent};
+use stealthy_core::signing::Signing;
+use stealthy_core::handshake::RekeyPolicy;
+use stealthy_core::outbox::Outbox;
+use stealthy_core::presence::{Presence, AWAY_TIMEOUT};
+use stealthy_core::envelope::Envelope;
+use stealthy_core::sendhandle::WouldBlock;
+use stealthy_core::{binding, gpg, negotiation, presence, reorder, replay, rsatools, tools};
+
+type ArcModel = Arc<Mutex<Model>>;
+type ArcView = Arc<Mutex<View>>;
+
+/// Listens for incoming messages from the network.
+fn recv_loop(o: Console, rx: Receiver<IncomingMessage>, alerts: Alerts) {
+
+    thread::spawn(move || {
+        loop { match rx.recv() {
+            Ok(msg) => {
+                match msg {
+                    IncomingMessage::New(msg) => {
+                        o.new_msg(msg);
+                        alerts.fire(AlertEvent::Message);
+                    }
+                    IncomingMessage::Ack(id) => {
+                        o.ack_msg(id);
+                    }
+                    IncomingMessage::Delivered(id) => {
+                        o.delivered_msg(id);
+                    }
+                    IncomingMessage::Read(id) => {
+                        o.read_msg(id);
+                    }
+                    IncomingMessage::Presence(ip, p) => {
+                        if p == Presence::Offline {
+                            o.status(format!("{} has gone offline.", ip));
+                        }
+                        o.presence(ip, p);
+                    }
+                    IncomingMessage::SendFailed(id, reason) => {
+                        o.failed_msg(id);
+                        o.error(format!("Message could not be delivered: {}", reason));
+                        alerts.fire(AlertEvent::Error);
+                    }
+                    IncomingMessage::Error(e) => {
+                        o.error(e.to_string());
+                        alerts.fire(AlertEvent::Error);
+                    }
+                    IncomingMessage::FileUpload(msg) => {
+                        upload::save_upload(o.clone(), msg);
+                        alerts.fire(AlertEvent::FileUpload);
+                    }
+                    IncomingMessage::AckProgress(id, done, total) => {
+                        o.ack_msg_progress(id, done, total);
+                    }
+                    IncomingMessage::KeyChanged(ip, a) => {
+                        commands::key_changed(o.clone(), &ip, &a);
+                    }
+                    IncomingMessage::SessionOpened(s) => match s.replaces {
+                        Some(_) => o.status(format!("Rekeyed session with {}.", s.ip)),
+                        None => o.status(format!("Established session with forward secrecy with {}.", s.ip))
+                    }
+                    IncomingMessage::SessionClosed(s) => {
+                        o.status(format!("Discarded the keys of an old session with {}.", s.ip));
+                    }
+                    IncomingMessage::Writable(ip) => {
+                        o.status(format!("The link to {} has room again.", ip));
+                    }
+                    IncomingMessage::Cancelled(ip, id) => {
+                        o.failed_msg(id);
+                        o.status(format!("The message or file transfer with {} has been cancelled.", ip));
+                    }
+                    // Handled by the network layer.
+                    IncomingMessage::KeyChallenge(_) | IncomingMessage::KeyResponse(_) | IncomingMessage::Batch(_) |
+                    IncomingMessage::Handshake(_) | IncomingMessage::Receipt(_) | IncomingMessage::Rollover(_) |
+                    IncomingMessage::Keepalive(_) | IncomingMessage::Group(_) | IncomingMessage::Cancel(_) | IncomingMessage::Timeout(_, _, _) | IncomingMessage::PacketFailed(_, _) => { }
+                }
+            },
+            // The layers have been shut down.
+            Err(_) => break
+        }}
+    });
+}
+
+#[derive(Clone, Debug)]
+pub struct GlobalState {
+    start_time: time::Timespec
+}
+
+static mut GLOBAL_STATE: Option<GlobalState> = None;
+
+// returns the uptime of stealthy in seconds
+fn uptime() -> i64 {
+    // TODO access to global state needs to be synchronized
+    unsafe {
+        time::get_time().sec - GLOBAL_STATE.clone().unwrap().start_time.sec
+    }
+}
+
+fn init_global_state() {
+    unsafe {
+        GLOBAL_STATE = Some(GlobalState {
+            start_time: time::get_time(),
+        })
+    };
+}
+
+
+/// Returns the message for the ip. Peers which do not support the options of the envelope
+/// receive the plain text. Retractions are not sent to such peers.
+fn create_data(dstip: String, env: &Envelope, o: &Console, l: &Layers) -> Option<(Message, u64)> {
+    let buf = match l.supports(&dstip, negotiation::FEATURE_ENVELOPE) {
+        Some(true) => env.encode(),
+        _ if env.delete.is_some() => {
+            o.status(format!("{} runs an older version of stealthy which cannot delete messages.", dstip));
+            return None;
+        },
+        _ => {
+            if env.has_options() {
+                o.status(format!("{} runs an older version of stealthy. The message is sent without options, e.g. it does not expire and an edit is shown as a new message.", dstip));
+            }
+            env.text.clone()
+        }
+    };
+    Some((Message::new(dstip, buf), rand::random::<u64>()))
+}
+
+fn send_message(txt: String, o: Console, l: &Layers, dstips: &IpAddresses) {
+    send_envelope(Envelope::new(txt.into_bytes()), o, l, dstips);
+}
+
+/// Sends a text message together with its options (see `Envelope`). Each message gets a
+/// random id so that it can be edited or deleted later. Edits and retractions do not
+/// create a new item but change the referenced one.
+fn send_envelope(env: Envelope, o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let envelope_id = rand::random::<u64>();
+    let env = env.with_id(envelope_id);
+    let txt = String::from_utf8_lossy(&env.text).to_string();
+    let mut item = Item::new(txt, ItemType::MyMessage, model::Source::You);
+
+    let v = dstips.as_strings()
+        .iter()
+        .filter_map(|dstip| create_data(dstip.clone(), &env, &o, l))
+        .collect::<Vec<_>>();
+
+    match (env.edit, env.delete) {
+        (_, Some(id)) => o.send(ConsoleMessage::Delete(id, model::Source::You)),
+        (Some(id), _) => o.send(ConsoleMessage::Edit(id, item)),
+        _ => {
+            for (_, id) in &v {
+                item = item.add_id(*id);
+            }
+            if let [(ref msg, _)] = v.as_slice() {
+                item = item.to(&msg.ip);
+            }
+            o.msg_item(item.envelope_id(envelope_id));
+        }
+    }
+
+    for (msg, id) in v {
+        send_nonblocking(msg, id, &o, l);
+    }
+}
+
+/// Sends the message without blocking the keyboard loop. If the link to the peer is
+/// congested the message waits in background until the window of the peer has room.
+fn send_nonblocking(msg: Message, id: u64, o: &Console, l: &Layers) {
+    if let Err(WouldBlock(msg)) = l.try_send(msg, id) {
+        o.status(format!("The link to {} is congested. The message is sent as soon as it has room.", msg.ip));
+        l.send(msg, id, true);
+    }
+}
+
+/// Sends an individually encrypted copy of the message to each ip of the broadcast list.
+/// The status of each recipient is shown in one item.
+fn send_broadcast(list: &str, txt: String, o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let env = Envelope::new(txt.clone().into_bytes());
+    let mut item = Item::new(txt, ItemType::MyMessage, model::Source::You).broadcast(list);
+
+    let v = dstips.as_strings()
+        .into_iter()
+        .filter_map(|dstip| create_data(dstip.clone(), &env, &o, l).map(|(msg, id)| (dstip, msg, id)))
+        .collect::<Vec<_>>();
+
+    for (dstip, _, id) in &v {
+        item = item.add_recipient(dstip.clone(), *id);
+    }
+    o.msg_item(item);
+
+    for (_, msg, id) in v {
+        send_nonblocking(msg, id, &o, l);
+    }
+}
+
+/// Sends several lines at once. Small lines are packed into one packet by the network layer.
+fn send_messages(lines: Vec<String>, o: Console, l: &Layers, dstips: &IpAddresses) {
+
+    let msgs = lines.iter()
+        .flat_map(|txt| dstips.as_strings().into_iter().map(move |dstip| Message::new(dstip, txt.clone().into_bytes())))
+        .collect::<Vec<_>>();
+
+    let n = dstips.as_strings().len();
+    if n == 0 {
+        return;
+    }
+    let ids = l.send_batch(msgs);
+
+    // For each line there is one message per destination IP.
+    for (txt, chunk) in lines.iter().zip(ids.chunks(n)) {
+        let mut item = Item::new(format!("{}", txt), ItemType::MyMessage, model::Source::You);
+        for id in chunk {
+            item = item.add_id(*id);
+        }
+        if n == 1 {
+            item = item.to(&dstips.as_strings()[0]);
+        }
+        o.msg_item(item);
+    }
+}
+
+fn init_network_layer(args: &Arguments, console: Console, dstips: &IpAddresses) -> Layer {
+    let signing = Signing::from_files(args.sign_key_file.as_ref(), args.sign_key_passphrase.as_ref(), args.verify_keys_file.as_ref())
+        .expect("Could not load signing keys.");
+    let rekey = RekeyPolicy::new(args.rekey_interval, args.rekey_messages);
+    let outbox = args.outbox_file.as_ref().map(|f| Outbox::open(f, args.outbox_passphrase.as_ref().map(|p| p.as_str()).unwrap_or(""))
+        .expect("Could not open outbox."));
+    let builder = LayersBuilder::new()
+        .with_transport(args.transport.clone())
+        .with_accept(dstips.clone())
+        .with_status(console.to_status())
+        .with_replay_window(args.replay_window)
+        .with_signing(signing)
+        .with_rekey(rekey)
+        .with_max_retries(args.max_retries.map(|n| n.min(u32::max_value() as u64) as u32).unwrap_or(binding::DEFAULT_MAX_RETRIES));
+    let builder =
+        if args.hybrid_mode {
+            // use asymmetric encryption
+            builder.with_hybrid(&args.rcpt_pubkey_file, &args.privkey_file, args.token_pin.as_ref())
+        } else {
+            // use symmetric encryption
+            let b = builder.with_key(&args.secret_key).with_cipher(&args.cipher);
+            match args.peer_keys {
+                Some(ref c) => b.with_peer_keys(c),
+                None => b
+            }
+        };
+    let identity = match args.hybrid_mode {
+        true => rsatools::key_as_der(&gpg::read_public_key(&args.pubkey_file).expect("Could not read public key.")),
+        false => vec![]
+    };
+    builder.build().expect("Initialization failed.")
+        .with_padding(args.padding)
+        .with_jitter(args.jitter.unwrap_or(0))
+        .with_max_rate(args.max_rate)
+        .with_outbox(outbox)
+        .with_reorder_timeout(args.reorder_timeout.unwrap_or(reorder::DEFAULT_REORDER_TIMEOUT))
+        .with_deniable(args.deniable)
+        .with_identity(identity)
+        .with_cover_traffic(args.cover, dstips.as_strings())
+}
+
+fn keyboard_loop(o: Console, l: Layers, dstips: IpAddresses, model: ArcModel, view: ArcView) {
+    let mut input = InputKeyboard::new();
+
+    loop {
+        let i = input.read_char();
+        model.lock().unwrap().update_last_keypress();
+        // While searching the keys are used by the search.
+        let i = match view.lock().unwrap().search_key(i) {
+            Some(i) => i,
+            None => continue
+        };
+        match i {
+            UserInput::Character(buf) => {
+                let mut v = vec![];
+                for c in buf {
+                    let mut m = model.lock().unwrap();
+                    if c == 13 {
+                        let s = m.apply_enter();
+                        send_message(s, o.clone(), &l, &m.recipients(&dstips.only(&l.members())));
+                    } else {
+                        v.push(c);
+                        if String::from_utf8(v.clone()).is_ok() {
+                            m.update_input(v.clone());
+                            v.clear();
+                        }
+                    }
+                }
+                view.lock().unwrap().refresh();
+            },
+            UserInput::Escape | UserInput::CtrlD => {
+                // The peers mark us as offline immediately.
+                l.announce_offline(dstips.as_strings());
+                l.shutdown();
+                view.lock().unwrap().close();
+                o.send(ConsoleMessage::Exit);
+                // Wait some seconds to give the thread in create_console_sender a chance to
+                // release its view so that the terminal is recovered correctly.
+                thread::sleep(Duration::from_millis(100));
+                break;
+            },
+            UserInput::ArrowDown => {
+                view.lock().unwrap().scroll_down();
+            },
+            UserInput::ArrowUp => {
+                view.lock().unwrap().scroll_up();
+            },
+            UserInput::Backspace => {
+                model.lock().unwrap().apply_backspace();
+                view.lock().unwrap().refresh();
+            },
+            UserInput::End => {
+                view.lock().unwrap().key_end();
+            },
+            UserInput::PageDown => {
+                view.lock().unwrap().page_down();
+            },
+            UserInput::PageUp => {
+                view.lock().unwrap().page_up();
+            },
+            UserInput::CtrlR => {
+                view.lock().unwrap().toggle_raw_view();
+            },
+            UserInput::CtrlS => {
+                model.lock().unwrap().toggle_scramble();
+                view.lock().unwrap().refresh();
+            },
+            UserInput::CtrlF => {
+                view.lock().unwrap().start_search();
+            },
+            UserInput::Tab => {
+                if model.lock().unwrap().next_tab() {
+                    view.lock().unwrap().select_tab();
+                }
+            },
+            UserInput::ShiftTab => {
+                if model.lock().unwrap().prev_tab() {
+                    view.lock().unwrap().select_tab();
+                }
+            },
+            UserInput::Enter => {
+                let s = model.lock().unwrap().apply_enter();
+                view.lock().unwrap().refresh();
+                // Messages are only sent to the current members of the group.
+                let members = dstips.only(&l.members());
+                if s.len() > 0 {
+                    if s.starts_with("/") {
+                        commands::parse_command(s, o.clone(), &l, &members, &model);
+                    } else {
+                        // In the tab of a peer messages are only sent to the peer.
+                        let to = model.lock().unwrap().recipients(&members);
+                        send_message(s, o.clone(), &l, &to);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn create_console(model: ArcModel, view: ArcView) -> Console {
+
+    // The sender "tx" is used at other locations to send messages to the output.
+    let (tx, rx) = channel::<ConsoleMessage>();
+
+    thread::spawn(move || {
+        loop { match rx.recv().unwrap() {
+            ConsoleMessage::TextMessage(item) => {
+                model.lock().unwrap().add_message(item.clone());
+                view.lock().unwrap().adjust_scroll_offset(item);
+            },
+            ConsoleMessage::Ack(id) => {
+                model.lock().unwrap().ack(id);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Delivered(id) => {
+                model.lock().unwrap().delivered(id);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Read(id) => {
+                model.lock().unwrap().read(id);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Failed(id) => {
+                model.lock().unwrap().failed(id);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::PeerVerified(ip, verified) => {
+                view.lock().unwrap().set_verified(ip, verified);
+            },
+            ConsoleMessage::Presence(ip, p) => {
+                model.lock().unwrap().peers.update(&ip, p, replay::now());
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Who => {
+                let now = replay::now();
+                let peers = model.lock().unwrap().peers.list(now);
+                if peers.is_empty() {
+                    model.lock().unwrap().add_message(Item::new_system("No peers."));
+                }
+                for (ip, p, t) in peers {
+                    let item = Item::new_system(&format!("{}: {} (last seen {})", ip, p.name(), presence::last_seen(t, now)));
+                    model.lock().unwrap().add_message(item.clone());
+                    view.lock().unwrap().adjust_scroll_offset(item);
+                }
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Edit(id, item) => {
+                model.lock().unwrap().edit(id, item);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::Delete(id, from) => {
+                model.lock().unwrap().delete(id, from);
+                view.lock().unwrap().refresh();
+            },
+            ConsoleMessage::AckProgress(id, done, total) => {
+                let refresh= model.lock().unwrap().ack_progress(id, done, total);
+                if refresh {
+                    view.lock().unwrap().refresh();
+                }
+            },
+            // We need this as otherwise "out" is not dropped and the terminal state
+            // is not restored.
+            ConsoleMessage::Exit => {
+                break;
+            },
+            ConsoleMessage::SetScrambleTimeout(n) => {
+                model.lock().unwrap().scramble_timeout = n;
+            },
+            ConsoleMessage::ScrambleTick => {
+                let mut redraw = false;
+                {
+                    let mut m = model.lock().unwrap();
+                    redraw |= m.remove_expired(SystemTime::now());
+                    if !m.is_scrambled() {
+                        let last_keypress = m.last_keypress();
+                        if last_keypress.elapsed().unwrap().as_secs() > m.scramble_timeout as u64 {
+                            m.scramble(true);
+                            redraw = true;
+                        }
+                    }
+                }
+                if redraw {
+                    view.lock().unwrap().refresh();
+                }
+            }
+        }}
+    });
+    Console::new(tx)
+}
+
+fn scramble_trigger(o: Console) {
+    thread::spawn(move || {
+       loop {
+           thread::sleep(Duration::from_secs(1));
+           o.send(ConsoleMessage::ScrambleTick);
+       }
+    });
+}
+
+/// Announces when we are away because no key has been pressed for a while and redraws
+/// the screen when the state of a peer has changed because its keepalives are missing.
+fn presence_trigger(model: ArcModel, view: ArcView, tx: Sender<Presence>) {
+    thread::spawn(move || {
+        let mut away = false;
+        let mut peers = vec![];
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            let (idle, current) = {
+                let m = model.lock().unwrap();
+                let idle = m.last_keypress().elapsed().map(|d| d.as_secs() >= AWAY_TIMEOUT).unwrap_or(false);
+                (idle, m.peers.list(replay::now()).into_iter().map(|(_, p, _)| p).collect::<Vec<_>>())
+            };
+            if idle != away {
+                away = idle;
+                let _ = tx.send(if away { Presence::Away } else { Presence::Online });
+            }
+            if current != peers {
+                peers = current;
+                view.lock().unwrap().refresh();
+            }
+        }
+    });
+}
+
+fn welcome_data(args: &Arguments, network_layer: &Layer, dstips: &IpAddresses) -> WelcomeData {
+    let mut hashed_encryption_keys = vec![];
+    let mut hashed_public_key = String::new();
+
+    if args.hybrid_mode {
+        hashed_encryption_keys = dstips.as_strings().into_iter()
+            .map(|ip| { let h = tools::sha1(network_layer.layers.encryption_key(&ip).as_slice()); (ip, h) })
+            .collect();
+        hashed_public_key = tools::sha1(&rsatools::key_as_der(&gpg::read_public_key(&args.pubkey_file).unwrap()));
+    }
+
+    WelcomeData {
+        hybrid_mode: args.hybrid_mode,
+        fingerprint_mismatches: args.fingerprints.iter()
+            .filter(|f| args.hybrid_mode && hashed_encryption_keys.iter().any(|k| k.0 == f.0 && k.1 != f.1))
+            .map(|(ip, _)| ip.clone())
+            .collect(),
+        hashed_hybrid_encryption_keys: hashed_encryption_keys,
+        hashed_hybrid_public_key: hashed_public_key,
+        signing_public_key: network_layer.layers.signing_key(),
+        kdf_params: args.kdf_params.clone(),
+    }
+}
+
+fn main() {
+    init_global_state();
+
+    // Parse command line arguments.
+	let args = parse_arguments().expect("Cannot parse arguments");;
+
+    let dstips = IpAddresses::from_comma_list(&args.dstip);
+
+    // The model stores all information which is required to show the screen.
+    let model = Arc::new(Mutex::new(Model::new()));
+
+    let view = Arc::new(Mutex::new(View::new(model.clone())));
+
+    let c = create_console(model.clone(), view.clone());
+
+    let network_layer = init_network_layer(&args, c.clone(), &dstips);
+
+    // Show welchome message.
+    outputs::welcome(&args, c.clone(), welcome_data(&args, &network_layer, &dstips), &dstips);
+
+    commands::load_verified(c.clone(), &network_layer.layers, &dstips);
+
+    view.lock().unwrap().set_read_receipts(network_layer.layers.read_receipts());
+
+    model.lock().unwrap().peers.set_ips(dstips.as_strings());
+    model.lock().unwrap().set_tabs(dstips.as_strings());
+    model.lock().unwrap().lists = args.lists;
+    presence_trigger(model.clone(), view.clone(), network_layer.layers.keepalives(dstips.as_strings()));
+
+    scramble_trigger(c.clone());
+
+    // This is the loop which handles messages received from the network.
+    recv_loop(c.clone(), network_layer.rx, args.alerts.clone());
+
+    // Waits for data from the keyboard.
+    // If data is received the model and the view will be updated.
+    keyboard_loop(c.clone(), network_layer.layers, dstips, model, view);
+
+    // IMPORTANT! If the are threads which are using a clone of the view, the view isn't destroyed
+    // properly and the terminal state is not restored.
+}
+
@@ -0,0 +1,589 @@
+use time::Tm;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use stealthy_core::signing::SignatureStatus;
+use stealthy_core::replay::Stamp;
+use stealthy_core::presence::Peers;
+use stealthy_core::iptools::IpAddresses;
+use crate::broadcast::BroadcastLists;
+
+static MAX_BUF_LEN: usize = 500;
+
+pub struct Model {
+    /// List of all messages for the main window.
+    pub buf: Vec<Item>,
+    /// Content of the input field.
+    pub input: Vec<u8>,
+    /// Time of last keypress
+    pub last_key: SystemTime,
+    scrambled: bool,
+    pub scramble_timeout: u32,
+    last_ack_progress_view_update: SystemTime,
+    /// State of the peers announced by their keepalives.
+    pub peers: Peers,
+    /// Named lists of peers for /broadcast.
+    pub lists: BroadcastLists,
+    /// Peers which have their own tab besides the tab of the group.
+    tabs: Vec<String>,
+    /// Index of the current tab. 0 is the tab of the group.
+    tab: usize,
+    /// Number of received messages of each peer which have not been seen yet.
+    unread: HashMap<String, usize>,
+}
+
+impl Model {
+    pub fn new() -> Model {
+        Model {
+            buf: vec![],
+            input: vec![],
+            last_key: SystemTime::now(),
+            scrambled: false,
+            scramble_timeout: 20,
+            last_ack_progress_view_update: SystemTime::now(),
+            peers: Peers::new(),
+            lists: BroadcastLists::new(),
+            tabs: vec![],
+            tab: 0,
+            unread: HashMap::new(),
+        }
+    }
+
+    /// Creates a tab for each peer in addition to the tab of the group which shows all
+    /// messages. There are no tabs for a single peer.
+    pub fn set_tabs(&mut self, ips: Vec<String>) {
+        self.tabs = match ips.len() {
+            0 | 1 => vec![],
+            _ => ips
+        };
+        self.tab = 0;
+    }
+
+    /// Returns the peers which have a tab and the index of the current tab. The tab of the
+    /// group has the index 0, the tab of the first peer 1.
+    pub fn tabs(&self) -> (&[String], usize) {
+        (&self.tabs, self.tab)
+    }
+
+    /// Switches to the next tab. Returns false if there are no tabs.
+    pub fn next_tab(&mut self) -> bool {
+        if self.tabs.is_empty() {
+            return false;
+        }
+        self.tab = (self.tab + 1) % (self.tabs.len() + 1);
+        true
+    }
+
+    /// Switches to the previous tab. Returns false if there are no tabs.
+    pub fn prev_tab(&mut self) -> bool {
+        if self.tabs.is_empty() {
+            return false;
+        }
+        self.tab = (self.tab + self.tabs.len()) % (self.tabs.len() + 1);
+        true
+    }
+
+    /// Returns the peer of the current tab or None for the tab of the group.
+    pub fn current_peer(&self) -> Option<&str> {
+        self.tab.checked_sub(1).map(|i| self.tabs[i].as_str())
+    }
+
+    /// Returns the destinations of the messages typed in the current tab.
+    pub fn recipients(&self, dstips: &IpAddresses) -> IpAddresses {
+        match self.current_peer() {
+            Some(ip) => dstips.only(&[ip.to_string()]),
+            None => dstips.clone()
+        }
+    }
+
+    /// Returns true if the item is shown in the current tab.
+    pub fn is_visible(&self, i: &Item) -> bool {
+        self.current_peer().map(|ip| i.belongs_to(ip)).unwrap_or(true)
+    }
+
+    /// Returns the number of received messages of the peer which have not been seen yet or
+    /// of all peers if no peer is given.
+    pub fn unread(&self, ip: Option<&str>) -> usize {
+        match ip {
+            Some(ip) => self.unread.get(ip).cloned().unwrap_or(0),
+            None => self.unread.values().sum()
+        }
+    }
+
+    /// Is called when the newest messages of the current tab are on the screen.
+    pub fn mark_read(&mut self) {
+        match self.current_peer().map(|ip| ip.to_string()) {
+            Some(ip) => { self.unread.remove(&ip); },
+            None => self.unread.clear()
+        }
+    }
+
+    /// Returns the items which are shown in the current tab.
+    pub fn visible(&self) -> Vec<Item> {
+        self.buf.iter().filter(|i| self.is_visible(i)).cloned().collect()
+    }
+
+    pub fn toggle_scramble(&mut self) {
+        self.scrambled = !self.scrambled;
+    }
+
+    pub fn scramble(&mut self, value: bool) {
+        self.scrambled = value;
+    }
+
+    pub fn is_scrambled(&self) -> bool {
+        self.scrambled
+    }
+
+    pub fn update_last_keypress(&mut self) {
+        self.last_key = SystemTime::now();
+    }
+
+    pub fn last_keypress(&self) -> SystemTime {
+        self.last_key.clone()
+    }
+
+    /// Adds a new character (given as byte stream) to the input field.
+    pub fn update_input(&mut self, buf: Vec<u8>) {
+        for b in String::from_utf8(buf)
+            .unwrap().chars().filter(|c| !c.is_control()).collect::<String>().as_bytes() {
+            self.input.push(*b);
+        }
+    }
+
+    /// Deletes one character from the input field.
+    pub fn apply_backspace(&mut self) {
+        loop {
+            self.input.pop();
+            if String::from_utf8(self.input.clone()).is_ok() {
+                break;
+            }
+        }
+    }
+
+    pub fn apply_enter(&mut self) -> String {
+        let s = String::from_utf8(self.input.clone()).expect("Invalid utf8 string.");
+        self.input.clear();
+        s
+    }
+
+    // Is called when we receive an ack for a file upload.
+    /// `id` - id of the item in the buffer
+    /// `nbytes` - number of bytes of the corresponding package that was transmitted
+    ///
+    /// Several items can share the same id if they have been sent in one packet.
+    pub fn ack(&mut self, id: u64) {
+        for item in self.buf.iter_mut().rev() {
+            let exists = item.id.iter().find(|i| **i == id).is_some();
+            if exists {
+                item.acks_received += 1;
+                item.update_recipient(id, Status::Acked);
+            }
+        }
+    }
+
+    /// Is called when we receive a verified receipt of the recipient for a message.
+    pub fn delivered(&mut self, id: u64) {
+        for item in self.buf.iter_mut().rev() {
+            if item.id.contains(&id) {
+                item.receipts_received += 1;
+                item.update_recipient(id, Status::Delivered);
+            }
+        }
+    }
+
+    /// Is called when we receive a verified read receipt of the recipient for a message.
+    pub fn read(&mut self, id: u64) {
+        for item in self.buf.iter_mut().rev() {
+            if item.id.contains(&id) {
+                item.reads_received += 1;
+                item.update_recipient(id, Status::Read);
+            }
+        }
+    }
+
+    /// Returns true if the read receipt for the received message with the given stamp has
+    /// not been sent yet. Afterwards the message counts as read.
+    pub fn take_read_receipt(&mut self, ip: &str, stamp: &Stamp) -> bool {
+        for item in self.buf.iter_mut().rev() {
+            let found = match item.read_receipt {
+                Some((ref i, ref s)) => i == ip && s == stamp,
+                None => false
+            };
+            if found {
+                item.read_receipt = None;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Is called when a message cannot be delivered.
+    pub fn failed(&mut self, id: u64) {
+        for item in self.buf.iter_mut().rev() {
+            if item.id.contains(&id) {
+                item.failed = true;
+                item.update_recipient(id, Status::Failed);
+            }
+        }
+    }
+
+    pub fn ack_progress(&mut self, id: u64, done: usize, total: usize) -> bool {
+        let mut exists = false;
+        for item in self.buf.iter_mut().rev() {
+            exists = item.id.iter().find(|i| **i == id).is_some();
+            if exists {
+                item.pending_acks = done;
+                item.total_acks = total;
+                break;
+            }
+        }
+        // Do not refresh the display too often as otherwise the console will have a slack.
+        let mut refresh = false;
+        if exists {
+            let elapsed = self.last_ack_progress_view_update.elapsed().unwrap().as_millis();
+            if elapsed > 20 {
+                refresh = true;
+                self.last_ack_progress_view_update = SystemTime::now();
+            }
+        }
+        done == total || refresh
+    }
+
+    /// Removes the received messages which have expired. Returns true if a message has
+    /// been removed.
+    pub fn remove_expired(&mut self, now: SystemTime) -> bool {
+        let n = self.buf.len();
+        self.buf.retain(|i| match i.expires {
+            Some(t) => t > now,
+            None => true
+        });
+        self.buf.len() != n
+    }
+
+    /// Replaces the text of the message with the given envelope id which has been sent by
+    /// the source of the item. If the message does not exist anymore, e.g. because it has
+    /// expired, the item is added as a new message.
+    pub fn edit(&mut self, id: u64, i: Item) {
+        let from = i.source();
+        match self.buf.iter_mut().rev().find(|item| item.from == from && item.envelope_id == Some(id)) {
+            // A deleted message cannot be edited.
+            Some(item) if item.deleted => { },
+            Some(item) => {
+                item.msg = i.msg;
+                item.signature = i.signature;
+                item.edited = true;
+            },
+            None => self.add_message(i.edited())
+        }
+    }
+
+    /// Removes the text of the message with the given envelope id which has been sent by
+    /// the source. The message is shown as deleted.
+    pub fn delete(&mut self, id: u64, from: Source) {
+        if let Some(item) = self.buf.iter_mut().find(|item| item.from == from && item.envelope_id == Some(id)) {
+            item.msg = String::new();
+            item.signature = SignatureStatus::None;
+            item.edited = false;
+            item.deleted = true;
+        }
+    }
+
+    /// Returns the envelope id of the n-th last message we have sent which has not been
+    /// deleted. The last message is 1.
+    pub fn sent(&self, n: usize) -> Option<u64> {
+        self.buf.iter().rev()
+            .filter(|i| i.from == Source::You && !i.deleted)
+            .filter_map(|i| i.envelope_id)
+            .nth(n.checked_sub(1)?)
+    }
+
+    pub fn add_message(&mut self, i: Item) {
+        if let Source::Ip(ref ip) = i.from {
+            *self.unread.entry(ip.clone()).or_insert(0) += 1;
+        }
+        self.buf.push(i);
+        // TODO not very efficient
+        while self.buf.len() > MAX_BUF_LEN {
+            self.buf.remove(0);
+        }
+    }
+}
+
+/// Status of a message for one recipient of a broadcast.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Status {
+    Pending,
+    Acked,
+    /// A verified receipt has been received.
+    Delivered,
+    /// A verified read receipt has been received.
+    Read,
+    Failed,
+}
+
+impl Status {
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Status::Pending => "pending",
+            Status::Acked => "sent",
+            Status::Delivered => "delivered",
+            Status::Read => "read",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// A recipient of a broadcast and the id of its copy of the message.
+#[derive(Clone)]
+pub struct Recipient {
+    pub ip: String,
+    pub id: u64,
+    pub status: Status,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Source {
+    Ip(String),
+    You,
+    System,
+    Raw,
+}
+
+#[derive(Clone)]
+pub struct Item {
+    pub msg: String,
+    pub typ: ItemType,
+    pub id: Vec<u64>,  // In group chat scenarios one item can have several IDs.
+    pub acks_received: usize,
+    /// Number of verified receipts of the recipients.
+    pub receipts_received: usize,
+    /// Number of verified read receipts of the recipients.
+    pub reads_received: usize,
+    /// Ip and stamp of a received message whose read receipt has not been sent yet.
+    pub read_receipt: Option<(String, Stamp)>,
+    /// True if the message could not be delivered to one of the recipients.
+    pub failed: bool,
+    /// Time when a received message is removed because it expires.
+    pub expires: Option<SystemTime>,
+    /// Id of the message chosen by the sender which is referenced by edits.
+    pub envelope_id: Option<u64>,
+    /// True if the text has been replaced by an edit.
+    pub edited: bool,
+    /// True if the sender has deleted the message.
+    pub deleted: bool,
+    /// Name of the broadcast list to which the message has been sent.
+    pub list: Option<String>,
+    /// Status of the message for each recipient of a broadcast.
+    pub recipients: Vec<Recipient>,
+    /// Peer to which the message has been sent if it has been sent to only one peer.
+    pub to: Option<String>,
+    pub tim: Tm,
+    pub total_acks: usize,
+    pub pending_acks: usize,
+    /// Result of the signature check of a received message.
+    pub signature: SignatureStatus,
+    from: Source,
+}
+
+impl Item {
+    pub fn new_system(msg: &str) -> Item {
+        Item::new(msg.to_string(), ItemType::Info, Source::System)
+    }
+
+    /// Creates a new item without a symbol and without an id.
+    pub fn new(msg: String, typ: ItemType, from: Source) -> Item {
+        Item {
+            msg,
+            typ,
+            id: vec![],
+            acks_received: 0,
+            receipts_received: 0,
+            reads_received: 0,
+            read_receipt: None,
+            failed: false,
+            expires: None,
+            envelope_id: None,
+            edited: false,
+            deleted: false,
+            list: None,
+            recipients: vec![],
+            to: None,
+            tim: time::now(),
+            from,
+            total_acks: 0,
+            pending_acks: 0,
+            signature: SignatureStatus::None,
+        }
+    }
+
+    pub fn add_size(mut self, n: usize) -> Item {
+        self.total_acks = n;
+        self.pending_acks = n;
+        self
+    }
+
+    pub fn raw(mut self) -> Item {
+        self.from = Source::Raw;
+        self
+    }
+
+    /// Sets the message of an item.
+    pub fn message(mut self, msg: String) -> Item {
+        self.msg = msg;
+        self
+    }
+
+    /// Sets the result of the signature check.
+    pub fn signature(mut self, s: SignatureStatus) -> Item {
+        self.signature = s;
+        self
+    }
+
+    /// Requests a read receipt for a received message as soon as it is shown.
+    pub fn read_receipt(mut self, ip: String, stamp: Stamp) -> Item {
+        self.read_receipt = Some((ip, stamp));
+        self
+    }
+
+    /// Removes the item the given number of seconds from now.
+    pub fn expire_after(mut self, secs: u32) -> Item {
+        self.expires = Some(SystemTime::now() + Duration::from_secs(secs as u64));
+        self
+    }
+
+    pub fn envelope_id(mut self, id: u64) -> Item {
+        self.envelope_id = Some(id);
+        self
+    }
+
+    pub fn edited(mut self) -> Item {
+        self.edited = true;
+        self
+    }
+
+    /// Marks the item as message to the given broadcast list.
+    pub fn broadcast(mut self, list: &str) -> Item {
+        self.list = Some(list.to_string());
+        self
+    }
+
+    /// Adds a recipient of a broadcast together with the id of its copy.
+    pub fn add_recipient(mut self, ip: String, id: u64) -> Item {
+        self.recipients.push(Recipient { ip, id, status: Status::Pending });
+        self.add_id(id)
+    }
+
+    /// Marks the item as message to the given peer only.
+    pub fn to(mut self, ip: &str) -> Item {
+        self.to = Some(ip.to_string());
+        self
+    }
+
+    /// Returns true if the item is part of the conversation with the peer. Messages to the
+    /// whole group and status messages are part of every conversation.
+    pub fn belongs_to(&self, ip: &str) -> bool {
+        match self.from {
+            Source::Ip(ref i) => i == ip,
+            Source::You if self.list.is_some() => self.recipients.iter().any(|r| r.ip == ip),
+            Source::You => self.to.as_ref().map(|t| t == ip).unwrap_or(true),
+            Source::System | Source::Raw => true
+        }
+    }
+
+    /// Raises the status of the recipient whose copy has the given id.
+    fn update_recipient(&mut self, id: u64, s: Status) {
+        for r in self.recipients.iter_mut().filter(|r| r.id == id && r.status < s) {
+            r.status = s;
+        }
+    }
+
+    /// Sets the id of the item.
+    pub fn add_id(mut self, id: u64) -> Item {
+        self.id.push(id);
+        self
+    }
+
+    pub fn source(&self) -> Source {
+        self.from.clone()
+    }
+}
+
+// The type is used to determine the color.
+#[derive(Clone)]
+pub enum ItemType {
+    Introduction,
+    Received,
+    Error,
+    Info,
+    NewFile,
+    MyMessage,
+    UploadMessage,
+    /// Lines of a QR code.
+    Code,
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Item, ItemType, Model, Source};
+    use stealthy_core::iptools::IpAddresses;
+
+    #[test]
+    fn test_tabs() {
+
+        let mut m = Model::new();
+        m.set_tabs(vec!["1.1.1.1".to_string()]);
+        assert!(!m.next_tab());
+
+        m.set_tabs(vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]);
+        m.add_message(Item::new("a".to_string(), ItemType::Received, Source::Ip("1.1.1.1".to_string())));
+        m.add_message(Item::new("b".to_string(), ItemType::Received, Source::Ip("2.2.2.2".to_string())));
+        m.add_message(Item::new("c".to_string(), ItemType::MyMessage, Source::You).to("2.2.2.2"));
+        m.add_message(Item::new("d".to_string(), ItemType::MyMessage, Source::You));
+        m.add_message(Item::new_system("e"));
+        let texts = |m: &Model| m.visible().into_iter().map(|i| i.msg).collect::<Vec<_>>().join("");
+        let all = IpAddresses::from_comma_list("1.1.1.1,2.2.2.2");
+
+        assert_eq!(texts(&m), "abcde");
+        assert_eq!(m.recipients(&all).as_strings(), all.as_strings());
+        assert!(m.next_tab());
+        assert_eq!(m.current_peer(), Some("1.1.1.1"));
+        assert_eq!(texts(&m), "ade");
+        assert_eq!(m.recipients(&all).as_strings(), vec!["1.1.1.1".to_string()]);
+        assert!(m.next_tab());
+        assert_eq!(texts(&m), "bcde");
+        assert!(m.next_tab());
+        assert_eq!(m.current_peer(), None);
+        assert!(m.prev_tab());
+        assert_eq!(m.current_peer(), Some("2.2.2.2"));
+    }
+
+    #[test]
+    fn test_unread() {
+
+        let mut m = Model::new();
+        m.set_tabs(vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]);
+        for ip in &["1.1.1.1", "2.2.2.2", "2.2.2.2"] {
+            m.add_message(Item::new("a".to_string(), ItemType::Received, Source::Ip(ip.to_string())));
+        }
+        m.add_message(Item::new("b".to_string(), ItemType::MyMessage, Source::You));
+        assert_eq!(m.unread(Some("2.2.2.2")), 2);
+        assert_eq!(m.unread(None), 3);
+
+        // Only the messages of the peer of the current tab have been seen.
+        m.prev_tab();
+        m.mark_read();
+        assert_eq!(m.unread(Some("2.2.2.2")), 0);
+        assert_eq!(m.unread(None), 1);
+
+        m.next_tab();
+        m.mark_read();
+        assert_eq!(m.unread(None), 0);
+    }
+}
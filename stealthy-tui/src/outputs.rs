@@ -31,6 +31,23 @@ pub fn help_message(o: Console) {
         " ",
         "/help                 - this help message",
         "/uptime, /up          - uptime",
+        "/stats                - statistics, e.g. packets, bytes per peer and round-trip times",
+        "/pending              - list the packets which have not been acknowledged yet",
+        "/cancel               - cancel the messages and uploads which have not been acknowledged yet",
+        "/who                  - show which peers are online and when they were last seen",
+        "/group                - show the members of the group",
+        "/invite <ip>          - invite a peer to the group",
+        "/remove <ip>          - remove a peer from the group so that it cannot read new messages",
+        "/rekey                - replace the sessions with new keys",
+        "/rate [<bytes/s>|off] - show or limit the rate of outgoing packets, e.g. 64k",
+        "/genkey [rsa|ed25519] <prefix> - generate a new key pair",
+        "/verify <ip> [confirm|revoke] - compare the keys used for a peer",
+        "/qr [key]             - show the fingerprint or your public key as QR code",
+        "/rollover <file>|revoke - replace or revoke your signing key",
+        "/send [--expire <time>] <text> - send a message which expires, e.g. after 10m",
+        "/broadcast [<list> <text>] - send a message to the peers of a broadcast list",
+        "/edit <text>          - replace the text of the last message you have sent",
+        "/delete <n>           - delete the n-th last message you have sent at the receivers",
         "/cat <filename>       - send content of an UTF-8 encoded text file",
         "/upload <filename>    - send binary file",
         "/set scramble <value> - set timeout in seconds when to scramble content (default: 20)",
@@ -51,8 +68,15 @@ pub fn help_message(o: Console) {
 
 pub struct WelcomeData {
     pub hybrid_mode: bool,
-    pub hashed_hybrid_encryption_key: String,
+    /// Hash of the recipient key for each destination.
+    pub hashed_hybrid_encryption_keys: Vec<(String, String)>,
     pub hashed_hybrid_public_key: String,
+    /// Public key used to verify our signatures, if messages are signed.
+    pub signing_public_key: Option<String>,
+    /// Parameters of the KDF if the key has been derived from a passphrase.
+    pub kdf_params: Option<String>,
+    /// Destinations whose fingerprint in the keystore does not match the recipient key.
+    pub fingerprint_mismatches: Vec<String>,
 }
 
 pub fn welcome(args: &Arguments, o: Console, data: WelcomeData, dstips: &IpAddresses) {
@@ -62,13 +86,17 @@ pub fn welcome(args: &Arguments, o: Console, data: WelcomeData, dstips: &IpAddre
 
     let ips = dstips.as_strings().join(", ");
 
-    let (values, n) = normalize(&[&args.device, &ips, &ips], ' ');
+    let transport = match args.device_detected {
+        true => format!("{} (default route)", args.transport.description()),
+        false => args.transport.description()
+    };
+    let (values, n) = normalize(&[&transport, &ips, &ips], ' ');
 
     let v = vec![
         format!("The most secure ICMP messenger."),
         format!(" "),
         format!("┌─────────────────────┬─{}┐", chars(n, '─')),
-        format!("│ Listening on device │ {}│", values[0]),
+        format!("│ Listening on        │ {}│", values[0]),
         format!("│ Talking to IPs      │ {}│", values[1]),
         format!("│ Accepting IPs       │ {}│", values[2]),
         format!("└─────────────────────┴─{}┘", chars(n, '─')),
@@ -86,9 +114,30 @@ pub fn welcome(args: &Arguments, o: Console, data: WelcomeData, dstips: &IpAddre
     );
 
     if data.hybrid_mode {
-        o.raw(format!("Hash of encryption key : {}", data.hashed_hybrid_encryption_key), ItemType::Introduction, Source::System);
+        let keys = &data.hashed_hybrid_encryption_keys;
+        match keys.iter().all(|(_, h)| *h == keys[0].1) {
+            true => o.raw(format!("Hash of encryption key : {}", keys[0].1), ItemType::Introduction, Source::System),
+            false => for (ip, h) in keys {
+                o.raw(format!("Hash of key for {:<8}: {}", ip, h), ItemType::Introduction, Source::System);
+            }
+        }
         o.raw(format!("Hash of your public key: {}", data.hashed_hybrid_public_key), ItemType::Introduction, Source::System);
     }
+    if let Some(k) = data.signing_public_key {
+        o.raw(format!("Your signing key       : {}", k), ItemType::Introduction, Source::System);
+    }
+    for ip in data.fingerprint_mismatches {
+        o.raw(format!("WARNING: The recipient key does not match the fingerprint stored for {}.", ip), ItemType::Error, Source::System);
+    }
+    if let Some(p) = data.kdf_params {
+        o.raw(format!("KDF parameters         : {}", p), ItemType::Introduction, Source::System);
+    }
+    if args.deniable {
+        o.raw(format!("Deniable mode          : MAC keys are published after use"), ItemType::Introduction, Source::System);
+    }
+    if args.cover.is_some() {
+        o.raw(format!("Cover traffic          : dummy messages are sent at random intervals"), ItemType::Introduction, Source::System);
+    }
     o.raw(format!(" "), ItemType::Introduction, Source::System);
     o.raw(format!("Happy chatting..."), ItemType::Introduction, Source::System);
     o.raw(format!(" "), ItemType::Introduction, Source::System);
@@ -0,0 +1,452 @@
+/// Number of error correction codewords per block for the error correction level M
+/// (about 15% of the data can be restored) indexed by the version - 1.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 40] = [
+    10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26,
+    26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+/// Number of error correction blocks for the error correction level M.
+const ECC_BLOCKS: [usize; 40] = [
+    1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16,
+    17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+/// Format bits of the error correction level M.
+const ECL_M: u32 = 0;
+/// Number of light modules around the code which are required by scanners.
+const QUIET_ZONE: usize = 2;
+
+/// A QR code in byte mode with error correction level M.
+///
+/// Keys can be exchanged or verified by scanning the code shown in the terminal with a
+/// phone. The smallest version which can hold the data is used.
+pub struct QrCode {
+    size: usize,
+    modules: Vec<Vec<bool>>,
+    /// Modules of the finder, timing and alignment patterns and of the format and version
+    /// information which are not masked.
+    function: Vec<Vec<bool>>,
+}
+
+impl QrCode {
+
+    pub fn encode(data: &[u8]) -> Result<QrCode, &'static str> {
+
+        let version = (1..=40)
+            .find(|&v| 4 + count_bits(v) + data.len() * 8 <= data_codewords(v) * 8)
+            .ok_or("Too much data for a QR code.")?;
+
+        // mode indicator for bytes, character count, data, terminator and padding
+        let mut bits = Bits::new();
+        bits.push(4, 4);
+        bits.push(data.len() as u32, count_bits(version));
+        for &b in data {
+            bits.push(b as u32, 8);
+        }
+        let capacity = data_codewords(version) * 8;
+        bits.push(0, (capacity - bits.len()).min(4));
+        bits.push(0, (8 - bits.len() % 8) % 8);
+        let mut codewords = bits.bytes();
+        for pad in [0xec, 0x11].iter().cycle().take(capacity / 8 - codewords.len()) {
+            codewords.push(*pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut q = QrCode {
+            size,
+            modules: vec![vec![false; size]; size],
+            function: vec![vec![false; size]; size],
+        };
+        q.draw_function_patterns(version);
+        q.draw_codewords(&interleave(version, &codewords));
+
+        // The mask with the lowest penalty is used.
+        let mask = (0..8).min_by_key(|&m| {
+            q.apply_mask(m);
+            q.draw_format_bits(m);
+            let p = q.penalty();
+            q.apply_mask(m);
+            p
+        }).unwrap();
+        q.apply_mask(mask);
+        q.draw_format_bits(mask);
+        Ok(q)
+    }
+
+    /// Returns the lines of the code drawn with block characters. Each line contains two
+    /// rows of modules. Light modules are drawn as blocks so that the code is shown dark on
+    /// light in terminals with a dark background.
+    pub fn lines(&self) -> Vec<String> {
+
+        let n = self.size + 2 * QUIET_ZONE;
+        let light = |x: usize, y: usize| {
+            x < QUIET_ZONE || y < QUIET_ZONE || x >= self.size + QUIET_ZONE || y >= self.size + QUIET_ZONE
+                || !self.modules[y - QUIET_ZONE][x - QUIET_ZONE]
+        };
+        (0..n).step_by(2).map(|y| {
+            (0..n).map(|x| match (light(x, y), y + 1 < n && light(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            }).collect()
+        }).collect()
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y][x] = dark;
+        self.function[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        for &(cx, cy) in &[(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if x >= 0 && y >= 0 && (x as usize) < size && (y as usize) < size {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+
+        let pos = alignment_positions(version);
+        let last = pos.len().saturating_sub(1);
+        for (i, &cx) in pos.iter().enumerate() {
+            for (j, &cy) in pos.iter().enumerate() {
+                // The corners are occupied by the finder patterns.
+                if (i == 0 && j == 0) || (i == 0 && j == last) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function((cx as i32 + dx) as usize, (cy as i32 + dy) as usize, dark);
+                    }
+                }
+            }
+        }
+
+        // Reserves the modules of the format information.
+        self.draw_format_bits(0);
+
+        if version >= 7 {
+            let mut rem = version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+            }
+            let bits = (version as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+
+        let data = ECL_M << 3 | mask;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = (data << 10 | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        let size = self.size;
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Places the codewords in a zigzag from the bottom right corner.
+    fn draw_codewords(&mut self, data: &[u8]) {
+
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.function[y][x] && i < data.len() * 8 {
+                        self.modules[y][x] = (data[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    /// Inverts the data modules for which the mask pattern is true. Applying a mask twice
+    /// removes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.function[y][x] {
+                    self.modules[y][x] ^= true;
+                }
+            }
+        }
+    }
+
+    /// Penalty of the current mask. Long runs and blocks of the same color, patterns which
+    /// look like finder patterns and an unbalanced number of dark modules make a code
+    /// harder to scan.
+    fn penalty(&self) -> usize {
+
+        let n = self.size;
+        let mut p = 0;
+        let rows = (0..n).map(|y| (0..n).map(|x| self.modules[y][x]).collect::<Vec<_>>());
+        let cols = (0..n).map(|x| (0..n).map(|y| self.modules[y][x]).collect::<Vec<_>>());
+        let finder = [true, false, true, true, true, false, true, false, false, false, false];
+        for line in rows.chain(cols) {
+            let mut run = 1;
+            for i in 1..=n {
+                if i < n && line[i] == line[i - 1] {
+                    run += 1;
+                } else {
+                    if run >= 5 {
+                        p += run - 2;
+                    }
+                    run = 1;
+                }
+            }
+            for w in line.windows(finder.len()) {
+                if w == finder || w.iter().rev().eq(finder.iter()) {
+                    p += 40;
+                }
+            }
+        }
+
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let c = self.modules[y][x];
+                if c == self.modules[y][x + 1] && c == self.modules[y + 1][x] && c == self.modules[y + 1][x + 1] {
+                    p += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().flatten().filter(|&&m| m).count();
+        let total = n * n;
+        let k = ((dark * 20) as i64 - (total * 10) as i64).abs() as usize;
+        p + (k + total - 1) / total * 10
+    }
+}
+
+/// Number of bits of the character count.
+fn count_bits(version: usize) -> usize {
+    if version <= 9 { 8 } else { 16 }
+}
+
+/// Number of modules which are available for data and error correction.
+fn raw_modules(version: usize) -> usize {
+    let mut r = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let n = version / 7 + 2;
+        r -= (25 * n - 10) * n - 55;
+        if version >= 7 {
+            r -= 36;
+        }
+    }
+    r
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version - 1] * ECC_BLOCKS[version - 1]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let n = version / 7 + 2;
+    let step = match version {
+        32 => 26,
+        _ => (version * 4 + n * 2 + 1) / (n * 2 - 2) * 2
+    };
+    let mut r = vec![6];
+    let mut pos = version * 4 + 17 - 7;
+    for _ in 0..n - 1 {
+        r.insert(1, pos);
+        pos -= step;
+    }
+    r
+}
+
+/// Splits the data into blocks, appends the error correction codewords to each block and
+/// interleaves the blocks.
+fn interleave(version: usize, data: &[u8]) -> Vec<u8> {
+
+    let blocks = ECC_BLOCKS[version - 1];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version - 1];
+    let raw = raw_modules(version) / 8;
+    let short_blocks = blocks - raw % blocks;
+    let short_len = raw / blocks;
+    let divisor = rs_divisor(ecc_len);
+
+    let mut v = vec![];
+    let mut k = 0;
+    for i in 0..blocks {
+        let len = short_len - ecc_len + if i < short_blocks { 0 } else { 1 };
+        let d = &data[k..k + len];
+        k += len;
+        v.push((d.to_vec(), rs_remainder(d, &divisor)));
+    }
+
+    let mut r = vec![];
+    for i in 0..=short_len - ecc_len {
+        for (d, _) in &v {
+            if i < d.len() {
+                r.push(d[i]);
+            }
+        }
+    }
+    for i in 0..ecc_len {
+        for (_, e) in &v {
+            r.push(e[i]);
+        }
+    }
+    r
+}
+
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut r = vec![0; degree];
+    r[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            r[j] = gf_mul(r[j], root);
+            if j + 1 < degree {
+                r[j] ^= r[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+    r
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut r = vec![0; divisor.len()];
+    for &b in data {
+        let factor = b ^ r.remove(0);
+        r.push(0);
+        for (x, &d) in r.iter_mut().zip(divisor) {
+            *x ^= gf_mul(d, factor);
+        }
+    }
+    r
+}
+
+struct Bits {
+    v: Vec<bool>,
+}
+
+impl Bits {
+
+    fn new() -> Bits {
+        Bits { v: vec![] }
+    }
+
+    fn push(&mut self, val: u32, n: usize) {
+        for i in (0..n).rev() {
+            self.v.push((val >> i) & 1 != 0);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.v.chunks(8).map(|c| c.iter().fold(0, |acc, &b| acc << 1 | b as u8)).collect()
+    }
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::{QrCode, data_codewords, rs_remainder, rs_divisor, QUIET_ZONE};
+
+    #[test]
+    fn test_capacity() {
+        // Number of data codewords of the error correction level M.
+        assert_eq!(data_codewords(1), 16);
+        assert_eq!(data_codewords(7), 124);
+        assert_eq!(data_codewords(40), 2334);
+        assert_eq!(QrCode::encode(&[0; 14]).unwrap().size, 21);
+        assert_eq!(QrCode::encode(&[0; 15]).unwrap().size, 25);
+        assert!(QrCode::encode(&[0; 3000]).is_err());
+    }
+
+    #[test]
+    fn test_reed_solomon() {
+        // Example of the specification: "01234567" in numeric mode, version 1-M.
+        let data = [0x10, 0x20, 0x0c, 0x56, 0x61, 0x80, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11];
+        assert_eq!(rs_remainder(&data, &rs_divisor(10)), vec![0xa5, 0x24, 0xd4, 0xc1, 0xed, 0x36, 0xc7, 0x87, 0x2c, 0x55]);
+    }
+
+    #[test]
+    fn test_lines() {
+        let q = QrCode::encode(b"hello").unwrap();
+        let lines = q.lines();
+        let n = q.size + 2 * QUIET_ZONE;
+        assert_eq!(lines.len(), (n + 1) / 2);
+        assert!(lines.iter().all(|l| l.chars().count() == n));
+        // The quiet zone is light and the top left module of the finder pattern is dark.
+        assert!(lines[0].chars().all(|c| c == '█'));
+        assert_eq!(lines[1].chars().nth(QUIET_ZONE), Some(' '));
+    }
+}
@@ -0,0 +1,799 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::io::Write;
+use std::io::Stdout;
+use std::io::stdout;
+use std::cmp::min;
+use std::collections::HashSet;
+use std::sync::mpsc::Sender;
+use termion::color::Fg;
+use termion::raw::RawTerminal;
+use termion::raw::IntoRawMode;
+
+use crate::model::{Item, ItemType, Model};
+use crate::model::Source;
+use crate::keyboad::UserInput;
+use stealthy_core::tools::rot13;
+use stealthy_core::signing::SignatureStatus;
+use stealthy_core::replay::{self, Stamp};
+use stealthy_core::presence::Presence;
+
+static ACK: char = '✔';
+static DELIVERED: char = '✔';
+static READ: char = '◉';
+static FAILED: char = '✘';
+static NUMBERS: &str = "➀➁➂➃➄➅➆➇➈➉";
+static VERIFIED: char = '✓';
+static UNVERIFIED: char = '✗';
+/// Shown next to the ip of peers whose short authentication string has been confirmed.
+static VERIFIED_PEER: char = '★';
+static ONLINE: char = '●';
+static AWAY: char = '◐';
+static OFFLINE: char = '○';
+/// Shown in front of the number of unread messages.
+static UNREAD: char = '✉';
+/// Appended to messages whose text has been replaced by an edit.
+static EDITED: &str = "(edited)";
+/// Shown instead of messages which have been deleted by their sender.
+static DELETED: &str = "(message deleted)";
+
+/// Write messages to the terminal.
+pub struct View {
+    stdout: RawTerminal<Stdout>,
+    model: Arc<Mutex<Model>>,
+    // The scroll_offset is the amount of "arrows up".
+    // When the user scrolls, i.e. the scroll_offset > 0, then a new message should not change the
+    // view on the messages in the window. Therefore, adjust_scroll_offset() needs to be called
+    // when a new message has been added to the buffer in the model.
+    scroll_offset: usize,
+    raw_view: bool,
+    /// Peers whose keys have been verified via /verify.
+    verified: HashSet<String>,
+    /// Receives the ips and stamps of received messages which have been shown.
+    reads: Option<Sender<(String, Stamp)>>,
+    /// Incremental search which has been started with Ctrl+F.
+    search: Option<Search>,
+}
+
+/// State of the incremental search in the messages of the current tab.
+struct Search {
+    query: String,
+    /// True while the query is typed. Afterwards n and N jump between the hits.
+    typing: bool,
+    /// Index of the current hit counted from the newest message.
+    hit: usize,
+}
+
+impl Search {
+
+    /// Returns true if the text of the item contains the query ignoring the case.
+    fn matches(&self, i: &Item) -> bool {
+        !self.query.is_empty() && !i.deleted && i.msg.to_lowercase().contains(&self.query.to_lowercase())
+    }
+
+    /// Returns the text which is shown in the input field during the search.
+    fn status(&self, hits: usize) -> String {
+        let n = match hits {
+            0 => "no hits".to_string(),
+            _ => format!("{}/{}", self.hit + 1, hits)
+        };
+        match self.typing {
+            true => format!("[{}] search: {}", n, self.query),
+            false => format!("[{}] search: {} (n: older, N: newer, Esc: end)", n, self.query)
+        }
+    }
+}
+
+impl View {
+
+    pub fn new(model: Arc<Mutex<Model>>) -> View {
+        View {
+            stdout: stdout().into_raw_mode().expect("No raw mode possible."),
+            model: model,
+            scroll_offset: 0,
+            raw_view: false,
+            verified: HashSet::new(),
+            reads: None,
+            search: None,
+        }.init()
+    }
+
+    pub fn close(&mut self) {
+        write!(self.stdout, "{}{}{}{}{}",
+               termion::clear::All,
+               termion::cursor::Goto(1, 1),
+               termion::cursor::Show,
+               termion::color::Fg(termion::color::Reset),
+               termion::color::Bg(termion::color::Reset)
+        ).expect("Write error.");
+        self.flush();
+    }
+
+    /// This method is called after a new message has been received. If the user has scrolled
+    /// to some position in the window this method ensures that the content of the window does
+    /// not scroll for the new message.
+    /// The message is added to the model.
+    pub fn adjust_scroll_offset(&mut self, i: Item) {
+        let (scrambled, visible) = {
+            let m = self.model.lock().unwrap();
+            (m.is_scrambled(), m.is_visible(&i))
+        };
+        if self.scroll_offset > 0 && visible {
+            self.increase_scroll_offset(View::split_line(self, &i, scrambled).len());
+        }
+
+        self.redraw();
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll_up_1();
+        self.redraw();
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll_down_1();
+        self.redraw();
+    }
+
+    pub fn refresh(&mut self) {
+        self.redraw();
+    }
+
+    pub fn page_up(&mut self) {
+        for _ in 0..self.window_height() {
+            self.scroll_up_1();
+        }
+        self.redraw();
+    }
+
+    pub fn page_down(&mut self) {
+        for _ in 0..self.window_height() {
+            self.scroll_down_1();
+        }
+        self.redraw();
+    }
+
+    pub fn key_end(&mut self) {
+        self.scroll_offset = 0;
+        self.redraw();
+    }
+
+    /// Starts the incremental search. The messages which contain the typed text are
+    /// highlighted and the newest of them is shown.
+    pub fn start_search(&mut self) {
+        self.search = Some(Search { query: String::new(), typing: true, hit: 0 });
+        self.redraw();
+    }
+
+    /// Handles the keys while the search is active. Returns the keys which are not used
+    /// by the search, e.g. to scroll.
+    pub fn search_key(&mut self, i: UserInput) -> Option<UserInput> {
+
+        let (typing, hits) = match self.search {
+            Some(ref s) => (s.typing, self.hits(&self.model.lock().unwrap().visible()).len()),
+            None => return Some(i)
+        };
+        let s = self.search.as_mut().unwrap();
+        match (i, typing) {
+            (UserInput::Escape, _) => self.search = None,
+            (UserInput::CtrlF, _) => s.typing = true,
+            (UserInput::Enter, true) => s.typing = false,
+            (UserInput::Enter, false) => self.search = None,
+            (UserInput::Backspace, true) => {
+                s.query.pop();
+                s.hit = 0;
+            },
+            (UserInput::Character(buf), true) => {
+                s.query.extend(String::from_utf8_lossy(&buf).chars().filter(|c| !c.is_control()));
+                s.hit = 0;
+            },
+            (UserInput::Character(ref buf), false) if buf == b"n" && hits > 0 => s.hit = (s.hit + 1) % hits,
+            (UserInput::Character(ref buf), false) if buf == b"N" && hits > 0 => s.hit = (s.hit + hits - 1) % hits,
+            (UserInput::Character(ref buf), false) if buf == b"n" || buf == b"N" => { },
+            // Typing ends the search.
+            (i @ UserInput::Character(_), false) => {
+                self.search = None;
+                self.redraw();
+                return Some(i);
+            },
+            (i, _) => return Some(i)
+        }
+        self.show_hit();
+        None
+    }
+
+    /// Returns the indices of the items which contain the query.
+    fn hits(&self, items: &[Item]) -> Vec<usize> {
+        match self.search {
+            Some(ref s) => (0..items.len()).filter(|k| s.matches(&items[*k])).collect(),
+            None => vec![]
+        }
+    }
+
+    /// Returns the index of the item of the current hit.
+    fn current_hit(&self, hits: &[usize]) -> Option<usize> {
+        let hit = self.search.as_ref()?.hit;
+        hits.len().checked_sub(hit + 1).map(|k| hits[k])
+    }
+
+    /// Scrolls so that the item of the current hit is at the bottom of the window.
+    fn show_hit(&mut self) {
+
+        let (items, scrambled) = {
+            let m = self.model.lock().unwrap();
+            (m.visible(), m.is_scrambled())
+        };
+        if let Some(k) = self.current_hit(&self.hits(&items)) {
+            let lens = items.iter().map(|i| self.split_line(i, scrambled).len()).collect::<Vec<_>>();
+            let n = lens.iter().sum::<usize>();
+            let end = lens[..=k].iter().sum::<usize>();
+            self.scroll_offset = (n - end).min(n.saturating_sub(self.window_height()));
+        }
+        self.redraw();
+    }
+
+    /// Shows the messages of the tab which has been selected in the model.
+    pub fn select_tab(&mut self) {
+        self.scroll_offset = 0;
+        write!(self.stdout, "{}", termion::clear::All).expect("Write error.");
+        self.redraw();
+    }
+
+    pub fn set_verified(&mut self, ip: String, verified: bool) {
+        match verified {
+            true => self.verified.insert(ip),
+            false => self.verified.remove(&ip)
+        };
+        self.redraw();
+    }
+
+    /// Sends a read receipt for each received message as soon as it has been shown on the
+    /// screen while the screen was not scrambled.
+    pub fn set_read_receipts(&mut self, tx: Sender<(String, Stamp)>) {
+        self.reads = Some(tx);
+        self.redraw();
+    }
+
+    pub fn toggle_raw_view(&mut self) {
+        self.raw_view = !self.raw_view;
+        self.increase_scroll_offset(0);
+        write!(self.stdout, "{}", termion::clear::All, ).expect("Write error.");
+        self.redraw();
+    }
+
+    // ===========================================================================================
+
+    fn init(mut self) -> View {
+        write!(self.stdout, "{}{}",
+               termion::clear::All,   // clear screen
+               termion::cursor::Hide  // hide cursor
+        ).expect("Error.");
+        self.flush();
+        self
+    }
+
+    fn flush(&mut self) {
+        self.stdout.flush().expect("Flush error.");
+    }
+
+    fn increase_scroll_offset(&mut self, n: usize) {
+        let model = self.model.lock().unwrap();
+        let scrambled = model.is_scrambled();
+        // The number of lines in the window.
+        let window_height = self.window_height();
+        // The number of lines required to show all messages. One message can consume multiple lines.
+        let buffer_lines = self.lines(&model.visible(), scrambled).len();
+
+        if buffer_lines > window_height {
+            let max_off = buffer_lines - window_height;
+            self.scroll_offset = min(max_off, self.scroll_offset + n);
+        }
+    }
+
+    fn scroll_up_1(&mut self) {
+        self.increase_scroll_offset(1);
+    }
+
+    fn scroll_down_1(&mut self) {
+        if self.scroll_offset > 0 {
+            self.scroll_offset -= 1;
+        }
+    }
+
+    fn draw_window(&mut self) {
+        let (maxx, maxy) = View::size();
+
+        for x in 2..maxx {
+            write!(self.stdout, "{}─{}─{}─",
+                   termion::cursor::Goto(x, 1),
+                   termion::cursor::Goto(x, maxy),
+                   termion::cursor::Goto(x, maxy - 2),
+            ).expect("Error.");
+        }
+        for y in 2..maxy {
+            write!(self.stdout, "{}│{}│",
+                   termion::cursor::Goto(1, y),
+                   termion::cursor::Goto(maxx, y)
+            ).expect("Error.");
+        }
+        write!(self.stdout,
+               "{}┌{}┐{}└{}┘{}├{}┤",
+               termion::cursor::Goto(1, 1),
+               termion::cursor::Goto(maxx, 1),
+               termion::cursor::Goto(1, maxy),
+               termion::cursor::Goto(maxx, maxy),
+               termion::cursor::Goto(1, maxy - 2),
+               termion::cursor::Goto(maxx, maxy - 2)
+        ).expect("Error.");
+    }
+
+    fn fm_time(&self, i: &Item) -> String {
+        time::strftime("%d.%m. %R", &i.tim).unwrap()
+    }
+
+    fn progress_bar(&self, pending: usize, total: usize, len: usize) -> String {
+        let k = len * (total - pending) / total;
+        let s_transmitted = std::iter::repeat('█').take(k).collect::<String>();
+        let s_remaining = std::iter::repeat('░').take(len - k).collect::<String>();
+        format!("|{}{}| {:.2}%", s_transmitted, s_remaining, (total - pending) as f64 / total as f64 * 100.0)
+    }
+
+    fn txt(&self, i: &Item, scrambled: bool) -> String {
+        let msg = match i.deleted {
+            true => DELETED.to_string(),
+            false => i.msg.to_string()
+        };
+
+        if self.raw_view {
+            return format!("{}", msg);
+        }
+
+        // Optionally Scramble
+        let maybe_scrambled_msg = if scrambled {
+            scramble(&msg)
+        } else {
+            msg.clone()
+        };
+        let maybe_scrambled_msg = match i.edited {
+            true => format!("{} {}", maybe_scrambled_msg, EDITED),
+            false => maybe_scrambled_msg
+        };
+
+        // Formatting
+        let t = self.fm_time(&i);
+        match i.source() {
+            Source::Ip(ip) if self.verified.contains(&ip) => {
+                format!("{} | [{} {}] {}", t, ip, VERIFIED_PEER, maybe_scrambled_msg)
+            },
+            Source::Ip(ip) => {
+                format!("{} | [{}] {}", t, ip, maybe_scrambled_msg)
+            },
+            Source::You => {
+                match i.typ {
+                    ItemType::UploadMessage => {
+                        format!("{} | [you] {} {}", t, maybe_scrambled_msg, self.progress_bar(i.pending_acks, i.total_acks, 40))
+                    },
+                    _ => match i.list {
+                        // A broadcast shows the status of each recipient.
+                        Some(ref list) => {
+                            let status = i.recipients.iter()
+                                .map(|r| format!("{}: {}", r.ip, r.status.name()))
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            format!("{} | [you → {}] {} ({})", t, list, maybe_scrambled_msg, status)
+                        },
+                        None => format!("{} | [you] {}", t, maybe_scrambled_msg)
+                    }
+                }
+            },
+            Source::System => {
+                let p = match i.typ {
+                    ItemType::Introduction => {
+                        String::from("")
+                    },
+                    _ => {
+                        t + " | "
+                    }
+                };
+                format!("{}{}", p, msg)
+            },
+            Source::Raw => {
+                format!("{}", msg)
+            }
+        }
+    }
+
+    fn redraw(&mut self) {
+        if !self.raw_view {
+            self.draw_window();
+        }
+
+        let wx = self.window_x_offset();
+        let wy = self.window_y_offset();
+
+        let mut model = self.model.lock().unwrap();
+        let scrambled = model.is_scrambled();
+        let screen_width = self.window_width();
+        let screen_height = self.window_height();
+
+        let items = model.visible();
+        let hits = self.hits(&items);
+        let current = self.current_hit(&hits);
+        // The index of the item of each line.
+        let mut owners = vec![];
+        let mut buffer = vec![];
+        for (k, i) in items.iter().enumerate() {
+            for line in self.split_line(i, scrambled) {
+                owners.push(k);
+                buffer.push(line);
+            }
+        }
+        let n = buffer.len();
+        let mut p = 0;
+
+        let buf = if n <= screen_height {
+            buffer.clone()
+        } else {
+            // n - screen_height: index for scroll_offset = 0
+            p = n - screen_height - self.scroll_offset;
+            buffer.iter().skip(p).take(screen_height).cloned().collect()
+        };
+
+        // Show messages.
+        for (y, line) in buf.iter().enumerate() {
+            let t = self.txt(&line, scrambled); // formatted line
+            let m = extend_line_to_screen_width(t, screen_width);
+
+            write_color(&mut self.stdout, line.typ.clone());
+            let k = owners[p + y];
+            if hits.contains(&k) {
+                write_highlight(&mut self.stdout, current == Some(k));
+            }
+            write_at(&mut self.stdout, wx, y + wy, &m);
+            if !self.raw_view {
+                write_symbol(&mut self.stdout, line, y);
+            }
+        }
+
+        // The messages on the screen have been read unless they are scrambled.
+        if let (Some(tx), false) = (&self.reads, scrambled) {
+            for (ip, stamp) in buf.iter().filter_map(|line| line.read_receipt.clone()) {
+                if model.take_read_receipt(&ip, &stamp) {
+                    let _ = tx.send((ip, stamp));
+                }
+            }
+        }
+
+        // Show input field.
+        if !self.raw_view {
+            let input = match self.search {
+                Some(ref s) => s.status(hits.len()).into_bytes(),
+                None => model.input.clone()
+            };
+            write_input_field(&mut self.stdout, input);
+        }
+
+        // Show scroll status.
+        if !self.raw_view && self.scroll_offset > 0 {
+            write_scroll_status(&mut self.stdout, p, buffer.len());
+        }
+
+        if !self.raw_view && scrambled {
+            write_scramble_status(&mut self.stdout);
+        }
+
+        // The newest messages are on the screen unless they are scrambled.
+        if self.scroll_offset == 0 && !scrambled {
+            model.mark_read();
+        }
+
+        if !self.raw_view {
+            write_peers(&mut self.stdout, &model.peers.list(replay::now()));
+            let (tabs, current) = model.tabs();
+            let tabs = std::iter::once(("group".to_string(), model.unread(None)))
+                .chain(tabs.iter().map(|ip| (ip.clone(), model.unread(Some(ip)))))
+                .collect::<Vec<_>>();
+            write_tabs(&mut self.stdout, &tabs, current);
+        }
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn window_x_offset(&self) -> usize {
+        if self.raw_view {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn window_y_offset(&self) -> usize {
+        if self.raw_view {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn window_height(&self) -> usize {
+        if self.raw_view {
+            View::size().1 as usize
+        } else {
+            View::size().1 as usize - 4
+        }
+    }
+
+    fn window_width(&self) -> usize {
+        if self.raw_view {
+            View::size().0 as usize
+        } else {
+            View::size().0 as usize - 2
+        }
+    }
+
+    // ===========================================================================================
+
+    fn size() -> (u16, u16) {
+        termion::terminal_size().unwrap()
+    }
+
+    fn split_line(&self, s: &Item, scrambled: bool) -> Vec<Item> {
+        // TODO use https://github.com/unicode-rs/unicode-width to estimate the width of UTF-8 characters
+        View::remove_symbol(self.txt(s, scrambled).chars().collect::<Vec<char>>()
+            .chunks(self.window_width())
+            .map(|x| s.clone().message(x.iter().collect()).raw())
+            .collect()
+        )
+    }
+
+    /// If a message spans multiple lines this method ensures that the symbol is shown only
+    /// for the first line.
+    fn remove_symbol(mut v: Vec<Item>) -> Vec<Item> {
+        for i in v.iter_mut().skip(1) {
+            i.id.clear();
+            i.signature = SignatureStatus::None;
+        }
+        v
+    }
+
+    fn lines(&self, buf: &Vec<Item>, scrambled: bool) -> Vec<Item> {
+        buf.iter()
+            .map(|v| self.split_line(v, scrambled))
+            .flatten()
+            .collect()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn write_color(o: &mut RawTerminal<Stdout>, typ: ItemType) {
+    match typ {
+        ItemType::Received => write!(o, "{}", Fg(termion::color::LightGreen)),
+        ItemType::Info => write!(o, "{}", Fg(termion::color::Yellow)),
+        ItemType::Introduction => write!(o, "{}", Fg(termion::color::Green)),
+        ItemType::Error => write!(o, "{}", Fg(termion::color::Red)),
+        ItemType::NewFile => write!(o, "{}", Fg(termion::color::LightWhite)),
+        ItemType::MyMessage => write!(o, "{}", Fg(termion::color::Green)),
+        ItemType::UploadMessage => write!(o, "{}", Fg(termion::color::Green)),
+        ItemType::Code => write!(o, "{}", Fg(termion::color::LightWhite)),
+    }.unwrap();
+}
+
+/// Highlights a line of a message which contains the query of the search.
+fn write_highlight(o: &mut RawTerminal<Stdout>, current: bool) {
+    match current {
+        true => write!(o, "{}{}", termion::color::Bg(termion::color::Yellow), Fg(termion::color::Black)),
+        false => write!(o, "{}", termion::color::Bg(termion::color::LightBlack))
+    }.unwrap();
+}
+
+fn extend_line_to_screen_width(s: String, screen_width: usize) -> String {
+    let mut v = s;
+    while v.chars().count() < screen_width {
+        v.push(' ');
+    }
+    v
+}
+
+fn symbol_for_item(item: &Item) -> String {
+    // Received messages show the result of the signature check.
+    match item.signature {
+        SignatureStatus::Verified => return format!("{}{}", Fg(termion::color::Green), VERIFIED),
+        SignatureStatus::Unverified => return format!("{}{}", Fg(termion::color::LightRed), UNVERIFIED),
+        SignatureStatus::None => { }
+    }
+
+    if item.id.len() == 0 {
+        return format!("");
+    }
+
+    if item.failed {
+        return format!("{}{}", Fg(termion::color::Red), FAILED);
+    }
+
+    // Verified read receipts of all recipients.
+    if item.reads_received >= item.id.len() {
+        return format!("{}{}", Fg(termion::color::Green), READ);
+    }
+
+    // Verified receipts of all recipients.
+    if item.receipts_received + item.reads_received >= item.id.len() {
+        return format!("{}{}", Fg(termion::color::Green), DELIVERED);
+    }
+
+    // The network layer has received an ACK which could be forged.
+    if item.acks_received >= item.id.len() {
+        return format!("{}{}", Fg(termion::color::LightBlue), ACK);
+    }
+
+    // pending cannot be zero
+    let pending = item.id.len() - item.acks_received;
+    let p = min(pending, 10) - 1;
+    let v = NUMBERS.chars().collect::<Vec<_>>();
+
+    format!("{}{}", Fg(termion::color::LightYellow), v[p])
+}
+
+fn write_symbol(o: &mut RawTerminal<Stdout>, item: &Item, y: usize) {
+    let symbol = symbol_for_item(item);
+    if symbol.len() == 0 {
+        return;
+    }
+    write!(o, "{}{}{}",
+           termion::cursor::Goto(16, y as u16 + 2),
+           symbol,
+           termion::color::Fg(termion::color::Reset)
+    ).expect("Write failed.");
+}
+
+fn write_at(o: &mut RawTerminal<Stdout>, x: usize, y: usize, s: &str) {
+    write!(o, "{}{}{}{}",
+           termion::cursor::Goto(x as u16, y as u16),
+           s,
+           termion::color::Fg(termion::color::Reset),
+           termion::color::Bg(termion::color::Reset)
+    ).expect("Error.");
+}
+
+fn write_input_field(o: &mut RawTerminal<Stdout>, input: Vec<u8>) {
+
+    let (maxx, maxy) = View::size();
+    let input_field_len = maxx - 2 - 1;
+
+    write!(o, "{}", termion::color::Bg(termion::color::Blue)).expect("Error.");
+    for x in 2..maxx {
+        write!(o, "{} ", termion::cursor::Goto(x, maxy - 1)).expect("Error.");
+    }
+    let mut s = String::from_utf8(input).unwrap();
+    while s.chars().count() > input_field_len as usize {
+        s.remove(0);
+
+    }
+    s.push('▂');
+    write_at(o, 2, maxy as usize - 1, &s);
+}
+
+fn write_scroll_status(o: &mut RawTerminal<Stdout>, current: usize, len: usize) {
+    let (maxx, _) = View::size();
+    let s = format!("line:{}/{}", current, len);
+    let x = maxx as usize - s.len();
+    write!(o, "{}{}{}{}{}{}",
+           termion::cursor::Goto(x as u16, 2),
+           termion::color::Bg(termion::color::Red),
+           termion::color::Fg(termion::color::LightWhite),
+           s,
+           termion::color::Bg(termion::color::Reset),
+           termion::color::Fg(termion::color::Reset)
+    ).expect("Error.");
+}
+
+fn write_scramble_status(o: &mut RawTerminal<Stdout>) {
+    write!(o, "{}{}{}scrambled{}{}",
+           termion::cursor::Goto(2, 1),
+           termion::color::Bg(termion::color::Red),
+           termion::color::Fg(termion::color::LightWhite),
+           termion::color::Bg(termion::color::Reset),
+           termion::color::Fg(termion::color::Reset)
+    ).expect("Error.");
+}
+
+/// Shows the state of the peers in the top right corner of the window.
+fn write_peers(o: &mut RawTerminal<Stdout>, peers: &[(String, Presence, Option<i64>)]) {
+
+    let (maxx, _) = View::size();
+    // Leave space for the corners and the scramble status.
+    let space = (maxx as usize).saturating_sub(14);
+    let mut entries = peers.iter()
+        .map(|(ip, p, _)| match p {
+            Presence::Online => (Fg(termion::color::LightGreen).to_string(), format!(" {} {} ", ONLINE, ip)),
+            Presence::Away => (Fg(termion::color::Yellow).to_string(), format!(" {} {} ", AWAY, ip)),
+            Presence::Offline => (Fg(termion::color::LightBlack).to_string(), format!(" {} {} ", OFFLINE, ip)),
+        })
+        .collect::<Vec<_>>();
+    while entries.iter().map(|(_, s)| s.chars().count()).sum::<usize>() > space {
+        entries.pop();
+    }
+    let len = entries.iter().map(|(_, s)| s.chars().count()).sum::<usize>();
+    if len == 0 {
+        return;
+    }
+    write!(o, "{}", termion::cursor::Goto((maxx as usize - len) as u16, 1)).expect("Error.");
+    for (color, s) in entries {
+        write!(o, "{}{}", color, s).expect("Error.");
+    }
+    write!(o, "{}", termion::color::Fg(termion::color::Reset)).expect("Error.");
+}
+
+/// Shows the tabs with the number of unread messages in the bottom line of the window. The
+/// first tab is the tab of the group. The current tab is highlighted and tabs with unread
+/// messages are shown in yellow. Without tabs only the number of unread messages is shown.
+fn write_tabs(o: &mut RawTerminal<Stdout>, tabs: &[(String, usize)], current: usize) {
+
+    let (maxx, maxy) = View::size();
+    write!(o, "{}", termion::cursor::Goto(2, maxy)).expect("Error.");
+    if tabs.len() == 1 {
+        if tabs[0].1 > 0 {
+            write!(o, "{}{} {} new {}",
+                   Fg(termion::color::LightYellow),
+                   UNREAD,
+                   tabs[0].1,
+                   termion::color::Fg(termion::color::Reset)
+            ).expect("Error.");
+        }
+        return;
+    }
+    let mut len = 0;
+    for (i, (name, unread)) in tabs.iter().enumerate() {
+        let s = match unread {
+            0 => format!(" {} ", name),
+            n => format!(" {} ({}) ", name, n)
+        };
+        len += s.chars().count();
+        // Leave space for the corner.
+        if len > (maxx as usize).saturating_sub(2) {
+            break;
+        }
+        match (i == current, *unread > 0) {
+            (true, _) => write!(o, "{}{}{}{}{}",
+                                termion::color::Bg(termion::color::Blue),
+                                termion::color::Fg(termion::color::LightWhite),
+                                s,
+                                termion::color::Bg(termion::color::Reset),
+                                termion::color::Fg(termion::color::Reset)),
+            (false, true) => write!(o, "{}{}{}", Fg(termion::color::LightYellow), s, termion::color::Fg(termion::color::Reset)),
+            (false, false) => write!(o, "{}", s)
+        }.expect("Error.");
+    }
+}
+
+fn scramble(i: &String) -> String {
+    i.chars().map(|c| rot13(c)).collect::<String>()
+}
+
+// ------------------------------------------------------------------------
+// TESTS
+// ------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::Search;
+    use crate::model::{Item, ItemType, Source};
+
+    #[test]
+    fn test_search() {
+
+        let mut s = Search { query: "HEL".to_string(), typing: true, hit: 0 };
+        let mut i = Item::new("hello".to_string(), ItemType::Received, Source::Ip("1.1.1.1".to_string()));
+        assert!(s.matches(&i));
+        assert!(!s.matches(&i.clone().message("world".to_string())));
+        assert_eq!(s.status(2), "[1/2] search: HEL");
+        assert_eq!(s.status(0), "[no hits] search: HEL");
+
+        // Deleted messages and an empty query have no hits.
+        i.deleted = true;
+        assert!(!s.matches(&i));
+        s.query.clear();
+        assert!(!s.matches(&Item::new_system("hello")));
+    }
+}